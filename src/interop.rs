@@ -0,0 +1,2120 @@
+//! Conversion of Churchroad designs to and from other formats.
+//!
+//! Importers are named `from_{format}`, and return the imported design.
+//! Exporters are named `to_{format}`. Those that work on serialized egraphs
+//! take the egraph and a choice of node for each eclass, e.g. from
+//! [`AnythingExtractor`](crate::AnythingExtractor), and return the design as
+//! a string, or an [`ExportError`] if the design uses an op the format can't
+//! describe. Each exporter writes the logic driving the design's outputs, as
+//! given by their `IsPort` facts.
+//!
+//! ```
+//! use churchroad::*;
+//! use egglog::{EGraph, SerializeConfig};
+//!
+//! let mut egraph = EGraph::default();
+//! import_churchroad(&mut egraph).unwrap();
+//! egraph
+//!     .parse_and_run_program(
+//!         r#"
+//! (let a (Var "a" 8))
+//! (IsPort "" "a" (Input) a)
+//! (let b (Var "b" 8))
+//! (IsPort "" "b" (Input) b)
+//! (IsPort "" "out" (Output) (Op2 (And) a b))
+//! (run-schedule (saturate typing))
+//! "#,
+//!     )
+//!     .unwrap();
+//! let serialized = egraph.serialize(SerializeConfig::default());
+//! let choices = AnythingExtractor.extract(&serialized, &[]);
+//!
+//! let verilog = to_verilog_egraph_serialize(&serialized, &choices, "clk");
+//! assert!(verilog.contains("input [8-1:0] a"));
+//!
+//! let smtlib = to_smtlib(&serialized, &choices).unwrap();
+//! assert!(smtlib.contains("(declare-const a (_ BitVec 8))"));
+//!
+//! let btor2 = to_btor2(&serialized, &choices).unwrap();
+//! assert!(btor2.contains("input 1 a"));
+//!
+//! let blif = to_blif(&serialized, &choices).unwrap();
+//! assert!(blif.contains(".outputs out[0]"));
+//!
+//! // The egglog program can be read back into a fresh egraph.
+//! let program = to_egglog(&serialized, &choices).unwrap();
+//! let mut reimported = EGraph::default();
+//! import_churchroad(&mut reimported).unwrap();
+//! reimported.parse_and_run_program(&program).unwrap();
+//!
+//! // As can the serialized egraph, once written as JSON.
+//! let json = serde_json::to_string(&serialized).unwrap();
+//! assert_eq!(from_egraph_json(&json).unwrap().nodes.len(), serialized.nodes.len());
+//! ```
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use egglog::EGraph;
+use egraph_serialize::{ClassId, Node, NodeId};
+use indexmap::{IndexMap, IndexSet};
+
+use crate::{
+    get_bitwidth, get_inputs_and_outputs_serialized, import_churchroad, truncate_value_to_bitwidth,
+    ChurchroadImportError,
+};
+pub use crate::{to_verilog, to_verilog_egraph_serialize};
+
+/// Error returned when importing a Verilog design, e.g. by
+/// [`from_verilog_file`].
+#[derive(Debug)]
+pub enum ImportError {
+    /// Yosys couldn't be run, or a file couldn't be read or written.
+    Io(std::io::Error),
+    /// There's no Yosys binary on the `PATH`.
+    YosysNotFound,
+    /// Yosys couldn't load the Churchroad plugin, with the given stderr.
+    /// This is usually because the plugin wasn't built, or was built against
+    /// a different version of Yosys.
+    PluginLoad(String),
+    /// Yosys couldn't parse the Verilog, with the given stderr.
+    Verilog(String),
+    /// Yosys failed for some other reason, with the given stderr.
+    Yosys(String),
+    /// A JSON netlist couldn't be read, or uses cells which aren't supported,
+    /// as described by the message.
+    JsonNetlist(String),
+    /// Churchroad couldn't be imported into the egraph.
+    Churchroad(ChurchroadImportError),
+    /// egglog failed to run the Churchroad program written by Yosys, first
+    /// failing on the command starting at the given line, counting from one.
+    ChurchroadProgram {
+        line: usize,
+        command: String,
+        error: egglog::Error,
+    },
+    /// egglog failed on a Churchroad program.
+    Egglog(egglog::Error),
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::Io(err) => write!(f, "I/O error: {}", err),
+            ImportError::YosysNotFound => write!(f, "couldn't find yosys; is it on the PATH?"),
+            ImportError::PluginLoad(stderr) => {
+                write!(f, "Yosys couldn't load the Churchroad plugin: {}", stderr)
+            }
+            ImportError::Verilog(stderr) => {
+                write!(f, "Yosys couldn't parse the Verilog: {}", stderr)
+            }
+            ImportError::Yosys(stderr) => write!(f, "Yosys failed: {}", stderr),
+            ImportError::JsonNetlist(message) => write!(f, "bad JSON netlist: {}", message),
+            ImportError::Churchroad(err) => write!(f, "{}", err),
+            ImportError::ChurchroadProgram {
+                line,
+                command,
+                error,
+            } => write!(
+                f,
+                "egglog failed on line {} of the Churchroad program written by Yosys, `{}`: {}",
+                line, command, error
+            ),
+            ImportError::Egglog(err) => write!(f, "egglog failed: {}", err),
+        }
+    }
+}
+
+impl From<std::io::Error> for ImportError {
+    fn from(err: std::io::Error) -> Self {
+        ImportError::Io(err)
+    }
+}
+
+impl From<ChurchroadImportError> for ImportError {
+    fn from(err: ChurchroadImportError) -> Self {
+        ImportError::Churchroad(err)
+    }
+}
+
+impl From<egglog::Error> for ImportError {
+    fn from(err: egglog::Error) -> Self {
+        ImportError::Egglog(err)
+    }
+}
+
+/// Import the Verilog design in `path`, with top module `top`, into a new
+/// egraph with Churchroad imported. Yosys translates the design to Churchroad
+/// using the Churchroad Yosys plugin at `yosys_plugin` (churchroad.so). The
+/// design is not typed; see [`crate::ChurchroadEGraph::from_verilog`] for the full
+/// import pipeline.
+///
+/// Yosys normally flattens the design. With `keep_hierarchy`, each module
+/// instantiated by `top` is instead imported alongside it, with its ports
+/// marked by `IsPort` facts naming the module, and the instances become
+/// `GetOutput`s of `ModuleInstance`s of it. Lets in the program, and the
+/// `Wire`s they name, are then prefixed with their module's name and a `-`,
+/// so that modules don't clash. Modules which are instantiated with parameters
+/// are imported once per set of parameter values, under a name which Yosys
+/// derives from them, and the parameter values are bound in each
+/// `ModuleInstance` of them.
+///
+/// The top module's parameters take their default values, except for those in
+/// `parameters`, which maps parameter names to Verilog constants, e.g.
+/// `64'h8000000000000000`. Either way, they're baked into the design as
+/// constants.
+///
+/// Memories become `Mem`s, with a `MemWrite` fact for their write port, if
+/// any, and `MemRead`s for their read ports; synchronous reads are read into
+/// registers. Features Churchroad doesn't model, such as more than one write
+/// port, words with different initial values, or asynchronous reads which
+/// are transparent, fail the import.
+///
+/// Failures are told apart where possible: a missing Yosys, a plugin which
+/// can't be loaded, Verilog which can't be parsed, and Churchroad which egglog
+/// can't run each have their own [`ImportError`].
+pub fn from_verilog_file(
+    path: &std::path::Path,
+    top: &str,
+    yosys_plugin: &std::path::Path,
+    keep_hierarchy: bool,
+    parameters: &HashMap<String, String>,
+) -> Result<EGraph, ImportError> {
+    from_verilog_file_with_yosys(
+        std::path::Path::new("yosys"),
+        path,
+        top,
+        yosys_plugin,
+        keep_hierarchy,
+        parameters,
+    )
+}
+
+/// [`from_verilog_file`], running the given Yosys binary.
+pub(crate) fn from_verilog_file_with_yosys(
+    yosys: &std::path::Path,
+    path: &std::path::Path,
+    top: &str,
+    yosys_plugin: &std::path::Path,
+    keep_hierarchy: bool,
+    parameters: &HashMap<String, String>,
+) -> Result<EGraph, ImportError> {
+    // Sorted, so that the script doesn't depend on the map's order.
+    let mut chparams = parameters
+        .iter()
+        .map(|(name, value)| format!("chparam -set {} {} {}; ", name, value, top))
+        .collect::<Vec<_>>();
+    chparams.sort();
+    let output = std::process::Command::new(yosys)
+        .arg("-m")
+        .arg(yosys_plugin)
+        .arg("-q")
+        .arg("-p")
+        .arg(if keep_hierarchy {
+            format!(
+                "read_verilog -sv {}; {}prep -top {}; write_lakeroad -hierarchy",
+                path.display(),
+                chparams.concat(),
+                top
+            )
+        } else {
+            format!(
+                "read_verilog -sv {}; {}prep -flatten -top {}; write_lakeroad",
+                path.display(),
+                chparams.concat(),
+                top
+            )
+        })
+        .output()
+        .map_err(|err| match err.kind() {
+            std::io::ErrorKind::NotFound => ImportError::YosysNotFound,
+            _ => ImportError::Io(err),
+        })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        // On macOS, a plugin built against another Yosys fails with dyld's
+        // "Missing symbol called" rather than when it's loaded.
+        return Err(
+            if stderr.contains("Can't load module") || stderr.contains("Missing symbol called") {
+                ImportError::PluginLoad(stderr)
+            } else if stderr.contains("Parser error") || stderr.contains("syntax error") {
+                ImportError::Verilog(stderr)
+            } else {
+                ImportError::Yosys(stderr)
+            },
+        );
+    }
+
+    let program = String::from_utf8_lossy(&output.stdout);
+    let mut egraph = EGraph::default();
+    import_churchroad(&mut egraph)?;
+    if let Err(error) = egraph.parse_and_run_program(&program) {
+        return Err(match failing_command(&program) {
+            Some((line, command)) => ImportError::ChurchroadProgram {
+                line,
+                command,
+                error,
+            },
+            None => ImportError::Egglog(error),
+        });
+    }
+    Ok(egraph)
+}
+
+/// The line number, counting from one, and text of the first top-level
+/// command of a Churchroad program which egglog fails on when the commands
+/// are run one at a time.
+fn failing_command(program: &str) -> Option<(usize, String)> {
+    let mut egraph = EGraph::default();
+    import_churchroad(&mut egraph).ok()?;
+
+    let (mut depth, mut in_string, mut in_comment) = (0, false, false);
+    let (mut start, mut start_line, mut line) = (None, 1, 1);
+    for (i, c) in program.char_indices() {
+        match c {
+            '\n' => {
+                line += 1;
+                in_comment = false;
+            }
+            _ if in_comment => (),
+            '"' => in_string = !in_string,
+            _ if in_string => (),
+            ';' => in_comment = true,
+            '(' => {
+                if depth == 0 {
+                    (start, start_line) = (Some(i), line);
+                }
+                depth += 1;
+            }
+            ')' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    let command = &program[start.take()?..=i];
+                    if egraph.parse_and_run_program(command).is_err() {
+                        return Some((start_line, command.to_owned()));
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // Commands left unclosed, or stray text, are what fails.
+    start.map(|start| (start_line, program[start..].trim_end().to_owned()))
+}
+
+/// Like [`from_verilog_file`], but for Verilog source in a string, e.g. one
+/// generated in memory. Yosys can only read Verilog from files, so the source
+/// is written to a temporary file, which is removed again afterwards.
+pub fn from_verilog_string(
+    verilog: &str,
+    top: &str,
+    yosys_plugin: &std::path::Path,
+    keep_hierarchy: bool,
+    parameters: &HashMap<String, String>,
+) -> Result<EGraph, ImportError> {
+    static NUM_IMPORTS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "churchroad_{}_{}.sv",
+        std::process::id(),
+        NUM_IMPORTS.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+    ));
+    std::fs::write(&path, verilog)?;
+    let egraph = from_verilog_file(&path, top, yosys_plugin, keep_hierarchy, parameters);
+    std::fs::remove_file(&path)?;
+    egraph
+}
+
+/// Import the top module `top` of a netlist written by Yosys's `write_json`,
+/// e.g. after `prep`, into a new egraph with Churchroad imported. Unlike
+/// [`from_verilog_file`], this doesn't need the Churchroad Yosys plugin.
+///
+/// Only the cells `$not`, `$and`, `$or`, `$xor`, `$add`, `$sub`, `$mul`,
+/// `$shl`, `$shr`, `$shiftx`, `$eq`, `$ne`, `$logic_not`, `$reduce_and`,
+/// `$reduce_or`, `$reduce_bool`, `$mux`, `$pmux`, `$dff` and `$mem_v2` are
+/// supported; case statements needn't be run through `pmuxtree` first.
+/// Memories are imported as described in [`from_verilog_file`]. Each bit of each
+/// net is a `Wire`, so [`crate::auto_delete_wires`] should be called once the
+/// design is typed. Constant `x` and `z` bits are taken to be 0, and registers
+/// start at 0.
+pub fn from_json_netlist_file(path: &std::path::Path, top: &str) -> Result<EGraph, ImportError> {
+    from_json_netlist_string(&std::fs::read_to_string(path)?, top)
+}
+
+/// Like [`from_json_netlist_file`], but for a netlist in a string.
+pub fn from_json_netlist_string(json: &str, top: &str) -> Result<EGraph, ImportError> {
+    let program = json_netlist_to_churchroad(json, top).map_err(ImportError::JsonNetlist)?;
+    let mut egraph = EGraph::default();
+    import_churchroad(&mut egraph)?;
+    egraph.parse_and_run_program(&program)?;
+    Ok(egraph)
+}
+
+/// The Churchroad program for a module of a JSON netlist.
+fn json_netlist_to_churchroad(json: &str, top: &str) -> Result<String, String> {
+    use serde_json::Value;
+
+    let netlist: Value = serde_json::from_str(json).map_err(|err| err.to_string())?;
+    let module = &netlist["modules"][top];
+    if module.is_null() {
+        return Err(format!("no module named {top:?}"));
+    }
+
+    // A signal is a list of bits, least significant first, each either a net
+    // number or a constant.
+    let bits = |signal: &Value| -> Result<Vec<Value>, String> {
+        match signal.as_array() {
+            Some(bits) if !bits.is_empty() => Ok(bits.clone()),
+            _ => Err(format!("expected a list of bits, found {signal}")),
+        }
+    };
+    let signal =
+        |signal: &Value| -> Result<String, String> { json_signal_to_churchroad(&bits(signal)?) };
+    // Union each net of `signal` with its bit of the expression bound to
+    // `name`.
+    let drive = |program: &mut String, signal: &Value, name: &str| -> Result<(), String> {
+        for (i, bit) in bits(signal)?.iter().enumerate() {
+            if let Value::Number(net) = bit {
+                program.push_str(&format!("(union b{net} (Op1 (Extract {i} {i}) {name}))\n"));
+            }
+        }
+        Ok(())
+    };
+
+    let mut program = String::from("; wire declarations\n");
+    let mut nets = std::collections::BTreeSet::new();
+    let connections = module["ports"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(_, port)| &port["bits"])
+        .chain(
+            module["cells"]
+                .as_object()
+                .into_iter()
+                .flatten()
+                .flat_map(|(_, cell)| cell["connections"].as_object().into_iter().flatten())
+                .map(|(_, signal)| signal),
+        );
+    for signal in connections {
+        nets.extend(bits(signal)?.iter().filter_map(Value::as_u64));
+    }
+    for net in nets {
+        program.push_str(&format!("(let b{net} (Wire \"b{net}\" 1))\n"));
+    }
+
+    program.push_str("\n; cells\n");
+    for (i, (name, cell)) in module["cells"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .enumerate()
+    {
+        let param = |param: &str| -> Result<u64, String> {
+            match &cell["parameters"][param] {
+                Value::Number(value) => value.as_u64(),
+                Value::String(bits) => u64::from_str_radix(bits, 2).ok(),
+                _ => None,
+            }
+            .ok_or_else(|| format!("cell {name} has no valid {param} parameter"))
+        };
+        let input = |port: &str| signal(&cell["connections"][port]);
+        let resize = |expr: String, from: u64, to: u64, signed: bool| match to.cmp(&from) {
+            std::cmp::Ordering::Greater if signed => format!("(Op1 (SignExtend {to}) {expr})"),
+            std::cmp::Ordering::Greater => format!("(Op1 (ZeroExtend {to}) {expr})"),
+            std::cmp::Ordering::Less => format!("(Op1 (Extract {} 0) {expr})", to - 1),
+            std::cmp::Ordering::Equal => expr,
+        };
+        // An input resized to the given width.
+        let resized_to = |port: &str, to: u64, signed: bool| -> Result<String, String> {
+            let from = param(&format!("{port}_WIDTH"))?;
+            Ok(resize(input(port)?, from, to, signed))
+        };
+        // An input resized to the output's width, as Yosys does before
+        // applying the cell's op.
+        let resized = |port: &str, signed: bool| resized_to(port, param("Y_WIDTH")?, signed);
+
+        let cell_type = cell["type"].as_str().unwrap_or_default();
+        // Memories, with any number of read ports, don't drive a single
+        // output like the other cells.
+        if cell_type == "$mem_v2" {
+            program.push_str(&format!("; {name}\n"));
+            program.push_str(&json_memory_to_churchroad(name, cell, &format!("c{i}"))?);
+            continue;
+        }
+        let (expr, output) = match cell_type {
+            "$not" => (
+                format!("(Op1 (Not) {})", resized("A", param("A_SIGNED")? != 0)?),
+                "Y",
+            ),
+            "$and" | "$or" | "$xor" | "$add" | "$sub" | "$mul" => {
+                let signed = param("A_SIGNED")? != 0 && param("B_SIGNED")? != 0;
+                let op = match cell_type {
+                    "$and" => "And",
+                    "$or" => "Or",
+                    "$xor" => "Xor",
+                    "$add" => "Add",
+                    "$sub" => "Sub",
+                    "$mul" => "Mul",
+                    _ => unreachable!(),
+                };
+                (
+                    format!(
+                        "(Op2 ({op}) {} {})",
+                        resized("A", signed)?,
+                        resized("B", signed)?
+                    ),
+                    "Y",
+                )
+            }
+            // Shifts are done at the wider of the input's and the output's
+            // widths, and the shift amount keeps its own width. The bits a
+            // `$shiftx` shifts in, which Yosys leaves undefined, are 0.
+            "$shl" | "$shr" | "$shiftx" => {
+                if cell_type == "$shiftx" && param("B_SIGNED")? != 0 {
+                    return Err(format!(
+                        "cell {name} has an unsupported signed shift amount"
+                    ));
+                }
+                let (a_width, y_width) = (param("A_WIDTH")?, param("Y_WIDTH")?);
+                let width = a_width.max(y_width);
+                let shift = format!(
+                    "(Op2 ({}) {} {})",
+                    if cell_type == "$shl" { "Shl" } else { "Shr" },
+                    resized_to("A", width, param("A_SIGNED")? != 0)?,
+                    input("B")?
+                );
+                (resize(shift, width, y_width, false), "Y")
+            }
+            // Comparisons are done at the wider of the inputs' widths.
+            "$eq" | "$ne" => {
+                let signed = param("A_SIGNED")? != 0 && param("B_SIGNED")? != 0;
+                let width = param("A_WIDTH")?.max(param("B_WIDTH")?);
+                let compare = format!(
+                    "(Op2 ({}) {} {})",
+                    if cell_type == "$eq" { "Eq" } else { "Ne" },
+                    resized_to("A", width, signed)?,
+                    resized_to("B", width, signed)?
+                );
+                (resize(compare, 1, param("Y_WIDTH")?, false), "Y")
+            }
+            "$logic_not" | "$reduce_and" | "$reduce_or" | "$reduce_bool" => {
+                let op = match cell_type {
+                    "$logic_not" => "LogicNot",
+                    "$reduce_and" => "ReduceAnd",
+                    _ => "ReduceOr",
+                };
+                let reduce = format!("(Op1 ({op}) {})", input("A")?);
+                (resize(reduce, 1, param("Y_WIDTH")?, false), "Y")
+            }
+            "$mux" => (
+                format!(
+                    "(Op3 (Mux) {} {} {})",
+                    input("S")?,
+                    input("A")?,
+                    input("B")?
+                ),
+                "Y",
+            ),
+            // The cases are concatenated in `B`, the first in the lowest bits.
+            "$pmux" => (
+                format!(
+                    "(Op3 (Pmux) {} {} {})",
+                    input("S")?,
+                    input("A")?,
+                    input("B")?
+                ),
+                "Y",
+            ),
+            "$dff" => {
+                let clk = match param("CLK_POLARITY")? {
+                    0 => format!("(Op1 (Not) {})", input("CLK")?),
+                    _ => input("CLK")?,
+                };
+                (format!("(Op2 (Reg 0) {clk} {})", input("D")?), "Q")
+            }
+            _ => return Err(format!("cell {name} has unsupported type {cell_type:?}")),
+        };
+        program.push_str(&format!("; {name}\n(let c{i} {expr})\n"));
+        drive(&mut program, &cell["connections"][output], &format!("c{i}"))?;
+    }
+
+    program.push_str("\n; ports\n");
+    for (index, (name, port)) in module["ports"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .enumerate()
+    {
+        match port["direction"].as_str() {
+            Some("input") => {
+                let var = format!("(Var \"{name}\" {})", bits(&port["bits"])?.len());
+                program.push_str(&format!("(IsPort \"{top}\" \"{name}\" (Input) {var})\n"));
+                drive(&mut program, &port["bits"], &var)?;
+            }
+            Some("output") => program.push_str(&format!(
+                "(IsPort \"{top}\" \"{name}\" (Output) {})\n",
+                signal(&port["bits"])?
+            )),
+            direction => {
+                return Err(format!(
+                    "port {name} has unsupported direction {direction:?}"
+                ))
+            }
+        }
+        program.push_str(&format!("(PortIndex \"{top}\" \"{name}\" {index})\n"));
+    }
+
+    Ok(program)
+}
+
+/// The Churchroad for a signal of a JSON netlist, given as its bits, least
+/// significant first, each either a net number or a constant. Each net is a
+/// `Wire` named `b` followed by the net's number.
+fn json_signal_to_churchroad(bits: &[serde_json::Value]) -> Result<String, String> {
+    use serde_json::Value;
+
+    let mut expr = String::new();
+    for bit in bits {
+        let bit = match bit {
+            Value::Number(net) => format!("b{net}"),
+            Value::String(constant) if constant == "1" => "(Op0 (BV 1 1))".to_owned(),
+            Value::String(_) => "(Op0 (BV 0 1))".to_owned(),
+            bit => return Err(format!("expected a bit, found {bit}")),
+        };
+        // The first child of a Concat holds the high bits.
+        expr = match expr.is_empty() {
+            true => bit,
+            false => format!("(Op2 (Concat) {bit} {expr})"),
+        };
+    }
+    Ok(expr)
+}
+
+/// The Churchroad for a `$mem_v2` cell `name` of a JSON netlist, binding the
+/// memory to `mem` and its read ports to `mem` followed by `r` and the port's
+/// index. Nets are `Wire`s named as in [`json_signal_to_churchroad`].
+///
+/// Each word of the memory must start with the same value, and there can be
+/// at most one write port, as Churchroad doesn't model the priority between
+/// write ports. Writes of whole words, with a single enable, are supported,
+/// as are asynchronous reads and synchronous reads without enables or resets.
+fn json_memory_to_churchroad(
+    name: &str,
+    cell: &serde_json::Value,
+    mem: &str,
+) -> Result<String, String> {
+    use serde_json::Value;
+
+    // A parameter's bits, least significant first, with x bits being 0.
+    let param_bits = |param: &str| -> Result<Vec<bool>, String> {
+        match &cell["parameters"][param] {
+            Value::String(bits) => Some(
+                bits.trim_end()
+                    .chars()
+                    .rev()
+                    .map(|bit| bit == '1')
+                    .collect(),
+            ),
+            Value::Number(value) => value
+                .as_u64()
+                .map(|value| (0..64).map(|i| value >> i & 1 == 1).collect()),
+            _ => None,
+        }
+        .ok_or_else(|| format!("memory {name} has no valid {param} parameter"))
+    };
+    let word = |bits: &[bool]| {
+        bits.iter()
+            .rev()
+            .fold(0u64, |word, bit| word << 1 | u64::from(*bit))
+    };
+    let param = |param: &str| param_bits(param).map(|bits| word(&bits));
+    let bit = |param: &str, i: u64| -> Result<bool, String> {
+        Ok(param_bits(param)?.get(i as usize).copied().unwrap_or(false))
+    };
+    // The bits of the given port of one of the memory's read or write ports,
+    // each `width` bits of the port's connection.
+    let port_bits = |port: &str, index: u64, width: u64| -> Result<&[Value], String> {
+        cell["connections"][port]
+            .as_array()
+            .and_then(|bits| bits.get((index * width) as usize..((index + 1) * width) as usize))
+            .ok_or_else(|| format!("memory {name} has no valid {port} connection"))
+    };
+    let signal = |port: &str, index: u64, width: u64| {
+        json_signal_to_churchroad(port_bits(port, index, width)?)
+    };
+    // Clocks on the negative edge are inverted.
+    let clock = |port: &str, index: u64, polarity: bool| -> Result<String, String> {
+        let clk = signal(port, index, 1)?;
+        Ok(match polarity {
+            true => clk,
+            false => format!("(Op1 (Not) {clk})"),
+        })
+    };
+    let unsupported = |feature: &str| format!("memory {name} has {feature}, which isn't supported");
+
+    let (size, width, abits) = (param("SIZE")?, param("WIDTH")?, param("ABITS")?);
+    let (read_ports, write_ports) = (param("RD_PORTS")?, param("WR_PORTS")?);
+    if param("OFFSET")? != 0 {
+        return Err(unsupported("an address offset"));
+    }
+    if width > 64 {
+        return Err(unsupported("words wider than 64 bits"));
+    }
+    if write_ports > 1 {
+        return Err(unsupported("more than one write port"));
+    }
+    let init = param_bits("INIT")?
+        .chunks(width as usize)
+        .map(word)
+        .collect::<Vec<_>>();
+    if init.iter().any(|value| *value != init[0]) {
+        return Err(unsupported("different initial values in different words"));
+    }
+    let memid = cell["parameters"]["MEMID"]
+        .as_str()
+        .unwrap_or(name)
+        .trim_end()
+        .trim_start_matches('\\');
+
+    let mut program = format!(
+        "(let {mem} (Op0 (Mem \"{memid}\" {} {width} {})))\n",
+        size.next_power_of_two().trailing_zeros(),
+        init.first().copied().unwrap_or(0) as i64
+    );
+
+    if write_ports == 1 {
+        if !bit("WR_CLK_ENABLE", 0)? {
+            return Err(unsupported("an asynchronous write port"));
+        }
+        let enable = port_bits("WR_EN", 0, width)?;
+        if enable.iter().any(|bit| *bit != enable[0]) {
+            return Err(unsupported("a write port with per-bit enables"));
+        }
+        program.push_str(&format!(
+            "(MemWrite {mem} {} {} {} {})\n",
+            clock("WR_CLK", 0, bit("WR_CLK_POLARITY", 0)?)?,
+            signal("WR_ADDR", 0, abits)?,
+            signal("WR_DATA", 0, width)?,
+            json_signal_to_churchroad(&enable[..1])?
+        ));
+    }
+
+    for port in 0..read_ports {
+        let transparent = bit("RD_TRANSPARENCY_MASK", port)?;
+        let read = format!(
+            "(Op2 (MemRead ({})) {mem} {})",
+            if transparent {
+                "WriteFirst"
+            } else {
+                "ReadFirst"
+            },
+            signal("RD_ADDR", port, abits)?
+        );
+        let read = if bit("RD_CLK_ENABLE", port)? {
+            // A synchronous read is an asynchronous read into a register,
+            // which sees the data being written if the port is transparent.
+            let is = |port_name: &str, value: &str| {
+                port_bits(port_name, port, 1).map(|bits| bits[0] == *value)
+            };
+            if !is("RD_EN", "1")? || !is("RD_ARST", "0")? || !is("RD_SRST", "0")? {
+                return Err(unsupported("a read port with an enable or a reset"));
+            }
+            let init = param_bits("RD_INIT_VALUE")?;
+            let init = init
+                .get((port * width) as usize..((port + 1) * width) as usize)
+                .map(word)
+                .unwrap_or(0);
+            format!(
+                "(Op2 (Reg {}) {} {read})",
+                init as i64,
+                clock("RD_CLK", port, bit("RD_CLK_POLARITY", port)?)?
+            )
+        } else if transparent {
+            return Err(unsupported("an asynchronous read port with transparency"));
+        } else {
+            read
+        };
+        program.push_str(&format!("(let {mem}r{port} {read})\n"));
+        for (i, bit) in port_bits("RD_DATA", port, width)?.iter().enumerate() {
+            if let Value::Number(net) = bit {
+                program.push_str(&format!(
+                    "(union b{net} (Op1 (Extract {i} {i}) {mem}r{port}))\n"
+                ));
+            }
+        }
+    }
+
+    Ok(program)
+}
+
+/// Read a serialized egraph from JSON, as written by
+/// [`egraph_serialize::EGraph::to_json_file`].
+pub fn from_egraph_json(input: &str) -> Result<egraph_serialize::EGraph, serde_json::Error> {
+    serde_json::from_str(input)
+}
+
+/// The argument of an op node, e.g. the 3 in `(Extract 3 0)`.
+fn op_arg(egraph: &egraph_serialize::EGraph, op_node: &Node, i: usize) -> i64 {
+    egraph[&op_node.children[i]].op.parse().unwrap()
+}
+
+/// Error returned by the exporters, e.g. [`to_smtlib`], for designs which
+/// can't be written in their format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExportError {
+    /// The design uses an op, or a node which isn't an op such as a
+    /// `GetOutput`, which the format, e.g. `"SMT-LIB"`, can't describe.
+    UnsupportedOp { op: String, format: &'static str },
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::UnsupportedOp { op, format } => {
+                write!(f, "Can't export {} to {}.", op, format)
+            }
+        }
+    }
+}
+
+/// A string literal node's value, without quotes.
+fn string_value<'a>(egraph: &'a egraph_serialize::EGraph, id: &NodeId) -> &'a str {
+    egraph[id]
+        .op
+        .strip_prefix('"')
+        .unwrap()
+        .strip_suffix('"')
+        .unwrap()
+}
+
+fn width(egraph: &egraph_serialize::EGraph, class: &ClassId) -> u64 {
+    get_bitwidth(egraph, class).unwrap_or_else(|| panic!("eclass {class} is untyped"))
+}
+
+fn is_register(egraph: &egraph_serialize::EGraph, node: &Node) -> bool {
+    node.op.starts_with("Op")
+        && ["Reg", "RegWithClk", "ShiftReg"].contains(&egraph[&node.children[0]].op.as_str())
+}
+
+/// Whether a node is an expression which the exporters know how to walk,
+/// i.e. a `Var` or an op.
+fn is_expr(node: &Node) -> bool {
+    matches!(node.op.as_str(), "Var" | "Op0" | "Op1" | "Op2" | "Op3")
+}
+
+/// The eclasses of the expression children of a chosen node, which must be an
+/// expression (see [`is_expr`]).
+fn expr_children(egraph: &egraph_serialize::EGraph, node: &Node) -> Vec<ClassId> {
+    match node.op.as_str() {
+        "Op0" | "Op1" | "Op2" | "Op3" => node.children[1..]
+            .iter()
+            .map(|child| egraph[child].eclass.clone())
+            .collect(),
+        "Var" => vec![],
+        op => unreachable!("{op} isn't an expression"),
+    }
+}
+
+/// The eclasses of the design's outputs.
+fn output_classes(egraph: &egraph_serialize::EGraph) -> Vec<ClassId> {
+    let (_, outputs) = get_inputs_and_outputs_serialized(egraph);
+    outputs.into_iter().map(|(_, class)| class).collect()
+}
+
+/// The given eclasses and everything they depend on, with each eclass after
+/// the eclasses it depends on. Registers break this order: a register comes
+/// before its inputs, so that cycles through registers can be written out.
+/// Fails if a chosen node isn't an expression, naming `format` in the error.
+fn classes_in_dependency_order(
+    egraph: &egraph_serialize::EGraph,
+    choices: &IndexMap<ClassId, NodeId>,
+    roots: Vec<ClassId>,
+    format: &'static str,
+) -> Result<IndexSet<ClassId>, ExportError> {
+    fn visit(
+        egraph: &egraph_serialize::EGraph,
+        choices: &IndexMap<ClassId, NodeId>,
+        class: &ClassId,
+        visiting: &mut HashSet<ClassId>,
+        order: &mut IndexSet<ClassId>,
+        roots: &mut VecDeque<ClassId>,
+        format: &'static str,
+    ) -> Result<(), ExportError> {
+        if order.contains(class) {
+            return Ok(());
+        }
+        assert!(
+            visiting.insert(class.clone()),
+            "combinational cycle through eclass {class}"
+        );
+        let node = &egraph[&choices[class]];
+        if !is_expr(node) {
+            return Err(ExportError::UnsupportedOp {
+                op: node.op.clone(),
+                format,
+            });
+        }
+        if is_register(egraph, node) {
+            roots.extend(expr_children(egraph, node));
+        } else {
+            for child in expr_children(egraph, node) {
+                visit(egraph, choices, &child, visiting, order, roots, format)?;
+            }
+        }
+        order.insert(class.clone());
+        Ok(())
+    }
+
+    let mut roots = VecDeque::from(roots);
+    let mut visiting = HashSet::new();
+    let mut order = IndexSet::new();
+    while let Some(root) = roots.pop_front() {
+        visit(
+            egraph,
+            choices,
+            &root,
+            &mut visiting,
+            &mut order,
+            &mut roots,
+            format,
+        )?;
+    }
+    Ok(order)
+}
+
+/// Write the design as an egglog program, which can be run in any egraph into
+/// which Churchroad has been imported.
+///
+/// Each eclass is bound to a variable, and the design's ports are written as
+/// `IsPort` facts. Registers are written as `Wire`s which are later unioned
+/// with the registers, as Yosys does, so [`crate::auto_delete_wires`] should
+/// be run after the program. For the AND gate in the [module
+/// documentation](self), the program looks like:
+///
+/// ```text
+/// (let wire_1 (Var "a" 8))
+/// (let wire_2 (Var "b" 8))
+/// (let wire_3 (Op2 (And) wire_1 wire_2))
+/// (IsPort "" "a" (Input) wire_1)
+/// (IsPort "" "b" (Input) wire_2)
+/// (IsPort "" "out" (Output) wire_3)
+/// ```
+pub fn to_egglog(
+    egraph: &egraph_serialize::EGraph,
+    choices: &IndexMap<ClassId, NodeId>,
+) -> Result<String, ExportError> {
+    /// A node which isn't an expression, e.g. an op, written in full.
+    fn term(egraph: &egraph_serialize::EGraph, id: &NodeId) -> String {
+        let node = &egraph[id];
+        if node.children.is_empty() && (node.op.parse::<i64>().is_ok() || node.op.starts_with('"'))
+        {
+            node.op.clone()
+        } else {
+            let children = node
+                .children
+                .iter()
+                .map(|child| format!(" {}", term(egraph, child)))
+                .collect::<String>();
+            format!("({}{children})", node.op)
+        }
+    }
+    let wire = |class: &ClassId| format!("wire_{class}");
+
+    let order = classes_in_dependency_order(egraph, choices, output_classes(egraph), "egglog")?;
+    let mut program = String::new();
+    let mut register_unions = String::new();
+    for class in &order {
+        let node = &egraph[&choices[class]];
+        let expr = if node.op == "Var" {
+            term(egraph, &choices[class])
+        } else {
+            let args = node.children[1..]
+                .iter()
+                .map(|child| format!(" {}", wire(&egraph[child].eclass)))
+                .collect::<String>();
+            format!("({} {}{args})", node.op, term(egraph, &node.children[0]))
+        };
+        if is_register(egraph, node) {
+            program.push_str(&format!(
+                "(let {wire} (Wire \"{wire}\" {bw}))\n",
+                wire = wire(class),
+                bw = width(egraph, class)
+            ));
+            register_unions.push_str(&format!("(union {} {expr})\n", wire(class)));
+        } else {
+            program.push_str(&format!("(let {} {expr})\n", wire(class)));
+        }
+    }
+    program.push_str(&register_unions);
+
+    for node in egraph.nodes.values() {
+        if node.op == "IsPort" && order.contains(&egraph[&node.children[3]].eclass) {
+            program.push_str(&format!(
+                "(IsPort {} {} {} {})\n",
+                term(egraph, &node.children[0]),
+                term(egraph, &node.children[1]),
+                term(egraph, &node.children[2]),
+                wire(&egraph[&node.children[3]].eclass)
+            ));
+        } else if node.op == "PortIndex" {
+            program.push_str(&format!(
+                "(PortIndex {} {} {})\n",
+                term(egraph, &node.children[0]),
+                term(egraph, &node.children[1]),
+                term(egraph, &node.children[2])
+            ));
+        }
+    }
+
+    Ok(program)
+}
+
+/// Write the design as SMT-LIB 2 definitions over bitvectors, e.g. for
+/// checking equivalence with an SMT solver.
+///
+/// Each input is declared as a constant, and each eclass and output is
+/// defined as a function of no arguments. Only combinational designs can be
+/// written, as SMT-LIB has no notion of time. For the AND gate in the [module
+/// documentation](self), the definitions look like:
+///
+/// ```text
+/// (declare-const a (_ BitVec 8))
+/// (declare-const b (_ BitVec 8))
+/// (define-fun wire_1 () (_ BitVec 8) a)
+/// (define-fun wire_2 () (_ BitVec 8) b)
+/// (define-fun wire_3 () (_ BitVec 8) (bvand wire_1 wire_2))
+/// (define-fun out () (_ BitVec 8) wire_3)
+/// ```
+pub fn to_smtlib(
+    egraph: &egraph_serialize::EGraph,
+    choices: &IndexMap<ClassId, NodeId>,
+) -> Result<String, ExportError> {
+    let outputs = get_inputs_and_outputs_serialized(egraph).1;
+    let (inputs, definitions) = smtlib_definitions(
+        egraph,
+        choices,
+        outputs.iter().map(|(_, class)| class.clone()).collect(),
+        "",
+    )?;
+
+    let mut smtlib = String::new();
+    for (name, bw) in inputs {
+        smtlib.push_str(&format!("(declare-const {name} (_ BitVec {bw}))\n"));
+    }
+    smtlib.push_str(&definitions);
+    for (name, class) in outputs {
+        smtlib.push_str(&format!(
+            "(define-fun {name} () (_ BitVec {}) wire_{class})\n",
+            width(egraph, &class),
+        ));
+    }
+
+    Ok(smtlib)
+}
+
+/// The ops which [`smtlib_definitions`] can write.
+const SMTLIB_OPS: &[&str] = &[
+    "BV",
+    "SignedBV",
+    "Not",
+    "And",
+    "Or",
+    "Xor",
+    "Add",
+    "Sub",
+    "Mul",
+    "UDiv",
+    "URem",
+    "SDiv",
+    "SRem",
+    "Shl",
+    "Shr",
+    "Ashr",
+    "Eq",
+    "Ne",
+    "SLt",
+    "SLe",
+    "SGt",
+    "SGe",
+    "ReduceOr",
+    "ReduceAnd",
+    "ReduceXor",
+    "LogicNot",
+    "LogicAnd",
+    "LogicOr",
+    "Concat",
+    "Extract",
+    "ZeroExtend",
+    "SignExtend",
+    "Mux",
+];
+
+/// Whether [`smtlib_definitions`] can write the given eclass and everything
+/// it depends on, i.e. whether they're all `Var`s or ops in [`SMTLIB_OPS`].
+pub(crate) fn supported_by_smtlib(
+    egraph: &egraph_serialize::EGraph,
+    choices: &IndexMap<ClassId, NodeId>,
+    root: &ClassId,
+) -> bool {
+    let mut queue = vec![root.clone()];
+    let mut seen = HashSet::new();
+    while let Some(class) = queue.pop() {
+        if !seen.insert(class.clone()) {
+            continue;
+        }
+        let Some(node) = choices.get(&class).map(|id| &egraph[id]) else {
+            return false;
+        };
+        match node.op.as_str() {
+            "Var" => (),
+            "Op0" | "Op1" | "Op2" | "Op3"
+                if SMTLIB_OPS.contains(&egraph[&node.children[0]].op.as_str()) =>
+            {
+                queue.extend(expr_children(egraph, node))
+            }
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// SMT-LIB definitions of the given eclasses and everything they depend on,
+/// each named `{prefix}wire_{class}`, along with the names and widths of the
+/// inputs they use, which are left for the caller to declare. Fails unless
+/// [`supported_by_smtlib`] holds for each root.
+pub(crate) fn smtlib_definitions(
+    egraph: &egraph_serialize::EGraph,
+    choices: &IndexMap<ClassId, NodeId>,
+    roots: Vec<ClassId>,
+    prefix: &str,
+) -> Result<(IndexMap<String, u64>, String), ExportError> {
+    fn resize(expr: String, from: u64, to: u64, signed: bool) -> String {
+        match to.cmp(&from) {
+            std::cmp::Ordering::Greater if signed => {
+                format!("((_ sign_extend {}) {expr})", to - from)
+            }
+            std::cmp::Ordering::Greater => format!("((_ zero_extend {}) {expr})", to - from),
+            std::cmp::Ordering::Less => format!("((_ extract {} 0) {expr})", to - 1),
+            std::cmp::Ordering::Equal => expr,
+        }
+    }
+    let wire = |class: &ClassId| format!("{prefix}wire_{class}");
+    let bool_to_bv = |condition: String| format!("(ite {condition} #b1 #b0)");
+
+    let mut inputs = IndexMap::new();
+    let mut smtlib = String::new();
+    for class in &classes_in_dependency_order(egraph, choices, roots, "SMT-LIB")? {
+        let node = &egraph[&choices[class]];
+        let bw = width(egraph, class);
+        if node.op == "Var" {
+            let name = string_value(egraph, &node.children[0]);
+            inputs.insert(name.to_owned(), bw);
+            smtlib.push_str(&format!(
+                "(define-fun {} () (_ BitVec {bw}) {name})\n",
+                wire(class)
+            ));
+            continue;
+        }
+
+        // SMT-LIB has no notion of time, so registers aren't in SMTLIB_OPS
+        // either.
+        let op_node = &egraph[&node.children[0]];
+        let args: Vec<ClassId> = expr_children(egraph, node);
+        let arg = |i: usize| wire(&args[i]);
+        let arg_bw = |i: usize| width(egraph, &args[i]);
+        let zero = |bw: u64| format!("(_ bv0 {bw})");
+        let expr = match op_node.op.as_str() {
+            "BV" | "SignedBV" => format!(
+                "(_ bv{} {bw})",
+                truncate_value_to_bitwidth(op_arg(egraph, op_node, 0) as u64, bw)
+            ),
+            "Not" => format!("(bvnot {})", arg(0)),
+            "And" | "Or" | "Xor" | "Add" | "Sub" | "Mul" | "URem" | "SRem" => format!(
+                "({} {} {})",
+                match op_node.op.as_str() {
+                    "And" => "bvand",
+                    "Or" => "bvor",
+                    "Xor" => "bvxor",
+                    "Add" => "bvadd",
+                    "Sub" => "bvsub",
+                    "Mul" => "bvmul",
+                    // SMT-LIB's remainder by zero is the dividend, as in
+                    // Churchroad.
+                    "URem" => "bvurem",
+                    "SRem" => "bvsrem",
+                    _ => unreachable!(),
+                },
+                arg(0),
+                arg(1)
+            ),
+            // SMT-LIB's quotient by zero is all ones, but Churchroad's is zero.
+            "UDiv" | "SDiv" => format!(
+                "(ite (= {b} {zero}) {zero} ({op} {a} {b}))",
+                op = if op_node.op == "UDiv" {
+                    "bvudiv"
+                } else {
+                    "bvsdiv"
+                },
+                a = arg(0),
+                b = arg(1),
+                zero = zero(bw)
+            ),
+            // SMT-LIB's shifts need operands of the same width, so we shift
+            // at the wider of the two widths and truncate the result.
+            "Shl" | "Shr" | "Ashr" => {
+                let shift_bw = bw.max(arg_bw(1));
+                let signed = op_node.op == "Ashr";
+                let shifted = format!(
+                    "({} {} {})",
+                    match op_node.op.as_str() {
+                        "Shl" => "bvshl",
+                        "Shr" => "bvlshr",
+                        "Ashr" => "bvashr",
+                        _ => unreachable!(),
+                    },
+                    resize(arg(0), bw, shift_bw, signed),
+                    resize(arg(1), arg_bw(1), shift_bw, false)
+                );
+                resize(shifted, shift_bw, bw, false)
+            }
+            "Eq" => bool_to_bv(format!("(= {} {})", arg(0), arg(1))),
+            "Ne" => bool_to_bv(format!("(distinct {} {})", arg(0), arg(1))),
+            "SLt" | "SLe" | "SGt" | "SGe" => bool_to_bv(format!(
+                "({} {} {})",
+                match op_node.op.as_str() {
+                    "SLt" => "bvslt",
+                    "SLe" => "bvsle",
+                    "SGt" => "bvsgt",
+                    "SGe" => "bvsge",
+                    _ => unreachable!(),
+                },
+                arg(0),
+                arg(1)
+            )),
+            "ReduceOr" => bool_to_bv(format!("(distinct {} {})", arg(0), zero(arg_bw(0)))),
+            "ReduceAnd" => bool_to_bv(format!("(= {} (bvnot {}))", arg(0), zero(arg_bw(0)))),
+            "ReduceXor" => (1..arg_bw(0))
+                .fold(format!("((_ extract 0 0) {})", arg(0)), |acc, i| {
+                    format!("(bvxor {acc} ((_ extract {i} {i}) {}))", arg(0))
+                }),
+            "LogicNot" => bool_to_bv(format!("(= {} {})", arg(0), zero(arg_bw(0)))),
+            "LogicAnd" | "LogicOr" => bool_to_bv(format!(
+                "({} (distinct {} {}) (distinct {} {}))",
+                if op_node.op == "LogicAnd" {
+                    "and"
+                } else {
+                    "or"
+                },
+                arg(0),
+                zero(arg_bw(0)),
+                arg(1),
+                zero(arg_bw(1))
+            )),
+            "Concat" => format!("(concat {} {})", arg(0), arg(1)),
+            "Extract" => format!(
+                "((_ extract {} {}) {})",
+                op_arg(egraph, op_node, 0),
+                op_arg(egraph, op_node, 1),
+                arg(0)
+            ),
+            "ZeroExtend" | "SignExtend" => {
+                resize(arg(0), arg_bw(0), bw, op_node.op == "SignExtend")
+            }
+            // A select of zero picks the first expression.
+            "Mux" => format!(
+                "(ite (= {} {}) {} {})",
+                arg(0),
+                zero(arg_bw(0)),
+                arg(1),
+                arg(2)
+            ),
+            op => {
+                return Err(ExportError::UnsupportedOp {
+                    op: op.to_owned(),
+                    format: "SMT-LIB",
+                })
+            }
+        };
+        smtlib.push_str(&format!(
+            "(define-fun {} () (_ BitVec {bw}) {expr})\n",
+            wire(class)
+        ));
+    }
+
+    Ok((inputs, smtlib))
+}
+
+/// Builds a BTOR2 model, one numbered line at a time.
+#[derive(Default)]
+struct Btor2 {
+    lines: Vec<String>,
+    sorts: HashMap<u64, usize>,
+}
+
+impl Btor2 {
+    /// Add a line, returning its id.
+    fn push(&mut self, line: String) -> usize {
+        self.lines.push(format!("{} {line}", self.lines.len() + 1));
+        self.lines.len()
+    }
+
+    /// The id of the bitvector sort of the given width, declaring it if
+    /// needed.
+    fn sort(&mut self, bw: u64) -> usize {
+        if let Some(sort) = self.sorts.get(&bw) {
+            return *sort;
+        }
+        let sort = self.push(format!("sort bitvec {bw}"));
+        self.sorts.insert(bw, sort);
+        sort
+    }
+
+    fn resize(&mut self, id: usize, from: u64, to: u64, signed: bool) -> usize {
+        let sort = self.sort(to);
+        match to.cmp(&from) {
+            std::cmp::Ordering::Greater => self.push(format!(
+                "{} {sort} {id} {}",
+                if signed { "sext" } else { "uext" },
+                to - from
+            )),
+            std::cmp::Ordering::Less => self.push(format!("slice {sort} {id} {} 0", to - 1)),
+            std::cmp::Ordering::Equal => id,
+        }
+    }
+
+    /// Whether the value is nonzero, as a bitvector of width 1.
+    fn nonzero(&mut self, id: usize, bw: u64) -> usize {
+        if bw == 1 {
+            id
+        } else {
+            let sort = self.sort(1);
+            self.push(format!("redor {sort} {id}"))
+        }
+    }
+}
+
+/// Write the design as a BTOR2 model, e.g. for model checking with btormc or
+/// Pono.
+///
+/// Inputs, outputs and registers are named after the design's ports and
+/// eclasses. All registers are clocked by the model's single implicit clock.
+/// For the AND gate in the [module documentation](self), the model looks
+/// like:
+///
+/// ```text
+/// 1 sort bitvec 8
+/// 2 input 1 a
+/// 3 input 1 b
+/// 4 and 1 2 3
+/// 5 output 4 out
+/// ```
+pub fn to_btor2(
+    egraph: &egraph_serialize::EGraph,
+    choices: &IndexMap<ClassId, NodeId>,
+) -> Result<String, ExportError> {
+    let mut btor2 = Btor2::default();
+    let mut ids: HashMap<ClassId, usize> = HashMap::new();
+    // Each register stage's state, sort and init value, along with the stage
+    // before it in a shift register, or else the register's data input.
+    let mut states: Vec<(usize, usize, u64, Option<usize>, ClassId)> = Vec::new();
+
+    for class in &classes_in_dependency_order(egraph, choices, output_classes(egraph), "BTOR2")? {
+        let node = &egraph[&choices[class]];
+        let bw = width(egraph, class);
+        let sort = btor2.sort(bw);
+        if node.op == "Var" {
+            let name = string_value(egraph, &node.children[0]);
+            ids.insert(class.clone(), btor2.push(format!("input {sort} {name}")));
+            continue;
+        }
+
+        let op_node = &egraph[&node.children[0]];
+        if is_register(egraph, node) {
+            let init = truncate_value_to_bitwidth(
+                op_arg(egraph, op_node, op_node.children.len() - 1) as u64,
+                bw,
+            );
+            let d = egraph[node.children.last().unwrap()].eclass.clone();
+            let depth = if op_node.op == "ShiftReg" {
+                op_arg(egraph, op_node, 0)
+            } else {
+                1
+            };
+            let mut stage = None;
+            for i in 0..depth {
+                // As in the Verilog, the last stage is named after the
+                // register's eclass.
+                let name = if i == depth - 1 {
+                    format!("wire_{class}")
+                } else {
+                    format!("wire_{class}_stage{i}")
+                };
+                let state = btor2.push(format!("state {sort} {name}"));
+                states.push((state, sort, init, stage, d.clone()));
+                stage = Some(state);
+            }
+            ids.insert(class.clone(), stage.unwrap());
+            continue;
+        }
+
+        let args: Vec<ClassId> = expr_children(egraph, node);
+        let arg = |i: usize| ids[&args[i]];
+        let arg_bw = |i: usize| width(egraph, &args[i]);
+        let id = match op_node.op.as_str() {
+            "BV" | "SignedBV" => btor2.push(format!(
+                "constd {sort} {}",
+                truncate_value_to_bitwidth(op_arg(egraph, op_node, 0) as u64, bw)
+            )),
+            "Not" => btor2.push(format!("not {sort} {}", arg(0))),
+            "And" | "Or" | "Xor" | "Add" | "Sub" | "Mul" | "URem" | "SRem" | "Eq" | "Ne"
+            | "SLt" | "SLe" | "SGt" | "SGe" | "Concat" => btor2.push(format!(
+                "{} {sort} {} {}",
+                match op_node.op.as_str() {
+                    "And" => "and",
+                    "Or" => "or",
+                    "Xor" => "xor",
+                    "Add" => "add",
+                    "Sub" => "sub",
+                    "Mul" => "mul",
+                    // BTOR2's remainder by zero is the dividend, as in
+                    // Churchroad.
+                    "URem" => "urem",
+                    "SRem" => "srem",
+                    "Eq" => "eq",
+                    "Ne" => "neq",
+                    "SLt" => "slt",
+                    "SLe" => "slte",
+                    "SGt" => "sgt",
+                    "SGe" => "sgte",
+                    "Concat" => "concat",
+                    _ => unreachable!(),
+                },
+                arg(0),
+                arg(1)
+            )),
+            // BTOR2's quotient by zero is all ones, but Churchroad's is zero.
+            "UDiv" | "SDiv" => {
+                let quotient = btor2.push(format!(
+                    "{} {sort} {} {}",
+                    if op_node.op == "UDiv" { "udiv" } else { "sdiv" },
+                    arg(0),
+                    arg(1)
+                ));
+                let zero = btor2.push(format!("zero {sort}"));
+                let bool_sort = btor2.sort(1);
+                let divisor_is_zero = btor2.push(format!("eq {bool_sort} {} {zero}", arg(1)));
+                btor2.push(format!("ite {sort} {divisor_is_zero} {zero} {quotient}"))
+            }
+            // BTOR2's shifts need operands of the same width, so we shift at
+            // the wider of the two widths and truncate the result.
+            "Shl" | "Shr" | "Ashr" => {
+                let shift_bw = bw.max(arg_bw(1));
+                let value = btor2.resize(arg(0), bw, shift_bw, op_node.op == "Ashr");
+                let amount = btor2.resize(arg(1), arg_bw(1), shift_bw, false);
+                let shift_sort = btor2.sort(shift_bw);
+                let shifted = btor2.push(format!(
+                    "{} {shift_sort} {value} {amount}",
+                    match op_node.op.as_str() {
+                        "Shl" => "sll",
+                        "Shr" => "srl",
+                        "Ashr" => "sra",
+                        _ => unreachable!(),
+                    }
+                ));
+                btor2.resize(shifted, shift_bw, bw, false)
+            }
+            "ReduceOr" | "ReduceAnd" | "ReduceXor" => btor2.push(format!(
+                "{} {sort} {}",
+                match op_node.op.as_str() {
+                    "ReduceOr" => "redor",
+                    "ReduceAnd" => "redand",
+                    "ReduceXor" => "redxor",
+                    _ => unreachable!(),
+                },
+                arg(0)
+            )),
+            "LogicNot" => {
+                let nonzero = btor2.nonzero(arg(0), arg_bw(0));
+                btor2.push(format!("not {sort} {nonzero}"))
+            }
+            "LogicAnd" | "LogicOr" => {
+                let lhs = btor2.nonzero(arg(0), arg_bw(0));
+                let rhs = btor2.nonzero(arg(1), arg_bw(1));
+                btor2.push(format!(
+                    "{} {sort} {lhs} {rhs}",
+                    if op_node.op == "LogicAnd" {
+                        "and"
+                    } else {
+                        "or"
+                    }
+                ))
+            }
+            "Extract" => btor2.push(format!(
+                "slice {sort} {} {} {}",
+                arg(0),
+                op_arg(egraph, op_node, 0),
+                op_arg(egraph, op_node, 1)
+            )),
+            "ZeroExtend" | "SignExtend" => {
+                btor2.resize(arg(0), arg_bw(0), bw, op_node.op == "SignExtend")
+            }
+            // A select of zero picks the first expression.
+            "Mux" => {
+                let select = btor2.nonzero(arg(0), arg_bw(0));
+                btor2.push(format!("ite {sort} {select} {} {}", arg(2), arg(1)))
+            }
+            op => {
+                return Err(ExportError::UnsupportedOp {
+                    op: op.to_owned(),
+                    format: "BTOR2",
+                })
+            }
+        };
+        ids.insert(class.clone(), id);
+    }
+
+    for (state, sort, init, previous, d) in states {
+        let init = btor2.push(format!("constd {sort} {init}"));
+        btor2.push(format!("init {sort} {state} {init}"));
+        let next = previous.unwrap_or_else(|| ids[&d]);
+        btor2.push(format!("next {sort} {state} {next}"));
+    }
+
+    let (_, outputs) = get_inputs_and_outputs_serialized(egraph);
+    for (name, class) in outputs {
+        btor2.push(format!("output {} {name}", ids[&class]));
+    }
+
+    Ok(btor2.lines.join("\n") + "\n")
+}
+
+/// Write the design as a BLIF netlist, e.g. for ABC or VPR.
+///
+/// BLIF is bit-level, so each bit of each eclass gets its own signal, e.g.
+/// `wire_3[0]`, and only bitwise ops, LUTs and registers can be written.
+/// `Reg`s without a clock are clocked by an input named `clk`. For the AND
+/// gate in the [module documentation](self), the netlist looks like:
+///
+/// ```text
+/// .model top
+/// .inputs a[0] ... a[7] b[0] ... b[7]
+/// .outputs out[0] ... out[7]
+/// .names a[0] wire_1[0]
+/// 1 1
+/// ...
+/// .names wire_1[0] wire_2[0] wire_3[0]
+/// 11 1
+/// ...
+/// .names wire_3[0] out[0]
+/// 1 1
+/// ...
+/// .end
+/// ```
+pub fn to_blif(
+    egraph: &egraph_serialize::EGraph,
+    choices: &IndexMap<ClassId, NodeId>,
+) -> Result<String, ExportError> {
+    fn names(blif: &mut String, inputs: &[String], output: &str, cover: &[String]) {
+        let signals = inputs
+            .iter()
+            .map(String::as_str)
+            .chain([output])
+            .collect::<Vec<_>>();
+        blif.push_str(&format!(".names {}\n", signals.join(" ")));
+        for row in cover {
+            blif.push_str(row);
+            blif.push('\n');
+        }
+    }
+    fn buffer(blif: &mut String, input: &str, output: &str) {
+        names(blif, &[input.to_owned()], output, &["1 1".to_owned()]);
+    }
+    let bit = |class: &ClassId, i: u64| format!("wire_{class}[{i}]");
+
+    let mut inputs: IndexSet<String> = IndexSet::new();
+    let mut clocks: IndexSet<String> = IndexSet::new();
+    let mut logic = String::new();
+    for class in &classes_in_dependency_order(egraph, choices, output_classes(egraph), "BLIF")? {
+        let node = &egraph[&choices[class]];
+        let bw = width(egraph, class);
+        if node.op == "Var" {
+            let name = string_value(egraph, &node.children[0]);
+            for i in 0..bw {
+                inputs.insert(format!("{name}[{i}]"));
+                buffer(&mut logic, &format!("{name}[{i}]"), &bit(class, i));
+            }
+            continue;
+        }
+
+        let op_node = &egraph[&node.children[0]];
+        let args: Vec<ClassId> = expr_children(egraph, node);
+        let arg_bw = |i: usize| width(egraph, &args[i]);
+        match op_node.op.as_str() {
+            "BV" | "SignedBV" => {
+                let value = truncate_value_to_bitwidth(op_arg(egraph, op_node, 0) as u64, bw);
+                for i in 0..bw {
+                    let cover = if (value >> i) & 1 == 1 {
+                        vec!["1".to_owned()]
+                    } else {
+                        vec![]
+                    };
+                    names(&mut logic, &[], &bit(class, i), &cover);
+                }
+            }
+            "Not" | "And" | "Or" | "Xor" => {
+                let cover: &[&str] = match op_node.op.as_str() {
+                    "Not" => &["0 1"],
+                    "And" => &["11 1"],
+                    "Or" => &["1- 1", "-1 1"],
+                    "Xor" => &["10 1", "01 1"],
+                    _ => unreachable!(),
+                };
+                let cover: Vec<String> = cover.iter().map(|row| row.to_string()).collect();
+                for i in 0..bw {
+                    let inputs: Vec<String> = args.iter().map(|arg| bit(arg, i)).collect();
+                    names(&mut logic, &inputs, &bit(class, i), &cover);
+                }
+            }
+            "ReduceOr" | "ReduceAnd" => {
+                let n = arg_bw(0) as usize;
+                let cover = if op_node.op == "ReduceAnd" {
+                    vec![format!("{} 1", "1".repeat(n))]
+                } else {
+                    (0..n)
+                        .map(|i| format!("{}1{} 1", "-".repeat(i), "-".repeat(n - i - 1)))
+                        .collect()
+                };
+                let inputs: Vec<String> = (0..n as u64).map(|i| bit(&args[0], i)).collect();
+                names(&mut logic, &inputs, &bit(class, 0), &cover);
+            }
+            // The first argument holds the high bits.
+            "Concat" => {
+                let low_bw = arg_bw(1);
+                for i in 0..bw {
+                    if i < low_bw {
+                        buffer(&mut logic, &bit(&args[1], i), &bit(class, i));
+                    } else {
+                        buffer(&mut logic, &bit(&args[0], i - low_bw), &bit(class, i));
+                    }
+                }
+            }
+            "Extract" => {
+                let lo = op_arg(egraph, op_node, 1) as u64;
+                for i in 0..bw {
+                    buffer(&mut logic, &bit(&args[0], lo + i), &bit(class, i));
+                }
+            }
+            "ZeroExtend" | "SignExtend" => {
+                let from = arg_bw(0);
+                for i in 0..bw {
+                    if i < from {
+                        buffer(&mut logic, &bit(&args[0], i), &bit(class, i));
+                    } else if op_node.op == "SignExtend" {
+                        buffer(&mut logic, &bit(&args[0], from - 1), &bit(class, i));
+                    } else {
+                        names(&mut logic, &[], &bit(class, i), &[]);
+                    }
+                }
+            }
+            // A select of zero picks the first expression.
+            "Mux" if arg_bw(0) == 1 => {
+                let cover = vec!["01- 1".to_owned(), "1-1 1".to_owned()];
+                for i in 0..bw {
+                    let inputs = vec![bit(&args[0], 0), bit(&args[1], i), bit(&args[2], i)];
+                    names(&mut logic, &inputs, &bit(class, i), &cover);
+                }
+            }
+            // Row j of the LUT's truth table is bit j of its INIT, with input
+            // i set to bit i of j.
+            "LUT" => {
+                let k = op_arg(egraph, op_node, 0) as u64;
+                let init = op_arg(egraph, op_node, 1) as u64;
+                let cover = (0..1u64 << k)
+                    .filter(|j| (init >> j) & 1 == 1)
+                    .map(|j| {
+                        let row = (0..k)
+                            .map(|i| if (j >> i) & 1 == 1 { '1' } else { '0' })
+                            .collect::<String>();
+                        format!("{row} 1")
+                    })
+                    .collect::<Vec<_>>();
+                let inputs: Vec<String> = (0..k).map(|i| bit(&args[0], i)).collect();
+                names(&mut logic, &inputs, &bit(class, 0), &cover);
+            }
+            "Reg" | "RegWithClk" | "ShiftReg" => {
+                let clk = if args.len() == 2 {
+                    bit(&args[0], 0)
+                } else {
+                    clocks.insert("clk".to_owned());
+                    "clk".to_owned()
+                };
+                let init = truncate_value_to_bitwidth(
+                    op_arg(egraph, op_node, op_node.children.len() - 1) as u64,
+                    bw,
+                );
+                let depth = if op_node.op == "ShiftReg" {
+                    op_arg(egraph, op_node, 0)
+                } else {
+                    1
+                };
+                // As in the Verilog, the last stage is named after the
+                // register's eclass.
+                let d = args.last().unwrap();
+                let stages: Vec<String> = (0..depth - 1)
+                    .map(|stage| format!("wire_{class}_stage{stage}"))
+                    .chain(std::iter::once(format!("wire_{class}")))
+                    .collect();
+                for i in 0..bw {
+                    let mut input = bit(d, i);
+                    for stage in &stages {
+                        let output = format!("{stage}[{i}]");
+                        logic.push_str(&format!(
+                            ".latch {input} {output} re {clk} {}\n",
+                            (init >> i) & 1
+                        ));
+                        input = output;
+                    }
+                }
+            }
+            // Wider muxes fall through to here, too.
+            op => {
+                return Err(ExportError::UnsupportedOp {
+                    op: op.to_owned(),
+                    format: "BLIF",
+                })
+            }
+        }
+    }
+
+    let (_, outputs) = get_inputs_and_outputs_serialized(egraph);
+    let mut output_bits = Vec::new();
+    for (name, class) in &outputs {
+        for i in 0..width(egraph, class) {
+            let output = format!("{name}[{i}]");
+            buffer(&mut logic, &bit(class, i), &output);
+            output_bits.push(output);
+        }
+    }
+
+    Ok(format!(
+        ".model top\n.inputs {}\n.outputs {}\n{logic}.end\n",
+        inputs
+            .iter()
+            .cloned()
+            .chain(clocks)
+            .collect::<Vec<_>>()
+            .join(" "),
+        output_bits.join(" ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{auto_delete_wires, interpret, AnythingExtractor, Interpreter, InterpreterResult};
+    use egglog::SerializeConfig;
+
+    /// Import the given Churchroad program, type it and delete its wires.
+    fn import(program: &str) -> egraph_serialize::EGraph {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph.parse_and_run_program(program).unwrap();
+        auto_delete_wires(&mut egraph);
+        egraph
+            .parse_and_run_program("(run-schedule (saturate typing))")
+            .unwrap();
+        egraph.serialize(SerializeConfig::default())
+    }
+
+    const COUNTER: &str = r#"
+        (let count (Wire "count" 8))
+        (union count (Op1 (Reg 0) (Op2 (Add) count (Op0 (BV 1 8)))))
+        (IsPort "" "count" (Output) count)
+    "#;
+
+    #[test]
+    fn to_egglog_round_trip_counter() {
+        let serialized = import(COUNTER);
+        let choices = AnythingExtractor.extract(&serialized, &[]);
+        let reimported = import(&to_egglog(&serialized, &choices).unwrap());
+
+        let output = |egraph: &egraph_serialize::EGraph| {
+            get_inputs_and_outputs_serialized(egraph).1[0].1.clone()
+        };
+        for time in 0..4 {
+            assert_eq!(
+                interpret(&reimported, &output(&reimported), time, &HashMap::new()),
+                interpret(&serialized, &output(&serialized), time, &HashMap::new())
+            );
+        }
+    }
+
+    #[test]
+    fn to_btor2_counter() {
+        let serialized = import(COUNTER);
+        let choices = AnythingExtractor.extract(&serialized, &[]);
+        let btor2 = to_btor2(&serialized, &choices).unwrap();
+
+        let line = |op: &str| {
+            btor2
+                .lines()
+                .map(|line| line.split(' ').collect::<Vec<_>>())
+                .find(|line| line[1] == op)
+                .unwrap_or_else(|| panic!("no {op} in:\n{btor2}"))
+        };
+        let state = line("state")[0];
+        let add = line("add")[0];
+        assert_eq!(line("next")[3], state);
+        assert_eq!(line("next")[4], add);
+        assert_eq!(line("init")[3], state);
+        assert_eq!(line("output")[2], state);
+        assert_eq!(line("output")[3], "count");
+    }
+
+    #[test]
+    fn to_smtlib_udiv() {
+        let serialized = import(
+            r#"
+            (let a (Var "a" 8))
+            (IsPort "" "a" (Input) a)
+            (let b (Var "b" 4))
+            (IsPort "" "b" (Input) b)
+            (IsPort "" "out" (Output) (Op2 (UDiv) a (Op1 (ZeroExtend 8) b)))
+        "#,
+        );
+        let choices = AnythingExtractor.extract(&serialized, &[]);
+        let smtlib = to_smtlib(&serialized, &choices).unwrap();
+
+        assert!(smtlib.contains("(declare-const a (_ BitVec 8))"));
+        assert!(smtlib.contains("(declare-const b (_ BitVec 4))"));
+        assert!(smtlib.contains("((_ zero_extend 4) wire_"));
+        assert!(smtlib.contains("(_ bv0 8) (bvudiv wire_"));
+        assert!(smtlib.contains("(define-fun out () (_ BitVec 8) wire_"));
+    }
+
+    #[test]
+    fn to_blif_lut() {
+        let serialized = import(
+            r#"
+            (let x (Var "x" 2))
+            (IsPort "" "x" (Input) x)
+            (IsPort "" "out" (Output) (Op1 (LUT 2 8) x))
+        "#,
+        );
+        let choices = AnythingExtractor.extract(&serialized, &[]);
+        let blif = to_blif(&serialized, &choices).unwrap();
+
+        assert!(blif.starts_with(".model top\n.inputs x[0] x[1]\n.outputs out[0]\n"));
+        assert!(blif.ends_with(".end\n"));
+        // INIT 8 sets only the row in which both inputs are 1.
+        let lut = blif
+            .split(".names")
+            .find(|names| names.split_whitespace().count() == 5)
+            .unwrap();
+        assert!(lut.ends_with("\n11 1\n"), "{lut}");
+    }
+
+    #[test]
+    fn export_unsupported_ops() {
+        let serialized = import(COUNTER);
+        let choices = AnythingExtractor.extract(&serialized, &[]);
+        assert_eq!(
+            to_smtlib(&serialized, &choices),
+            Err(ExportError::UnsupportedOp {
+                op: "Reg".to_owned(),
+                format: "SMT-LIB"
+            })
+        );
+        // BLIF is bit-level, so it has no adders.
+        assert_eq!(
+            to_blif(&serialized, &choices),
+            Err(ExportError::UnsupportedOp {
+                op: "Add".to_owned(),
+                format: "BLIF"
+            })
+        );
+        assert_eq!(
+            to_blif(&serialized, &choices).unwrap_err().to_string(),
+            "Can't export Add to BLIF."
+        );
+    }
+
+    fn yosys_plugin() -> std::path::PathBuf {
+        std::path::Path::new(&std::env::var("CHURCHROAD_DIR").unwrap())
+            .join("yosys-plugin")
+            .join("churchroad.so")
+    }
+
+    #[test]
+    fn from_verilog_file_errors() {
+        let path = std::env::temp_dir().join("from_verilog_file_errors.sv");
+        std::fs::write(
+            &path,
+            "module broken(input a, output b); assign b = a &; endmodule",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            from_verilog_file_with_yosys(
+                std::path::Path::new("/nonexistent/yosys"),
+                &path,
+                "broken",
+                &yosys_plugin(),
+                false,
+                &HashMap::new()
+            ),
+            Err(ImportError::YosysNotFound)
+        ));
+        assert!(matches!(
+            from_verilog_file(
+                &path,
+                "broken",
+                &std::env::temp_dir().join("missing.so"),
+                false,
+                &HashMap::new()
+            ),
+            Err(ImportError::PluginLoad(_))
+        ));
+        assert!(matches!(
+            from_verilog_file(&path, "broken", &yosys_plugin(), false, &HashMap::new()),
+            Err(ImportError::Verilog(_))
+        ));
+    }
+
+    /// Write `verilog` to a file named after `name`, and a JSON netlist of its
+    /// module `top` after `prep` to another, returning both paths.
+    fn write_json_netlist(
+        name: &str,
+        verilog: &str,
+        top: &str,
+    ) -> (std::path::PathBuf, std::path::PathBuf) {
+        let dir = std::env::temp_dir();
+        let verilog_path = dir.join(format!("{name}.sv"));
+        let json_path = dir.join(format!("{name}.json"));
+        std::fs::write(&verilog_path, verilog).unwrap();
+        let yosys = std::process::Command::new("yosys")
+            .arg("-q")
+            .arg("-p")
+            .arg(format!(
+                "read_verilog -sv {}; prep -top {}; write_json {}",
+                verilog_path.display(),
+                top,
+                json_path.display()
+            ))
+            .output()
+            .unwrap();
+        assert!(yosys.status.success(), "{yosys:?}");
+        (verilog_path, json_path)
+    }
+
+    #[test]
+    fn from_json_netlist_matches_plugin() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let (verilog_path, json_path) = write_json_netlist(
+            "from_json_netlist_matches_plugin",
+            "module json_top(input clk, input s, input [3:0] a, input [3:0] b,
+                             output [3:0] o, output reg [3:0] q, output reg [3:0] p,
+                             output x);
+               assign o = s ? ((a & b) ^ ~(a | b)) : ((a + b) - (a * b));
+               always @(posedge clk) q <= (a << b[1:0]) + (b >> a[1:0]);
+               always @* case (a[2:0])
+                 3'd0: p = b;
+                 3'd1: p = ~b;
+                 3'd2: p = a ^ b;
+                 3'd5: p = a + b;
+                 default: p = 4'd5;
+               endcase
+               wire [7:0] ab = {a, b};
+               assign x = ab[b[2:0]];
+             endmodule",
+            "json_top",
+        );
+
+        let import = |mut egraph: EGraph| {
+            egraph
+                .parse_and_run_program("(run-schedule (saturate typing))")
+                .unwrap();
+            auto_delete_wires(&mut egraph);
+            egraph.serialize(SerializeConfig::default())
+        };
+        let from_plugin = import(
+            from_verilog_file(
+                &verilog_path,
+                "json_top",
+                &yosys_plugin(),
+                false,
+                &HashMap::new(),
+            )
+            .unwrap(),
+        );
+        let from_json = import(from_json_netlist_file(&json_path, "json_top").unwrap());
+
+        let (plugin_inputs, plugin_outputs) = get_inputs_and_outputs_serialized(&from_plugin);
+        let (json_inputs, json_outputs) = get_inputs_and_outputs_serialized(&from_json);
+        let names = |ports: &[crate::SerializedPort]| {
+            ports
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&json_inputs), names(&plugin_inputs));
+        assert_eq!(names(&json_outputs), names(&plugin_outputs));
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..20 {
+            let env: HashMap<&str, Vec<u64>> = [
+                ("clk", vec![0, 1, 0, 1, 0, 1]),
+                ("s", (0..6).map(|_| rng.gen_range(0..2)).collect()),
+                ("a", (0..6).map(|_| rng.gen_range(0..16)).collect()),
+                ("b", (0..6).map(|_| rng.gen_range(0..16)).collect()),
+            ]
+            .into();
+            for ((name, plugin_output), (_, json_output)) in
+                plugin_outputs.iter().zip(&json_outputs)
+            {
+                for time in 0..6 {
+                    assert_eq!(
+                        interpret(&from_json, json_output, time, &env),
+                        interpret(&from_plugin, plugin_output, time, &env),
+                        "output {name} at time {time}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn memory_from_json_netlist_matches_plugin() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        // An asynchronous read port and a synchronous one, which isn't
+        // transparent.
+        let (verilog_path, json_path) = write_json_netlist(
+            "memory_from_json_netlist_matches_plugin",
+            "module json_ram(input clk, input we, input [3:0] wa, input [3:0] ra,
+                             input [7:0] din, output [7:0] async_out,
+                             output reg [7:0] sync_out);
+               reg [7:0] mem [0:15];
+               initial for (integer i = 0; i < 16; i = i + 1) mem[i] = 8'd7;
+               always @(posedge clk) begin
+                 if (we) mem[wa] <= din;
+                 sync_out <= mem[ra];
+               end
+               assign async_out = mem[wa];
+             endmodule",
+            "json_ram",
+        );
+
+        let import = |mut egraph: EGraph| {
+            egraph
+                .parse_and_run_program("(run-schedule (saturate typing))")
+                .unwrap();
+            auto_delete_wires(&mut egraph);
+            egraph.serialize(SerializeConfig::default())
+        };
+        let from_plugin = import(
+            from_verilog_file(
+                &verilog_path,
+                "json_ram",
+                &yosys_plugin(),
+                false,
+                &HashMap::new(),
+            )
+            .unwrap(),
+        );
+        let from_json = import(from_json_netlist_file(&json_path, "json_ram").unwrap());
+        for serialized in [&from_plugin, &from_json] {
+            let ops = serialized
+                .nodes
+                .values()
+                .map(|node| node.op.as_str())
+                .collect::<HashSet<_>>();
+            assert!(ops.contains("Mem") && ops.contains("MemWrite") && ops.contains("MemRead"));
+        }
+
+        let (_, plugin_outputs) = get_inputs_and_outputs_serialized(&from_plugin);
+        let (_, json_outputs) = get_inputs_and_outputs_serialized(&from_json);
+        let mut from_plugin = Interpreter::new(&from_plugin, plugin_outputs).unwrap();
+        let mut from_json = Interpreter::new(&from_json, json_outputs).unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        for cycle in 0..50 {
+            let env: HashMap<String, u64> = [
+                ("we".to_owned(), rng.gen_range(0..2)),
+                ("wa".to_owned(), rng.gen_range(0..16)),
+                ("ra".to_owned(), rng.gen_range(0..16)),
+                ("din".to_owned(), rng.gen_range(0..256)),
+            ]
+            .into();
+            let plugin_results = from_plugin.step(&env);
+            let json_results = from_json.step(&env);
+            if cycle == 0 {
+                // Every word starts as 7.
+                assert_eq!(
+                    plugin_results["async_out"],
+                    InterpreterResult::Bitvector(7, 8)
+                );
+            }
+            assert_eq!(json_results, plugin_results, "cycle {cycle}");
+        }
+    }
+
+    #[test]
+    fn memory_import_errors() {
+        let (verilog_path, json_path) = write_json_netlist(
+            "memory_import_errors",
+            "module two_writes(input clk, input [3:0] a, input [3:0] b,
+                               input [7:0] din, output [7:0] dout);
+               reg [7:0] mem [0:15];
+               always @(posedge clk) begin
+                 mem[a] <= din;
+                 mem[b] <= ~din;
+               end
+               assign dout = mem[a];
+             endmodule",
+            "two_writes",
+        );
+
+        assert!(matches!(
+            from_json_netlist_file(&json_path, "two_writes"),
+            Err(ImportError::JsonNetlist(message)) if message.contains("more than one write port")
+        ));
+        assert!(matches!(
+            from_verilog_file(
+                &verilog_path,
+                "two_writes",
+                &yosys_plugin(),
+                false,
+                &HashMap::new()
+            ),
+            Err(ImportError::Yosys(stderr)) if stderr.contains("write ports")
+        ));
+    }
+
+    #[test]
+    fn failing_command_finds_line() {
+        let program = r#"
+; a comment (with parentheses
+(let a (Var "a; not a comment" 8))
+(let b
+  (Op2 (And) a c))
+(let d (Var "d" 8))
+"#;
+        assert_eq!(
+            failing_command(program),
+            Some((4, "(let b\n  (Op2 (And) a c))".to_owned()))
+        );
+        assert_eq!(failing_command("(let a (Var \"a\" 8))"), None);
+    }
+
+    #[test]
+    fn from_egraph_json_round_trip() {
+        let serialized = import(COUNTER);
+        let json = serde_json::to_string(&serialized).unwrap();
+        let deserialized = from_egraph_json(&json).unwrap();
+        assert_eq!(
+            deserialized.nodes.keys().collect::<Vec<_>>(),
+            serialized.nodes.keys().collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use egraph_serialize::{ClassId, Node, NodeId};
-use indexmap::IndexMap;
+use indexmap::{IndexMap, IndexSet};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     sync::Arc,
 };
 
@@ -9,9 +9,13 @@ use egglog::{
     ast::{Literal, Symbol},
     constraint::{SimpleTypeConstraint, TypeConstraint},
     sort::{FromSort, I64Sort, IntoSort, Sort, VecSort},
-    ArcSort, EGraph, PrimitiveLike, Term, TermDag, Value,
+    ArcSort, EGraph, PrimitiveLike, SerializeConfig, Term, TermDag, Value,
 };
 
+pub mod interop;
+pub use interop::*;
+pub mod verilator;
+
 // The result of interpreting a Churchroad program.
 #[derive(Debug, PartialEq, Clone)]
 pub enum InterpreterResult {
@@ -26,7 +30,7 @@ pub enum InterpreterResult {
 /// use egglog::{EGraph, SerializeConfig};
 /// use egraph_serialize::NodeId;
 /// let mut egraph = EGraph::default();
-/// import_churchroad(&mut egraph);
+/// import_churchroad(&mut egraph).unwrap();
 /// egraph.parse_and_run_program(
 /// r#"
 /// (let v0 (Wire "v0" 1))
@@ -41,11 +45,9 @@ pub enum InterpreterResult {
 /// (union v1 b)
 /// (let out v2)
 /// (IsPort "" "out" (Output) out)
-/// (delete (Wire "v0" 1))
-/// (delete (Wire "v1" 1))
-/// (delete (Wire "v2" 1))
 /// "#
 /// ).unwrap();
+/// auto_delete_wires(&mut egraph);
 ///
 /// let serialized = egraph.serialize(SerializeConfig::default());
 ///
@@ -76,11 +78,310 @@ pub fn interpret(
     env: &HashMap<&str, Vec<u64>>,
 ) -> Result<InterpreterResult, String> {
     let result = match egraph.classes().iter().find(|(id, _)| *id == class_id) {
-        Some((id, _)) => interpret_helper(egraph, id, time, env, &mut HashMap::default()),
+        Some((id, _)) => interpret_helper(egraph, id, time, env, false, &mut HashMap::default()),
         None => return Err("No class with the given ID.".to_string()),
     };
 
-    result
+    // Without partial evaluation, missing inputs panic rather than giving
+    // `None`.
+    result.map(Option::unwrap)
+}
+
+/// Like [`interpret`], but gives `Ok(None)` rather than panicking when the
+/// value of the signal depends on an input which `env` doesn't give a value
+/// for at the needed timestep. A `Mux` whose select is known only depends on
+/// the input it selects. When `env` gives every input, this is the same as
+/// [`interpret`].
+///
+/// ```
+/// use churchroad::*;
+/// use egglog::{EGraph, SerializeConfig};
+///
+/// let mut egraph = EGraph::default();
+/// import_churchroad(&mut egraph).unwrap();
+/// egraph
+///     .parse_and_run_program(
+///         r#"
+/// (let s (Var "s" 1))
+/// (let a (Var "a" 4))
+/// (let b (Var "b" 4))
+/// (IsPort "" "out" (Output) (Op3 (Mux) s a b))
+/// (IsPort "" "sum" (Output) (Op2 (Add) a b))
+/// "#,
+///     )
+///     .unwrap();
+///
+/// let serialized = egraph.serialize(SerializeConfig::default());
+/// let (_, outputs) = get_inputs_and_outputs_serialized(&serialized);
+/// let env = [("s", vec![0]), ("a", vec![3])].into();
+///
+/// assert_eq!(
+///     interpret_partial(&serialized, &outputs[0].1, 0, &env),
+///     Ok(Some(InterpreterResult::Bitvector(3, 4)))
+/// );
+/// assert_eq!(interpret_partial(&serialized, &outputs[1].1, 0, &env), Ok(None));
+/// ```
+pub fn interpret_partial(
+    egraph: &egraph_serialize::EGraph,
+    class_id: &ClassId,
+    time: usize,
+    env: &HashMap<&str, Vec<u64>>,
+) -> Result<Option<InterpreterResult>, String> {
+    match egraph.classes().iter().find(|(id, _)| *id == class_id) {
+        Some((id, _)) => interpret_helper(egraph, id, time, env, true, &mut HashMap::default()),
+        None => Err("No class with the given ID.".to_string()),
+    }
+}
+
+/// Interprets a Churchroad program once per environment in `envs`.
+///
+/// This is equivalent to calling [`interpret`] once per environment, but the
+/// order in which to evaluate the design is computed once and shared across
+/// all environments. Unlike [`interpret`], every signal reachable from
+/// `class_id` is evaluated at every timestep up to `time`, so each environment
+/// must provide every input at each of those timesteps. Designs imported with
+/// their hierarchy kept aren't supported: each result is an error if a
+/// `GetOutput` is reachable from `class_id`.
+pub fn interpret_batch(
+    egraph: &egraph_serialize::EGraph,
+    class_id: &ClassId,
+    time: usize,
+    envs: &[HashMap<&str, Vec<u64>>],
+) -> Vec<Result<InterpreterResult, String>> {
+    match evaluation_order(egraph, class_id) {
+        Ok(order) => envs
+            .iter()
+            .map(|env| interpret_in_order(egraph, &order, class_id, time, env))
+            .collect(),
+        Err(e) => envs.iter().map(|_| Err(e.clone())).collect(),
+    }
+}
+
+/// Interprets every output of a design, and every signal marked with an
+/// `Observe` fact, at timestep `time`. The results are keyed by port or
+/// observation name.
+///
+/// ```
+/// use churchroad::*;
+/// use egglog::{EGraph, SerializeConfig};
+///
+/// let mut egraph = EGraph::default();
+/// import_churchroad(&mut egraph).unwrap();
+/// egraph
+///     .parse_and_run_program(
+///         r#"
+/// (let a (Var "a" 4))
+/// (let b (Var "b" 4))
+/// (let sum (Op2 (Add) a b))
+/// (Observe "sum" sum)
+/// (IsPort "" "out" (Output) (Op2 (Xor) sum b))
+/// "#,
+///     )
+///     .unwrap();
+///
+/// let serialized = egraph.serialize(SerializeConfig::default());
+/// let results =
+///     interpret_design(&serialized, 0, &[("a", vec![3]), ("b", vec![5])].into()).unwrap();
+///
+/// assert_eq!(results["sum"], InterpreterResult::Bitvector(8, 4));
+/// assert_eq!(results["out"], InterpreterResult::Bitvector(13, 4));
+/// ```
+pub fn interpret_design(
+    egraph: &egraph_serialize::EGraph,
+    time: usize,
+    env: &HashMap<&str, Vec<u64>>,
+) -> Result<HashMap<String, InterpreterResult>, String> {
+    let (_, outputs) = get_inputs_and_outputs_serialized(egraph);
+    let observations = egraph
+        .nodes
+        .values()
+        .filter(|node| node.op == "Observe")
+        .map(|node| {
+            let name = &egraph[&node.children[0]].op;
+            (
+                name[1..name.len() - 1].to_owned(),
+                egraph[&node.children[1]].eclass.clone(),
+            )
+        });
+
+    let mut results = HashMap::new();
+    for (name, id) in outputs.into_iter().chain(observations) {
+        let result = interpret(egraph, &id, time, env)?;
+        if results.insert(name.clone(), result).is_some() {
+            return Err(format!(
+                "Multiple outputs or observations named {:?}.",
+                name
+            ));
+        }
+    }
+    Ok(results)
+}
+
+/// Compute an order in which the eclasses reachable from `root` can be
+/// evaluated within a single timestep, i.e. in which every eclass comes after
+/// the eclasses it depends on. A register depends on its clock, but not on its
+/// data input, which it reads from the previous timestep.
+fn evaluation_order(
+    egraph: &egraph_serialize::EGraph,
+    root: &ClassId,
+) -> Result<Vec<ClassId>, String> {
+    enum Visit {
+        Enter(ClassId),
+        Exit(ClassId),
+    }
+
+    let mut order = vec![];
+    let mut done = HashSet::new();
+    // The eclasses on the current DFS path.
+    let mut in_progress = HashSet::new();
+    // Data inputs of registers still need to be evaluated, but needn't come
+    // before their registers, so they're visited as separate roots.
+    let mut roots = vec![root.clone()];
+    while let Some(root) = roots.pop() {
+        let mut stack = vec![Visit::Enter(root)];
+        while let Some(visit) = stack.pop() {
+            match visit {
+                Visit::Enter(id) => {
+                    if done.contains(&id) {
+                        continue;
+                    }
+                    if !in_progress.insert(id.clone()) {
+                        return Err(format!("Combinational cycle through class {:?}.", id));
+                    }
+                    stack.push(Visit::Exit(id.clone()));
+
+                    let node = get_single_node(egraph, &id)?;
+                    match node.op.as_str() {
+                        "Var" => (),
+                        "Op0" | "Op1" | "Op2" | "Op3" => {
+                            let op = &egraph[&node.children[0]];
+                            let mut dependencies = node.children[1..].iter().collect::<Vec<_>>();
+                            if matches!(op.op.as_str(), "Reg" | "RegWithClk") {
+                                let d = dependencies.pop().unwrap();
+                                roots.push(egraph[d].eclass.clone());
+                            }
+                            stack.extend(
+                                dependencies
+                                    .into_iter()
+                                    .map(|child| Visit::Enter(egraph[child].eclass.clone())),
+                            );
+                        }
+                        "GetOutput" => {
+                            return Err(
+                                "Module instances are only supported by interpret.".to_string()
+                            )
+                        }
+                        _ => return Err(format!("unimplemented node type: {:?}", node.op)),
+                    }
+                }
+                Visit::Exit(id) => {
+                    in_progress.remove(&id);
+                    if done.insert(id.clone()) {
+                        order.push(id);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(order)
+}
+
+/// Interpret the eclasses in `order` (as computed by [`evaluation_order`])
+/// at each timestep up to `time`, returning the value of `class_id` at `time`.
+fn interpret_in_order(
+    egraph: &egraph_serialize::EGraph,
+    order: &[ClassId],
+    class_id: &ClassId,
+    time: usize,
+    env: &HashMap<&str, Vec<u64>>,
+) -> Result<InterpreterResult, String> {
+    let get_input = |name: &str, time: usize| -> Result<u64, String> {
+        env.get(name)
+            .ok_or_else(|| format!("didn't find var {:?}", name))?
+            .get(time)
+            .copied()
+            .ok_or_else(|| format!("no value for {:?} at time {:?}", name, time))
+    };
+
+    // The values of all eclasses at the previous timestep.
+    let mut prev_values: HashMap<ClassId, InterpreterResult> = HashMap::new();
+    for t in 0..=time {
+        let mut values: HashMap<ClassId, InterpreterResult> = HashMap::new();
+        for id in order {
+            let node = get_single_node(egraph, id)?;
+            let result = if node.op == "Var" {
+                let (name, bw) = get_var_name_and_bitwidth(egraph, node);
+                InterpreterResult::Bitvector(get_input(name, t)?, bw)
+            } else {
+                let op = &egraph[&node.children[0]];
+                match op.op.as_str() {
+                    "Mem" | "MemRead" => {
+                        return Err(
+                            "Memories are only supported by the stateful Interpreter.".to_string()
+                        )
+                    }
+                    "ShiftReg" => {
+                        return Err(
+                            "Shift registers are only supported by the stateful Interpreter."
+                                .to_string(),
+                        )
+                    }
+                    "Reg" | "RegWithClk" => {
+                        let clk_at = |values: &HashMap<ClassId, InterpreterResult>,
+                                      time: usize|
+                         -> Result<u64, String> {
+                            if node.children.len() == 3 {
+                                let InterpreterResult::Bitvector(clk_val, _) =
+                                    values[&egraph[&node.children[1]].eclass];
+                                Ok(clk_val)
+                            } else {
+                                Err("Reg without a clock expression can't be interpreted."
+                                    .to_string())
+                            }
+                        };
+
+                        if t == 0 {
+                            assert_eq!(
+                                clk_at(&values, t)?,
+                                0,
+                                "We don't currently know what to do when clk=1 at time 0! See #88"
+                            );
+                            // The init may be negative, and is truncated below.
+                            let init = egraph[op.children.last().unwrap()]
+                                .op
+                                .parse::<i64>()
+                                .unwrap();
+                            InterpreterResult::Bitvector(
+                                init as u64,
+                                get_bitwidth_for_node(egraph, node.children.last().unwrap())?,
+                            )
+                        } else if clk_at(&prev_values, t - 1)? == 0 && clk_at(&values, t)? == 1 {
+                            prev_values[&egraph[node.children.last().unwrap()].eclass].clone()
+                        } else {
+                            prev_values[id].clone()
+                        }
+                    }
+                    _ => {
+                        let children = node.children[1..]
+                            .iter()
+                            .map(|child| values[&egraph[child].eclass].clone())
+                            .collect::<Vec<_>>();
+                        interpret_op(egraph, node, op, &children)?
+                    }
+                }
+            };
+
+            let InterpreterResult::Bitvector(val, bw) = result;
+            values.insert(
+                id.clone(),
+                InterpreterResult::Bitvector(truncate_value_to_bitwidth(val, bw), bw),
+            );
+        }
+        prev_values = values;
+    }
+
+    Ok(prev_values[class_id].clone())
 }
 
 pub fn get_bitwidth_for_node(
@@ -94,7 +395,10 @@ pub fn get_bitwidth_for_node(
     {
         Some((_, has_type_node)) => {
             let type_node = egraph.nodes.get(&has_type_node.children[1]).unwrap();
-            assert!(type_node.op == "Bitvector");
+            assert!(matches!(
+                type_node.op.as_str(),
+                "Bitvector" | "SignedBitvector"
+            ));
 
             let bw: u64 = egraph
                 .nodes
@@ -109,6 +413,52 @@ pub fn get_bitwidth_for_node(
     }
 }
 
+/// Get the bitwidth of an eclass from the `HasType` facts in a serialized
+/// egraph. Returns `None` if the eclass has no bitvector type, e.g. because
+/// typing hasn't been run.
+///
+/// ```
+/// use churchroad::*;
+/// use egglog::{EGraph, SerializeConfig};
+/// let mut egraph = EGraph::default();
+/// import_churchroad(&mut egraph).unwrap();
+/// egraph
+///     .parse_and_run_program(
+///         r#"
+/// (let a (Var "a" 8))
+/// (IsPort "" "a" (Input) a)
+/// (IsPort "" "out" (Output) (Op1 (Extract 3 0) a))
+/// (run-schedule (saturate typing))
+/// "#,
+///     )
+///     .unwrap();
+///
+/// let serialized = egraph.serialize(SerializeConfig::default());
+/// let (inputs, outputs) = get_inputs_and_outputs_serialized(&serialized);
+/// assert_eq!(get_bitwidth(&serialized, &inputs[0].1), Some(8));
+/// assert_eq!(get_bitwidth(&serialized, &outputs[0].1), Some(4));
+/// ```
+pub fn get_bitwidth(egraph: &egraph_serialize::EGraph, class: &ClassId) -> Option<u64> {
+    egraph.nodes.values().find_map(|node| {
+        if node.op != "HasType" || egraph[&node.children[0]].eclass != *class {
+            return None;
+        }
+        let type_node = &egraph[&node.children[1]];
+        if !matches!(type_node.op.as_str(), "Bitvector" | "SignedBitvector") {
+            return None;
+        }
+        egraph[&type_node.children[0]].op.parse().ok()
+    })
+}
+
+/// Interpret the low `bw` bits of `val` as a two's complement number.
+fn sign_extend_value(val: u64, bw: u64) -> i64 {
+    assert!(bw <= 64);
+    assert!(bw > 0);
+    let shift = 64 - bw;
+    ((val << shift) as i64) >> shift
+}
+
 fn truncate_value_to_bitwidth(val: u64, bw: u64) -> u64 {
     assert!(bw <= 64);
     assert!(bw > 0);
@@ -119,17 +469,18 @@ fn truncate_value_to_bitwidth(val: u64, bw: u64) -> u64 {
     }
 }
 
-fn interpret_helper(
-    egraph: &egraph_serialize::EGraph,
+/// Get the single node in the given eclass. The interpreter requires that each
+/// eclass it visits has exactly one node, i.e. that extraction has already
+/// happened.
+fn get_single_node<'a>(
+    egraph: &'a egraph_serialize::EGraph,
     id: &ClassId,
-    time: usize,
-    env: &HashMap<&str, Vec<u64>>,
-    cache: &mut HashMap<(ClassId, usize), InterpreterResult>,
-) -> Result<InterpreterResult, String> {
-    if cache.contains_key(&(id.clone(), time)) {
-        return Ok(cache[&(id.clone(), time)].clone());
-    }
-    let node_ids = &egraph.classes().get(id).unwrap().nodes;
+) -> Result<&'a Node, String> {
+    let node_ids = &egraph
+        .classes()
+        .get(id)
+        .ok_or_else(|| format!("No class with ID {:?}.", id))?
+        .nodes;
     if node_ids.len() != 1 {
         return Err(format!(
             "There should be exactly one node in the class, but there are {}.",
@@ -137,294 +488,236 @@ fn interpret_helper(
         ));
     }
 
-    let node_id = node_ids.first().unwrap();
-    let node = egraph.nodes.get(node_id).unwrap();
+    Ok(egraph.nodes.get(node_ids.first().unwrap()).unwrap())
+}
+
+/// Get the name and bitwidth of a `Var` node.
+fn get_var_name_and_bitwidth<'a>(
+    egraph: &'a egraph_serialize::EGraph,
+    node: &Node,
+) -> (&'a str, u64) {
+    assert_eq!(node.op, "Var");
+    let bw: u64 = egraph
+        .nodes
+        .get(&node.children[1])
+        .unwrap()
+        .op
+        .parse()
+        .unwrap();
+    let name = egraph.nodes.get(&node.children[0]).unwrap().op.as_str();
+    // cut off the quotes on the beginning and end
+    let name = &name[1..name.len() - 1];
+
+    (name, bw)
+}
+
+/// Look up the value of the input `name` at the given time. If it's missing,
+/// gives `None` when evaluating partially, and panics otherwise.
+fn input_value(
+    env: &HashMap<&str, Vec<u64>>,
+    name: &str,
+    time: usize,
+    partial: bool,
+) -> Option<u64> {
+    let value = env.get(name).and_then(|values| values.get(time)).copied();
+    if value.is_none() && !partial {
+        match env.get(name) {
+            None => panic!("didn't find var {:?}", name),
+            Some(_) => panic!("no value at time {:?}", time),
+        }
+    }
+    value
+}
+
+/// Get the value of a register's clock, i.e. of the `clk` expression of an
+/// `(Op2 (Reg init) clk d)` or `(Op2 (RegWithClk init) clk d)` register, at
+/// the given time.
+fn interpret_reg_clock(
+    egraph: &egraph_serialize::EGraph,
+    node: &Node,
+    op: &Node,
+    time: usize,
+    env: &HashMap<&str, Vec<u64>>,
+    partial: bool,
+    cache: &mut HashMap<(ClassId, usize), InterpreterResult>,
+) -> Result<Option<u64>, String> {
+    assert!(
+        matches!(op.op.as_str(), "Reg" | "RegWithClk"),
+        "not a register: {:?}",
+        op.op
+    );
+    if node.children.len() != 3 {
+        return Err("Reg without a clock expression can't be interpreted.".to_string());
+    }
+    let clk = egraph.nodes.get(&node.children[1]).unwrap();
+    Ok(
+        interpret_helper(egraph, &clk.eclass, time, env, partial, cache)?
+            .map(|InterpreterResult::Bitvector(clk_val, _)| clk_val),
+    )
+}
+
+/// Interpret the eclass `id` at the given time. Gives `None` if it depends on
+/// an input missing from `env`, which is only possible when evaluating
+/// `partial`ly.
+fn interpret_helper(
+    egraph: &egraph_serialize::EGraph,
+    id: &ClassId,
+    time: usize,
+    env: &HashMap<&str, Vec<u64>>,
+    partial: bool,
+    cache: &mut HashMap<(ClassId, usize), InterpreterResult>,
+) -> Result<Option<InterpreterResult>, String> {
+    if cache.contains_key(&(id.clone(), time)) {
+        return Ok(Some(cache[&(id.clone(), time)].clone()));
+    }
+    let node = get_single_node(egraph, id)?;
 
     let result = match node.op.as_str() {
         "Var" => {
-            let bw: u64 = egraph
-                .nodes
-                .get(&node.children[1])
-                .unwrap()
-                .op
-                .parse()
-                .unwrap();
-            let name = egraph.nodes.get(&node.children[0]).unwrap().op.as_str();
-            // cut off the quotes on the beginning and end
-            let name = &name[1..name.len() - 1];
+            let (name, bw) = get_var_name_and_bitwidth(egraph, node);
 
-            Ok(InterpreterResult::Bitvector(
-                *env.get(name)
-                    .unwrap_or_else(|| panic!("didn't find var {:?}", name))
-                    .get(time)
-                    .unwrap_or_else(|| panic!("no value at time {:?}", time)),
-                bw,
-            ))
+            match input_value(env, name, time, partial) {
+                Some(value) => Ok(InterpreterResult::Bitvector(value, bw)),
+                None => return Ok(None),
+            }
         }
         "Op0" | "Op1" | "Op2" | "Op3" => {
             assert!(!node.children.is_empty());
             let op = egraph.nodes.get(&node.children[0]).unwrap();
 
-            if op.op.as_str() == "Reg" {
+            if matches!(op.op.as_str(), "Mem" | "MemRead") {
+                return Err("Memories are only supported by the stateful Interpreter.".to_string());
+            }
+            if op.op == "ShiftReg" {
+                return Err(
+                    "Shift registers are only supported by the stateful Interpreter.".to_string(),
+                );
+            }
+
+            if matches!(op.op.as_str(), "Reg" | "RegWithClk") {
                 if time == 0 {
-                    let clk = egraph.nodes.get(&node.children[1]).unwrap();
-                    let InterpreterResult::Bitvector(curr_clk_val, _) =
-                        interpret_helper(egraph, &clk.eclass, time, env, cache).unwrap();
+                    let Some(curr_clk_val) =
+                        interpret_reg_clock(egraph, node, op, time, env, partial, cache)?
+                    else {
+                        return Ok(None);
+                    };
                     assert_eq!(
                         curr_clk_val, 0,
                         "We don't currently know what to do when clk=1 at time 0! See #88"
                     );
-                    let initial_value = egraph.nodes.get(&op.children[0]).unwrap();
-                    return Ok(InterpreterResult::Bitvector(
-                        initial_value.op.parse().unwrap(),
-                        get_bitwidth_for_node(egraph, &node.children[2]).unwrap(),
-                    ));
+                    let initial_value = egraph.nodes.get(op.children.last().unwrap()).unwrap();
+                    let bw = get_bitwidth_for_node(egraph, node.children.last().unwrap()).unwrap();
+                    // Negative initial values, e.g. `(Reg -1)`, are truncated
+                    // to the register's width.
+                    return Ok(Some(InterpreterResult::Bitvector(
+                        truncate_value_to_bitwidth(
+                            initial_value.op.parse::<i64>().unwrap() as u64,
+                            bw,
+                        ),
+                        bw,
+                    )));
                 } else {
-                    let clk = egraph.nodes.get(&node.children[1]).unwrap();
-                    let InterpreterResult::Bitvector(prev_clk_val, _) =
-                        interpret_helper(egraph, &clk.eclass, time - 1, env, cache).unwrap();
-                    let InterpreterResult::Bitvector(curr_clk_val, _) =
-                        interpret_helper(egraph, &clk.eclass, time, env, cache).unwrap();
+                    let (Some(prev_clk_val), Some(curr_clk_val)) = (
+                        interpret_reg_clock(egraph, node, op, time - 1, env, partial, cache)?,
+                        interpret_reg_clock(egraph, node, op, time, env, partial, cache)?,
+                    ) else {
+                        return Ok(None);
+                    };
 
                     if prev_clk_val == 0 && curr_clk_val == 1 {
-                        let d = egraph.nodes.get(&node.children[2]).unwrap();
-                        return interpret_helper(egraph, &d.eclass, time - 1, env, cache);
+                        let d = egraph.nodes.get(node.children.last().unwrap()).unwrap();
+                        return interpret_helper(egraph, &d.eclass, time - 1, env, partial, cache);
                     } else {
-                        return interpret_helper(egraph, id, time - 1, env, cache);
+                        return interpret_helper(egraph, id, time - 1, env, partial, cache);
                     }
                 }
             }
-            let children: Vec<_> = node
+            let children = node
                 .children
                 .iter()
                 .skip(1)
                 .map(|id| {
                     let child = egraph.nodes.get(id).unwrap();
-                    interpret_helper(egraph, &child.eclass, time, env, cache)
+                    interpret_helper(egraph, &child.eclass, time, env, partial, cache)
                 })
-                .collect();
-
-            match op.op.as_str() {
-                // Binary operations that condense to a single bit.
-                "Eq" | "LogicOr" | "LogicAnd" | "Ne" => {
-                    assert_eq!(children.len(), 2);
-                    let result = match op.op.as_str() {
-                        "Eq" => {
-                            let a = match &children[0] {
-                                Ok(InterpreterResult::Bitvector(val, _)) => *val,
-                                _ => todo!(),
-                            };
-                            let b = match &children[1] {
-                                Ok(InterpreterResult::Bitvector(val, _)) => *val,
-                                _ => todo!(),
-                            };
-                            a == b
-                        }
-                        "Ne" => {
-                            let a = match &children[0] {
-                                Ok(InterpreterResult::Bitvector(val, _)) => *val,
-                                _ => todo!(),
-                            };
-                            let b = match &children[1] {
-                                Ok(InterpreterResult::Bitvector(val, _)) => *val,
-                                _ => todo!(),
-                            };
-                            a != b
-                        }
-                        "LogicOr" => {
-                            let result = children.iter().any(|child| match child {
-                                Ok(InterpreterResult::Bitvector(val, _)) => *val != 0,
-                                _ => todo!(),
-                            });
-                            result
-                        }
-                        "LogicAnd" => {
-                            // if any of the children are false, the result is false
-                            let result = children.iter().all(|child| match child {
-                                Ok(InterpreterResult::Bitvector(val, _)) => *val != 0,
-                                _ => todo!(),
-                            });
-                            result
-                        }
-                        _ => todo!(),
-                    };
-                    Ok(InterpreterResult::Bitvector(result as u64, 1))
-                }
-                // Unary operations that condense to a single bit.
-                "ReduceOr" | "ReduceAnd" | "LogicNot" => {
-                    assert_eq!(children.len(), 1);
-                    match op.op.as_str() {
-                        "ReduceOr" => {
-                            let value = match children[0] {
-                                Ok(InterpreterResult::Bitvector(val, _)) => val,
-                                _ => todo!(),
-                            };
-                            let result = value != 0;
-                            Ok(InterpreterResult::Bitvector(result as u64, 1))
-                        }
-                        "ReduceAnd" => {
-                            // if any bit of children[0] is 0, the result is 0
-                            match children[0] {
-                                Ok(InterpreterResult::Bitvector(val, bw)) => {
-                                    let result = val == (1 << bw) - 1;
-                                    Ok(InterpreterResult::Bitvector(result as u64, 1))
-                                }
-                                _ => todo!(),
-                            }
-                        }
-                        "LogicNot" => match children[0] {
-                            Ok(InterpreterResult::Bitvector(val, _)) => {
-                                let new_val = if val == 0 { 1 } else { 0 };
-                                Ok(InterpreterResult::Bitvector(new_val, 1))
-                            }
-                            _ => todo!(),
-                        },
-                        _ => todo!(),
-                    }
-                }
-                // Unary operations that preserve bitwidth.
-                "Not" => {
-                    assert_eq!(children.len(), 1);
-                    match children[0] {
-                        Ok(InterpreterResult::Bitvector(val, bw)) => {
-                            let result = !val & ((1 << bw) - 1);
-                            Ok(InterpreterResult::Bitvector(result, bw))
-                        }
-                        _ => todo!(),
-                    }
-                }
-                // Binary operations that preserve bitwidth.
-                "And" | "Or" | "Shr" | "Xor" | "Add" | "Sub" | "Mul" => {
-                    assert_eq!(children.len(), 2);
-                    match (&children[0], &children[1]) {
-                        (
-                            Ok(InterpreterResult::Bitvector(a, a_bw)),
-                            Ok(InterpreterResult::Bitvector(b, b_bw)),
-                        ) => {
-                            assert_eq!(a_bw, b_bw);
-                            let result = match op.op.as_str() {
-                                "And" => a & b,
-                                "Or" => a | b,
-                                "Shr" => a >> b,
-                                "Xor" => a ^ b,
-                                // TODO(@gussmith23): These might not work -- do we need to simulate lower bitwidths?
-                                "Add" => (a.overflowing_add(*b).0) & ((1 << a_bw) - 1),
-                                "Sub" => (a.overflowing_sub(*b).0) & ((1 << a_bw) - 1),
-                                "Mul" => (a.overflowing_mul(*b).0) & ((1 << a_bw) - 1),
-                                _ => unreachable!(),
-                            };
-                            Ok(InterpreterResult::Bitvector(result, *a_bw))
-                        }
-                        _ => todo!(),
+                .collect::<Result<Vec<_>, _>>()?;
+
+            // A mux only depends on the input it selects.
+            match (op.op.as_str(), &children[..]) {
+                ("Mux", [Some(InterpreterResult::Bitvector(cond, _)), a, b]) => {
+                    match if *cond == 0 { a } else { b } {
+                        Some(selected) => Ok(selected.clone()),
+                        None => return Ok(None),
                     }
                 }
-                "Mux" => {
-                    assert_eq!(children.len(), 3);
-
-                    match children[0] {
-                        Ok(InterpreterResult::Bitvector(cond, _)) => {
-                            if cond == 0 {
-                                children[1].clone()
-                            } else {
-                                children[2].clone()
-                            }
-                        }
-                        _ => todo!(),
-                    }
-                }
-                "BV" => {
-                    assert_eq!(op.children.len(), 2);
-                    let args = &op
-                        .children
-                        .iter()
-                        .map(|id| {
-                            let (_, node) = egraph
-                                .nodes
-                                .iter()
-                                .find(|(node_id, _)| **node_id == *id)
-                                .unwrap();
-                            assert_eq!(node.children.len(), 0);
-                            // TODO(@ninehusky): here, reading node.op.parse() as i64, then convert to u64
-                            let val: i64 = node.op.parse().unwrap();
-                            val as u64
-                        })
-                        .collect::<Vec<_>>()[..];
-
-                    assert!(args[1] <= 64);
-                    Ok(InterpreterResult::Bitvector(args[0], args[1]))
-                }
-                "Extract" => {
-                    assert_eq!(op.children.len(), 2);
-                    let args = &op
-                        .children
-                        .iter()
-                        .map(|id| {
-                            let (_, node) = egraph
-                                .nodes
-                                .iter()
-                                .find(|(node_id, _)| **node_id == *id)
-                                .unwrap();
-                            assert_eq!(node.children.len(), 0);
-                            let val: u64 = node.op.parse().unwrap();
-                            val
-                        })
-                        .collect::<Vec<_>>()[..];
-
-                    let i = args[0];
-                    let j = args[1];
-
-                    let val = match children[0].as_ref().unwrap() {
-                        InterpreterResult::Bitvector(val, bw) => {
-                            // from Rosette docs:
-                            // https://docs.racket-lang.org/rosette-guide/sec_bitvectors.html#%28def._%28%28lib._rosette%2Fbase%2Fbase..rkt%29._extract%29%29
-                            // TODO(@ninehusky): here, we should also assert that j >= 0 if churchroad handles signed numbers
-                            assert!(
-                                *bw > i && i >= j,
-                                "i is {}, j is {} node has bw {}, has node_id {:?}",
-                                i,
-                                j,
-                                bw,
-                                node.children[1]
-                            );
-
-                            let mask = (1 << (i - j + 1)) - 1;
-                            (val >> j) & mask
-                        }
-                    };
-                    assert!(i - j < 64);
-                    Ok(InterpreterResult::Bitvector(val, i - j + 1))
-                }
-                "Concat" => match (&children[0], &children[1]) {
-                    (
-                        Ok(InterpreterResult::Bitvector(a, a_bw)),
-                        Ok(InterpreterResult::Bitvector(b, b_bw)),
-                    ) => {
-                        let result = (a << b_bw) | b;
-                        assert!(a_bw + b_bw <= 64);
-                        Ok(InterpreterResult::Bitvector(result, a_bw + b_bw))
-                    }
-                    _ => todo!(),
+                _ => match children.iter().cloned().collect::<Option<Vec<_>>>() {
+                    Some(children) => interpret_op(egraph, node, op, &children),
+                    None => return Ok(None),
                 },
-                "ZeroExtend" => {
-                    let extension_bw: u64 = egraph
-                        .nodes
-                        .iter()
-                        .find(|(id, _)| *id == &op.children[0])
-                        .unwrap()
-                        .1
-                        .op
-                        .parse()
-                        .unwrap();
-                    assert!(extension_bw <= 64);
-                    match children[0] {
-                        Ok(InterpreterResult::Bitvector(val, _)) => {
-                            Ok(InterpreterResult::Bitvector(val, extension_bw))
+            }
+        }
+        "GetOutput" => {
+            let instance = get_single_node(egraph, &egraph[&node.children[0]].eclass)?;
+            if instance.op != "ModuleInstance" {
+                return Err(format!(
+                    "Expected a ModuleInstance, found {:?}.",
+                    instance.op
+                ));
+            }
+            let unquote = |id: &NodeId| {
+                let name = egraph[id].op.as_str();
+                &name[1..name.len() - 1]
+            };
+            let module_name = unquote(&instance.children[0]);
+            let output_name = unquote(&node.children[1]);
+
+            // The instantiated module is found through its output's `IsPort`.
+            let output = egraph
+                .nodes
+                .values()
+                .find(|port| {
+                    port.op == "IsPort"
+                        && unquote(&port.children[0]) == module_name
+                        && unquote(&port.children[1]) == output_name
+                        && egraph[&port.children[2]].op == "Output"
+                })
+                .ok_or_else(|| {
+                    format!("Module {:?} has no output {:?}.", module_name, output_name)
+                })?;
+
+            // The module's Vars are named after its parameters and inputs, and
+            // take the values of the instance's expressions up to `time`.
+            let mut module_env = HashMap::new();
+            for (names, exprs) in [(1, 2), (3, 4)] {
+                let names = cons_list_classes(egraph, &egraph[&instance.children[names]].eclass)?;
+                let exprs = cons_list_classes(egraph, &egraph[&instance.children[exprs]].eclass)?;
+                for (name, expr) in names.iter().zip(exprs.iter()) {
+                    let name = get_single_node(egraph, name)?.op.as_str();
+                    let mut values = vec![];
+                    for t in 0..=time {
+                        match interpret_helper(egraph, expr, t, env, partial, cache)? {
+                            Some(InterpreterResult::Bitvector(value, _)) => values.push(value),
+                            None => break,
                         }
-                        _ => todo!(),
                     }
+                    module_env.insert(&name[1..name.len() - 1], values);
                 }
-                _ => todo!("unimplemented op: {:?}", op.op),
             }
+
+            return interpret_helper(
+                egraph,
+                &egraph[&output.children[3]].eclass,
+                time,
+                &module_env,
+                partial,
+                &mut HashMap::default(),
+            )
+            .map(|result| {
+                if let Some(result) = &result {
+                    cache.insert((id.clone(), time), result.clone());
+                }
+                result
+            });
         }
         _ => todo!("unimplemented node type: {:?}", node.op),
     };
@@ -441,1352 +734,11265 @@ fn interpret_helper(
     if result.is_ok() {
         cache.insert((id.clone(), time), result.clone().unwrap());
     }
-    result
+    result.map(Some)
 }
 
-#[derive(Default)]
-pub struct AnythingExtractor;
-impl AnythingExtractor {
-    pub fn extract(
-        &self,
-        egraph: &egraph_serialize::EGraph,
-        _roots: &[egraph_serialize::ClassId],
-    ) -> IndexMap<egraph_serialize::ClassId, egraph_serialize::NodeId> {
-        egraph
-            .classes()
-            .iter()
-            .map(|(id, class)| {
-                let node_id = class.nodes.first().unwrap().clone();
-                (id.clone(), node_id)
-            })
-            .collect()
-    }
-}
-
-pub fn to_verilog_egraph_serialize(
+/// The eclasses of the elements of a `StringCons`/`StringNil` or
+/// `ExprCons`/`ExprNil` list.
+fn cons_list_classes(
     egraph: &egraph_serialize::EGraph,
-    choices: &IndexMap<egraph_serialize::ClassId, egraph_serialize::NodeId>,
-    clk_name: &str,
-) -> String {
-    // let mut wires = HashMap::default();
-
-    fn id_to_wire_name(id: &ClassId) -> String {
-        format!("wire_{}", id)
+    id: &ClassId,
+) -> Result<Vec<ClassId>, String> {
+    let mut classes = vec![];
+    let mut node = get_single_node(egraph, id)?;
+    while matches!(node.op.as_str(), "StringCons" | "ExprCons") {
+        classes.push(egraph[&node.children[0]].eclass.clone());
+        node = get_single_node(egraph, &egraph[&node.children[1]].eclass)?;
     }
-
-    struct ModuleInstance {
-        module_class_name: String,
-        instance_name: String,
-        parameters: HashMap<String, ClassId>,
-        inputs: HashMap<String, ClassId>,
-        outputs: HashMap<String, ClassId>,
+    match node.op.as_str() {
+        "StringNil" | "ExprNil" => Ok(classes),
+        op => Err(format!("Expected a list, found {:?}.", op)),
     }
-    // Maps EClass ID to the module instance at that class.
-    let mut module_instantiations: HashMap<ClassId, ModuleInstance> = HashMap::new();
-
-    let mut inputs = String::new();
-    let mut outputs = String::new();
-    let mut logic_declarations = String::new();
-    let mut registers = String::new();
+}
 
-    // Collect all the outputs.
-    let mut queue: Vec<ClassId> = egraph
-        .nodes
-        .iter()
-        .filter_map(|(_id, node)| {
-            // op should be IsPort
-            let op = &node.op;
-            if op != "IsPort" {
-                return None;
+/// Interpret a single (combinational) op, given the already-interpreted values
+/// of its children.
+///
+/// - node: the `Op0`/`Op1`/`Op2`/`Op3` node being interpreted.
+/// - op: the op node, i.e. the first child of `node`.
+/// - children: the values of the remaining children of `node`.
+fn interpret_op(
+    egraph: &egraph_serialize::EGraph,
+    node: &Node,
+    op: &Node,
+    children: &[InterpreterResult],
+) -> Result<InterpreterResult, String> {
+    match op.op.as_str() {
+        // Binary operations that condense to a single bit.
+        "Eq" | "LogicOr" | "LogicAnd" | "Ne" => {
+            assert_eq!(children.len(), 2);
+            let InterpreterResult::Bitvector(a, _) = children[0];
+            let InterpreterResult::Bitvector(b, _) = children[1];
+            let result = match op.op.as_str() {
+                "Eq" => a == b,
+                "Ne" => a != b,
+                "LogicOr" => a != 0 || b != 0,
+                // if any of the children are false, the result is false
+                "LogicAnd" => a != 0 && b != 0,
+                _ => unreachable!(),
+            };
+            Ok(InterpreterResult::Bitvector(result as u64, 1))
+        }
+        // Unary operations that condense to a single bit.
+        "ReduceOr" | "ReduceAnd" | "LogicNot" => {
+            assert_eq!(children.len(), 1);
+            let InterpreterResult::Bitvector(val, bw) = children[0];
+            let result = match op.op.as_str() {
+                "ReduceOr" => val != 0,
+                // if any bit of children[0] is 0, the result is 0
+                "ReduceAnd" => val == truncate_value_to_bitwidth(u64::MAX, bw),
+                "LogicNot" => val == 0,
+                _ => unreachable!(),
+            };
+            Ok(InterpreterResult::Bitvector(result as u64, 1))
+        }
+        // Unary operations that preserve bitwidth.
+        "Not" => {
+            assert_eq!(children.len(), 1);
+            let InterpreterResult::Bitvector(val, bw) = children[0];
+            Ok(InterpreterResult::Bitvector(
+                truncate_value_to_bitwidth(!val, bw),
+                bw,
+            ))
+        }
+        // Rotations, with the amount taken modulo the width.
+        "Rotl" | "Rotr" => {
+            assert_eq!(children.len(), 2);
+            let InterpreterResult::Bitvector(a, bw) = children[0];
+            let InterpreterResult::Bitvector(b, _) = children[1];
+            let left_amount = match op.op.as_str() {
+                "Rotl" => b % bw,
+                "Rotr" => (bw - b % bw) % bw,
+                _ => unreachable!(),
+            } as u32;
+            // Shifting right by the full width gives zero.
+            let result = (a << left_amount) | a.checked_shr(bw as u32 - left_amount).unwrap_or(0);
+            Ok(InterpreterResult::Bitvector(
+                truncate_value_to_bitwidth(result, bw),
+                bw,
+            ))
+        }
+        // Binary operations that preserve bitwidth.
+        "And" | "Or" | "Shl" | "Shr" | "Xor" | "Add" | "Sub" | "Mul" => {
+            assert_eq!(children.len(), 2);
+            let InterpreterResult::Bitvector(a, a_bw) = children[0];
+            let InterpreterResult::Bitvector(b, b_bw) = children[1];
+            // Only the shift amount may have a different width.
+            if !matches!(op.op.as_str(), "Shl" | "Shr") {
+                assert_eq!(a_bw, b_bw);
             }
-
-            assert_eq!(node.children.len(), 4);
-
-            if egraph[&node.children[2]].op != "Output" {
-                return None;
+            let result = match op.op.as_str() {
+                "And" => a & b,
+                "Or" => a | b,
+                "Shl" => a.checked_shl(b.try_into().unwrap_or(u32::MAX)).unwrap_or(0),
+                "Shr" => a.checked_shr(b.try_into().unwrap_or(u32::MAX)).unwrap_or(0),
+                "Xor" => a ^ b,
+                "Add" => a.wrapping_add(b),
+                "Sub" => a.wrapping_sub(b),
+                "Mul" => a.wrapping_mul(b),
+                _ => unreachable!(),
+            };
+            Ok(InterpreterResult::Bitvector(
+                truncate_value_to_bitwidth(result, a_bw),
+                a_bw,
+            ))
+        }
+        // Division and remainder, masked to the operands' width. Dividing by
+        // zero gives zero, as most FPGA synthesis tools do, and the remainder
+        // of dividing by zero is the dividend.
+        "UDiv" | "URem" => {
+            assert_eq!(children.len(), 2);
+            let InterpreterResult::Bitvector(a, a_bw) = children[0];
+            let InterpreterResult::Bitvector(b, b_bw) = children[1];
+            assert_eq!(a_bw, b_bw);
+            let result = match op.op.as_str() {
+                "UDiv" => a.checked_div(b).unwrap_or(0),
+                "URem" => a.checked_rem(b).unwrap_or(a),
+                _ => unreachable!(),
+            };
+            Ok(InterpreterResult::Bitvector(
+                truncate_value_to_bitwidth(result, a_bw),
+                a_bw,
+            ))
+        }
+        // Signed operations, on two's complement operands.
+        "SMul" | "SDiv" | "SRem" | "Ashr" | "SLt" | "SLe" | "SGt" | "SGe" => {
+            assert_eq!(children.len(), 2);
+            let InterpreterResult::Bitvector(a, a_bw) = children[0];
+            let InterpreterResult::Bitvector(b, b_bw) = children[1];
+            let a = sign_extend_value(a, a_bw);
+            let (result, bw) = match op.op.as_str() {
+                "SMul" => (a.wrapping_mul(sign_extend_value(b, b_bw)) as u64, a_bw),
+                // Dividing the most negative value by -1 overflows back to the
+                // most negative value. Dividing by zero is as for UDiv and
+                // URem. The i64 results are masked back to two's complement
+                // at the operands' width below.
+                "SDiv" => match sign_extend_value(b, b_bw) {
+                    0 => (0, a_bw),
+                    b => (a.wrapping_div(b) as u64, a_bw),
+                },
+                "SRem" => match sign_extend_value(b, b_bw) {
+                    0 => (a as u64, a_bw),
+                    b => (a.wrapping_rem(b) as u64, a_bw),
+                },
+                // The shift amount is unsigned, and shifting by the width or
+                // more leaves only copies of the sign bit.
+                "Ashr" => ((a >> b.min(63)) as u64, a_bw),
+                "SLt" => ((a < sign_extend_value(b, b_bw)) as u64, 1),
+                "SLe" => ((a <= sign_extend_value(b, b_bw)) as u64, 1),
+                "SGt" => ((a > sign_extend_value(b, b_bw)) as u64, 1),
+                "SGe" => ((a >= sign_extend_value(b, b_bw)) as u64, 1),
+                _ => unreachable!(),
+            };
+            Ok(InterpreterResult::Bitvector(
+                truncate_value_to_bitwidth(result, bw),
+                bw,
+            ))
+        }
+        "SignExtend" => {
+            let extension_bw: u64 = egraph[&op.children[0]].op.parse().unwrap();
+            assert!(extension_bw <= 64);
+            let InterpreterResult::Bitvector(val, bw) = children[0];
+            Ok(InterpreterResult::Bitvector(
+                truncate_value_to_bitwidth(sign_extend_value(val, bw) as u64, extension_bw),
+                extension_bw,
+            ))
+        }
+        // Multi-hot selects are an error, as different tools resolve them
+        // differently.
+        "Pmux" => {
+            assert_eq!(children.len(), 3);
+            let InterpreterResult::Bitvector(sel, _) = children[0];
+            let InterpreterResult::Bitvector(_, bw) = children[1];
+            let InterpreterResult::Bitvector(cases, _) = children[2];
+            match sel.count_ones() {
+                0 => Ok(children[1].clone()),
+                1 => Ok(InterpreterResult::Bitvector(
+                    truncate_value_to_bitwidth(cases >> (sel.trailing_zeros() as u64 * bw), bw),
+                    bw,
+                )),
+                _ => Err(format!("Pmux select {:#b} is not one-hot.", sel)),
             }
-
-            Some(egraph[&node.children[3]].eclass.clone())
-        })
-        .collect();
-
-    // Generate outputs.
-    for (_, node) in egraph.nodes.iter() {
-        // op should be IsPort
-        let op = &node.op;
-        if op != "IsPort" {
-            continue;
         }
+        "Mux" => {
+            assert_eq!(children.len(), 3);
+            let InterpreterResult::Bitvector(cond, _) = children[0];
+            if cond == 0 {
+                Ok(children[1].clone())
+            } else {
+                Ok(children[2].clone())
+            }
+        }
+        // Signed constants have the same bits as unsigned ones.
+        "BV" | "SignedBV" => {
+            assert_eq!(op.children.len(), 2);
+            let args = &op
+                .children
+                .iter()
+                .map(|id| {
+                    let node = egraph.nodes.get(id).unwrap();
+                    assert_eq!(node.children.len(), 0);
+                    // TODO(@ninehusky): here, reading node.op.parse() as i64, then convert to u64
+                    let val: i64 = node.op.parse().unwrap();
+                    val as u64
+                })
+                .collect::<Vec<_>>()[..];
 
-        assert_eq!(node.children.len(), 4);
-
-        if egraph[&node.children[2]].op != "Output" {
-            continue;
+            assert!(args[1] <= 64);
+            Ok(InterpreterResult::Bitvector(args[0], args[1]))
         }
+        "Extract" => {
+            assert_eq!(op.children.len(), 2);
+            let args = &op
+                .children
+                .iter()
+                .map(|id| {
+                    let node = egraph.nodes.get(id).unwrap();
+                    assert_eq!(node.children.len(), 0);
+                    let val: u64 = node.op.parse().unwrap();
+                    val
+                })
+                .collect::<Vec<_>>()[..];
+
+            let i = args[0];
+            let j = args[1];
+
+            let InterpreterResult::Bitvector(val, bw) = children[0];
+            // from Rosette docs:
+            // https://docs.racket-lang.org/rosette-guide/sec_bitvectors.html#%28def._%28%28lib._rosette%2Fbase%2Fbase..rkt%29._extract%29%29
+            // TODO(@ninehusky): here, we should also assert that j >= 0 if churchroad handles signed numbers
+            assert!(
+                bw > i && i >= j,
+                "i is {}, j is {} node has bw {}, has node_id {:?}",
+                i,
+                j,
+                bw,
+                node.children[1]
+            );
+            assert!(i - j < 64);
 
-        outputs.push_str(&format!(
-            "output {name},\n",
-            name = egraph[&node.children[1]]
-                .op
-                .as_str()
-                .strip_prefix('\"')
+            Ok(InterpreterResult::Bitvector(
+                truncate_value_to_bitwidth(val >> j, i - j + 1),
+                i - j + 1,
+            ))
+        }
+        "Concat" => {
+            // The first child holds the high bits; see the definition of
+            // Concat in churchroad.egg.
+            assert_eq!(children.len(), 2);
+            let InterpreterResult::Bitvector(a, a_bw) = children[0];
+            let InterpreterResult::Bitvector(b, b_bw) = children[1];
+            assert!(a_bw + b_bw <= 64);
+            let result = a.checked_shl(b_bw.try_into().unwrap()).unwrap_or(0) | b;
+            Ok(InterpreterResult::Bitvector(result, a_bw + b_bw))
+        }
+        "ZeroExtend" => {
+            let extension_bw: u64 = egraph
+                .nodes
+                .get(&op.children[0])
                 .unwrap()
-                .strip_suffix('\"')
+                .op
+                .parse()
+                .unwrap();
+            assert!(extension_bw <= 64);
+            let InterpreterResult::Bitvector(val, _) = children[0];
+            Ok(InterpreterResult::Bitvector(val, extension_bw))
+        }
+        "LUT" => {
+            assert_eq!(op.children.len(), 2);
+            assert_eq!(children.len(), 1);
+            let k: u64 = egraph
+                .nodes
+                .get(&op.children[0])
                 .unwrap()
-        ));
-
-        logic_declarations.push_str(&format!(
-            "logic {name} = {wire};\n",
-            name = egraph[&node.children[1]]
                 .op
-                .as_str()
-                .strip_prefix('\"')
+                .parse()
+                .unwrap();
+            // A LUT6's INIT uses all 64 bits, so it may be stored as a
+            // negative i64.
+            let init: i64 = egraph
+                .nodes
+                .get(&op.children[1])
                 .unwrap()
-                .strip_suffix('\"')
-                .unwrap(),
-            wire = id_to_wire_name(&egraph[&node.children[3]].eclass)
-        ))
+                .op
+                .parse()
+                .unwrap();
+            let InterpreterResult::Bitvector(inputs, bw) = children[0];
+            assert!(k <= 6, "LUTs have at most 6 inputs, found {}", k);
+            assert_eq!(bw, k, "LUT{} given {} bits of input", k, bw);
+            Ok(InterpreterResult::Bitvector((init as u64 >> inputs) & 1, 1))
+        }
+        _ => todo!("unimplemented op: {:?}", op.op),
     }
+}
 
-    let mut done = HashSet::new();
+// The result of interpreting a Churchroad program with `interpret_wide`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum InterpreterResultWide {
+    // Bitvector(little-endian 64-bit limbs, bitwidth)
+    Bitvector(Vec<u64>, u32),
+}
 
-    fn maybe_push_expr_on_queue(
-        queue: &mut Vec<ClassId>,
-        done: &HashSet<ClassId>,
-        class_id: &ClassId,
-    ) {
-        if !queue.contains(class_id) && !done.contains(class_id) {
-            queue.push(class_id.clone());
+impl InterpreterResultWide {
+    /// A bitvector of width `bw` holding `limbs`, truncated or zero-extended
+    /// to fit.
+    fn new(mut limbs: Vec<u64>, bw: u32) -> Self {
+        limbs.resize(bw.div_ceil(64) as usize, 0);
+        if bw % 64 != 0 {
+            if let Some(last) = limbs.last_mut() {
+                *last &= (1 << (bw % 64)) - 1;
+            }
         }
+        InterpreterResultWide::Bitvector(limbs, bw)
     }
 
-    while let Some(id) = queue.pop() {
-        done.insert(id.clone());
-        let term = &egraph[&choices[&id]];
+    fn limbs(&self) -> &[u64] {
+        let InterpreterResultWide::Bitvector(limbs, _) = self;
+        limbs
+    }
 
-        let op = &term.op;
-        match op.as_str() {
-            // Things to ignore.
-            //
-            // Ignore the Unit.
-            "()" |
-            // Ignore various relations/facts.
-            "IsPort" |
-            "Input" |
-            "Output" |
-            // Ignore the nodes for the ops themselves.
-            "ZeroExtend" |
-            "Concat" |
-            "Extract" |
-            "Or" |
-            "And" |
-            "Add" |
-            "Shr" |
-            "Eq" |
-            "Xor" |
-            "Reg" => (),
-            // Ignore integer literals.
-            v if v.parse::<i64>().is_ok() => (),
+    fn bw(&self) -> u32 {
+        let InterpreterResultWide::Bitvector(_, bw) = self;
+        *bw
+    }
 
-            "Op0" | "Op1" | "Op2" => {
-                let op_node = &egraph[&term.children[0]];
-                match op_node.op.as_str() {
-                    "ZeroExtend" => {
-                        assert_eq!(op_node.children.len(), 1);
-                        assert_eq!(term.children.len(), 2);
-                        let bw = egraph[&op_node.children[0]].op.parse::<i64>().unwrap();
-                    logic_declarations.push_str(
-                        format!(
-                            "logic [{bw}-1:0] {this_wire} = {bw}'d{value};\n",
-                            this_wire = id_to_wire_name(&id),
-                            value = id_to_wire_name(&egraph[&term.children[1]].eclass)
+    fn is_zero(&self) -> bool {
+        self.limbs().iter().all(|limb| *limb == 0)
+    }
 
-                        )
-                        .as_str(),
-                    );
+    /// The value as a shift amount, saturating at `u64::MAX`.
+    fn to_shift_amount(&self) -> u64 {
+        match self.limbs() {
+            [] => 0,
+            [low, high @ ..] if high.iter().all(|limb| *limb == 0) => *low,
+            _ => u64::MAX,
+        }
+    }
 
-                    }
-                    "BV" => {
-                        assert_eq!(op_node.children.len(), 2);
-                        let value = egraph[&op_node.children[0]].op.parse::<i64>().unwrap();
-                        let bw = egraph[&op_node.children[1]].op.parse::<i64>().unwrap();
+    /// The value shifted right by `amount` bits, at the same width.
+    fn shr(&self, amount: u64) -> Self {
+        let limbs = self.limbs();
+        let skip = usize::try_from(amount / 64).unwrap_or(usize::MAX);
+        let bits = (amount % 64) as u32;
+        let limb = |i: usize| {
+            i.checked_add(skip)
+                .and_then(|i| limbs.get(i))
+                .copied()
+                .unwrap_or(0)
+        };
+        let shifted = (0..limbs.len())
+            .map(|i| match bits {
+                0 => limb(i),
+                _ => (limb(i) >> bits) | (limb(i + 1) << (64 - bits)),
+            })
+            .collect();
+        Self::new(shifted, self.bw())
+    }
 
-                    logic_declarations.push_str(
-                        format!(
-                            "logic [{bw}-1:0] {this_wire} = {bw}'d{value};\n",
-                            this_wire = id_to_wire_name(&id),
-                        )
-                        .as_str(),
-                    );
-                    }
-                    "Reg" => {
-                        let default_val = egraph[&op_node.children[0]].op.parse::<i64>().unwrap();
-                        let d_id = &egraph[&term.children[1]].eclass;
+    /// The value shifted left by `amount` bits, at width `bw`.
+    fn shl(&self, amount: u64, bw: u32) -> Self {
+        let limbs = self.limbs();
+        let skip = usize::try_from(amount / 64).unwrap_or(usize::MAX);
+        let bits = (amount % 64) as u32;
+        let limb = |i: usize| {
+            i.checked_sub(skip)
+                .and_then(|i| limbs.get(i))
+                .copied()
+                .unwrap_or(0)
+        };
+        let shifted = (0..bw.div_ceil(64) as usize)
+            .map(|i| match (bits, i) {
+                (0, _) => limb(i),
+                (_, 0) => limb(0) << bits,
+                _ => (limb(i) << bits) | (limb(i - 1) >> (64 - bits)),
+            })
+            .collect();
+        Self::new(shifted, bw)
+    }
 
+    /// The sum of two values and a carry in, at the width of the first.
+    fn add(&self, other: &Self, carry: bool) -> Self {
+        let mut carry = carry as u64;
+        let sum = self
+            .limbs()
+            .iter()
+            .zip(other.limbs().iter().chain(std::iter::repeat(&0)))
+            .map(|(a, b)| {
+                let (sum, overflow_a) = a.overflowing_add(*b);
+                let (sum, overflow_b) = sum.overflowing_add(carry);
+                carry = (overflow_a || overflow_b) as u64;
+                sum
+            })
+            .collect();
+        Self::new(sum, self.bw())
+    }
 
-                    logic_declarations.push_str(
-                        format!(
-                            "logic {this_wire} = {default};\n",
-                            this_wire = id_to_wire_name(&id),
-                            default = default_val
-                        )
-                        .as_str(),
-                    );
+    /// The value with its bits inverted.
+    fn not(&self) -> Self {
+        Self::new(self.limbs().iter().map(|limb| !limb).collect(), self.bw())
+    }
 
-                    registers.push_str(&format!(
-                        "always @(posedge {clk_name}) begin
-                            {this_wire} <= {d};
-                        end\n",
-                        // clk = id_to_wire_name(clk_id),
-                        this_wire = id_to_wire_name(&id),
-                        d = id_to_wire_name(d_id)
-                    ));
+    fn narrow(&self) -> Option<InterpreterResult> {
+        (self.bw() <= 64).then(|| {
+            InterpreterResult::Bitvector(
+                self.limbs().first().copied().unwrap_or(0),
+                self.bw().into(),
+            )
+        })
+    }
+}
 
-                    if !done.contains(d_id) {
-                        queue.push(d_id.clone());
-                    }
-                    },
-                    "Concat" | "Xor" |"And" | "Or" =>  {
-                            assert_eq!(term.children.len(), 3);
-                    let expr0_id = &egraph[&term.children[1]].eclass;
-                    let  expr1_id = &egraph[&term.children[2]].eclass;
-                    logic_declarations.push_str(&format!(
-                        "logic {this_wire} = {op};\n",
-                        op = match op_node.op.as_str() {
+/// Like [`interpret`], but for designs with signals wider than 64 bits.
+/// Values, including those of the inputs in `env`, are given as little-endian
+/// 64-bit limbs.
+///
+/// Bitwise ops, `Concat`, `Extract`, extensions, shifts, comparisons for
+/// equality, `Add`, `Sub` and `Mux` work at any width. Other ops are
+/// interpreted as by [`interpret`], so their operands must be no wider than
+/// 64 bits.
+///
+/// ```
+/// use churchroad::*;
+/// use egglog::{EGraph, SerializeConfig};
+///
+/// let mut egraph = EGraph::default();
+/// import_churchroad(&mut egraph).unwrap();
+/// egraph
+///     .parse_and_run_program(
+///         r#"
+/// (let a (Var "a" 128))
+/// (let b (Var "b" 128))
+/// (IsPort "" "sum" (Output) (Op2 (Add) a b))
+/// (run-schedule (saturate typing))
+/// "#,
+///     )
+///     .unwrap();
+///
+/// let serialized = egraph.serialize(SerializeConfig::default());
+/// let (_, outputs) = get_inputs_and_outputs_serialized(&serialized);
+/// let env = [("a", vec![vec![u64::MAX, 0]]), ("b", vec![vec![1, 1]])].into();
+///
+/// assert_eq!(
+///     interpret_wide(&serialized, &outputs[0].1, 0, &env),
+///     Ok(InterpreterResultWide::Bitvector(vec![0, 2], 128))
+/// );
+/// ```
+pub fn interpret_wide(
+    egraph: &egraph_serialize::EGraph,
+    class_id: &ClassId,
+    time: usize,
+    env: &HashMap<&str, Vec<Vec<u64>>>,
+) -> Result<InterpreterResultWide, String> {
+    if !egraph.classes().contains_key(class_id) {
+        return Err("No class with the given ID.".to_string());
+    }
+    interpret_wide_helper(egraph, class_id, time, env, &mut HashMap::default())
+}
 
-                            "Concat" => format!("{{ {expr0}, {expr1} }}",
-                        expr0 = id_to_wire_name(expr0_id),
-                        expr1 = id_to_wire_name(expr1_id),
-                        ),
-                            "Xor" => format!("{expr0}^{expr1}",
-                        expr0 = id_to_wire_name(expr0_id),
-                        expr1 = id_to_wire_name(expr1_id),
-                        ),
-                            "And" => format!("{expr0}&{expr1}",
-                        expr0 = id_to_wire_name(expr0_id),
-                        expr1 = id_to_wire_name(expr1_id),
-                        ),
-                            "Or" => format!("{expr0}|{expr1}",
-                        expr0 = id_to_wire_name(expr0_id),
-                        expr1 = id_to_wire_name(expr1_id),
-                        ),
-                        _ => unreachable!("missing a match arm"),
-                        } ,
-                        this_wire = id_to_wire_name(&term.eclass),
-                    ));
+fn interpret_wide_helper(
+    egraph: &egraph_serialize::EGraph,
+    id: &ClassId,
+    time: usize,
+    env: &HashMap<&str, Vec<Vec<u64>>>,
+    cache: &mut HashMap<(ClassId, usize), InterpreterResultWide>,
+) -> Result<InterpreterResultWide, String> {
+    if let Some(result) = cache.get(&(id.clone(), time)) {
+        return Ok(result.clone());
+    }
+    let node = get_single_node(egraph, id)?;
+
+    let input = |name: &str, time: usize| {
+        env.get(name)
+            .ok_or_else(|| format!("didn't find var {:?}", name))?
+            .get(time)
+            .cloned()
+            .ok_or_else(|| format!("no value for {:?} at time {:?}", name, time))
+    };
 
-                    maybe_push_expr_on_queue(&mut queue, &done, expr0_id);
-                    maybe_push_expr_on_queue(&mut queue, &done, expr1_id);
+    let result = match node.op.as_str() {
+        "Var" => {
+            let (name, bw) = get_var_name_and_bitwidth(egraph, node);
+            InterpreterResultWide::new(input(name, time)?, bw as u32)
+        }
+        "Op0" | "Op1" | "Op2" | "Op3" => {
+            let op = &egraph[&node.children[0]];
+            match op.op.as_str() {
+                "Mem" | "MemRead" | "ShiftReg" => {
+                    return Err(format!(
+                        "{} is only supported by the stateful Interpreter.",
+                        op.op
+                    ))
                 }
-                "Extract" => {//}, [hi_id, lo_id, expr_id]) => {
-                    assert_eq!(term.children.len(), 2);
-                    assert_eq!(op_node.children.len(), 2);
-                    let hi:i64 = egraph[&op_node.children[0]].op.parse().unwrap();
-                    let lo:i64 = egraph[&op_node.children[1]].op.parse().unwrap();
-                    let id = &term.eclass;
-                    let expr_id = &egraph[&term.children[1]].eclass;
-                    logic_declarations.push_str(&format!(
-                        "logic {this_wire} = {expr}[{hi}:{lo}];\n",
-                        hi = hi,
-                        lo = lo,
-                        this_wire = id_to_wire_name(id),
-                        expr = id_to_wire_name(expr_id),
-                    ));
-
-                    maybe_push_expr_on_queue(&mut queue, &done, expr_id);
+                "Reg" | "RegWithClk" => {
+                    let mut clk_at = |time: usize| -> Result<bool, String> {
+                        if node.children.len() == 3 {
+                            let clk = &egraph[&node.children[1]].eclass;
+                            Ok(!interpret_wide_helper(egraph, clk, time, env, cache)?.is_zero())
+                        } else {
+                            Err("Reg without a clock expression can't be interpreted.".to_string())
+                        }
+                    };
+                    let d = &egraph[node.children.last().unwrap()].eclass;
+                    if time == 0 {
+                        assert!(
+                            !clk_at(0)?,
+                            "We don't currently know what to do when clk=1 at time 0! See #88"
+                        );
+                        let init: i64 = egraph[op.children.last().unwrap()].op.parse().unwrap();
+                        let bw = get_bitwidth(egraph, d)
+                            .ok_or_else(|| format!("Class {:?} is untyped.", d))?;
+                        // Negative initial values fill every limb with ones.
+                        let fill = if init < 0 { u64::MAX } else { 0 };
+                        let mut limbs = vec![fill; (bw as u32).div_ceil(64) as usize];
+                        if let Some(low) = limbs.first_mut() {
+                            *low = init as u64;
+                        }
+                        InterpreterResultWide::new(limbs, bw as u32)
+                    } else if !clk_at(time - 1)? && clk_at(time)? {
+                        interpret_wide_helper(egraph, d, time - 1, env, cache)?
+                    } else {
+                        interpret_wide_helper(egraph, id, time - 1, env, cache)?
+                    }
                 }
-
-                v => todo!("{:?}", v),
-
+                _ => {
+                    let children = node.children[1..]
+                        .iter()
+                        .map(|child| {
+                            interpret_wide_helper(egraph, &egraph[child].eclass, time, env, cache)
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    interpret_wide_op(egraph, node, op, &children)?
                 }
+            }
+        }
+        _ => return Err(format!("unimplemented node type: {:?}", node.op)),
+    };
+
+    cache.insert((id.clone(), time), result.clone());
+    Ok(result)
+}
 
+/// Interpret a single (combinational) op over values of any width, falling
+/// back to [`interpret_op`] for ops without wide implementations.
+fn interpret_wide_op(
+    egraph: &egraph_serialize::EGraph,
+    node: &Node,
+    op: &Node,
+    children: &[InterpreterResultWide],
+) -> Result<InterpreterResultWide, String> {
+    let int = |i: usize| -> i64 { egraph[&op.children[i]].op.parse().unwrap() };
+    let bitwise = |f: fn(u64, u64) -> u64| {
+        let limbs = children[0]
+            .limbs()
+            .iter()
+            .zip(children[1].limbs())
+            .map(|(a, b)| f(*a, *b))
+            .collect();
+        InterpreterResultWide::new(limbs, children[0].bw())
+    };
+    let bit = |value: bool| InterpreterResultWide::new(vec![value as u64], 1);
+
+    Ok(match op.op.as_str() {
+        "Not" => children[0].not(),
+        "And" => bitwise(|a, b| a & b),
+        "Or" => bitwise(|a, b| a | b),
+        "Xor" => bitwise(|a, b| a ^ b),
+        "Add" => children[0].add(&children[1], false),
+        "Sub" => children[0].add(&children[1].not(), true),
+        "Shl" => children[0].shl(children[1].to_shift_amount(), children[0].bw()),
+        "Shr" => children[0].shr(children[1].to_shift_amount()),
+        "Eq" => bit(children[0] == children[1]),
+        "Ne" => bit(children[0] != children[1]),
+        "ReduceOr" => bit(!children[0].is_zero()),
+        "ReduceAnd" => bit(children[0].not().is_zero()),
+        "LogicNot" => bit(children[0].is_zero()),
+        "LogicAnd" => bit(!children[0].is_zero() && !children[1].is_zero()),
+        "LogicOr" => bit(!children[0].is_zero() || !children[1].is_zero()),
+        "Mux" => match children[0].is_zero() {
+            true => children[1].clone(),
+            false => children[2].clone(),
+        },
+        // The first child holds the high bits, as for `interpret`.
+        "Concat" => {
+            let bw = children[0].bw() + children[1].bw();
+            let high = children[0].shl(children[1].bw().into(), bw);
+            let low = InterpreterResultWide::new(children[1].limbs().to_vec(), bw);
+            InterpreterResultWide::new(
+                high.limbs()
+                    .iter()
+                    .zip(low.limbs())
+                    .map(|(a, b)| a | b)
+                    .collect(),
+                bw,
+            )
+        }
+        "Extract" => {
+            let (i, j) = (int(0) as u32, int(1) as u32);
+            let bw = children[0].bw();
+            if !(bw > i && i >= j) {
+                return Err(format!("Can't extract bits {i}:{j} of a {bw}-bit value."));
+            }
+            let shifted = children[0].shr(j.into());
+            InterpreterResultWide::new(shifted.limbs().to_vec(), i - j + 1)
+        }
+        "ZeroExtend" => InterpreterResultWide::new(children[0].limbs().to_vec(), int(0) as u32),
+        "SignExtend" => {
+            let bw = int(0) as u32;
+            let value = InterpreterResultWide::new(children[0].limbs().to_vec(), bw);
+            let from_bw = children[0].bw();
+            if children[0].shr((from_bw - 1).into()).is_zero() {
+                value
+            } else {
+                let ones = InterpreterResultWide::new(vec![u64::MAX; bw.div_ceil(64) as usize], bw)
+                    .shl(from_bw.into(), bw);
+                value.add(&ones, false)
+            }
+        }
+        // Signed constants are sign extended, unsigned ones zero extended.
+        "BV" | "SignedBV" => {
+            let (value, bw) = (int(0), int(1) as u32);
+            let fill = if op.op == "SignedBV" && value < 0 {
+                u64::MAX
+            } else {
+                0
+            };
+            let mut limbs = vec![fill; bw.div_ceil(64) as usize];
+            if let Some(low) = limbs.first_mut() {
+                *low = value as u64;
             }
+            InterpreterResultWide::new(limbs, bw)
+        }
+        _ => {
+            let children = children
+                .iter()
+                .map(InterpreterResultWide::narrow)
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| {
+                    format!("{} is not supported on values wider than 64 bits.", op.op)
+                })?;
+            let InterpreterResult::Bitvector(value, bw) =
+                interpret_op(egraph, node, op, &children)?;
+            InterpreterResultWide::new(vec![value], bw as u32)
+        }
+    })
+}
 
-                "Var" => {//}, [name_id, bw_id]) => {
-                    assert_eq!(term.children.len(), 2);
+/// An abstract value of a bitvector signal, as computed by
+/// [`symbolic_interpret`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BitvectorDomain {
+    /// Exactly this value.
+    Concrete(u64),
+    /// Any value of the signal's width.
+    AnyValue,
+    /// Any value between the two bounds, inclusive.
+    Range(u64, u64),
+}
 
-                        let name = egraph[&term.children[0]].op.as_str().strip_prefix('\"').unwrap().strip_suffix('\"').unwrap();
-                        let bw: i64 = egraph[&term.children[1]].op.parse().unwrap();
+impl BitvectorDomain {
+    /// The smallest domain holding the values from `lo` to `hi` of a signal
+    /// of width `bw`.
+    fn from_bounds(lo: u64, hi: u64, bw: u64) -> Self {
+        if lo == hi {
+            BitvectorDomain::Concrete(lo)
+        } else if lo == 0 && hi == truncate_value_to_bitwidth(u64::MAX, bw) {
+            BitvectorDomain::AnyValue
+        } else {
+            BitvectorDomain::Range(lo, hi)
+        }
+    }
 
-                    inputs.push_str(
-                        format!("input [{bw}-1:0] {name},\n", bw = bw, name = name).as_str(),
-                    );
+    /// The least and greatest values in the domain, for a signal of width
+    /// `bw`.
+    fn bounds(self, bw: u64) -> (u64, u64) {
+        match self {
+            BitvectorDomain::Concrete(value) => (value, value),
+            BitvectorDomain::AnyValue => (0, truncate_value_to_bitwidth(u64::MAX, bw)),
+            BitvectorDomain::Range(lo, hi) => (lo, hi),
+        }
+    }
 
-                    logic_declarations.push_str(
-                        format!(
-                            "logic [{bw}-1:0] {this_wire} = {name};\n",
-                            bw = bw,
-                            this_wire = id_to_wire_name(&term.eclass),
-                            name = name
+    /// The smallest domain holding the values of both domains.
+    fn join(self, other: Self, bw: u64) -> Self {
+        let (a_lo, a_hi) = self.bounds(bw);
+        let (b_lo, b_hi) = other.bounds(bw);
+        Self::from_bounds(a_lo.min(b_lo), a_hi.max(b_hi), bw)
+    }
+}
+
+/// Interprets a Churchroad program over abstract values rather than concrete
+/// ones, finding the values `class_id` can take given the values its inputs
+/// can take. Inputs missing from `env` can take any value.
+///
+/// Registers can take any value they hold at any time, regardless of their
+/// clocks, so their values are computed as a fixed point starting from their
+/// initial values. A register whose value keeps growing is widened to
+/// [`BitvectorDomain::AnyValue`]. The design must have been typed.
+///
+/// ```
+/// use churchroad::*;
+/// use egglog::{EGraph, SerializeConfig};
+///
+/// let mut egraph = EGraph::default();
+/// import_churchroad(&mut egraph).unwrap();
+/// egraph
+///     .parse_and_run_program(
+///         r#"
+/// (let a (Var "a" 8))
+/// (let b (Var "b" 8))
+/// (IsPort "" "sum" (Output) (Op2 (Add) a (Op0 (BV 5 8))))
+/// (IsPort "" "masked" (Output) (Op2 (And) b (Op0 (BV 0 8))))
+/// (run-schedule (saturate typing))
+/// "#,
+///     )
+///     .unwrap();
+///
+/// let serialized = egraph.serialize(SerializeConfig::default());
+/// let (_, outputs) = get_inputs_and_outputs_serialized(&serialized);
+/// let env = [("a", BitvectorDomain::Range(0, 10))].into();
+///
+/// assert_eq!(
+///     symbolic_interpret(&serialized, &outputs[0].1, &env),
+///     Ok(BitvectorDomain::Range(5, 15))
+/// );
+/// assert_eq!(
+///     symbolic_interpret(&serialized, &outputs[1].1, &env),
+///     Ok(BitvectorDomain::Concrete(0))
+/// );
+/// ```
+pub fn symbolic_interpret(
+    egraph: &egraph_serialize::EGraph,
+    class_id: &ClassId,
+    env: &HashMap<&str, BitvectorDomain>,
+) -> Result<BitvectorDomain, String> {
+    /// How many times a register's value may grow before it's widened.
+    const MAX_GROWTH: usize = 4;
+
+    let order = evaluation_order(egraph, class_id)?;
+    let width = |id: &ClassId, node: &Node| -> Result<u64, String> {
+        if node.op == "Var" {
+            Ok(get_var_name_and_bitwidth(egraph, node).1)
+        } else {
+            get_bitwidth(egraph, id).ok_or_else(|| format!("Class {:?} is untyped.", id))
+        }
+    };
+
+    // The values of the registers so far, and how many times each has grown.
+    let mut registers: HashMap<ClassId, (BitvectorDomain, usize)> = HashMap::new();
+    loop {
+        let mut values: HashMap<ClassId, BitvectorDomain> = HashMap::new();
+        // The registers, with their data inputs and widths.
+        let mut register_inputs = vec![];
+        for id in &order {
+            let node = get_single_node(egraph, id)?;
+            let bw = width(id, node)?;
+            let value = if node.op == "Var" {
+                let (name, _) = get_var_name_and_bitwidth(egraph, node);
+                let (lo, hi) = env
+                    .get(name)
+                    .copied()
+                    .unwrap_or(BitvectorDomain::AnyValue)
+                    .bounds(bw);
+                BitvectorDomain::from_bounds(lo, hi, bw)
+            } else {
+                let op = &egraph[&node.children[0]];
+                match op.op.as_str() {
+                    "Mem" | "MemRead" => {
+                        return Err(
+                            "Memories are only supported by the stateful Interpreter.".to_string()
                         )
-                        .as_str(),
-                    );
+                    }
+                    "ShiftReg" => {
+                        return Err(
+                            "Shift registers are only supported by the stateful Interpreter."
+                                .to_string(),
+                        )
+                    }
+                    "Reg" | "RegWithClk" => {
+                        let d = &egraph[node.children.last().unwrap()].eclass;
+                        register_inputs.push((id, d, bw));
+                        let init: i64 = egraph[op.children.last().unwrap()].op.parse().unwrap();
+                        registers
+                            .entry(id.clone())
+                            .or_insert((
+                                BitvectorDomain::Concrete(truncate_value_to_bitwidth(
+                                    init as u64,
+                                    bw,
+                                )),
+                                0,
+                            ))
+                            .0
+                    }
+                    _ => {
+                        let children = node.children[1..]
+                            .iter()
+                            .map(|child| {
+                                let class = &egraph[child].eclass;
+                                Ok((values[class], width(class, &egraph[child])?))
+                            })
+                            .collect::<Result<Vec<_>, String>>()?;
+                        symbolic_interpret_op(egraph, node, op, &children, bw)?
+                    }
                 }
+            };
+            values.insert(id.clone(), value);
+        }
 
-                // Skip string literals.
-            _ if term.eclass.to_string().starts_with("String") => (),
+        let mut grew = false;
+        for (id, d, bw) in register_inputs {
+            let (value, growth) = registers.get_mut(id).unwrap();
+            let joined = value.join(values[d], bw);
+            if joined != *value {
+                grew = true;
+                *growth += 1;
+                *value = if *growth > MAX_GROWTH {
+                    BitvectorDomain::AnyValue
+                } else {
+                    joined
+                };
+            }
+        }
+        if !grew {
+            return Ok(values[class_id]);
+        }
+    }
+}
 
-            "GetOutput" => {
-                assert_eq!(term.children.len(), 2);
+/// Interpret a single (combinational) op over abstract values, given the
+/// values and widths of its children and the width `bw` of its result.
+/// Concrete children are interpreted with [`interpret_op`]; otherwise, the
+/// result is a range for ops whose bounds are easy to compute, and
+/// [`BitvectorDomain::AnyValue`] for the rest.
+fn symbolic_interpret_op(
+    egraph: &egraph_serialize::EGraph,
+    node: &Node,
+    op: &Node,
+    children: &[(BitvectorDomain, u64)],
+    bw: u64,
+) -> Result<BitvectorDomain, String> {
+    let concrete = children
+        .iter()
+        .map(|(value, bw)| match value {
+            BitvectorDomain::Concrete(value) => Some(InterpreterResult::Bitvector(*value, *bw)),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>();
+    if let Some(children) = concrete {
+        let InterpreterResult::Bitvector(value, bw) = interpret_op(egraph, node, op, &children)?;
+        return Ok(BitvectorDomain::Concrete(truncate_value_to_bitwidth(
+            value, bw,
+        )));
+    }
 
-                let module_class = &egraph[&term.children[0]].eclass;
-                let _output_class = &egraph[&term.children[1]].eclass;
-                let output_name = egraph[&term.children[1]].op.as_str().strip_prefix('\"').unwrap().strip_suffix('\"').unwrap();
+    let max = truncate_value_to_bitwidth(u64::MAX, bw);
+    let bounds = children
+        .iter()
+        .map(|(value, bw)| value.bounds(*bw))
+        .collect::<Vec<_>>();
+    let int = |i: usize| -> u64 { egraph[&op.children[i]].op.parse().unwrap() };
+    let range = match (op.op.as_str(), &bounds[..]) {
+        ("Mux", _) => {
+            return Ok(match children[0].0 {
+                BitvectorDomain::Concrete(0) => children[1].0,
+                BitvectorDomain::Concrete(_) => children[2].0,
+                _ => children[1].0.join(children[2].0, bw),
+            })
+        }
+        ("Add", [(a_lo, a_hi), (b_lo, b_hi)]) => a_hi
+            .checked_add(*b_hi)
+            .filter(|hi| *hi <= max)
+            .map(|hi| (a_lo + b_lo, hi)),
+        ("Sub", [(a_lo, a_hi), (b_lo, b_hi)]) => (a_lo >= b_hi).then(|| (a_lo - b_hi, a_hi - b_lo)),
+        ("Mul", [(a_lo, a_hi), (b_lo, b_hi)]) => a_hi
+            .checked_mul(*b_hi)
+            .filter(|hi| *hi <= max)
+            .map(|hi| (a_lo * b_lo, hi)),
+        ("And", [(_, a_hi), (_, b_hi)]) => Some((0, *a_hi.min(b_hi))),
+        // The result has no more bits than the wider operand.
+        ("Or", [(a_lo, a_hi), (b_lo, b_hi)]) => Some((
+            *a_lo.max(b_lo),
+            u64::MAX
+                .checked_shr(a_hi.max(b_hi).leading_zeros())
+                .unwrap_or(0),
+        )),
+        ("Shr", [(lo, hi), (shift, shift_hi)]) if shift == shift_hi => Some((
+            lo.checked_shr(*shift as u32).unwrap_or(0),
+            hi.checked_shr(*shift as u32).unwrap_or(0),
+        )),
+        ("UDiv", [(lo, hi), (divisor, divisor_hi)]) if divisor == divisor_hi && *divisor != 0 => {
+            Some((lo / divisor, hi / divisor))
+        }
+        ("ZeroExtend", [bounds]) => Some(*bounds),
+        // Without truncation, extracting the low bits of a value keeps its
+        // order.
+        ("Extract", [(lo, hi)]) if hi.checked_shr(int(0) as u32 + 1).unwrap_or(0) == 0 => {
+            Some((lo >> int(1), hi >> int(1)))
+        }
+        ("Concat", [(a_lo, a_hi), (b_lo, b_hi)]) => {
+            let b_bw = children[1].1;
+            Some(((a_lo << b_bw) | b_lo, (a_hi << b_bw) | b_hi))
+        }
+        ("Eq" | "Ne", [(a_lo, a_hi), (b_lo, b_hi)]) if a_hi < b_lo || b_hi < a_lo => {
+            let ne = (op.op == "Ne") as u64;
+            Some((ne, ne))
+        }
+        ("ReduceOr", [(lo, _)]) if *lo > 0 => Some((1, 1)),
+        _ => None,
+    };
 
-                // get module class name (e.g. mymodule in `mymodule m (ports);`)
-                assert_eq!(egraph[module_class].nodes.len(),1);
-                let module_instance_node = &egraph[&egraph[module_class].nodes[0]];
-                assert_eq!(module_instance_node.op, "ModuleInstance");
-                assert_eq!(module_instance_node.children.len(), 5);
-                let module_class_name = egraph[&module_instance_node.children[0].clone()].op.as_str().strip_prefix('\"').unwrap().strip_suffix('\"').unwrap();
+    Ok(match range {
+        Some((lo, hi)) => BitvectorDomain::from_bounds(lo, hi, bw),
+        None => BitvectorDomain::AnyValue,
+    })
+}
 
+/// Identifies a signal either directly by its eclass or by the name it was
+/// given when constructing an [`Interpreter`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ClassIdOrName {
+    ClassId(ClassId),
+    Name(String),
+}
 
-                fn cons_list_to_vec(egraph: &egraph_serialize::EGraph, cons_class_id: &ClassId) -> Vec<ClassId> {
-                    assert_eq!(egraph[cons_class_id].nodes.len(), 1);
-                    let cons_node = &egraph[&egraph[cons_class_id].nodes[0]];
-                    match cons_node.op.as_str() {
-                        "StringCons" | "ExprCons" => {
-                            assert_eq!(cons_node.children.len(), 2);
-                            [egraph[&cons_node.children[0]].eclass.clone()].iter().chain(cons_list_to_vec(egraph, &egraph[&cons_node.children[1]].eclass).iter()).cloned().collect()
-                        }
-                        "StringNil" | "ExprNil" => {
-                            assert_eq!(cons_node.children.len(), 0);
-                            vec![]
+/// A stateful interpreter for sequential Churchroad designs.
+///
+/// Whereas [`interpret`] computes the value of a signal at a given timestep by
+/// walking back through the design's entire input history, an [`Interpreter`]
+/// holds the current value of each register and advances the design one clock
+/// cycle per call to [`Interpreter::step`]. By default, each call to `step`
+/// represents one rising edge of the clock, so clock signals do not need to be
+/// provided as inputs. In designs with multiple clocks (e.g. using
+/// `RegWithClk`), each call to `step` is a rising edge of every clock.
+///
+/// Clocks can instead be given explicitly, as one value per call to `step`,
+/// e.g. to model gated or derived clocks. A register whose clock can be
+/// computed from the inputs only updates when its clock rises, i.e. when the
+/// clock is 1 and was 0 (or not yet given) during the previous step.
+///
+/// Memories are only supported by this interpreter and not by [`interpret`].
+/// They are written on every step, regardless of any clocks in the inputs.
+/// The same goes for shift registers, which are clocked like registers.
+///
+/// ```
+/// use churchroad::*;
+/// use egglog::{EGraph, SerializeConfig};
+/// use std::collections::HashMap;
+///
+/// let mut egraph = EGraph::default();
+/// import_churchroad(&mut egraph).unwrap();
+/// egraph
+///     .parse_and_run_program(
+///         r#"
+/// (let placeholder (Wire "placeholder" 4))
+/// (let clk (Var "clk" 1))
+/// (IsPort "" "clk" (Input) clk)
+/// (let count (Op2 (Reg 0) clk (Op2 (Add) placeholder (Op0 (BV 1 4)))))
+/// (union placeholder count)
+/// (IsPort "" "count" (Output) count)
+/// (run-schedule (saturate typing))
+/// "#,
+///     )
+///     .unwrap();
+/// auto_delete_wires(&mut egraph);
+///
+/// let serialized = egraph.serialize(SerializeConfig::default());
+/// let (_, outputs) = get_inputs_and_outputs_serialized(&serialized);
+/// let mut interpreter = Interpreter::new(&serialized, outputs).unwrap();
+///
+/// for expected in 0..20 {
+///     let outputs = interpreter.step(&HashMap::default());
+///     assert_eq!(outputs["count"], InterpreterResult::Bitvector(expected % 16, 4));
+/// }
+/// ```
+pub struct Interpreter<'a> {
+    egraph: &'a egraph_serialize::EGraph,
+    /// The signals reported by [`Interpreter::step`]: name, eclass.
+    outputs: Vec<(String, ClassId)>,
+    /// The current value of each register, keyed by the register's eclass.
+    registers: HashMap<ClassId, InterpreterResult>,
+    /// The value of each explicitly clocked register's clock during the last
+    /// step, keyed by the register's eclass.
+    clocks: HashMap<ClassId, u64>,
+    /// The stages of each shift register, oldest (i.e. the shift register's
+    /// output) first, keyed by the shift register's eclass.
+    shift_registers: HashMap<ClassId, VecDeque<InterpreterResult>>,
+    /// The current state of each memory, keyed by the memory's eclass.
+    memories: HashMap<ClassId, Memory>,
+    /// The values of all signals computed during the last call to
+    /// [`Interpreter::step`].
+    values: HashMap<ClassId, InterpreterResult>,
+}
+
+/// The state of a memory in an [`Interpreter`].
+struct Memory {
+    /// The value of words which haven't been written yet.
+    init: u64,
+    data_bw: u64,
+    /// The words which have been written, keyed by address.
+    contents: HashMap<u64, u64>,
+    /// The memory's write ports as (address, data, enable) eclasses. When
+    /// several ports write the same address, the last one wins.
+    write_ports: Vec<(ClassId, ClassId, ClassId)>,
+}
+
+impl<'a> Interpreter<'a> {
+    /// Create an interpreter for the design rooted at the given signals. Each
+    /// root is a (name, eclass) pair, e.g. as returned by
+    /// [`get_inputs_and_outputs_serialized`]. Registers start at their `Reg`
+    /// init values; see [`Interpreter::with_initial_state`] to override them.
+    ///
+    /// Like [`interpret`], this requires that every eclass reachable from the
+    /// roots contains exactly one node.
+    pub fn new(
+        egraph: &'a egraph_serialize::EGraph,
+        outputs: Vec<(String, ClassId)>,
+    ) -> Result<Self, String> {
+        let mut registers = HashMap::new();
+        let mut shift_registers = HashMap::new();
+        let mut memories = HashMap::new();
+
+        let mut visited = HashSet::new();
+        let mut stack: Vec<ClassId> = outputs.iter().map(|(_, id)| id.clone()).collect();
+        while let Some(id) = stack.pop() {
+            if !visited.insert(id.clone()) {
+                continue;
+            }
+
+            let node = get_single_node(egraph, &id)?;
+            match node.op.as_str() {
+                "Var" => (),
+                "Op0" | "Op1" | "Op2" | "Op3" => {
+                    let op = &egraph[&node.children[0]];
+                    if op.op == "Mem" {
+                        let data_bw: u64 = egraph[&op.children[2]].op.parse().unwrap();
+                        let init: i64 = egraph[&op.children[3]].op.parse().unwrap();
+                        // Write ports are MemWrite facts, which aren't
+                        // reachable from the memory itself.
+                        let write_ports: Vec<(ClassId, ClassId, ClassId)> = egraph
+                            .nodes
+                            .values()
+                            .filter(|n| n.op == "MemWrite" && egraph[&n.children[0]].eclass == id)
+                            .map(|n| {
+                                (
+                                    egraph[&n.children[2]].eclass.clone(),
+                                    egraph[&n.children[3]].eclass.clone(),
+                                    egraph[&n.children[4]].eclass.clone(),
+                                )
+                            })
+                            .collect();
+                        for (address, data, enable) in &write_ports {
+                            stack.extend([address.clone(), data.clone(), enable.clone()]);
                         }
-                        _ => unreachable!()
+                        memories.insert(
+                            id.clone(),
+                            Memory {
+                                init: truncate_value_to_bitwidth(init as u64, data_bw),
+                                data_bw,
+                                contents: HashMap::new(),
+                                write_ports,
+                            },
+                        );
+                    }
+                    if matches!(op.op.as_str(), "Reg" | "RegWithClk") {
+                        let init: i64 = egraph[op.children.last().unwrap()].op.parse().unwrap();
+                        let bw = get_bitwidth_for_node(egraph, node.children.last().unwrap())?;
+                        registers.insert(
+                            id.clone(),
+                            InterpreterResult::Bitvector(
+                                truncate_value_to_bitwidth(init as u64, bw),
+                                bw,
+                            ),
+                        );
+                    }
+                    if op.op == "ShiftReg" {
+                        let depth: usize = egraph[&op.children[0]].op.parse().unwrap();
+                        let init: i64 = egraph[&op.children[1]].op.parse().unwrap();
+                        let bw = get_bitwidth_for_node(egraph, node.children.last().unwrap())?;
+                        shift_registers.insert(
+                            id.clone(),
+                            VecDeque::from(vec![
+                                InterpreterResult::Bitvector(
+                                    truncate_value_to_bitwidth(init as u64, bw),
+                                    bw,
+                                );
+                                depth
+                            ]),
+                        );
                     }
 
+                    stack.extend(
+                        node.children
+                            .iter()
+                            .skip(1)
+                            .map(|child| egraph[child].eclass.clone()),
+                    );
                 }
+                _ => return Err(format!("unimplemented node type: {:?}", node.op)),
+            }
+        }
 
-                fn class_id_vec_to_strings(egraph: &egraph_serialize::EGraph, class_id_vec: Vec<ClassId>) -> Vec<String> {
-                    class_id_vec.iter().map(|id| {
-                        assert_eq!(egraph[id].nodes.len(), 1);
-                        egraph[&egraph[id].nodes[0]].op.as_str().strip_prefix('\"').unwrap().strip_suffix('\"').unwrap().to_owned()
-                    }).collect()
-                }
+        Ok(Self {
+            egraph,
+            outputs,
+            registers,
+            clocks: HashMap::new(),
+            shift_registers,
+            memories,
+            values: HashMap::new(),
+        })
+    }
 
-                // Get module input names and input exprs.
-                let parameter_names= class_id_vec_to_strings(egraph, cons_list_to_vec(egraph, &egraph[&module_instance_node.children[1]].eclass));
-                let parameter_exprs=  cons_list_to_vec(egraph, &egraph[&module_instance_node.children[2]].eclass);
-                let input_port_names= class_id_vec_to_strings(egraph, cons_list_to_vec(egraph, &egraph[&module_instance_node.children[3]].eclass));
-                let input_port_exprs=  cons_list_to_vec(egraph, &egraph[&module_instance_node.children[4]].eclass);
-                assert_eq!(parameter_exprs.len(), parameter_names.len());
-                assert_eq!(input_port_exprs.len(), input_port_names.len());
+    /// Override the initial values of registers, e.g. to start simulating
+    /// from a specific architectural state rather than the `Reg` init values.
+    ///
+    /// Registers can be identified by eclass or by the name of one of the
+    /// interpreter's outputs. Errors if a key doesn't identify a register or
+    /// if a value doesn't fit in the register's bitwidth.
+    pub fn with_initial_state(
+        mut self,
+        initial_state: HashMap<ClassIdOrName, u64>,
+    ) -> Result<Self, String> {
+        for (key, value) in initial_state {
+            let id = match &key {
+                ClassIdOrName::ClassId(id) => id.clone(),
+                ClassIdOrName::Name(name) => self
+                    .outputs
+                    .iter()
+                    .find(|(output_name, _)| output_name == name)
+                    .map(|(_, id)| id.clone())
+                    .ok_or_else(|| format!("no signal named {:?}", name))?,
+            };
+            let register = self
+                .registers
+                .get_mut(&id)
+                .ok_or_else(|| format!("{:?} is not a register", key))?;
+            let InterpreterResult::Bitvector(_, bw) = *register;
+            if truncate_value_to_bitwidth(value, bw) != value {
+                return Err(format!(
+                    "initial value {} for {:?} does not fit in {} bits",
+                    value, key, bw
+                ));
+            }
+            *register = InterpreterResult::Bitvector(value, bw);
+        }
+        Ok(self)
+    }
 
-                for expr in input_port_exprs.iter().chain(parameter_exprs.iter()) {
-                    maybe_push_expr_on_queue(&mut queue, &done, expr);
+    /// Advance the design by one clock cycle.
+    ///
+    /// Returns the value of each output during this cycle, i.e. computed from
+    /// `inputs` and the register values from before the clock edge. Registers
+    /// are then updated with the values at their data inputs, except for
+    /// registers whose clock is given in `inputs` and didn't rise.
+    ///
+    /// Panics if an input needed to compute an output or a register's next
+    /// value is missing from `inputs`.
+    pub fn step(&mut self, inputs: &HashMap<String, u64>) -> HashMap<String, InterpreterResult> {
+        let mut values = HashMap::new();
+
+        let outputs = self
+            .outputs
+            .iter()
+            .map(|(name, id)| {
+                (
+                    name.clone(),
+                    self.evaluate(id, inputs, &mut values)
+                        .unwrap_or_else(|e| panic!("couldn't compute {:?}: {}", name, e)),
+                )
+            })
+            .collect();
+
+        // Compute each register's next value before updating any of them, so
+        // that all registers observe the same clock edge.
+        let mut clocks = vec![];
+        let next_register_values = self
+            .registers
+            .keys()
+            .chain(self.shift_registers.keys())
+            .filter_map(|id| {
+                if let Some(clk) = self.clock_value(id, inputs, &mut values) {
+                    let prev_clk = self.clocks.get(id).copied().unwrap_or(0);
+                    clocks.push((id.clone(), clk));
+                    if !(prev_clk == 0 && clk == 1) {
+                        return None;
+                    }
                 }
 
-                // If we haven't seen this module yet, create a new module instance.
-                if !module_instantiations.contains_key(module_class) {
-                    module_instantiations.insert(module_class.clone(), ModuleInstance {
-                        module_class_name: module_class_name.to_owned(),
-                        instance_name: format!("module_{}", module_class),
-                        parameters: parameter_names.into_iter().zip(parameter_exprs.into_iter()).collect(),
-                        inputs: input_port_names.into_iter().zip(input_port_exprs.into_iter()).collect(),
-                        outputs: [(output_name.to_owned(), term.eclass.clone())].into(),
-                    });
-                } else if let Some(module_instance) = module_instantiations.get_mut(module_class) {
-                    module_instance.outputs.insert(output_name.to_owned(), term.eclass.clone());
-                }else {
-                    unreachable!("module_instantiations should contain the module class");
+                let d = get_single_node(self.egraph, id)
+                    .unwrap()
+                    .children
+                    .last()
+                    .unwrap();
+                let d = &self.egraph[d].eclass;
+                let next = self
+                    .evaluate(d, inputs, &mut values)
+                    .unwrap_or_else(|e| panic!("couldn't compute register {:?}: {}", id, e));
+                Some((id.clone(), next))
+            })
+            .collect::<Vec<_>>();
+
+        // Likewise, compute all memory writes before performing any of them.
+        let memory_writes = self
+            .memories
+            .iter()
+            .flat_map(|(mem_id, memory)| {
+                memory
+                    .write_ports
+                    .iter()
+                    .map(move |write_port| (mem_id, write_port))
+            })
+            .filter_map(|(mem_id, (address, data, enable))| {
+                let mut evaluate = |id: &ClassId| {
+                    let InterpreterResult::Bitvector(val, _) = self
+                        .evaluate(id, inputs, &mut values)
+                        .unwrap_or_else(|e| panic!("couldn't compute memory write: {}", e));
+                    val
+                };
+                if evaluate(enable) == 0 {
+                    return None;
                 }
+                Some((mem_id.clone(), evaluate(address), evaluate(data)))
+            })
+            .collect::<Vec<_>>();
 
-                logic_declarations.push_str(
-                    format!(
-                        "logic {this_wire};\n",
-                        this_wire = id_to_wire_name(&term.eclass),
-                    )
-                    .as_str(),
-                );
+        for (id, next) in next_register_values {
+            if let Some(stages) = self.shift_registers.get_mut(&id) {
+                stages.pop_front();
+                stages.push_back(next);
+            } else {
+                self.registers.insert(id, next);
             }
+        }
+        self.clocks.extend(clocks);
+        for (mem_id, address, data) in memory_writes {
+            self.memories
+                .get_mut(&mem_id)
+                .unwrap()
+                .contents
+                .insert(address, data);
+        }
 
-            // Term::Lit(Literal::Int(v)) => {
-            //     logic_declarations.push_str(&format!(
-            //         "logic [31:0] {this_wire} = {val};\n",
-            //         this_wire = id_to_wire_name(id),
-            //         val = v
-            //     ));
-            // }
-            // Term::Var(_) => todo!(),
-            // Term::App(s, v) => match (s.as_str(), v.as_slice()) {
-            //     ("Reg", &[default_id, clk_id, d_id]) => {
-            //         let default_val = match term_dag.get(default_id) {
-            //             Term::Lit(Literal::Int(default_val)) => default_val,
-            //             _ => panic!(),
-            //         };
+        self.values = values;
+        outputs
+    }
 
-            //         logic_declarations.push_str(
-            //             format!(
-            //                 "logic {this_wire} = {default};\n",
-            //                 this_wire = id_to_wire_name(id),
-            //                 default = default_val
-            //             )
-            //             .as_str(),
-            //         );
+    /// Get the value a signal had during the last call to
+    /// [`Interpreter::step`]. Registers can also be peeked before the first
+    /// step, in which case their initial value is returned. Returns `None` for
+    /// signals that weren't needed to compute the last step's outputs.
+    pub fn peek(&self, signal: &ClassId) -> Option<&InterpreterResult> {
+        if self.values.is_empty() {
+            self.registers.get(signal).or_else(|| {
+                self.shift_registers
+                    .get(signal)
+                    .and_then(|stages| stages.front())
+            })
+        } else {
+            self.values.get(signal)
+        }
+    }
 
-            //         registers.push_str(&format!(
-            //             "always @(posedge {clk}) begin
-            //                 {this_wire} <= {d};
-            //             end\n",
-            //             clk = id_to_wire_name(clk_id),
-            //             this_wire = id_to_wire_name(id),
-            //             d = id_to_wire_name(d_id)
-            //         ));
+    /// Compute the value of a register's clock during the current cycle, or
+    /// `None` if it can't be computed from the inputs, e.g. because no clock
+    /// was given.
+    fn clock_value(
+        &self,
+        id: &ClassId,
+        inputs: &HashMap<String, u64>,
+        values: &mut HashMap<ClassId, InterpreterResult>,
+    ) -> Option<u64> {
+        let node = get_single_node(self.egraph, id).ok()?;
+        if node.children.len() == 3 {
+            let InterpreterResult::Bitvector(clk, _) = self
+                .evaluate(&self.egraph[&node.children[1]].eclass, inputs, values)
+                .ok()?;
+            Some(clk)
+        } else {
+            None
+        }
+    }
 
-            //         if !done.contains(&d_id) {
-            //             queue.push(d_id);
-            //         }
-            //         if !done.contains(&clk_id) {
-            //             queue.push(clk_id);
-            //         }
-            //     }
-            //     ("Var", [name_id, bw_id]) => {
-            //         let name = match term_dag.get(*name_id) {
-            //             Term::Lit(Literal::String(name)) => name,
-            //             _ => panic!(),
-            //         };
-            //         let bw = match term_dag.get(*bw_id) {
-            //             Term::Lit(Literal::Int(bw)) => bw,
-            //             _ => panic!(),
-            //         };
+    /// Compute the value of an eclass during the current cycle.
+    fn evaluate(
+        &self,
+        id: &ClassId,
+        inputs: &HashMap<String, u64>,
+        values: &mut HashMap<ClassId, InterpreterResult>,
+    ) -> Result<InterpreterResult, String> {
+        if let Some(value) = values.get(id) {
+            return Ok(value.clone());
+        }
 
-            //         inputs.push_str(
-            //             format!("input [{bw}-1:0] {name};\n", bw = bw, name = name).as_str(),
-            //         );
+        let node = get_single_node(self.egraph, id)?;
+        let result = match node.op.as_str() {
+            "Var" => {
+                let (name, bw) = get_var_name_and_bitwidth(self.egraph, node);
+                let value = inputs
+                    .get(name)
+                    .ok_or_else(|| format!("no value provided for input {:?}", name))?;
+                InterpreterResult::Bitvector(*value, bw)
+            }
+            "Op0" | "Op1" | "Op2" | "Op3" => {
+                let op = &self.egraph[&node.children[0]];
+                if matches!(op.op.as_str(), "Reg" | "RegWithClk") {
+                    self.registers[id].clone()
+                } else if op.op == "ShiftReg" {
+                    self.shift_registers[id][0].clone()
+                } else if op.op == "Mem" {
+                    return Err("a memory has no value; read it with MemRead".to_string());
+                } else if op.op == "MemRead" {
+                    self.read_memory(node, op, inputs, values)?
+                } else {
+                    let children = node
+                        .children
+                        .iter()
+                        .skip(1)
+                        .map(|child| self.evaluate(&self.egraph[child].eclass, inputs, values))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    interpret_op(self.egraph, node, op, &children)?
+                }
+            }
+            _ => return Err(format!("unimplemented node type: {:?}", node.op)),
+        };
 
-            //         logic_declarations.push_str(
-            //             format!(
-            //                 "logic [{bw}-1:0] {this_wire} = {name};\n",
-            //                 bw = bw,
-            //                 this_wire = id_to_wire_name(id),
-            //                 name = name
-            //             )
-            //             .as_str(),
-            //         );
-            //     }
-            //     ("Mux", []) => (),
-            //     ("LUT4", []) => (),
-            //     ("Or", []) => (),
-            //     ("Bitvector", [_]) => (),
-            //     ("Eq", []) => (),
-            //     ("BV", [val_id, bw_id]) => {
-            //         let val = match term_dag.get(*val_id) {
-            //             Term::Lit(Literal::Int(val)) => val,
-            //             _ => panic!(),
-            //         };
-            //         let bw = match term_dag.get(*bw_id) {
-            //             Term::Lit(Literal::Int(bw)) => bw,
-            //             _ => panic!(),
-            //         };
-            //         logic_declarations.push_str(
-            //             format!(
-            //                 "logic [{bw}-1:0] {this_wire} = {bw}'d{val};\n",
-            //                 bw = bw,
-            //                 this_wire = id_to_wire_name(id),
-            //                 val = val
-            //             )
-            //             .as_str(),
-            //         );
-            //     }
-            //     ("Extract", [hi_id, lo_id, expr_id]) => {
-            //         let hi = match term_dag.get(*hi_id) {
-            //             Term::Lit(Literal::Int(hi)) => hi,
-            //             _ => panic!(),
-            //         };
-            //         let lo = match term_dag.get(*lo_id) {
-            //             Term::Lit(Literal::Int(lo)) => lo,
-            //             _ => panic!(),
-            //         };
-            //         logic_declarations.push_str(&format!(
-            //             "logic {this_wire} = {expr}[{hi}:{lo}];\n",
-            //             hi = hi,
-            //             lo = lo,
-            //             this_wire = id_to_wire_name(id),
-            //             expr = id_to_wire_name(*expr_id),
-            //         ));
+        let InterpreterResult::Bitvector(val, bw) = result;
+        let result = InterpreterResult::Bitvector(truncate_value_to_bitwidth(val, bw), bw);
+        values.insert(id.clone(), result.clone());
+        Ok(result)
+    }
 
-            //         if !done.contains(&expr_id) {
-            //             queue.push(*expr_id);
-            //         }
-            //     }
-            //     ("Concat", [expr0_id, expr1_id]) => {
-            //         logic_declarations.push_str(&format!(
-            //             "logic {this_wire} = {{ {expr0}, {expr1} }};\n",
-            //             this_wire = id_to_wire_name(id),
-            //             expr0 = id_to_wire_name(*expr0_id),
-            //             expr1 = id_to_wire_name(*expr1_id),
-            //         ));
+    /// Compute the value of a `(Op2 (MemRead mode) mem addr)` node during the
+    /// current cycle.
+    fn read_memory(
+        &self,
+        node: &Node,
+        op: &Node,
+        inputs: &HashMap<String, u64>,
+        values: &mut HashMap<ClassId, InterpreterResult>,
+    ) -> Result<InterpreterResult, String> {
+        let memory = &self.memories[&self.egraph[&node.children[1]].eclass];
+        let InterpreterResult::Bitvector(address, _) =
+            self.evaluate(&self.egraph[&node.children[2]].eclass, inputs, values)?;
+
+        let mut data = *memory.contents.get(&address).unwrap_or(&memory.init);
+        // Writes take effect at the end of the cycle, so reads see the old
+        // data unless the read port forwards data being written.
+        if self.egraph[&op.children[0]].op == "WriteFirst" {
+            for (write_address, write_data, write_enable) in &memory.write_ports {
+                let InterpreterResult::Bitvector(enable, _) =
+                    self.evaluate(write_enable, inputs, values)?;
+                let InterpreterResult::Bitvector(write_address, _) =
+                    self.evaluate(write_address, inputs, values)?;
+                if enable != 0 && write_address == address {
+                    let InterpreterResult::Bitvector(write_data, _) =
+                        self.evaluate(write_data, inputs, values)?;
+                    data = write_data;
+                }
+            }
+        }
 
-            //         if !done.contains(&expr0_id) {
-            //             queue.push(*expr0_id);
-            //         }
-            //         if !done.contains(&expr1_id) {
-            //             queue.push(*expr1_id);
-            //         }
-            //     }
-            //     ("ZeroExtend", [expr_id, bw_id]) => {
-            //         let bw = match term_dag.get(*bw_id) {
-            //             Term::Lit(Literal::Int(bw)) => bw,
-            //             _ => panic!(),
-            //         };
-            //         logic_declarations.push_str(&format!(
-            //             "logic {this_wire} = {{ {bw}'d0, {expr} }};\n",
-            //             this_wire = id_to_wire_name(id),
-            //             bw = bw,
-            //             expr = id_to_wire_name(*expr_id),
-            //         ));
+        Ok(InterpreterResult::Bitvector(data, memory.data_bw))
+    }
+}
 
-            //         if !done.contains(&expr_id) {
-            //             queue.push(*expr_id);
-            //         }
-            //     }
-            //     ("Sketch1", [op_id, expr_id])
-            //         if match term_dag.get(*op_id) {
-            //             Term::App(s, v) => s.as_str() == "LUT4" && v.is_empty(),
-            //             _ => false,
-            //         } =>
-            //     {
-            //         logic_declarations.push_str(&format!(
-            //             "logic {this_wire};\n",
-            //             this_wire = id_to_wire_name(id),
-            //         ));
+#[derive(Default)]
+pub struct AnythingExtractor;
+impl AnythingExtractor {
+    pub fn extract(
+        &self,
+        egraph: &egraph_serialize::EGraph,
+        _roots: &[egraph_serialize::ClassId],
+    ) -> IndexMap<egraph_serialize::ClassId, egraph_serialize::NodeId> {
+        egraph
+            .classes()
+            .iter()
+            .map(|(id, class)| {
+                let node_id = class.nodes.first().unwrap().clone();
+                (id.clone(), node_id)
+            })
+            .collect()
+    }
+}
 
-            //         module_declarations.push_str(&format!(
-            //             "lut4 lut4_{id} (.in({expr}), .out({y}));\n",
-            //             id = id,
-            //             expr = id_to_wire_name(*expr_id),
-            //             y = id_to_wire_name(id),
-            //         ));
+/// Error returned by [`extract_one`] and [`extract_term`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtractionError {
+    /// The serialized egraph has no eclass with this ID.
+    UnknownClass(ClassId),
+    /// Every enode of this eclass depends on the eclass itself, so no finite
+    /// term represents it.
+    Cyclic(ClassId),
+}
 
-            //         if !done.contains(&expr_id) {
-            //             queue.push(*expr_id);
-            //         }
-            //     }
-            //     _ => todo!("{:?}", (s, v)),
-            // },
-            _ => todo!("{:?}", &term),
+impl std::fmt::Display for ExtractionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtractionError::UnknownClass(id) => write!(f, "No class with ID {:?}.", id),
+            ExtractionError::Cyclic(id) => write!(f, "Class {:?} has no finite term.", id),
         }
     }
+}
 
-    // For display purposes, we can clean this up later.
-    // We sort to make the output stable.
-    let inputs = {
-        let mut out = inputs
-            .split('\n')
-            .map(|line| format!("  {}", line))
-            .collect::<Vec<_>>();
+/// Whether a serialized enode is a literal, such as an `i64` or a `String`,
+/// rather than an application of a function.
+fn is_literal(node: &Node) -> bool {
+    node.children.is_empty() && (node.op.parse::<i64>().is_ok() || node.op.starts_with('"'))
+}
 
-        out.sort();
-        out.join("\n")
-    };
-    let outputs = {
-        let mut out = outputs
-            .split('\n')
-            .map(|line| format!("  {}", line))
-            .collect::<Vec<_>>();
-        out.sort();
-        out.join("\n")
-    };
-    let logic_declarations = logic_declarations
-        .split('\n')
-        .map(|line| format!("  {}", line))
-        .collect::<Vec<_>>()
-        .join("\n");
+/// Evaluate a term, written as egglog source, in `egraph`.
+fn eval_term_string(egraph: &mut EGraph, term: &str) -> (ArcSort, Value) {
+    egraph
+        .eval_expr(&egglog::ast::parse::ExprParser::new().parse(term).unwrap())
+        .unwrap()
+}
 
-    let module_instantiations = module_instantiations
-        .iter()
-        .map(
-            |(
-                _class_id,
-                ModuleInstance {
-                    module_class_name,
-                    instance_name,
-                    parameters,
-                    inputs,
-                    outputs,
-                },
-            )| {
-                let parameters = parameters
-                    .iter()
-                    .map(|(name, id)| format!("    .{}({})", name, id_to_wire_name(id)))
-                    .collect::<Vec<_>>()
-                    .join(",\n");
-                let inputs = {let mut out = inputs
-                    .iter()
-                    .map(|(name, id)| format!("    .{}({})", name, id_to_wire_name(id)))
-                    .collect::<Vec<_>>();
-                    out.sort();
-                    out.join(",\n")};
+/// Get the sort and value of the eclass `class_id`, an ID from a
+/// serialization of `egraph` with the default [`SerializeConfig`]. The value
+/// is found by evaluating a term of the eclass built from the first of its
+/// enodes, and of each eclass below it, which doesn't lead back to the
+/// eclass, with [`EGraph::eval_expr`], hence the `&mut EGraph`.
+///
+/// ```
+/// use churchroad::*;
+/// use egglog::{EGraph, SerializeConfig, TermDag};
+///
+/// let mut egraph = EGraph::default();
+/// import_churchroad(&mut egraph).unwrap();
+/// egraph
+///     .parse_and_run_program(
+///         r#"
+/// (let a (Var "a" 8))
+/// (let sum (Op2 (Add) a (Op0 (BV 0 8))))
+/// (union sum a)
+/// "#,
+///     )
+///     .unwrap();
+///
+/// let serialized = egraph.serialize(SerializeConfig::default());
+/// let class_id = serialized
+///     .nodes
+///     .values()
+///     .find(|node| node.op == "Var")
+///     .unwrap()
+///     .eclass
+///     .clone();
+///
+/// let (_, value) = extract_one(&mut egraph, &class_id).unwrap();
+/// assert_eq!(egraph.eval_expr(&egglog::ast::Expr::Var((), "sum".into())).unwrap().1, value);
+///
+/// let mut termdag = TermDag::default();
+/// let term = extract_term(&mut egraph, &class_id, &mut termdag).unwrap();
+/// assert_eq!(termdag.to_string(&term), "(Var \"a\" 8)");
+/// ```
+pub fn extract_one(
+    egraph: &mut EGraph,
+    class_id: &ClassId,
+) -> Result<(ArcSort, Value), ExtractionError> {
+    let serialized = egraph.serialize(SerializeConfig::default());
+    let term = class_term(&serialized, class_id)?;
+    Ok(eval_term_string(egraph, &term))
+}
+
+/// A term of the serialized eclass `class_id`, as egglog source, built from
+/// the first of its enodes, and of each eclass below it, which doesn't lead
+/// back to an eclass being written. egglog has no way to refer to an eclass
+/// by its ID, but hashconsing finds the eclass from the term.
+fn class_term(
+    egraph: &egraph_serialize::EGraph,
+    class_id: &ClassId,
+) -> Result<String, ExtractionError> {
+    // Terms found so far, so that shared eclasses are only written once. A
+    // term is valid wherever its eclass appears, but failing to find one
+    // depends on the path taken, so failures aren't cached.
+    fn term(
+        egraph: &egraph_serialize::EGraph,
+        class_id: &ClassId,
+        path: &mut HashSet<ClassId>,
+        cache: &mut HashMap<ClassId, String>,
+    ) -> Result<String, ExtractionError> {
+        if let Some(term) = cache.get(class_id) {
+            return Ok(term.clone());
+        }
+        let class = egraph
+            .classes()
+            .get(class_id)
+            .ok_or_else(|| ExtractionError::UnknownClass(class_id.clone()))?;
+        if !path.insert(class_id.clone()) {
+            return Err(ExtractionError::Cyclic(class_id.clone()));
+        }
+        let term = class.nodes.iter().map(|id| &egraph[id]).find_map(|node| {
+            if is_literal(node) {
+                return Some(node.op.clone());
+            }
+            let children = node
+                .children
+                .iter()
+                .map(|child| term(egraph, &egraph[child].eclass, path, cache))
+                .collect::<Result<Vec<_>, _>>()
+                .ok()?;
+            Some(format!(
+                "({})",
+                [vec![node.op.clone()], children].concat().join(" ")
+            ))
+        });
+        path.remove(class_id);
+        let term = term.ok_or_else(|| ExtractionError::Cyclic(class_id.clone()))?;
+        cache.insert(class_id.clone(), term.clone());
+        Ok(term)
+    }
+
+    term(egraph, class_id, &mut HashSet::new(), &mut HashMap::new())
+}
+
+/// Like [`extract_one`], but extracts the eclass's cheapest term into
+/// `termdag`, as [`EGraph::extract`] does.
+pub fn extract_term(
+    egraph: &mut EGraph,
+    class_id: &ClassId,
+    termdag: &mut TermDag,
+) -> Result<Term, ExtractionError> {
+    let (sort, value) = extract_one(egraph, class_id)?;
+    Ok(egraph.extract(value, termdag, &sort).1)
+}
+
+/// Error returned by [`apply_rewrite`].
+#[derive(Debug)]
+pub enum RewriteError {
+    /// The given source, which isn't a `rewrite` command.
+    NotARewrite(String),
+    /// The eclass to rewrite can't be written as a term.
+    Extraction(ExtractionError),
+    /// egglog failed to run the rewrite, e.g. because it doesn't type check.
+    Egglog(egglog::Error),
+}
+
+impl std::fmt::Display for RewriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RewriteError::NotARewrite(source) => write!(f, "Not a rewrite: {}", source),
+            RewriteError::Extraction(err) => write!(f, "{}", err),
+            RewriteError::Egglog(err) => write!(f, "egglog failed: {}", err),
+        }
+    }
+}
+
+impl From<ExtractionError> for RewriteError {
+    fn from(err: ExtractionError) -> Self {
+        RewriteError::Extraction(err)
+    }
+}
+
+impl From<egglog::Error> for RewriteError {
+    fn from(err: egglog::Error) -> Self {
+        RewriteError::Egglog(err)
+    }
+}
+
+/// The source of each element of the list `sexp`, or `None` if `sexp` isn't
+/// a single list.
+fn list_elements(sexp: &str) -> Option<Vec<&str>> {
+    let inner = sexp.trim().strip_prefix('(')?.strip_suffix(')')?;
+    let mut elements = vec![];
+    let (mut depth, mut in_string, mut start) = (0usize, false, None);
+    for (i, c) in inner.char_indices() {
+        match c {
+            '"' => {
+                start.get_or_insert(i);
+                in_string = !in_string;
+            }
+            _ if in_string => (),
+            '(' => {
+                start.get_or_insert(i);
+                depth += 1;
+            }
+            ')' => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    elements.push(&inner[start.take()?..=i]);
+                }
+            }
+            c if c.is_whitespace() => {
+                if depth == 0 {
+                    if let Some(start) = start.take() {
+                        elements.push(&inner[start..i]);
+                    }
+                }
+            }
+            _ => {
+                start.get_or_insert(i);
+            }
+        }
+    }
+    if depth != 0 || in_string {
+        return None;
+    }
+    elements.extend(start.map(|start| &inner[start..]));
+    Some(elements)
+}
+
+/// Apply `rewrite`, the source of an egglog `rewrite` command such as those
+/// [`generate_module_enumeration_rewrite`] writes, once, only where its
+/// left-hand side matches a term of the serialized eclass `class_id`. Useful
+/// for seeing why a rewrite does or doesn't fire somewhere, as running its
+/// ruleset rewrites the whole egraph. The rewrite's `:ruleset`, if any, is
+/// ignored; its `:when` conditions still apply.
+///
+/// Returns the eclasses which changed, as found in a serialization of the
+/// rewritten egraph: the eclass `class_id` ended up in, if the rewrite fired,
+/// and any eclasses the rewrite created.
+pub fn apply_rewrite(
+    egraph: &mut EGraph,
+    rewrite: &str,
+    class_id: &ClassId,
+) -> Result<Vec<ClassId>, RewriteError> {
+    // Each call gets its own ruleset, as egglog won't let us redeclare one.
+    static NEXT_REWRITE_ID: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    let not_a_rewrite = || RewriteError::NotARewrite(rewrite.to_owned());
+    let elements = list_elements(rewrite)
+        .filter(|elements| elements.len() >= 3 && elements[0] == "rewrite")
+        .ok_or_else(not_a_rewrite)?;
+    let mut conditions = vec![];
+    for option in elements[3..].chunks(2) {
+        match option {
+            [":when", when] => conditions.extend(list_elements(when).ok_or_else(not_a_rewrite)?),
+            [":ruleset", _] => (),
+            _ => return Err(not_a_rewrite()),
+        }
+    }
+
+    let before = egraph.serialize(SerializeConfig::default());
+    let target = class_term(&before, class_id)?;
+    let (sort, _) = eval_term_string(egraph, &target);
+
+    // The rewritten eclass is marked in a relation of its own, which is
+    // emptied again once it's been read.
+    let id = NEXT_REWRITE_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    let (ruleset, applied) = (
+        format!("apply-rewrite-{id}"),
+        format!("rewrite-applied-{id}"),
+    );
+    egraph.parse_and_run_program(&format!(
+        "(ruleset {ruleset})
+         (relation {applied} ({sort}))
+         (rule ((= rewrite-root {lhs}) (= rewrite-root {target}) {conditions})
+               ((union rewrite-root {rhs}) ({applied} rewrite-root))
+               :ruleset {ruleset})
+         (run-schedule (repeat 1 {ruleset}))",
+        sort = sort.name(),
+        lhs = elements[1],
+        rhs = elements[2],
+        conditions = conditions.join(" "),
+    ))?;
+
+    let after = egraph.serialize(SerializeConfig::default());
+    let is_fact_class = |class: &ClassId| {
+        after.classes()[class]
+            .nodes
+            .iter()
+            .any(|id| after[id].op == "()")
+    };
+    let mut changed = after
+        .nodes
+        .values()
+        .filter(|node| node.op == applied)
+        .map(|node| after[&node.children[0]].eclass.clone())
+        .chain(
+            after
+                .classes()
+                .keys()
+                .filter(|class| !before.classes().contains_key(*class) && !is_fact_class(class))
+                .cloned(),
+        )
+        .collect::<Vec<_>>();
+    changed.sort();
+    changed.dedup();
+
+    egraph.parse_and_run_program(&format!(
+        "(ruleset {ruleset}-cleanup)
+         (rule (({applied} rewrite-root)) ((delete ({applied} rewrite-root)))
+               :ruleset {ruleset}-cleanup)
+         (run-schedule (repeat 1 {ruleset}-cleanup))"
+    ))?;
+
+    Ok(changed)
+}
+
+/// Check that the design described by an extraction has no combinational
+/// cycles, i.e. cycles which don't pass through a register.
+///
+/// Returns every cycle found, each as the sequence of eclasses along it.
+/// Cycles through `Reg` and `RegWithClk` nodes are legitimate, as registers
+/// break combinational paths.
+pub fn check_acyclicity(
+    egraph: &egraph_serialize::EGraph,
+    choices: &IndexMap<ClassId, NodeId>,
+) -> Result<(), Vec<Vec<ClassId>>> {
+    fn visit(
+        egraph: &egraph_serialize::EGraph,
+        choices: &IndexMap<ClassId, NodeId>,
+        id: &ClassId,
+        path: &mut Vec<ClassId>,
+        done: &mut HashSet<ClassId>,
+        roots: &mut Vec<ClassId>,
+        cycles: &mut Vec<Vec<ClassId>>,
+    ) {
+        if let Some(start) = path.iter().position(|path_id| path_id == id) {
+            cycles.push(path[start..].to_vec());
+            return;
+        }
+        if done.contains(id) {
+            return;
+        }
+
+        let node = &egraph[&choices[id]];
+        let children = node
+            .children
+            .iter()
+            .map(|child| egraph[child].eclass.clone());
+        let is_register = matches!(node.op.as_str(), "Op1" | "Op2")
+            && matches!(egraph[&node.children[0]].op.as_str(), "Reg" | "RegWithClk");
+        if is_register {
+            // A register's output doesn't depend combinationally on its
+            // inputs, but its inputs still need to be checked.
+            roots.extend(children);
+        } else {
+            path.push(id.clone());
+            for child in children {
+                visit(egraph, choices, &child, path, done, roots, cycles);
+            }
+            path.pop();
+        }
+        done.insert(id.clone());
+    }
+
+    let mut done = HashSet::new();
+    let mut cycles = vec![];
+    let mut roots: Vec<ClassId> = choices.keys().cloned().collect();
+    while let Some(root) = roots.pop() {
+        visit(
+            egraph,
+            choices,
+            &root,
+            &mut vec![],
+            &mut done,
+            &mut roots,
+            &mut cycles,
+        );
+    }
+
+    if cycles.is_empty() {
+        Ok(())
+    } else {
+        Err(cycles)
+    }
+}
+
+/// Extract the part of a design which `output_class` depends on, as chosen by
+/// `choices`, e.g. to generate Verilog for a single output.
+///
+/// The returned egraph contains the chosen nodes reachable from
+/// `output_class`, their types, and the `IsPort`s of `output_class` and of
+/// any inputs it depends on. Other outputs' ports are dropped. `choices`
+/// remains a valid choice of nodes for the returned egraph.
+pub fn extract_submodule(
+    egraph: &egraph_serialize::EGraph,
+    choices: &IndexMap<ClassId, NodeId>,
+    output_class: &ClassId,
+) -> egraph_serialize::EGraph {
+    let mut reached: IndexMap<NodeId, egraph_serialize::Node> = IndexMap::new();
+    let mut queue = VecDeque::from([choices[output_class].clone()]);
+    loop {
+        // Along with each node's children, we need the chosen node of each
+        // child's class, which is the node code generation will actually use.
+        while let Some(node_id) = queue.pop_front() {
+            if reached.contains_key(&node_id) {
+                continue;
+            }
+            let node = &egraph[&node_id];
+            for child in &node.children {
+                queue.push_back(child.clone());
+                if let Some(chosen) = choices.get(&egraph[child].eclass) {
+                    queue.push_back(chosen.clone());
+                }
+            }
+            reached.insert(node_id, node.clone());
+        }
+
+        // Then the types and ports of the classes reached, which may in turn
+        // reach more nodes.
+        let reached_classes: HashSet<&ClassId> =
+            reached.values().map(|node| &node.eclass).collect();
+        queue.extend(
+            egraph
+                .nodes
+                .iter()
+                .filter(|(node_id, _)| !reached.contains_key(*node_id))
+                .filter(|(_, node)| match node.op.as_str() {
+                    "HasType" => reached_classes.contains(&egraph[&node.children[0]].eclass),
+                    "IsPort" => {
+                        let class = &egraph[&node.children[3]].eclass;
+                        match egraph[&node.children[2]].op.as_str() {
+                            "Input" => reached_classes.contains(class),
+                            "Output" => class == output_class,
+                            _ => false,
+                        }
+                    }
+                    _ => false,
+                })
+                .map(|(node_id, _)| node_id.clone()),
+        );
+        if queue.is_empty() {
+            break;
+        }
+    }
+
+    let mut submodule = egraph_serialize::EGraph::default();
+    for (node_id, node) in reached {
+        submodule.add_node(node_id, node);
+    }
+    submodule.root_eclasses = vec![output_class.clone()];
+    submodule
+}
+
+/// The Verilog module definition for a `MakeModule` node, as instantiated by
+/// [`to_verilog_egraph_serialize`] for the `apply` nodes chosen by
+/// [`extract_with_module_library`].
+///
+/// The module has an input `in{i}` for each distinct argument `i` and one
+/// output `out`. Module graphs aren't typed, so port widths are parameters,
+/// set by each instantiation. Only ops which can be written as a single
+/// Verilog expression without knowing their widths are supported; others,
+/// e.g. registers, give an [`ExportError`].
+fn module_definition(
+    egraph: &egraph_serialize::EGraph,
+    make_module: &Node,
+    module_name: &str,
+) -> Result<String, ExportError> {
+    // Holes are numbered left to right; the IVec maps each to the argument
+    // filling it.
+    let argument_indices: Vec<usize> = egraph[&make_module.children[1]]
+        .children
+        .iter()
+        .map(|index| egraph[index].op.parse().unwrap())
+        .collect();
+
+    fn expr(
+        egraph: &egraph_serialize::EGraph,
+        graph: &Node,
+        argument_indices: &[usize],
+        next_hole: &mut usize,
+    ) -> Result<String, ExportError> {
+        if graph.op == "Hole" {
+            let index = argument_indices[*next_hole];
+            *next_hole += 1;
+            return Ok(format!("in{index}"));
+        }
+
+        let op = &egraph[&graph.children[0]];
+        let int = |i: usize| egraph[&op.children[i]].op.parse::<i64>().unwrap();
+        // Arguments must be visited left to right, to number the holes.
+        let args = graph.children[1..]
+            .iter()
+            .map(|child| expr(egraph, &egraph[child], argument_indices, next_hole))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(match (graph.op.as_str(), op.op.as_str()) {
+            // Negative constants are written as their two's complement bits.
+            ("Op0_", "BV") => format!(
+                "{}'d{}",
+                int(1),
+                truncate_value_to_bitwidth(int(0) as u64, int(1) as u64)
+            ),
+            ("Op1_", "Not") => format!("~({})", args[0]),
+            ("Op1_", "LogicNot") => format!("!({})", args[0]),
+            ("Op1_", "ReduceAnd") => format!("&({})", args[0]),
+            ("Op1_", "ReduceOr") => format!("|({})", args[0]),
+            ("Op1_", "ReduceXor") => format!("^({})", args[0]),
+            // Verilog can only select bits of a name.
+            ("Op1_", "Extract") if egraph[&graph.children[1]].op == "Hole" => {
+                format!("{}[{}:{}]", args[0], int(0), int(1))
+            }
+            // Anything else is shifted down and cut to size.
+            ("Op1_", "Extract") => {
+                format!("{}'(({}) >> {})", int(0) - int(1) + 1, args[0], int(1))
+            }
+            // As in `to_verilog_egraph_serialize`, a size cast zero-extends,
+            // and sign-extends a signed value.
+            ("Op1_", "ZeroExtend") => format!("{}'({})", int(0), args[0]),
+            ("Op1_", "SignExtend") => format!("{}'($signed({}))", int(0), args[0]),
+            ("Op2_", "Concat") => format!("{{{}, {}}}", args[0], args[1]),
+            // Division by zero is guarded as in `to_verilog_egraph_serialize`.
+            ("Op2_", "UDiv") => format!("(({1} == 0) ? 0 : {0} / {1})", args[0], args[1]),
+            ("Op2_", "URem") => format!("(({1} == 0) ? {0} : {0} % {1})", args[0], args[1]),
+            ("Op2_", "SDiv") => format!(
+                "(({1} == 0) ? 0 : $signed({0}) / $signed({1}))",
+                args[0], args[1]
+            ),
+            ("Op2_", "SRem") => format!(
+                "(({1} == 0) ? $signed({0}) : $signed({0}) % $signed({1}))",
+                args[0], args[1]
+            ),
+            ("Op2_", "Ashr") => format!("($signed({}) >>> {})", args[0], args[1]),
+            ("Op2_", op @ ("SMul" | "SLt" | "SLe" | "SGt" | "SGe")) => {
+                let verilog_op = match op {
+                    "SMul" => "*",
+                    "SLt" => "<",
+                    "SLe" => "<=",
+                    "SGt" => ">",
+                    "SGe" => ">=",
+                    _ => unreachable!(),
+                };
+                format!("($signed({}) {verilog_op} $signed({}))", args[0], args[1])
+            }
+            ("Op2_", op) => {
+                let verilog_op = match op {
+                    "And" => "&",
+                    "Or" => "|",
+                    "Xor" => "^",
+                    "Add" => "+",
+                    "Sub" => "-",
+                    "Mul" => "*",
+                    "Shl" => "<<",
+                    "Shr" => ">>",
+                    "Eq" => "==",
+                    "Ne" => "!=",
+                    "LogicAnd" => "&&",
+                    "LogicOr" => "||",
+                    _ => {
+                        return Err(ExportError::UnsupportedOp {
+                            op: op.to_owned(),
+                            format: "a Verilog module",
+                        })
+                    }
+                };
+                format!("({} {verilog_op} {})", args[0], args[1])
+            }
+            // A select of zero picks the first expression.
+            ("Op3_", "Mux") => format!("({} ? {} : {})", args[0], args[2], args[1]),
+            (_, op) => {
+                return Err(ExportError::UnsupportedOp {
+                    op: op.to_owned(),
+                    format: "a Verilog module",
+                })
+            }
+        })
+    }
+
+    let arity = argument_indices.iter().max().map_or(0, |max| max + 1);
+    let parameters = (0..arity)
+        .map(|i| format!("parameter IN{i}_WIDTH = 1, "))
+        .collect::<String>();
+    let inputs = (0..arity)
+        .map(|i| format!("  input [IN{i}_WIDTH-1:0] in{i},\n"))
+        .collect::<String>();
+    let body = expr(
+        egraph,
+        &egraph[&make_module.children[0]],
+        &argument_indices,
+        &mut 0,
+    )?;
+    Ok(format!(
+        "module {module_name} #({parameters}parameter OUT_WIDTH = 1) (
+{inputs}  output [OUT_WIDTH-1:0] out
+);
+  assign out = {body};
+endmodule"
+    ))
+}
+
+/// Error found when checking generated Verilog: two declarations drive the
+/// same wire, which would make the Verilog invalid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultipleDriverError {
+    pub wire: String,
+    /// The expressions driving the wire, in the order they were declared.
+    pub first: String,
+    pub second: String,
+}
+
+impl std::fmt::Display for MultipleDriverError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} is driven by both {} and {}",
+            self.wire, self.first, self.second
+        )
+    }
+}
+
+/// Error returned by [`try_to_verilog_egraph_serialize`] for egraphs which
+/// can't be written as valid Verilog.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerilogError {
+    /// Two declarations drive the same wire.
+    MultipleDrivers(MultipleDriverError),
+    /// A `ModuleInstance`'s parameters aren't a list, as described by the
+    /// message.
+    MalformedParameters(String),
+}
+
+impl std::fmt::Display for VerilogError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerilogError::MultipleDrivers(err) => write!(f, "{}", err),
+            VerilogError::MalformedParameters(msg) => {
+                write!(f, "Malformed module parameters: {}", msg)
+            }
+        }
+    }
+}
+
+impl From<MultipleDriverError> for VerilogError {
+    fn from(err: MultipleDriverError) -> Self {
+        VerilogError::MultipleDrivers(err)
+    }
+}
+
+/// Check that no two of the given lines of Verilog drive the same wire, where
+/// a line drives a wire if it's a `wire` or `assign` with an initializer, e.g.
+/// `wire [8-1:0] wire_3 = wire_1 + wire_2;`, or a nonblocking assignment to
+/// the whole wire in an `always` block, e.g. `wire_4 <= wire_3;`. A `logic`
+/// initializer only gives a register its initial value, so it isn't a
+/// driver.
+fn check_single_driver(declarations: &[String]) -> Result<(), MultipleDriverError> {
+    let mut drivers: HashMap<&str, &str> = HashMap::new();
+    for line in declarations {
+        let line = line.trim();
+        let (wire, expr) = if let Some(rest) = ["wire ", "assign "]
+            .iter()
+            .find_map(|keyword| line.strip_prefix(keyword))
+        {
+            // Skip the width, if any.
+            let rest = match rest.strip_prefix('[') {
+                Some(rest) => rest.split_once("] ").map_or(rest, |(_, rest)| rest),
+                None => rest,
+            };
+            let Some((wire, expr)) = rest.split_once(" = ") else {
+                continue;
+            };
+            (wire, expr)
+        } else {
+            // Writes to a memory word, e.g. `if (en != 0) mem[a] <= d;`, don't
+            // drive a whole wire.
+            match line.split_once(" <= ") {
+                Some((wire, expr))
+                    if wire.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') =>
+                {
+                    (wire, expr)
+                }
+                _ => continue,
+            }
+        };
+        let expr = expr.trim_end_matches(';');
+        if let Some(first) = drivers.insert(wire, expr) {
+            return Err(MultipleDriverError {
+                wire: wire.to_owned(),
+                first: first.to_owned(),
+                second: expr.to_owned(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Generate Verilog for the outputs of the design, using the nodes in
+/// `choices`.
+///
+/// Panics if two declarations would drive the same wire, e.g. when two
+/// outputs have the same name. See [`try_to_verilog_egraph_serialize`] to
+/// handle that case instead. The other `to_verilog_egraph_serialize_*`
+/// functions panic in the same way.
+pub fn to_verilog_egraph_serialize(
+    egraph: &egraph_serialize::EGraph,
+    choices: &IndexMap<egraph_serialize::ClassId, egraph_serialize::NodeId>,
+    clk_name: &str,
+) -> String {
+    to_verilog_egraph_serialize_multi_clk(egraph, choices, &HashMap::default(), clk_name)
+}
+
+/// Like [`to_verilog_egraph_serialize`], but returns a [`VerilogError`]
+/// rather than panicking, e.g. if two declarations would drive the same wire.
+pub fn try_to_verilog_egraph_serialize(
+    egraph: &egraph_serialize::EGraph,
+    choices: &IndexMap<egraph_serialize::ClassId, egraph_serialize::NodeId>,
+    clk_name: &str,
+) -> Result<String, VerilogError> {
+    to_verilog_egraph_serialize_impl(egraph, choices, &HashMap::default(), clk_name, None)
+}
+
+/// Like [`to_verilog_egraph_serialize`], but for designs with multiple clock
+/// domains.
+///
+/// The clock for each register is chosen as follows: `RegWithClk` registers
+/// use their clock expression; other registers use the clock assigned to
+/// their eclass in `clk_assignments`, if any. Failing that, `Op2` registers
+/// use their clock expression and `Op1` registers use `default_clk_name`.
+/// Clock expressions which are inputs are referred to by port name. Clocks
+/// named in `clk_assignments` which aren't already inputs of the design, and
+/// aren't `default_clk_name`, are declared as 1-bit inputs.
+pub fn to_verilog_egraph_serialize_multi_clk(
+    egraph: &egraph_serialize::EGraph,
+    choices: &IndexMap<egraph_serialize::ClassId, egraph_serialize::NodeId>,
+    clk_assignments: &HashMap<ClassId, String>,
+    default_clk_name: &str,
+) -> String {
+    to_verilog_egraph_serialize_impl(egraph, choices, clk_assignments, default_clk_name, None)
+        .unwrap_or_else(|err| panic!("{err}"))
+}
+
+/// Like [`to_verilog_egraph_serialize`], but only generates the module named
+/// `module_name` in the `IsPort` facts of an egraph holding several modules.
+/// The generated Verilog module is given the same name.
+///
+/// ```
+/// use churchroad::*;
+/// use egglog::{EGraph, SerializeConfig};
+///
+/// let mut egraph = EGraph::default();
+/// import_churchroad(&mut egraph).unwrap();
+/// egraph
+///     .parse_and_run_program(
+///         r#"
+/// (IsPort "inverter" "out" (Output) (Op1 (Not) (Var "a" 1)))
+/// (IsPort "buffer" "out2" (Output) (Var "b" 1))
+/// (run-schedule (saturate typing))
+/// "#,
+///     )
+///     .unwrap();
+///
+/// let serialized = egraph.serialize(SerializeConfig::default());
+/// let choices = AnythingExtractor.extract(&serialized, &[]);
+/// let verilog = to_verilog_egraph_serialize_module(&serialized, &choices, "inverter", "");
+/// assert!(verilog.contains("module inverter("));
+/// assert!(verilog.contains("output [1-1:0] out"));
+/// assert!(!verilog.contains("out2"));
+/// ```
+pub fn to_verilog_egraph_serialize_module(
+    egraph: &egraph_serialize::EGraph,
+    choices: &IndexMap<egraph_serialize::ClassId, egraph_serialize::NodeId>,
+    module_name: &str,
+    clk_name: &str,
+) -> String {
+    to_verilog_egraph_serialize_impl(
+        egraph,
+        choices,
+        &HashMap::default(),
+        clk_name,
+        Some(module_name),
+    )
+    .unwrap_or_else(|err| panic!("{err}"))
+}
+
+/// Generate Verilog for a design imported with its hierarchy kept (see
+/// [`from_verilog_file`]): the module `top`, followed by every other module
+/// with ports in the egraph, in order of name, each as generated by
+/// [`to_verilog_egraph_serialize_module`]. Instances of the other modules in
+/// `top` thus refer to their definitions.
+pub fn to_verilog_egraph_serialize_hierarchy(
+    egraph: &egraph_serialize::EGraph,
+    choices: &IndexMap<egraph_serialize::ClassId, egraph_serialize::NodeId>,
+    top: &str,
+    clk_name: &str,
+) -> String {
+    let modules = egraph
+        .nodes
+        .values()
+        .filter(|node| node.op == "IsPort")
+        .map(|node| {
+            let name = &egraph[&node.children[0]].op;
+            &name[1..name.len() - 1]
+        })
+        .filter(|module| *module != top)
+        .collect::<std::collections::BTreeSet<_>>();
+
+    std::iter::once(top)
+        .chain(modules)
+        .map(|module| to_verilog_egraph_serialize_module(egraph, choices, module, clk_name))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Generate Verilog for the outputs of `module`, or of every module if it's
+/// `None`, as described in [`to_verilog_egraph_serialize_multi_clk`].
+fn to_verilog_egraph_serialize_impl(
+    egraph: &egraph_serialize::EGraph,
+    choices: &IndexMap<egraph_serialize::ClassId, egraph_serialize::NodeId>,
+    clk_assignments: &HashMap<ClassId, String>,
+    default_clk_name: &str,
+    module: Option<&str>,
+) -> Result<String, VerilogError> {
+    // Whether an `IsPort` node is an output of the module being generated.
+    let is_output = |node: &Node| {
+        node.op == "IsPort"
+            && egraph[&node.children[2]].op == "Output"
+            && module.is_none_or(|module| egraph[&node.children[0]].op == format!("\"{module}\""))
+    };
+
+    // let mut wires = HashMap::default();
+
+    fn id_to_wire_name(id: &ClassId) -> String {
+        format!("wire_{}", id)
+    }
+
+    struct ModuleInstance {
+        module_class_name: String,
+        instance_name: String,
+        parameters: HashMap<String, ClassId>,
+        inputs: HashMap<String, ClassId>,
+        outputs: HashMap<String, ClassId>,
+    }
+    // Maps EClass ID to the module instance at that class.
+    let mut module_instantiations: HashMap<ClassId, ModuleInstance> = HashMap::new();
+    // Definitions of the modules instantiated by chosen `apply` nodes, keyed
+    // by the class of their `MakeModule`.
+    let mut module_definitions: IndexMap<ClassId, String> = IndexMap::new();
+
+    let mut inputs = String::new();
+    let mut outputs = String::new();
+    let mut logic_declarations = String::new();
+    let mut registers = String::new();
+
+    // The width declaration (e.g. `[8-1:0] `) for the wire holding the given
+    // eclass. Empty if the eclass hasn't been typed.
+    let width_of = |id: &ClassId| {
+        get_bitwidth(egraph, id)
+            .map(|bw| format!("[{bw}-1:0] "))
+            .unwrap_or_default()
+    };
+
+    // `Observe` facts don't name a module, so when generating a single module
+    // we only keep the observed signals reachable from its outputs.
+    let mut reachable = HashSet::new();
+    if module.is_some() {
+        let mut stack = egraph
+            .nodes
+            .values()
+            .filter(|node| is_output(node))
+            .map(|node| egraph[&node.children[3]].eclass.clone())
+            .collect::<Vec<_>>();
+        while let Some(id) = stack.pop() {
+            if !reachable.insert(id.clone()) {
+                continue;
+            }
+            // Write ports aren't reachable from the memory itself.
+            let writes = egraph
+                .nodes
+                .values()
+                .filter(|node| node.op == "MemWrite" && egraph[&node.children[0]].eclass == id);
+            stack.extend(
+                choices
+                    .get(&id)
+                    .map(|node_id| &egraph[node_id])
+                    .into_iter()
+                    .chain(writes)
+                    .flat_map(|node| &node.children)
+                    .map(|child| egraph[child].eclass.clone()),
+            );
+        }
+    }
+
+    // Collect all the outputs, including an extra output per `Observe` fact.
+    let output_ports = egraph
+        .nodes
+        .values()
+        .filter(|node| is_output(node))
+        .map(|node| {
+            (
+                &egraph[&node.children[1]].op,
+                &egraph[&node.children[3]].eclass,
+            )
+        })
+        .chain(
+            egraph
+                .nodes
+                .values()
+                .filter(|node| {
+                    node.op == "Observe"
+                        && (module.is_none()
+                            || reachable.contains(&egraph[&node.children[1]].eclass))
+                })
+                .map(|node| {
+                    (
+                        &egraph[&node.children[0]].op,
+                        &egraph[&node.children[1]].eclass,
+                    )
+                }),
+        )
+        .map(|(name, id)| (name[1..name.len() - 1].to_owned(), id.clone()))
+        .collect::<Vec<_>>();
+    let mut queue: Vec<ClassId> = output_ports.iter().map(|(_, id)| id.clone()).collect();
+
+    // Without outputs, there's nothing to generate, which usually means the
+    // program is missing its `IsPort` facts.
+    let warning = if queue.is_empty() {
+        log::warn!("design has no outputs, so the generated module is empty");
+        "// Warning: this design has no outputs. Did you forget to mark them with
+// `(IsPort \"\" \"name\" (Output) expr)` facts?
+"
+    } else {
+        ""
+    };
+
+    // Generate outputs.
+    for (name, id) in &output_ports {
+        outputs.push_str(&format!("output {width}{name},\n", width = width_of(id)));
+
+        // Outputs are driven continuously, so we use `assign` rather than a
+        // declaration with an initializer.
+        logic_declarations.push_str(&format!(
+            "assign {name} = {wire};\n",
+            wire = id_to_wire_name(id)
+        ))
+    }
+
+    // Names of the design's `Var` inputs, and of the clocks used by registers.
+    let mut input_names = HashSet::new();
+    let mut clk_names = HashSet::new();
+
+    let mut done = HashSet::new();
+
+    fn maybe_push_expr_on_queue(
+        queue: &mut Vec<ClassId>,
+        done: &HashSet<ClassId>,
+        class_id: &ClassId,
+    ) {
+        if !queue.contains(class_id) && !done.contains(class_id) {
+            queue.push(class_id.clone());
+        }
+    }
+
+    // The eclasses of the clock, address, data and enable of each write port
+    // of the memory in the given eclass. Write ports are `MemWrite` facts,
+    // which aren't reachable from the memory itself.
+    let memory_writes = |mem: &ClassId| {
+        egraph
+            .nodes
+            .values()
+            .filter(|node| node.op == "MemWrite" && egraph[&node.children[0]].eclass == *mem)
+            .map(|node| [1, 2, 3, 4].map(|i| egraph[&node.children[i]].eclass.clone()))
+            .collect::<Vec<_>>()
+    };
+
+    while let Some(id) = queue.pop() {
+        done.insert(id.clone());
+        let mut term = &egraph[&choices[&id]];
+        // An application of a module which can't be written as a module
+        // definition is replaced by the expression it was unioned with.
+        if term.op == "apply" && module_definition(egraph, &egraph[&term.children[0]], "").is_err()
+        {
+            if let Some(flat) = egraph[&id]
+                .nodes
+                .iter()
+                .map(|node_id| &egraph[node_id])
+                .find(|node| node.op != "apply")
+            {
+                term = flat;
+            }
+        }
+
+        let op = &term.op;
+        match op.as_str() {
+            // Things to ignore.
+            //
+            // Ignore the Unit.
+            "()" |
+            // Ignore various relations/facts.
+            "IsPort" |
+            "Observe" |
+            "Input" |
+            "Output" |
+            // Ignore the nodes for the ops themselves.
+            "ZeroExtend" |
+            "SignExtend" |
+            "Concat" |
+            "Extract" |
+            "Or" |
+            "And" |
+            "Add" |
+            "Sub" |
+            "Mul" |
+            "Mux" |
+            "Pmux" |
+            "LUT" |
+            "Shl" |
+            "Shr" |
+            "Rotl" |
+            "Rotr" |
+            "UDiv" |
+            "URem" |
+            "SMul" |
+            "SDiv" |
+            "SRem" |
+            "Ashr" |
+            "SLt" |
+            "SLe" |
+            "SGt" |
+            "SGe" |
+            "Eq" |
+            "Xor" |
+            "Reg" |
+            "RegWithClk" |
+            "ShiftReg" |
+            "Mem" |
+            "MemRead" |
+            "ReadFirst" |
+            "WriteFirst" |
+            "MemWrite" => (),
+            // Ignore integer literals.
+            v if v.parse::<i64>().is_ok() => (),
+
+            "Op0" | "Op1" | "Op2" | "Op3" => {
+                let op_node = &egraph[&term.children[0]];
+                match op_node.op.as_str() {
+                    "ZeroExtend" => {
+                        assert_eq!(op_node.children.len(), 1);
+                        assert_eq!(term.children.len(), 2);
+                        let bw = egraph[&op_node.children[0]].op.parse::<i64>().unwrap();
+                        let expr_id = &egraph[&term.children[1]].eclass;
+                    // A size cast zero-extends, as Churchroad bitvectors are
+                    // unsigned.
+                    logic_declarations.push_str(
+                        format!(
+                            "wire [{bw}-1:0] {this_wire} = {bw}'({value});\n",
+                            this_wire = id_to_wire_name(&id),
+                            value = id_to_wire_name(expr_id)
+
+                        )
+                        .as_str(),
+                    );
+
+                    maybe_push_expr_on_queue(&mut queue, &done, expr_id);
+
+                    }
+                    "SignExtend" => {
+                        assert_eq!(op_node.children.len(), 1);
+                        assert_eq!(term.children.len(), 2);
+                        let bw = egraph[&op_node.children[0]].op.parse::<i64>().unwrap();
+                        let expr_id = &egraph[&term.children[1]].eclass;
+                        // A size cast of a signed value sign-extends it.
+                        logic_declarations.push_str(&format!(
+                            "wire [{bw}-1:0] {this_wire} = {bw}'($signed({value}));\n",
+                            this_wire = id_to_wire_name(&id),
+                            value = id_to_wire_name(expr_id)
+                        ));
+
+                        maybe_push_expr_on_queue(&mut queue, &done, expr_id);
+                    }
+                    "BV" => {
+                        assert_eq!(op_node.children.len(), 2);
+                        let value = egraph[&op_node.children[0]].op.parse::<i64>().unwrap();
+                        let bw = egraph[&op_node.children[1]].op.parse::<u64>().unwrap();
+                        // 64-bit constants with the top bit set are negative.
+                        let bits = truncate_value_to_bitwidth(value as u64, bw);
+
+                    logic_declarations.push_str(
+                        format!(
+                            "wire [{bw}-1:0] {this_wire} = {bw}'d{bits};\n",
+                            this_wire = id_to_wire_name(&id),
+                        )
+                        .as_str(),
+                    );
+                    }
+                    "SignedBV" => {
+                        assert_eq!(op_node.children.len(), 2);
+                        let value = egraph[&op_node.children[0]].op.parse::<i64>().unwrap();
+                        let bw = egraph[&op_node.children[1]].op.parse::<u64>().unwrap();
+                        // Emit the two's complement bits as a signed literal,
+                        // e.g. 8'sh80 for -128.
+                        let bits = truncate_value_to_bitwidth(value as u64, bw);
+
+                    logic_declarations.push_str(
+                        format!(
+                            "wire [{bw}-1:0] {this_wire} = {bw}'sh{bits:x};\n",
+                            this_wire = id_to_wire_name(&id),
+                        )
+                        .as_str(),
+                    );
+                    }
+                    "Mem" => {
+                        let address_bw = egraph[&op_node.children[1]].op.parse::<u32>().unwrap();
+                        let data_bw = egraph[&op_node.children[2]].op.parse::<u64>().unwrap();
+                        let init = egraph[&op_node.children[3]].op.parse::<i64>().unwrap();
+                        let mem = id_to_wire_name(&id);
+                        let words = 1u64 << address_bw;
+                        logic_declarations.push_str(&format!(
+                            "logic [{data_bw}-1:0] {mem} [0:{words}-1];\ninitial for (int i = 0; i < {words}; i++) {mem}[i] = {data_bw}'d{init};\n",
+                            init = truncate_value_to_bitwidth(init as u64, data_bw),
+                        ));
+
+                        for [clk_id, address_id, data_id, enable_id] in memory_writes(&id) {
+                            // Clocks are named as for registers, below.
+                            let clk_node = &egraph[&choices[&clk_id]];
+                            let clk = if clk_node.op == "Var" {
+                                egraph[&clk_node.children[0]].op.as_str().strip_prefix('\"').unwrap().strip_suffix('\"').unwrap().to_owned()
+                            } else {
+                                id_to_wire_name(&clk_id)
+                            };
+                            registers.push_str(&format!(
+                                "always @(posedge {clk}) begin
+                            if ({enable} != 0) {mem}[{address}] <= {data};
+                        end\n",
+                                enable = id_to_wire_name(&enable_id),
+                                address = id_to_wire_name(&address_id),
+                                data = id_to_wire_name(&data_id),
+                            ));
+                            for class in [&clk_id, &address_id, &data_id, &enable_id] {
+                                maybe_push_expr_on_queue(&mut queue, &done, class);
+                            }
+                        }
+                    }
+                    "MemRead" => {
+                        assert_eq!(term.children.len(), 3);
+                        let mem_id = &egraph[&term.children[1]].eclass;
+                        let address_id = &egraph[&term.children[2]].eclass;
+                        let mut read = format!("{}[{}]", id_to_wire_name(mem_id), id_to_wire_name(address_id));
+                        // Writes land at the end of the cycle, so a
+                        // write-first read forwards the data being written.
+                        if egraph[&op_node.children[0]].op == "WriteFirst" {
+                            for [_, write_address_id, data_id, enable_id] in memory_writes(mem_id) {
+                                read = format!(
+                                    "({enable} != 0 && {write_address} == {address}) ? {data} : {read}",
+                                    enable = id_to_wire_name(&enable_id),
+                                    write_address = id_to_wire_name(&write_address_id),
+                                    address = id_to_wire_name(address_id),
+                                    data = id_to_wire_name(&data_id),
+                                );
+                                for class in [&write_address_id, &data_id, &enable_id] {
+                                    maybe_push_expr_on_queue(&mut queue, &done, class);
+                                }
+                            }
+                        }
+                        logic_declarations.push_str(&format!(
+                            "wire {width}{this_wire} = {read};\n",
+                            width = width_of(&term.eclass),
+                            this_wire = id_to_wire_name(&term.eclass),
+                        ));
+
+                        maybe_push_expr_on_queue(&mut queue, &done, mem_id);
+                        maybe_push_expr_on_queue(&mut queue, &done, address_id);
+                    }
+                    "Reg" | "RegWithClk" | "ShiftReg" => {
+                        // Registers come in three forms: `(Op1 (Reg init) d)`,
+                        // `(Op2 (Reg init) clk d)`, and
+                        // `(Op2 (RegWithClk init) clk d)`. In every form, the
+                        // init value is the op's last child and the data input
+                        // is the last child. The same goes for
+                        // `(Op2 (ShiftReg depth init) clk d)`, which is
+                        // emitted as a chain of `depth` registers.
+                        let reg_output_id = &term.eclass;
+                        let reg_default_val = egraph[op_node.children.last().unwrap()].op.parse::<i64>().unwrap();
+                        let reg_d_id = &egraph[term.children.last().unwrap()].eclass;
+
+                        // Every register is clocked on the positive edge of
+                        // the clock chosen as described in the doc comment of
+                        // this function.
+                        let reg_clk_name = if let Some(name) = clk_assignments
+                            .get(reg_output_id)
+                            .filter(|_| op_node.op != "RegWithClk")
+                        {
+                            clk_names.insert(name.clone());
+                            name.clone()
+                        } else if term.children.len() == 3 {
+                            // The clock expression is visited like any other,
+                            // so a clock input port gets declared, and any
+                            // other clock gets a wire.
+                            let clk_id = &egraph[&term.children[1]].eclass;
+                            maybe_push_expr_on_queue(&mut queue, &done, clk_id);
+                            let clk_node = &egraph[&choices[clk_id]];
+                            if clk_node.op == "Var" {
+                                egraph[&clk_node.children[0]].op.as_str().strip_prefix('\"').unwrap().strip_suffix('\"').unwrap().to_owned()
+                            } else {
+                                id_to_wire_name(clk_id)
+                            }
+                        } else {
+                            clk_names.insert(default_clk_name.to_owned());
+                            default_clk_name.to_owned()
+                        };
+
+                    // The registers making up the chain, from the one fed by
+                    // the data input to the one driving the output.
+                    let depth: usize = if op_node.op == "ShiftReg" {
+                        egraph[&op_node.children[0]].op.parse().unwrap()
+                    } else {
+                        1
+                    };
+                    let stages: Vec<String> = (0..depth - 1)
+                        .map(|i| format!("{}_stage{i}", id_to_wire_name(reg_output_id)))
+                        .chain(std::iter::once(id_to_wire_name(reg_output_id)))
+                        .collect();
+
+                    for stage in &stages {
+                        logic_declarations.push_str(
+                            format!(
+                                "logic {width}{this_wire} = {default};\n",
+                                width = width_of(reg_output_id),
+                                this_wire = stage,
+                                default = reg_default_val
+                            )
+                            .as_str(),
+                        );
+                    }
+
+                    let assignments = std::iter::once(id_to_wire_name(reg_d_id))
+                        .chain(stages.iter().cloned())
+                        .zip(stages.iter())
+                        .map(|(d, stage)| format!("{stage} <= {d};"))
+                        .collect::<Vec<_>>()
+                        .join("\n                            ");
+                    registers.push_str(&format!(
+                        "always @(posedge {reg_clk_name}) begin
+                            {assignments}
+                        end\n",
+                    ));
+
+                    maybe_push_expr_on_queue(&mut queue, &done, reg_d_id);
+                    },
+                    // Note that Shr is a logical (unsigned) right shift, so
+                    // we emit Verilog's `>>`. Verilog's `>>>` performs an
+                    // arithmetic shift when its left operand is signed; we
+                    // never emit it, as Churchroad bitvectors are unsigned.
+                    "Concat" | "Xor" |"And" | "Or" | "Add" | "Sub" | "Mul" | "Shl" | "Shr" =>  {
+                            assert_eq!(term.children.len(), 3);
+                    let expr0_id = &egraph[&term.children[1]].eclass;
+                    let  expr1_id = &egraph[&term.children[2]].eclass;
+                    logic_declarations.push_str(&format!(
+                        "wire {width}{this_wire} = {op};\n",
+                        width = width_of(&term.eclass),
+                        op = match op_node.op.as_str() {
+
+                            // The first child holds the high bits, matching
+                            // Verilog's concatenation order.
+                            "Concat" => format!("{{ {expr0}, {expr1} }}",
+                        expr0 = id_to_wire_name(expr0_id),
+                        expr1 = id_to_wire_name(expr1_id),
+                        ),
+                            "Xor" => format!("{expr0}^{expr1}",
+                        expr0 = id_to_wire_name(expr0_id),
+                        expr1 = id_to_wire_name(expr1_id),
+                        ),
+                            "And" => format!("{expr0}&{expr1}",
+                        expr0 = id_to_wire_name(expr0_id),
+                        expr1 = id_to_wire_name(expr1_id),
+                        ),
+                            "Or" => format!("{expr0}|{expr1}",
+                        expr0 = id_to_wire_name(expr0_id),
+                        expr1 = id_to_wire_name(expr1_id),
+                        ),
+                            "Add" => format!("{expr0}+{expr1}",
+                        expr0 = id_to_wire_name(expr0_id),
+                        expr1 = id_to_wire_name(expr1_id),
+                        ),
+                            "Sub" => format!("{expr0}-{expr1}",
+                        expr0 = id_to_wire_name(expr0_id),
+                        expr1 = id_to_wire_name(expr1_id),
+                        ),
+                            "Mul" => format!("{expr0}*{expr1}",
+                        expr0 = id_to_wire_name(expr0_id),
+                        expr1 = id_to_wire_name(expr1_id),
+                        ),
+                            "Shl" => format!("{expr0}<<{expr1}",
+                        expr0 = id_to_wire_name(expr0_id),
+                        expr1 = id_to_wire_name(expr1_id),
+                        ),
+                            "Shr" => format!("{expr0}>>{expr1}",
+                        expr0 = id_to_wire_name(expr0_id),
+                        expr1 = id_to_wire_name(expr1_id),
+                        ),
+                        _ => unreachable!("missing a match arm"),
+                        } ,
+                        this_wire = id_to_wire_name(&term.eclass),
+                    ));
+
+                    maybe_push_expr_on_queue(&mut queue, &done, expr0_id);
+                    maybe_push_expr_on_queue(&mut queue, &done, expr1_id);
+                }
+                // Verilog leaves division by zero undefined, so we guard it to
+                // match the interpreter: the quotient is zero and the
+                // remainder is the dividend.
+                // For the signed ops, every operand of the conditional must be
+                // signed, or the whole expression is evaluated as unsigned.
+                "UDiv" | "URem" | "SDiv" | "SRem" => {
+                    assert_eq!(term.children.len(), 3);
+                    let expr0 = id_to_wire_name(&egraph[&term.children[1]].eclass);
+                    let expr1 = id_to_wire_name(&egraph[&term.children[2]].eclass);
+                    let op = match op_node.op.as_str() {
+                        "UDiv" => format!("({expr1} == 0) ? 0 : {expr0} / {expr1}"),
+                        "URem" => format!("({expr1} == 0) ? {expr0} : {expr0} % {expr1}"),
+                        "SDiv" => {
+                            format!("({expr1} == 0) ? 0 : $signed({expr0}) / $signed({expr1})")
+                        }
+                        "SRem" => format!(
+                            "({expr1} == 0) ? $signed({expr0}) : $signed({expr0}) % $signed({expr1})"
+                        ),
+                        _ => unreachable!(),
+                    };
+                    logic_declarations.push_str(&format!(
+                        "wire {width}{this_wire} = {op};\n",
+                        width = width_of(&term.eclass),
+                        this_wire = id_to_wire_name(&term.eclass),
+                    ));
+
+                    maybe_push_expr_on_queue(&mut queue, &done, &egraph[&term.children[1]].eclass);
+                    maybe_push_expr_on_queue(&mut queue, &done, &egraph[&term.children[2]].eclass);
+                }
+                // Signed operations cast their operands with $signed. The
+                // shift amount of Ashr stays unsigned.
+                "SMul" | "Ashr" | "SLt" | "SLe" | "SGt" | "SGe" => {
+                    assert_eq!(term.children.len(), 3);
+                    let expr0 = id_to_wire_name(&egraph[&term.children[1]].eclass);
+                    let expr1 = id_to_wire_name(&egraph[&term.children[2]].eclass);
+                    let op = match op_node.op.as_str() {
+                        "SMul" => format!("$signed({expr0}) * $signed({expr1})"),
+                        "Ashr" => format!("$signed({expr0}) >>> {expr1}"),
+                        "SLt" => format!("$signed({expr0}) < $signed({expr1})"),
+                        "SLe" => format!("$signed({expr0}) <= $signed({expr1})"),
+                        "SGt" => format!("$signed({expr0}) > $signed({expr1})"),
+                        "SGe" => format!("$signed({expr0}) >= $signed({expr1})"),
+                        _ => unreachable!(),
+                    };
+                    logic_declarations.push_str(&format!(
+                        "wire {width}{this_wire} = {op};\n",
+                        width = width_of(&term.eclass),
+                        this_wire = id_to_wire_name(&term.eclass),
+                    ));
+
+                    maybe_push_expr_on_queue(&mut queue, &done, &egraph[&term.children[1]].eclass);
+                    maybe_push_expr_on_queue(&mut queue, &done, &egraph[&term.children[2]].eclass);
+                }
+                // Rotations are emitted as two shifts ORed together. Shifting
+                // by the full width gives zero, so rotating by a multiple of
+                // the width gives back the original value.
+                "Rotl" | "Rotr" => {
+                    assert_eq!(term.children.len(), 3);
+                    let expr0_id = &egraph[&term.children[1]].eclass;
+                    let expr1_id = &egraph[&term.children[2]].eclass;
+                    let bw = get_bitwidth(egraph, &term.eclass).unwrap();
+                    let (first_shift, second_shift) = match op_node.op.as_str() {
+                        "Rotl" => ("<<", ">>"),
+                        "Rotr" => (">>", "<<"),
+                        _ => unreachable!(),
+                    };
+                    logic_declarations.push_str(&format!(
+                        "wire [{bw}-1:0] {this_wire} = ({expr0} {first_shift} ({expr1} % {bw})) | ({expr0} {second_shift} ({bw} - ({expr1} % {bw})));\n",
+                        this_wire = id_to_wire_name(&term.eclass),
+                        expr0 = id_to_wire_name(expr0_id),
+                        expr1 = id_to_wire_name(expr1_id),
+                    ));
+
+                    maybe_push_expr_on_queue(&mut queue, &done, expr0_id);
+                    maybe_push_expr_on_queue(&mut queue, &done, expr1_id);
+                }
+                "Extract" => {//}, [hi_id, lo_id, expr_id]) => {
+                    assert_eq!(term.children.len(), 2);
+                    assert_eq!(op_node.children.len(), 2);
+                    let hi:i64 = egraph[&op_node.children[0]].op.parse().unwrap();
+                    let lo:i64 = egraph[&op_node.children[1]].op.parse().unwrap();
+                    let id = &term.eclass;
+                    let expr_id = &egraph[&term.children[1]].eclass;
+                    logic_declarations.push_str(&format!(
+                        "wire [{bw}-1:0] {this_wire} = {expr}[{hi}:{lo}];\n",
+                        bw = hi - lo + 1,
+                        hi = hi,
+                        lo = lo,
+                        this_wire = id_to_wire_name(id),
+                        expr = id_to_wire_name(expr_id),
+                    ));
+
+                    maybe_push_expr_on_queue(&mut queue, &done, expr_id);
+                }
+                "Mux" => {
+                    assert_eq!(term.children.len(), 4);
+                    let sel_id = &egraph[&term.children[1]].eclass;
+                    let expr0_id = &egraph[&term.children[2]].eclass;
+                    let expr1_id = &egraph[&term.children[3]].eclass;
+                    // As in the interpreter, a select of zero picks the
+                    // first expression.
+                    logic_declarations.push_str(&format!(
+                        "wire {width}{this_wire} = {sel} ? {expr1} : {expr0};\n",
+                        width = width_of(&term.eclass),
+                        this_wire = id_to_wire_name(&term.eclass),
+                        sel = id_to_wire_name(sel_id),
+                        expr0 = id_to_wire_name(expr0_id),
+                        expr1 = id_to_wire_name(expr1_id),
+                    ));
+
+                    maybe_push_expr_on_queue(&mut queue, &done, sel_id);
+                    maybe_push_expr_on_queue(&mut queue, &done, expr0_id);
+                    maybe_push_expr_on_queue(&mut queue, &done, expr1_id);
+                }
+                // Emitted as a chain of ternaries in which the highest set
+                // select bit wins, as in Yosys's simulation model of $pmux.
+                "Pmux" => {
+                    assert_eq!(term.children.len(), 4);
+                    let sel_id = &egraph[&term.children[1]].eclass;
+                    let default_id = &egraph[&term.children[2]].eclass;
+                    let cases_id = &egraph[&term.children[3]].eclass;
+                    let bw = get_bitwidth(egraph, &term.eclass).unwrap();
+                    let num_cases = get_bitwidth(egraph, sel_id).unwrap();
+                    let sel = id_to_wire_name(sel_id);
+                    let cases = id_to_wire_name(cases_id);
+                    // Build the chain inside out, so the highest select bit is
+                    // checked first.
+                    let mut op = id_to_wire_name(default_id);
+                    for i in 0..num_cases {
+                        op = format!("{sel}[{i}] ? {cases}[{lo} +: {bw}] : ({op})", lo = i * bw);
+                    }
+                    logic_declarations.push_str(&format!(
+                        "wire [{bw}-1:0] {this_wire} = {op};\n",
+                        this_wire = id_to_wire_name(&term.eclass),
+                    ));
+
+                    maybe_push_expr_on_queue(&mut queue, &done, sel_id);
+                    maybe_push_expr_on_queue(&mut queue, &done, default_id);
+                    maybe_push_expr_on_queue(&mut queue, &done, cases_id);
+                }
+                "LUT" => {
+                    assert_eq!(term.children.len(), 2);
+                    assert_eq!(op_node.children.len(), 2);
+                    let k: i64 = egraph[&op_node.children[0]].op.parse().unwrap();
+                    let init: i64 = egraph[&op_node.children[1]].op.parse().unwrap();
+                    assert!((1..=6).contains(&k));
+                    let expr_id = &egraph[&term.children[1]].eclass;
+                    // 4-input LUTs are emitted as Xilinx-style LUT4
+                    // primitives, with I0-I3 and O ports, which assumes a
+                    // Xilinx target; Lattice's LUT4, for one, has different
+                    // ports. Other LUTs are emitted as Xilinx LUT6
+                    // primitives, with inputs past the kth tied to zero, so
+                    // that only the low 2^k bits of INIT are used.
+                    let (lut_size, init) = if k == 4 {
+                        (4, format!("16'h{:04x}", init as u64 & 0xffff))
+                    } else {
+                        (6, format!("64'h{init:016x}"))
+                    };
+                    let lut_inputs = (0..lut_size)
+                        .map(|i| {
+                            if i < k {
+                                format!(".I{i}({expr}[{i}])", expr = id_to_wire_name(expr_id))
+                            } else {
+                                format!(".I{i}(1'b0)")
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    logic_declarations.push_str(&format!(
+                        "wire {this_wire};\nLUT{lut_size} #(.INIT({init})) lut_{id} ({lut_inputs}, .O({this_wire}));\n",
+                        this_wire = id_to_wire_name(&term.eclass),
+                        id = term.eclass,
+                    ));
+
+                    maybe_push_expr_on_queue(&mut queue, &done, expr_id);
+                }
+
+                v => todo!("{:?}", v),
+
+                }
+
+            }
+
+                "Var" => {//}, [name_id, bw_id]) => {
+                    assert_eq!(term.children.len(), 2);
+
+                        let name = egraph[&term.children[0]].op.as_str().strip_prefix('\"').unwrap().strip_suffix('\"').unwrap();
+                        let bw: i64 = egraph[&term.children[1]].op.parse().unwrap();
+
+                    inputs.push_str(
+                        format!("input [{bw}-1:0] {name},\n", bw = bw, name = name).as_str(),
+                    );
+                    input_names.insert(name.to_owned());
+
+                    logic_declarations.push_str(
+                        format!(
+                            "wire [{bw}-1:0] {this_wire} = {name};\n",
+                            bw = bw,
+                            this_wire = id_to_wire_name(&term.eclass),
+                            name = name
+                        )
+                        .as_str(),
+                    );
+                }
+
+                // Skip string literals.
+            _ if term.eclass.to_string().starts_with("String") => (),
+
+            "PrimitiveInterfaceCARRY8" | "PrimitiveInterfaceCARRY8_CO" => {
+                assert_eq!(term.children.len(), 3);
+                let ci_id = &egraph[&term.children[0]].eclass;
+                let a_id = &egraph[&term.children[1]].eclass;
+                let b_id = &egraph[&term.children[2]].eclass;
+                let bw = get_bitwidth(egraph, a_id).unwrap();
+                assert!(bw <= 8);
+                let instance_name = format!("carry8_{}", term.eclass);
+
+                // A Xilinx CARRY8 computes a + b + ci when given a as its
+                // generate (DI) input and a ^ b as its propagate (S) input.
+                // Each sum and carry out gets its own instance.
+                logic_declarations.push_str(&format!(
+                    "wire [7:0] {instance_name}_o;\nwire [7:0] {instance_name}_co;\nCARRY8 #(.CARRY_TYPE(\"SINGLE_CY8\")) {instance_name} (.CI({ci}), .CI_TOP(1'b0), .DI(8'({a})), .S(8'({a}) ^ 8'({b})), .O({instance_name}_o), .CO({instance_name}_co));\nwire {width}{this_wire} = {value};\n",
+                    ci = id_to_wire_name(ci_id),
+                    a = id_to_wire_name(a_id),
+                    b = id_to_wire_name(b_id),
+                    width = width_of(&term.eclass),
+                    this_wire = id_to_wire_name(&term.eclass),
+                    value = if op == "PrimitiveInterfaceCARRY8" {
+                        format!("{instance_name}_o[{}:0]", bw - 1)
+                    } else {
+                        format!("{instance_name}_co[{}]", bw - 1)
+                    },
+                ));
+
+                maybe_push_expr_on_queue(&mut queue, &done, ci_id);
+                maybe_push_expr_on_queue(&mut queue, &done, a_id);
+                maybe_push_expr_on_queue(&mut queue, &done, b_id);
+            }
+
+            // An application of a module becomes an instance of the module,
+            // whose definition follows the top module.
+            "apply" => {
+                assert_eq!(term.children.len(), 2);
+                let make_module = &egraph[&term.children[0]];
+                assert_eq!(make_module.op, "MakeModule");
+                let module_name = format!("churchroad_module_{}", make_module.eclass);
+                module_definitions
+                    .entry(make_module.eclass.clone())
+                    .or_insert_with(|| {
+                        module_definition(egraph, make_module, &module_name)
+                            .unwrap_or_else(|err| panic!("{err}"))
+                    });
+
+                // There's an argument for each hole, so a repeated argument is
+                // connected once, by its first hole.
+                let mut arguments: IndexMap<&str, &ClassId> = IndexMap::new();
+                for (index, arg) in egraph[&make_module.children[1]]
+                    .children
+                    .iter()
+                    .zip(egraph[&term.children[1]].children.iter())
+                {
+                    arguments
+                        .entry(egraph[index].op.as_str())
+                        .or_insert(&egraph[arg].eclass);
+                }
+
+                let parameters = arguments
+                    .iter()
+                    .map(|(index, arg)| {
+                        format!(".IN{index}_WIDTH({})", get_bitwidth(egraph, arg).unwrap())
+                    })
+                    .chain([format!(
+                        ".OUT_WIDTH({})",
+                        get_bitwidth(egraph, &term.eclass).unwrap()
+                    )])
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let ports = arguments
+                    .iter()
+                    .map(|(index, arg)| format!(".in{index}({})", id_to_wire_name(arg)))
+                    .chain([format!(".out({})", id_to_wire_name(&term.eclass))])
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                logic_declarations.push_str(&format!(
+                    "wire {width}{this_wire};\n{module_name} #({parameters}) apply_{id} ({ports});\n",
+                    width = width_of(&term.eclass),
+                    this_wire = id_to_wire_name(&term.eclass),
+                    id = term.eclass,
+                ));
+
+                for arg in arguments.values() {
+                    maybe_push_expr_on_queue(&mut queue, &done, arg);
+                }
+            }
+
+            "GetOutput" => {
+                assert_eq!(term.children.len(), 2);
+
+                let module_class = &egraph[&term.children[0]].eclass;
+                let _output_class = &egraph[&term.children[1]].eclass;
+                let output_name = egraph[&term.children[1]].op.as_str().strip_prefix('\"').unwrap().strip_suffix('\"').unwrap();
+
+                // get module class name (e.g. mymodule in `mymodule m (ports);`)
+                assert_eq!(egraph[module_class].nodes.len(),1);
+                let module_instance_node = &egraph[&egraph[module_class].nodes[0]];
+                assert_eq!(module_instance_node.op, "ModuleInstance");
+                assert_eq!(module_instance_node.children.len(), 5);
+                let module_class_name = egraph[&module_instance_node.children[0].clone()].op.as_str().strip_prefix('\"').unwrap().strip_suffix('\"').unwrap();
+
+
+                fn cons_list_to_vec(egraph: &egraph_serialize::EGraph, cons_class_id: &ClassId) -> Vec<ClassId> {
+                    assert_eq!(egraph[cons_class_id].nodes.len(), 1);
+                    let cons_node = &egraph[&egraph[cons_class_id].nodes[0]];
+                    match cons_node.op.as_str() {
+                        "StringCons" | "ExprCons" => {
+                            assert_eq!(cons_node.children.len(), 2);
+                            [egraph[&cons_node.children[0]].eclass.clone()].iter().chain(cons_list_to_vec(egraph, &egraph[&cons_node.children[1]].eclass).iter()).cloned().collect()
+                        }
+                        "StringNil" | "ExprNil" => {
+                            assert_eq!(cons_node.children.len(), 0);
+                            vec![]
+                        }
+                        _ => unreachable!()
+                    }
+
+                }
+
+                fn class_id_vec_to_strings(egraph: &egraph_serialize::EGraph, class_id_vec: Vec<ClassId>) -> Vec<String> {
+                    class_id_vec.iter().map(|id| {
+                        assert_eq!(egraph[id].nodes.len(), 1);
+                        egraph[&egraph[id].nodes[0]].op.as_str().strip_prefix('\"').unwrap().strip_suffix('\"').unwrap().to_owned()
+                    }).collect()
+                }
+
+                // Get module input names and input exprs.
+                let parameter_names= class_id_vec_to_strings(egraph, cons_list_to_vec(egraph, &egraph[&module_instance_node.children[1]].eclass));
+                let parameter_exprs=  cons_list_to_vec(egraph, &egraph[&module_instance_node.children[2]].eclass);
+                let input_port_names= class_id_vec_to_strings(egraph, cons_list_to_vec(egraph, &egraph[&module_instance_node.children[3]].eclass));
+                let input_port_exprs=  cons_list_to_vec(egraph, &egraph[&module_instance_node.children[4]].eclass);
+                assert_eq!(parameter_exprs.len(), parameter_names.len());
+                assert_eq!(input_port_exprs.len(), input_port_names.len());
+
+                for expr in input_port_exprs.iter().chain(parameter_exprs.iter()) {
+                    maybe_push_expr_on_queue(&mut queue, &done, expr);
+                }
+
+                // If we haven't seen this module yet, create a new module instance.
+                if !module_instantiations.contains_key(module_class) {
+                    module_instantiations.insert(module_class.clone(), ModuleInstance {
+                        module_class_name: module_class_name.to_owned(),
+                        instance_name: format!("module_{}", module_class),
+                        parameters: parameter_names.into_iter().zip(parameter_exprs.into_iter()).collect(),
+                        inputs: input_port_names.into_iter().zip(input_port_exprs.into_iter()).collect(),
+                        outputs: [(output_name.to_owned(), term.eclass.clone())].into(),
+                    });
+                } else if let Some(module_instance) = module_instantiations.get_mut(module_class) {
+                    module_instance.outputs.insert(output_name.to_owned(), term.eclass.clone());
+                }else {
+                    unreachable!("module_instantiations should contain the module class");
+                }
+
+                logic_declarations.push_str(
+                    format!(
+                        "logic {width}{this_wire};\n",
+                        width = width_of(&term.eclass),
+                        this_wire = id_to_wire_name(&term.eclass),
+                    )
+                    .as_str(),
+                );
+            }
+
+            // Term::Lit(Literal::Int(v)) => {
+            //     logic_declarations.push_str(&format!(
+            //         "logic [31:0] {this_wire} = {val};\n",
+            //         this_wire = id_to_wire_name(id),
+            //         val = v
+            //     ));
+            // }
+            // Term::Var(_) => todo!(),
+            // Term::App(s, v) => match (s.as_str(), v.as_slice()) {
+            //     ("Reg", &[default_id, clk_id, d_id]) => {
+            //         let default_val = match term_dag.get(default_id) {
+            //             Term::Lit(Literal::Int(default_val)) => default_val,
+            //             _ => panic!(),
+            //         };
+
+            //         logic_declarations.push_str(
+            //             format!(
+            //                 "logic {this_wire} = {default};\n",
+            //                 this_wire = id_to_wire_name(id),
+            //                 default = default_val
+            //             )
+            //             .as_str(),
+            //         );
+
+            //         registers.push_str(&format!(
+            //             "always @(posedge {clk}) begin
+            //                 {this_wire} <= {d};
+            //             end\n",
+            //             clk = id_to_wire_name(clk_id),
+            //             this_wire = id_to_wire_name(id),
+            //             d = id_to_wire_name(d_id)
+            //         ));
+
+            //         if !done.contains(&d_id) {
+            //             queue.push(d_id);
+            //         }
+            //         if !done.contains(&clk_id) {
+            //             queue.push(clk_id);
+            //         }
+            //     }
+            //     ("Var", [name_id, bw_id]) => {
+            //         let name = match term_dag.get(*name_id) {
+            //             Term::Lit(Literal::String(name)) => name,
+            //             _ => panic!(),
+            //         };
+            //         let bw = match term_dag.get(*bw_id) {
+            //             Term::Lit(Literal::Int(bw)) => bw,
+            //             _ => panic!(),
+            //         };
+
+            //         inputs.push_str(
+            //             format!("input [{bw}-1:0] {name};\n", bw = bw, name = name).as_str(),
+            //         );
+
+            //         logic_declarations.push_str(
+            //             format!(
+            //                 "logic [{bw}-1:0] {this_wire} = {name};\n",
+            //                 bw = bw,
+            //                 this_wire = id_to_wire_name(id),
+            //                 name = name
+            //             )
+            //             .as_str(),
+            //         );
+            //     }
+            //     ("Mux", []) => (),
+            //     ("LUT4", []) => (),
+            //     ("Or", []) => (),
+            //     ("Bitvector", [_]) => (),
+            //     ("Eq", []) => (),
+            //     ("BV", [val_id, bw_id]) => {
+            //         let val = match term_dag.get(*val_id) {
+            //             Term::Lit(Literal::Int(val)) => val,
+            //             _ => panic!(),
+            //         };
+            //         let bw = match term_dag.get(*bw_id) {
+            //             Term::Lit(Literal::Int(bw)) => bw,
+            //             _ => panic!(),
+            //         };
+            //         logic_declarations.push_str(
+            //             format!(
+            //                 "logic [{bw}-1:0] {this_wire} = {bw}'d{val};\n",
+            //                 bw = bw,
+            //                 this_wire = id_to_wire_name(id),
+            //                 val = val
+            //             )
+            //             .as_str(),
+            //         );
+            //     }
+            //     ("Extract", [hi_id, lo_id, expr_id]) => {
+            //         let hi = match term_dag.get(*hi_id) {
+            //             Term::Lit(Literal::Int(hi)) => hi,
+            //             _ => panic!(),
+            //         };
+            //         let lo = match term_dag.get(*lo_id) {
+            //             Term::Lit(Literal::Int(lo)) => lo,
+            //             _ => panic!(),
+            //         };
+            //         logic_declarations.push_str(&format!(
+            //             "logic {this_wire} = {expr}[{hi}:{lo}];\n",
+            //             hi = hi,
+            //             lo = lo,
+            //             this_wire = id_to_wire_name(id),
+            //             expr = id_to_wire_name(*expr_id),
+            //         ));
+
+            //         if !done.contains(&expr_id) {
+            //             queue.push(*expr_id);
+            //         }
+            //     }
+            //     ("Concat", [expr0_id, expr1_id]) => {
+            //         logic_declarations.push_str(&format!(
+            //             "logic {this_wire} = {{ {expr0}, {expr1} }};\n",
+            //             this_wire = id_to_wire_name(id),
+            //             expr0 = id_to_wire_name(*expr0_id),
+            //             expr1 = id_to_wire_name(*expr1_id),
+            //         ));
+
+            //         if !done.contains(&expr0_id) {
+            //             queue.push(*expr0_id);
+            //         }
+            //         if !done.contains(&expr1_id) {
+            //             queue.push(*expr1_id);
+            //         }
+            //     }
+            //     ("ZeroExtend", [expr_id, bw_id]) => {
+            //         let bw = match term_dag.get(*bw_id) {
+            //             Term::Lit(Literal::Int(bw)) => bw,
+            //             _ => panic!(),
+            //         };
+            //         logic_declarations.push_str(&format!(
+            //             "logic {this_wire} = {{ {bw}'d0, {expr} }};\n",
+            //             this_wire = id_to_wire_name(id),
+            //             bw = bw,
+            //             expr = id_to_wire_name(*expr_id),
+            //         ));
+
+            //         if !done.contains(&expr_id) {
+            //             queue.push(*expr_id);
+            //         }
+            //     }
+            //     ("Sketch1", [op_id, expr_id])
+            //         if match term_dag.get(*op_id) {
+            //             Term::App(s, v) => s.as_str() == "LUT4" && v.is_empty(),
+            //             _ => false,
+            //         } =>
+            //     {
+            //         logic_declarations.push_str(&format!(
+            //             "logic {this_wire};\n",
+            //             this_wire = id_to_wire_name(id),
+            //         ));
+
+            //         module_declarations.push_str(&format!(
+            //             "lut4 lut4_{id} (.in({expr}), .out({y}));\n",
+            //             id = id,
+            //             expr = id_to_wire_name(*expr_id),
+            //             y = id_to_wire_name(id),
+            //         ));
+
+            //         if !done.contains(&expr_id) {
+            //             queue.push(*expr_id);
+            //         }
+            //     }
+            //     _ => todo!("{:?}", (s, v)),
+            // },
+            _ => todo!("{:?}", &term),
+        }
+    }
+
+    for clk_name in clk_names {
+        if clk_name != default_clk_name && !input_names.contains(&clk_name) {
+            inputs.push_str(&format!("input {clk_name},\n"));
+        }
+    }
+
+    // Ports with `PortIndex` facts come first, in declaration order. The rest
+    // are sorted, inputs before outputs, to make the output stable.
+    let port_indices = &port_indices_serialized(egraph, module);
+    let mut ports = [inputs, outputs]
+        .iter()
+        .enumerate()
+        .flat_map(|(is_output, ports)| {
+            ports.lines().map(move |line| {
+                let name = line.trim_end_matches(',').rsplit(' ').next().unwrap();
+                let index = port_indices.get(name).copied().unwrap_or(usize::MAX);
+                ((index, is_output), format!("  {}", line))
+            })
+        })
+        .collect::<Vec<_>>();
+    ports.sort();
+    let mut ports = ports.into_iter().map(|(_, line)| line).collect::<Vec<_>>();
+    // Verilog doesn't allow a trailing comma in the port list.
+    if let Some(last) = ports.last_mut() {
+        last.pop();
+    }
+    let ports = ports.join("\n");
+    let declarations = logic_declarations
+        .lines()
+        .chain(registers.lines())
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    check_single_driver(&declarations)?;
+    let logic_declarations = logic_declarations
+        .split('\n')
+        .map(|line| format!("  {}", line))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    // Parameters must be constants, so constant parameters, e.g. a LUT's INIT,
+    // are bound to literals rather than to the wires holding them.
+    let literal = |node: &Node| {
+        if node.op == "Op0" && egraph[&node.children[0]].op == "BV" {
+            let op_node = &egraph[&node.children[0]];
+            let value = egraph[&op_node.children[0]].op.parse::<i64>().unwrap();
+            let bw = egraph[&op_node.children[1]].op.parse::<u64>().unwrap();
+            Some(format!(
+                "{bw}'h{:x}",
+                truncate_value_to_bitwidth(value as u64, bw)
+            ))
+        } else {
+            None
+        }
+    };
+    let parameter_value =
+        |id: &ClassId| literal(&egraph[&choices[id]]).unwrap_or_else(|| id_to_wire_name(id));
+
+    // A module imported once per set of parameter values (see
+    // `from_verilog_file`) has them baked in, but its instances still bind
+    // them, so it declares them with those values.
+    let parameter_declarations = egraph
+        .nodes
+        .values()
+        .find(|node| {
+            node.op == "ModuleInstance"
+                && module
+                    .is_some_and(|module| egraph[&node.children[0]].op == format!("\"{module}\""))
+        })
+        .map(|instance| {
+            let names = cons_list_classes(egraph, &egraph[&instance.children[1]].eclass)
+                .map_err(VerilogError::MalformedParameters)?;
+            let exprs = cons_list_classes(egraph, &egraph[&instance.children[2]].eclass)
+                .map_err(VerilogError::MalformedParameters)?;
+            Ok(names
+                .iter()
+                .zip(exprs)
+                .filter_map(|(name, expr)| {
+                    let name = &egraph[&egraph[name].nodes[0]].op;
+                    let value = egraph[&expr]
+                        .nodes
+                        .iter()
+                        .find_map(|id| literal(&egraph[id]))?;
+                    Some(format!(
+                        "  parameter {} = {};\n",
+                        &name[1..name.len() - 1],
+                        value
+                    ))
+                })
+                .collect::<String>())
+        })
+        .transpose()?
+        .unwrap_or_default();
+
+    let module_instantiations = module_instantiations
+        .iter()
+        .map(
+            |(
+                _class_id,
+                ModuleInstance {
+                    module_class_name,
+                    instance_name,
+                    parameters,
+                    inputs,
+                    outputs,
+                },
+            )| {
+                let parameters = {
+                    let mut out = parameters
+                        .iter()
+                        .map(|(name, id)| format!("    .{}({})", name, parameter_value(id)))
+                        .collect::<Vec<_>>();
+                    out.sort();
+                    out.join(",\n")
+                };
+                let inputs = {
+                    let mut out = inputs
+                        .iter()
+                        .map(|(name, id)| format!("    .{}({})", name, id_to_wire_name(id)))
+                        .collect::<Vec<_>>();
+                    out.sort();
+                    out.join(",\n")
+                };
+
+                let outputs = {
+                    let mut out = outputs
+                        .iter()
+                        .map(|(name, id)| format!("    .{}({})", name, id_to_wire_name(id)))
+                        .collect::<Vec<_>>();
+                    out.sort();
+                    out.join(",\n")
+                };
+
+                // Verilog doesn't allow an empty parameter list.
+                let parameters = if parameters.is_empty() {
+                    String::new()
+                } else {
+                    format!("#(\n{parameters}\n) ")
+                };
+
+                format!(
+                    "  {module_class_name} {parameters}{instance_name} (\n{inputs},\n{outputs});"
+                )
+            },
+        )
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let name = module.filter(|module| !module.is_empty()).unwrap_or("top");
+    Ok(format!(
+        "{warning}module {name}(
+{ports}
+);
+{parameter_declarations}{logic_declarations}
+{registers}
+{module_instantiations}
+endmodule{module_definitions}",
+        ports = ports,
+        logic_declarations = logic_declarations,
+        registers = registers,
+        module_definitions = module_definitions
+            .values()
+            .map(|definition| format!("\n\n{definition}"))
+            .collect::<String>(),
+    ))
+}
+pub fn to_verilog(term_dag: &TermDag, id: usize) -> String {
+    // let mut wires = HashMap::default();
+
+    fn id_to_wire_name(id: usize) -> String {
+        format!("wire_{}", id)
+    }
+
+    // The names of the inputs, for the module header, and their declarations.
+    let mut input_names = vec![];
+    let mut inputs = String::new();
+    let mut logic_declarations = String::new();
+    let mut registers = String::new();
+    let mut module_declarations = String::new();
+
+    let mut queue = vec![id];
+    let mut done = HashSet::new();
+
+    while let Some(id) = queue.pop() {
+        done.insert(id);
+        let term = term_dag.get(id);
+
+        match term {
+            Term::Lit(Literal::String(_)) => (),
+            Term::Lit(Literal::Int(v)) => {
+                logic_declarations.push_str(&format!(
+                    "logic [31:0] {this_wire} = {val};\n",
+                    this_wire = id_to_wire_name(id),
+                    val = v
+                ));
+            }
+            Term::Var(_) => todo!(),
+            Term::App(s, v) => match (s.as_str(), v.as_slice()) {
+                ("Reg", &[default_id, clk_id, d_id]) => {
+                    let default_val = match term_dag.get(default_id) {
+                        Term::Lit(Literal::Int(default_val)) => default_val,
+                        _ => panic!(),
+                    };
+
+                    logic_declarations.push_str(
+                        format!(
+                            "logic {this_wire} = {default};\n",
+                            this_wire = id_to_wire_name(id),
+                            default = default_val
+                        )
+                        .as_str(),
+                    );
+
+                    registers.push_str(&format!(
+                        "always @(posedge {clk}) begin
+                            {this_wire} <= {d};
+                        end\n",
+                        clk = id_to_wire_name(clk_id),
+                        this_wire = id_to_wire_name(id),
+                        d = id_to_wire_name(d_id)
+                    ));
+
+                    if !done.contains(&d_id) {
+                        queue.push(d_id);
+                    }
+                    if !done.contains(&clk_id) {
+                        queue.push(clk_id);
+                    }
+                }
+                ("Var", [name_id, bw_id]) => {
+                    let name = match term_dag.get(*name_id) {
+                        Term::Lit(Literal::String(name)) => name,
+                        _ => panic!(),
+                    };
+                    let bw = match term_dag.get(*bw_id) {
+                        Term::Lit(Literal::Int(bw)) => bw,
+                        _ => panic!(),
+                    };
+
+                    input_names.push(name.to_string());
+                    inputs.push_str(
+                        format!("input [{bw}-1:0] {name};\n", bw = bw, name = name).as_str(),
+                    );
+
+                    logic_declarations.push_str(
+                        format!(
+                            "logic [{bw}-1:0] {this_wire} = {name};\n",
+                            bw = bw,
+                            this_wire = id_to_wire_name(id),
+                            name = name
+                        )
+                        .as_str(),
+                    );
+                }
+                ("Mux", []) => (),
+                ("LUT4", []) => (),
+                ("Or", []) => (),
+                ("Bitvector", [_]) => (),
+                ("Eq", []) => (),
+                ("BV", [val_id, bw_id]) => {
+                    let val = match term_dag.get(*val_id) {
+                        Term::Lit(Literal::Int(val)) => val,
+                        _ => panic!(),
+                    };
+                    let bw = match term_dag.get(*bw_id) {
+                        Term::Lit(Literal::Int(bw)) => bw,
+                        _ => panic!(),
+                    };
+                    logic_declarations.push_str(
+                        format!(
+                            "logic [{bw}-1:0] {this_wire} = {bw}'d{val};\n",
+                            bw = bw,
+                            this_wire = id_to_wire_name(id),
+                            val = val
+                        )
+                        .as_str(),
+                    );
+                }
+                ("Extract", [hi_id, lo_id, expr_id]) => {
+                    let hi = match term_dag.get(*hi_id) {
+                        Term::Lit(Literal::Int(hi)) => hi,
+                        _ => panic!(),
+                    };
+                    let lo = match term_dag.get(*lo_id) {
+                        Term::Lit(Literal::Int(lo)) => lo,
+                        _ => panic!(),
+                    };
+                    // Single bits need no width.
+                    let bw = hi - lo + 1;
+                    logic_declarations.push_str(&format!(
+                        "logic {width}{this_wire} = {expr}[{hi}:{lo}];\n",
+                        width = if bw > 1 {
+                            format!("[{bw}-1:0] ")
+                        } else {
+                            String::new()
+                        },
+                        hi = hi,
+                        lo = lo,
+                        this_wire = id_to_wire_name(id),
+                        expr = id_to_wire_name(*expr_id),
+                    ));
+
+                    if !done.contains(expr_id) {
+                        queue.push(*expr_id);
+                    }
+                }
+                ("Concat", [expr0_id, expr1_id]) => {
+                    // The first child holds the high bits, matching Verilog's
+                    // concatenation order.
+                    logic_declarations.push_str(&format!(
+                        "logic {this_wire} = {{ {expr0}, {expr1} }};\n",
+                        this_wire = id_to_wire_name(id),
+                        expr0 = id_to_wire_name(*expr0_id),
+                        expr1 = id_to_wire_name(*expr1_id),
+                    ));
+
+                    if !done.contains(expr0_id) {
+                        queue.push(*expr0_id);
+                    }
+                    if !done.contains(expr1_id) {
+                        queue.push(*expr1_id);
+                    }
+                }
+                ("Add" | "Sub" | "Mul", [expr0_id, expr1_id]) => {
+                    logic_declarations.push_str(&format!(
+                        "logic {this_wire} = {expr0}{op}{expr1};\n",
+                        this_wire = id_to_wire_name(id),
+                        expr0 = id_to_wire_name(*expr0_id),
+                        op = match s.as_str() {
+                            "Add" => "+",
+                            "Sub" => "-",
+                            "Mul" => "*",
+                            _ => unreachable!("missing a match arm"),
+                        },
+                        expr1 = id_to_wire_name(*expr1_id),
+                    ));
+
+                    if !done.contains(expr0_id) {
+                        queue.push(*expr0_id);
+                    }
+                    if !done.contains(expr1_id) {
+                        queue.push(*expr1_id);
+                    }
+                }
+                ("ZeroExtend", [expr_id, bw_id]) => {
+                    let bw = match term_dag.get(*bw_id) {
+                        Term::Lit(Literal::Int(bw)) => bw,
+                        _ => panic!(),
+                    };
+                    logic_declarations.push_str(&format!(
+                        "logic {this_wire} = {{ {bw}'d0, {expr} }};\n",
+                        this_wire = id_to_wire_name(id),
+                        bw = bw,
+                        expr = id_to_wire_name(*expr_id),
+                    ));
+
+                    if !done.contains(expr_id) {
+                        queue.push(*expr_id);
+                    }
+                }
+                ("Sketch1", [op_id, expr_id])
+                    if match term_dag.get(*op_id) {
+                        Term::App(s, v) => s.as_str() == "LUT4" && v.is_empty(),
+                        _ => false,
+                    } =>
+                {
+                    logic_declarations.push_str(&format!(
+                        "logic {this_wire};\n",
+                        this_wire = id_to_wire_name(id),
+                    ));
+
+                    module_declarations.push_str(&format!(
+                        "lut4 lut4_{id} (.in({expr}), .out({y}));\n",
+                        id = id,
+                        expr = id_to_wire_name(*expr_id),
+                        y = id_to_wire_name(id),
+                    ));
+
+                    if !done.contains(expr_id) {
+                        queue.push(*expr_id);
+                    }
+                }
+                _ => todo!("{:?}", (s, v)),
+            },
+            _ => todo!("{:?}", term),
+        }
+    }
+
+    format!(
+        "module top({input_names});
+            {inputs}
+            {logic_declarations}
+            {registers}
+            {module_declarations}
+        endmodule",
+        input_names = input_names.join(", "),
+        inputs = inputs,
+        logic_declarations = logic_declarations,
+        registers = registers,
+        module_declarations = module_declarations,
+    )
+}
+
+/// A builder for SystemVerilog testbenches which drive a module from stdin,
+/// for simulating designs with Verilator.
+///
+/// The testbench first reads a line holding the number of inputs, the number
+/// of test cases and the number of clock cycles per test case. For each
+/// cycle of each test case, it then reads one hex value per input, in the
+/// order the inputs were added, waits for the design to settle, and prints
+/// `inputs: {name} {val}` for each input and `output: {val}` for each
+/// output.
+///
+/// If a clock is set, the testbench drives it rather than reading it from
+/// stdin: each cycle, the clock is low while the inputs are applied and the
+/// outputs are printed, and then rises.
+///
+/// ```
+/// use churchroad::VerilogTestbench;
+/// let testbench = VerilogTestbench::new("adder")
+///     .include("adder.sv")
+///     .add_input("a", 8)
+///     .add_input("b", 8)
+///     .add_output("out", 8)
+///     .generate();
+/// assert!(testbench.contains("adder simulate_with_verilator_test_module(.a(inputs[0]), .b(inputs[1]), .out(out));"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct VerilogTestbench {
+    module_name: String,
+    include: Option<String>,
+    inputs: Vec<(String, u32)>,
+    outputs: Vec<(String, u32)>,
+    clock: Option<String>,
+}
+
+impl VerilogTestbench {
+    pub fn new(module_name: &str) -> Self {
+        Self {
+            module_name: module_name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// `include the given file, e.g. the one defining the module under test.
+    pub fn include(&mut self, filename: &str) -> &mut Self {
+        self.include = Some(filename.to_owned());
+        self
+    }
+
+    pub fn add_input(&mut self, name: &str, bw: u32) -> &mut Self {
+        self.inputs.push((name.to_owned(), bw));
+        self
+    }
+
+    pub fn add_output(&mut self, name: &str, bw: u32) -> &mut Self {
+        self.outputs.push((name.to_owned(), bw));
+        self
+    }
+
+    /// Drive the module's 1-bit clock input with the given name from the
+    /// testbench.
+    pub fn set_clock(&mut self, name: &str) -> &mut Self {
+        self.clock = Some(name.to_owned());
+        self
+    }
+
+    pub fn generate(&self) -> String {
+        let max_input_bitwidth = self.inputs.iter().map(|(_, bw)| *bw).max().unwrap_or(1);
+
+        let port_list = self
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(i, (name, _))| format!(".{name}(inputs[{i}])"))
+            .chain(self.clock.iter().map(|name| format!(".{name}({name})")))
+            .chain(
+                self.outputs
+                    .iter()
+                    .map(|(name, _)| format!(".{name}({name})")),
+            )
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let declarations = self
+            .outputs
+            .iter()
+            .map(|(name, bw)| format!("logic [{bw}-1:0] {name};\n"))
+            .chain(self.clock.iter().map(|name| format!("logic {name} = 0;\n")))
+            .collect::<String>();
+
+        let displays = self
+            .inputs
+            .iter()
+            .map(|(name, _)| {
+                format!(
+                    "      $display(\"inputs: {name} %d\\n\", simulate_with_verilator_test_module.{name});\n"
+                )
+            })
+            .chain(self.outputs.iter().map(|(name, _)| {
+                format!(
+                    "      $display(\"output: %d\\n\", simulate_with_verilator_test_module.{name});\n"
+                )
+            }))
+            .collect::<String>();
+
+        // Without a clock, inputs just need time to propagate.
+        let (settle, clock_edge) = match &self.clock {
+            Some(clk) => (
+                "      #(CLK_PERIOD / 2);\n".to_string(),
+                format!("      {clk} = 1;\n      #(CLK_PERIOD / 2);\n      {clk} = 0;\n"),
+            ),
+            None => ("      #CLK_PERIOD;\n".to_string(), String::new()),
+        };
+
+        format!(
+            r#"{include}module testbench;
+
+{declarations}
+integer num_inputs;
+integer num_test_cases;
+integer num_clock_cycles;
+
+logic [{max_input_bitwidth}-1:0] inputs[];
+
+{module_name} simulate_with_verilator_test_module({port_list});
+
+localparam CLK_PERIOD = 10;
+localparam STDIN = 32'h8000_0000;
+
+initial begin
+  $fscanf(STDIN, "%d %d %d\n", num_inputs, num_test_cases, num_clock_cycles);
+  $display("num_inputs = %d, num_test_cases = %d, num_clock_cycles = %d\n",
+    num_inputs, num_test_cases, num_clock_cycles);
+
+  inputs = new[num_inputs];
+
+  for (int i = 0; i < num_test_cases; i++) begin
+    for (int clk_i = 0; clk_i < num_clock_cycles; clk_i++) begin
+      for (int j = 0; j < num_inputs; j++) begin
+        $fscanf(STDIN, "%h\n", inputs[j]);
+      end
+{settle}{displays}{clock_edge}    end
+  end
+
+  $finish;
+end
+endmodule
+"#,
+            include = self
+                .include
+                .as_ref()
+                .map(|filename| format!("`include \"{filename}\"\n"))
+                .unwrap_or_default(),
+            module_name = self.module_name,
+        )
+    }
+}
+
+/// Error returned by [`import_churchroad`].
+#[derive(Debug)]
+pub enum ChurchroadImportError {
+    /// Churchroad has already been imported into the egraph. Importing it
+    /// again would try to redeclare all of its sorts and functions.
+    AlreadyLoaded,
+    /// egglog failed to run one of the Churchroad source files.
+    EgglogError(egglog::Error),
+}
+
+impl std::fmt::Display for ChurchroadImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChurchroadImportError::AlreadyLoaded => {
+                write!(f, "Churchroad is already imported into this egraph.")
+            }
+            ChurchroadImportError::EgglogError(err) => write!(f, "egglog failed: {}", err),
+        }
+    }
+}
+
+impl From<egglog::Error> for ChurchroadImportError {
+    fn from(err: egglog::Error) -> Self {
+        ChurchroadImportError::EgglogError(err)
+    }
+}
+
+/// Import Churchroad language into an EGraph.
+///
+/// Returns [`ChurchroadImportError::AlreadyLoaded`], leaving the egraph as it
+/// is, if Churchroad has already been imported.
+///
+/// TODO(@gussmith23): Ideally, this would be done via an `import` statement.
+/// That's not currently possible because of the Rust-defined primitive
+/// `debruijnify` in Churchroad.
+pub fn import_churchroad(egraph: &mut EGraph) -> Result<(), ChurchroadImportError> {
+    // The `ChurchroadLoaded` sentinel is only declared, and so can only be
+    // checked, once Churchroad has been imported.
+    if egraph
+        .parse_and_run_program("(check (ChurchroadLoaded))")
+        .is_ok()
+    {
+        return Err(ChurchroadImportError::AlreadyLoaded);
+    }
+
+    // STEP 1: import primary language definitions.
+    egraph.parse_and_run_program(r#"(include "egglog_src/churchroad.egg")"#)?;
+
+    // STEP 2: add the `debruijnify` primitive to the egraph. This depends on
+    // the above language definitions, but it's not possible to do it in egglog,
+    // hence it's a Rust function.
+    add_debruijnify(egraph);
+
+    // STEP 3: import module enumeration rewrites. These depend on the
+    // `debruijnify` primitive.
+    egraph.parse_and_run_program(r#"(include "egglog_src/module_enumeration_rewrites.egg")"#)?;
+
+    // STEP 4: import constant folding rules, which depend on the bitvector
+    // primitives.
+    add_bitvector_primitives(egraph);
+    egraph.parse_and_run_program(r#"(include "egglog_src/fold_constants.egg")"#)?;
+
+    egraph.parse_and_run_program("(ChurchroadLoaded)")?;
+    Ok(())
+}
+
+/// Add the `debruijnify` primitive to an [`EGraph`].
+///
+/// `(debruijnify args)` numbers the distinct expressions in `args` in order
+/// of first occurrence, so `(debruijnify (vec-of x y x z))` is
+/// `(vec-of 0 1 0 2)`. The numbering depends only on which positions of `args`
+/// hold equal expressions, and not on how egglog numbers the values, so an
+/// identically built design gets identically numbered modules in every run.
+fn add_debruijnify(egraph: &mut EGraph) {
+    struct DeBruijnify {
+        in_sort: Arc<VecSort>,
+        out_sort: Arc<VecSort>,
+        i64_sort: Arc<I64Sort>,
+    }
+
+    impl PrimitiveLike for DeBruijnify {
+        fn name(&self) -> Symbol {
+            "debruijnify".into()
+        }
+
+        fn get_type_constraints(&self) -> Box<dyn TypeConstraint> {
+            Box::new(SimpleTypeConstraint::new(
+                self.name(),
+                vec![self.in_sort.clone(), self.out_sort.clone()],
+            ))
+        }
+
+        fn apply(
+            &self,
+            values: &[crate::Value],
+            egraph: Option<&mut EGraph>,
+        ) -> Option<crate::Value> {
+            let egraph = egraph.unwrap();
+
+            // Normalize all values to their representatives up front, so that
+            // numbering them is a single pass with one hash lookup per value.
+            let in_vec: Vec<Value> = Vec::<Value>::load(&self.in_sort, &values[0])
+                .into_iter()
+                .map(|value| egraph.find(value))
+                .collect();
+
+            let mut seen_values: HashMap<Value, i64> = HashMap::with_capacity(in_vec.len());
+            let out: Vec<Value> = in_vec
+                .into_iter()
+                .map(|value| {
+                    // If we haven't assigned it a number yet, give it the next
+                    // one.
+                    let next_id = seen_values.len() as i64;
+                    let id = *seen_values.entry(value).or_insert(next_id);
+                    id.store(&self.i64_sort).unwrap()
+                })
+                .collect();
+
+            out.store(&self.out_sort)
+        }
+    }
+
+    egraph.add_primitive(DeBruijnify {
+        i64_sort: egraph.get_sort().unwrap(),
+        in_sort: egraph
+            .get_sort_by(|s: &Arc<VecSort>| s.name() == "ExprVec".into())
+            .unwrap(),
+        out_sort: egraph
+            .get_sort_by(|s: &Arc<VecSort>| s.name() == "IVec".into())
+            .unwrap(),
+    });
+}
+
+/// Add primitives for bitvector arithmetic on `BV` literals to an
+/// [`EGraph`], for use in constant folding. egglog's own i64 arithmetic
+/// doesn't know about bitwidths and isn't guaranteed to wrap.
+///
+/// Each primitive takes its operands' values followed by bitwidth
+/// information, and returns the value of the result, truncated to the
+/// result's bitwidth. Values are stored in i64s, so 64-bit values with the
+/// top bit set are negative.
+///
+/// - `(bv-add a b bw)`, and likewise `bv-and`, `bv-or`, `bv-xor`, `bv-shl`
+///   and `bv-shr`: binary ops on `bw`-bit values.
+/// - `(bv-extract a high low)`: bits `high` through `low` of `a`.
+/// - `(bv-concat a b b-bw)`: `a` concatenated on top of the `b-bw`-bit `b`.
+fn add_bitvector_primitives(egraph: &mut EGraph) {
+    struct BitvectorPrimitive {
+        name: &'static str,
+        i64_sort: Arc<I64Sort>,
+        f: fn(u64, u64, u64) -> u64,
+    }
+
+    impl PrimitiveLike for BitvectorPrimitive {
+        fn name(&self) -> Symbol {
+            self.name.into()
+        }
+
+        fn get_type_constraints(&self) -> Box<dyn TypeConstraint> {
+            Box::new(SimpleTypeConstraint::new(
+                self.name(),
+                vec![self.i64_sort.clone(); 4],
+            ))
+        }
+
+        fn apply(
+            &self,
+            values: &[crate::Value],
+            _egraph: Option<&mut EGraph>,
+        ) -> Option<crate::Value> {
+            let [a, b, c] = [0, 1, 2].map(|i| i64::load(&self.i64_sort, &values[i]) as u64);
+            ((self.f)(a, b, c) as i64).store(&self.i64_sort)
+        }
+    }
+
+    fn shift_amount(amount: u64) -> u32 {
+        amount.try_into().unwrap_or(u32::MAX)
+    }
+
+    let primitives: [(&'static str, fn(u64, u64, u64) -> u64); 8] = [
+        ("bv-add", |a, b, bw| {
+            truncate_value_to_bitwidth(a.wrapping_add(b), bw)
+        }),
+        ("bv-and", |a, b, bw| truncate_value_to_bitwidth(a & b, bw)),
+        ("bv-or", |a, b, bw| truncate_value_to_bitwidth(a | b, bw)),
+        ("bv-xor", |a, b, bw| truncate_value_to_bitwidth(a ^ b, bw)),
+        ("bv-shl", |a, b, bw| {
+            truncate_value_to_bitwidth(a.checked_shl(shift_amount(b)).unwrap_or(0), bw)
+        }),
+        ("bv-shr", |a, b, bw| {
+            truncate_value_to_bitwidth(a.checked_shr(shift_amount(b)).unwrap_or(0), bw)
+        }),
+        ("bv-extract", |a, high, low| {
+            truncate_value_to_bitwidth(
+                a.checked_shr(shift_amount(low)).unwrap_or(0),
+                high - low + 1,
+            )
+        }),
+        ("bv-concat", |a, b, b_bw| {
+            a.checked_shl(shift_amount(b_bw)).unwrap_or(0) | b
+        }),
+    ];
+
+    for (name, f) in primitives {
+        egraph.add_primitive(BitvectorPrimitive {
+            name,
+            i64_sort: egraph.get_sort().unwrap(),
+            f,
+        });
+    }
+}
+
+/// Which passes [`ChurchroadEGraph::from_verilog`] runs after importing a
+/// design.
+#[derive(Debug, Clone)]
+pub struct ImportConfig {
+    /// The Churchroad Yosys plugin, churchroad.so.
+    pub yosys_plugin: std::path::PathBuf,
+    /// Run [`run_simplification`].
+    pub simplify: bool,
+    /// Run this many iterations of [`run_algebraic_rewrites`], if any.
+    pub algebraic_iterations: Option<usize>,
+    /// Run [`run_module_enumeration`] with this maximum module size, if any.
+    /// Enumeration is a research feature which can be very expensive on
+    /// large designs, and plain LUT or DSP mapping doesn't need it, so it's
+    /// off by default.
+    pub max_module_size: Option<usize>,
+    /// Import submodules as modules of their own, rather than flattening the
+    /// design; see [`from_verilog_file`].
+    pub keep_hierarchy: bool,
+    /// Values for the top module's parameters, as Verilog constants, by
+    /// name; see [`from_verilog_file`]. Parameters which aren't given keep
+    /// their defaults.
+    pub parameters: HashMap<String, String>,
+}
+
+impl Default for ImportConfig {
+    /// Uses the plugin built in this repository, and runs only
+    /// simplification.
+    fn default() -> Self {
+        ImportConfig {
+            yosys_plugin: std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("yosys-plugin")
+                .join("churchroad.so"),
+            simplify: true,
+            algebraic_iterations: None,
+            max_module_size: None,
+            keep_hierarchy: false,
+            parameters: HashMap::new(),
+        }
+    }
+}
+
+/// An egraph holding a design imported from Verilog, ready for mapping or
+/// extraction.
+pub struct ChurchroadEGraph {
+    pub egraph: EGraph,
+}
+
+impl ChurchroadEGraph {
+    /// Import the Verilog design in `path`, with top module `top`, then:
+    ///
+    /// 1. type the design, and delete the `Wire` placeholders Yosys leaves;
+    /// 2. simplify it, if `config.simplify` is set;
+    /// 3. run the algebraic rewrites, if `config.algebraic_iterations` is set;
+    /// 4. enumerate modules, if `config.max_module_size` is set.
+    ///
+    /// Constants need no sharing pass, as egglog already hashconses them.
+    pub fn from_verilog(
+        path: &std::path::Path,
+        top: &str,
+        config: &ImportConfig,
+    ) -> Result<Self, ImportError> {
+        Self::from_egraph(
+            from_verilog_file(
+                path,
+                top,
+                &config.yosys_plugin,
+                config.keep_hierarchy,
+                &config.parameters,
+            )?,
+            config,
+        )
+    }
+
+    /// Like [`ChurchroadEGraph::from_verilog`], but for Verilog source in a
+    /// string.
+    pub fn from_verilog_string(
+        verilog: &str,
+        top: &str,
+        config: &ImportConfig,
+    ) -> Result<Self, ImportError> {
+        Self::from_egraph(
+            from_verilog_string(
+                verilog,
+                top,
+                &config.yosys_plugin,
+                config.keep_hierarchy,
+                &config.parameters,
+            )?,
+            config,
+        )
+    }
+
+    fn from_egraph(mut egraph: EGraph, config: &ImportConfig) -> Result<Self, ImportError> {
+        egraph.parse_and_run_program("(run-schedule (saturate typing))")?;
+        auto_delete_wires(&mut egraph);
+        if config.simplify {
+            run_simplification(&mut egraph);
+        }
+        if let Some(iterations) = config.algebraic_iterations {
+            run_algebraic_rewrites(&mut egraph, iterations);
+        }
+        if let Some(max_size) = config.max_module_size {
+            run_module_enumeration(&mut egraph, max_size);
+        }
+        Ok(ChurchroadEGraph { egraph })
+    }
+}
+
+/// Reformat a Churchroad program, such as one written by the Yosys plugin, for
+/// reading.
+///
+/// Commands are indented consistently and broken across lines when they don't
+/// fit in 80 columns. Runs of `let`, `union`, `IsPort` and `PortIndex` commands
+/// are grouped into wire declarations, inputs, cells and outputs, under a
+/// heading comment for each, with the ports sorted by name and the `let`s and
+/// `union`s aligned. A run is left in its original order if grouping it would use a
+/// `let` before its definition. Comments stay with the command on the line
+/// below them. Formatting is idempotent, and input which can't be parsed,
+/// e.g. because of unbalanced parentheses, is returned unchanged.
+///
+/// ```
+/// use churchroad::format_churchroad_program;
+///
+/// let program = r#"
+/// ; inputs
+/// (let b (Var "b" 1))
+/// (IsPort "" "b" (Input) b)
+/// (let a (Var "a" 1))
+/// (IsPort "" "a" (Input) a)
+/// (let out (Op2 (And) a b))
+/// (IsPort "" "out" (Output) out)
+/// "#;
+/// assert_eq!(
+///     format_churchroad_program(program),
+///     r#"; inputs
+/// (let   b (Var "b" 1))
+/// (let   a (Var "a" 1))
+/// (IsPort "" "a" (Input) a)
+/// (IsPort "" "b" (Input) b)
+///
+/// ; cells
+/// (let   out (Op2 (And) a b))
+///
+/// ; outputs
+/// (IsPort "" "out" (Output) out)
+/// "#
+/// );
+/// ```
+pub fn format_churchroad_program(input: &str) -> String {
+    const WIDTH: usize = 80;
+    const HEADINGS: [&str; 4] = ["wire declarations", "inputs", "cells", "outputs"];
+
+    #[derive(Clone)]
+    enum Sexp {
+        Atom(String),
+        List(Vec<Sexp>),
+        Comment(String),
+    }
+
+    /// A top-level command, with the comments on the lines above it, or a
+    /// comment standing on its own.
+    struct Item {
+        comments: Vec<String>,
+        command: Option<Sexp>,
+        blank_line_before: bool,
+    }
+
+    fn parse(input: &str) -> Option<Vec<Item>> {
+        let mut chars = input.chars().peekable();
+        let mut items = vec![];
+        let mut comments = vec![];
+        let mut blank_line_before = false;
+        // The lists being parsed, innermost last.
+        let mut stack: Vec<Vec<Sexp>> = vec![];
+        // The number of newlines since the last top-level command or comment.
+        let mut newlines = 0;
+        while let Some(&c) = chars.peek() {
+            let sexp = match c {
+                '\n' => {
+                    chars.next();
+                    if stack.is_empty() {
+                        newlines += 1;
+                    }
+                    continue;
+                }
+                c if c.is_whitespace() => {
+                    chars.next();
+                    continue;
+                }
+                ';' => {
+                    let mut comment = String::new();
+                    while let Some(c) = chars.next_if(|c| *c != '\n') {
+                        comment.push(c);
+                    }
+                    Sexp::Comment(comment.trim_end().to_owned())
+                }
+                '(' => {
+                    chars.next();
+                    stack.push(vec![]);
+                    continue;
+                }
+                ')' => {
+                    chars.next();
+                    Sexp::List(stack.pop()?)
+                }
+                '"' => {
+                    let mut string = String::from(chars.next().unwrap());
+                    loop {
+                        let c = chars.next()?;
+                        string.push(c);
+                        match c {
+                            '\\' => string.push(chars.next()?),
+                            '"' => break,
+                            _ => (),
+                        }
+                    }
+                    Sexp::Atom(string)
+                }
+                _ => {
+                    let mut atom = String::new();
+                    while let Some(c) =
+                        chars.next_if(|c| !c.is_whitespace() && !"();\"".contains(*c))
+                    {
+                        atom.push(c);
+                    }
+                    Sexp::Atom(atom)
+                }
+            };
+
+            if let Some(list) = stack.last_mut() {
+                list.push(sexp);
+                continue;
+            }
+
+            // A blank line separates a comment from what follows it.
+            let blank_line = newlines > 1 && !(items.is_empty() && comments.is_empty());
+            newlines = 0;
+            if blank_line && !comments.is_empty() {
+                items.push(Item {
+                    comments: std::mem::take(&mut comments),
+                    command: None,
+                    blank_line_before,
+                });
+            }
+            if blank_line || comments.is_empty() {
+                blank_line_before = blank_line;
+            }
+            match sexp {
+                Sexp::Comment(comment) => comments.push(comment),
+                command => items.push(Item {
+                    comments: std::mem::take(&mut comments),
+                    command: Some(command),
+                    blank_line_before,
+                }),
+            }
+        }
+        if !stack.is_empty() {
+            return None;
+        }
+        if !comments.is_empty() {
+            items.push(Item {
+                comments,
+                command: None,
+                blank_line_before: newlines > 1 && !items.is_empty(),
+            });
+        }
+        Some(items)
+    }
+
+    fn flat(sexp: &Sexp) -> Option<String> {
+        match sexp {
+            Sexp::Atom(atom) => Some(atom.clone()),
+            Sexp::Comment(_) => None,
+            Sexp::List(list) => Some(format!(
+                "({})",
+                list.iter().map(flat).collect::<Option<Vec<_>>>()?.join(" ")
+            )),
+        }
+    }
+
+    fn pretty(sexp: &Sexp, column: usize) -> String {
+        let list = match sexp {
+            Sexp::Atom(atom) => return atom.clone(),
+            Sexp::Comment(comment) => return comment.clone(),
+            Sexp::List(list) if list.is_empty() => return "()".to_owned(),
+            Sexp::List(list) => list,
+        };
+        if let Some(flat) = flat(sexp).filter(|flat| column + flat.len() <= WIDTH) {
+            return flat;
+        }
+
+        // Lisp style: the rest of the elements line up under the first
+        // argument, or under the head if it isn't an atom.
+        let (mut out, rest, column) = match &list[0] {
+            Sexp::Atom(head) if list.len() > 1 => {
+                let column = column + head.len() + 2;
+                (
+                    format!("({head} {}", pretty(&list[1], column)),
+                    &list[2..],
+                    column,
+                )
+            }
+            head => (
+                format!("({}", pretty(head, column + 1)),
+                &list[1..],
+                column + 1,
+            ),
+        };
+        for sexp in rest {
+            out.push_str(&format!("\n{}{}", " ".repeat(column), pretty(sexp, column)));
+        }
+        // A comment runs to the end of the line, so can't be followed by the
+        // closing parenthesis.
+        if matches!(list.last(), Some(Sexp::Comment(_))) {
+            out.push_str(&format!("\n{}", " ".repeat(column)));
+        }
+        out.push(')');
+        out
+    }
+
+    fn head(sexp: &Sexp) -> Option<&str> {
+        match sexp {
+            Sexp::List(list) => match list.first() {
+                Some(Sexp::Atom(head)) => Some(head),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn atoms<'a>(sexp: &'a Sexp, out: &mut Vec<&'a str>) {
+        match sexp {
+            Sexp::Atom(atom) => out.push(atom),
+            Sexp::List(list) => list.iter().for_each(|sexp| atoms(sexp, out)),
+            Sexp::Comment(_) => (),
+        }
+    }
+
+    /// Group a run of `let`, `union`, `IsPort` and `PortIndex` commands as
+    /// described above, returning the groups under their headings, or `None`
+    /// if that would use a `let` before its definition.
+    fn group(block: &[Item]) -> Option<Vec<(&'static str, Vec<&Item>)>> {
+        let mut groups: [Vec<&Item>; 4] = Default::default();
+        let mut input_ports = vec![];
+        let mut output_ports = vec![];
+        // The direction of each port, by its name.
+        let directions: HashMap<String, &str> = block
+            .iter()
+            .filter_map(|item| match &item.command {
+                Some(Sexp::List(list)) if head(item.command.as_ref()?) == Some("IsPort") => {
+                    Some((flat(list.get(2)?)?, head(list.get(3)?)?))
+                }
+                _ => None,
+            })
+            .collect();
+        for item in block {
+            let Some(Sexp::List(list)) = &item.command else {
+                unreachable!()
+            };
+            match (
+                head(item.command.as_ref().unwrap()),
+                list.get(2),
+                list.get(3),
+            ) {
+                (Some("let"), Some(value), _) if head(value) == Some("Wire") => {
+                    groups[0].push(item)
+                }
+                (Some("let"), Some(value), _) if head(value) == Some("Var") => groups[1].push(item),
+                (Some("IsPort"), _, Some(direction)) => match head(direction) {
+                    Some("Input") => input_ports.push(item),
+                    Some("Output") => output_ports.push(item),
+                    _ => groups[2].push(item),
+                },
+                // A port's index goes with the port.
+                (Some("PortIndex"), Some(name), _) => {
+                    match flat(name).and_then(|name| directions.get(&name)) {
+                        Some(&"Input") => input_ports.push(item),
+                        Some(&"Output") => output_ports.push(item),
+                        _ => groups[2].push(item),
+                    }
+                }
+                _ => groups[2].push(item),
+            }
+        }
+        let port_name = |item: &&Item| {
+            let Some(Sexp::List(list)) = &item.command else {
+                unreachable!()
+            };
+            flat(&list[2])
+        };
+        input_ports.sort_by_key(port_name);
+        output_ports.sort_by_key(port_name);
+        groups[1].extend(input_ports);
+        groups[3].extend(output_ports);
+
+        // Check that every `let` is defined before it's used.
+        let order: Vec<&Item> = groups.iter().flatten().copied().collect();
+        let mut definitions = HashMap::new();
+        for (i, item) in order.iter().enumerate() {
+            if let Some(Sexp::List(list)) = &item.command {
+                if let (Some("let"), Some(Sexp::Atom(name))) =
+                    (head(item.command.as_ref().unwrap()), list.get(1))
+                {
+                    definitions.insert(name.as_str(), i);
+                }
+            }
+        }
+        for (i, item) in order.iter().enumerate() {
+            let Some(Sexp::List(list)) = &item.command else {
+                unreachable!()
+            };
+            let mut used = vec![];
+            let skip = if head(item.command.as_ref().unwrap()) == Some("let") {
+                2
+            } else {
+                1
+            };
+            list[skip..].iter().for_each(|sexp| atoms(sexp, &mut used));
+            if used.iter().any(|atom| {
+                definitions
+                    .get(atom)
+                    .is_some_and(|&definition| definition > i)
+            }) {
+                return None;
+            }
+        }
+
+        Some(
+            HEADINGS
+                .into_iter()
+                .zip(groups)
+                .filter(|(_, group)| !group.is_empty())
+                .collect(),
+        )
+    }
+
+    /// The item's command, with `let` padded so that the names it binds line
+    /// up with those of `union`s.
+    fn aligned(item: &Item) -> Sexp {
+        let mut command = item.command.clone().unwrap();
+        if let Sexp::List(list) = &mut command {
+            if let Sexp::Atom(head) = &mut list[0] {
+                if head == "let" {
+                    *head = "let  ".to_owned();
+                }
+            }
+        }
+        command
+    }
+
+    let Some(items) = parse(input) else {
+        return input.to_owned();
+    };
+
+    let mut out = String::new();
+    let mut emit = |comments: &[&String], command: Option<&Sexp>, blank_line_before: bool| {
+        if blank_line_before && !out.is_empty() {
+            out.push('\n');
+        }
+        for comment in comments {
+            out.push_str(comment);
+            out.push('\n');
+        }
+        if let Some(command) = command {
+            out.push_str(&pretty(command, 0));
+            out.push('\n');
+        }
+    };
+    let is_grouped = |item: &Item| {
+        item.command
+            .as_ref()
+            .and_then(head)
+            .is_some_and(|head| ["let", "union", "IsPort", "PortIndex"].contains(&head))
+    };
+
+    let mut i = 0;
+    while i < items.len() {
+        if !is_grouped(&items[i]) {
+            let item = &items[i];
+            emit(
+                &item.comments.iter().collect::<Vec<_>>(),
+                item.command.as_ref(),
+                item.blank_line_before,
+            );
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        while i < items.len() && is_grouped(&items[i]) {
+            i += 1;
+        }
+        let block = &items[start..i];
+        match group(block) {
+            Some(groups) => {
+                for (heading, group) in groups {
+                    emit(&[&format!("; {heading}")], None, true);
+                    for item in group {
+                        // Our headings replace the old ones.
+                        let comments = item
+                            .comments
+                            .iter()
+                            .filter(|comment| {
+                                !HEADINGS.contains(&comment.trim_start_matches(';').trim())
+                            })
+                            .collect::<Vec<_>>();
+                        emit(&comments, Some(&aligned(item)), false);
+                    }
+                }
+            }
+            None => {
+                for item in block {
+                    emit(
+                        &item.comments.iter().collect::<Vec<_>>(),
+                        Some(&aligned(item)),
+                        item.blank_line_before,
+                    );
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Write the contents of an egraph to `path` as a Churchroad program which,
+/// run after [`import_churchroad`], rebuilds an equivalent egraph, e.g. to
+/// share a design after rewriting it.
+///
+/// Each eclass is bound with a `let`, in an order in which every `let` only
+/// uses earlier ones, and the eclass's other enodes are added with `union`s.
+/// Relations such as `IsPort` follow as facts. Like the programs written by
+/// the Yosys plugin, loops through registers are broken with `Wire`
+/// placeholders, so [`auto_delete_wires`] should be called after importing
+/// the program. Placeholders need widths, so loops must be typed; otherwise
+/// an [`std::io::ErrorKind::InvalidData`] error is returned.
+pub fn write_churchroad_egg(egraph: &EGraph, path: &std::path::Path) -> std::io::Result<()> {
+    let program = churchroad_egg_program(&egraph.serialize(SerializeConfig::default()))
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    std::fs::write(path, program)
+}
+
+/// The program written by [`write_churchroad_egg`].
+fn churchroad_egg_program(egraph: &egraph_serialize::EGraph) -> Result<String, String> {
+    fn literal_of<'a>(egraph: &'a egraph_serialize::EGraph, class: &ClassId) -> Option<&'a Node> {
+        egraph.classes()[class]
+            .nodes
+            .iter()
+            .map(|id| &egraph[id])
+            .find(|node| is_literal(node))
+    }
+
+    /// The enode as an egglog term, using the names of the named eclasses.
+    /// Other eclasses are written inline.
+    fn render(
+        egraph: &egraph_serialize::EGraph,
+        names: &IndexMap<&ClassId, String>,
+        node: &Node,
+    ) -> String {
+        let children = node
+            .children
+            .iter()
+            .map(|id| {
+                let class = &egraph[id].eclass;
+                match (names.get(class), literal_of(egraph, class)) {
+                    (Some(name), _) => format!(" {name}"),
+                    (None, Some(literal)) => format!(" {}", literal.op),
+                    (None, None) => format!(" {}", render(egraph, names, &egraph[id])),
+                }
+            })
+            .collect::<String>();
+        format!("({}{})", node.op, children)
+    }
+
+    // Entries of relations, such as `IsPort`, share the eclass of the unit
+    // value.
+    let is_fact_class = |class: &ClassId| {
+        egraph.classes()[class]
+            .nodes
+            .iter()
+            .any(|id| egraph[id].op == "()")
+    };
+    // Eclasses holding literals, and eclasses of a single enode with only
+    // literal children, such as `(Extract 0 0)`, are written inline rather
+    // than bound to names.
+    let is_inline = |class: &ClassId| match egraph.classes()[class].nodes.as_slice() {
+        [id] => egraph[id]
+            .children
+            .iter()
+            .all(|child| literal_of(egraph, &egraph[child].eclass).is_some()),
+        _ => literal_of(egraph, class).is_some(),
+    };
+    let names: IndexMap<&ClassId, String> = egraph
+        .classes()
+        .keys()
+        .filter(|class| !is_fact_class(class) && !is_inline(class))
+        .enumerate()
+        .map(|(i, class)| (class, format!("v{i}")))
+        .collect();
+
+    // Bind each named eclass to one of its enodes once the eclasses that enode
+    // uses are bound. `bound` holds the enode each eclass was bound to, or
+    // `None` for placeholders.
+    let mut placeholders = String::new();
+    let mut lets = String::new();
+    let mut bound: HashMap<&ClassId, Option<&NodeId>> = HashMap::new();
+    while bound.len() < names.len() {
+        let mut progress = false;
+        for (class, name) in &names {
+            if bound.contains_key(class) {
+                continue;
+            }
+            let definition = egraph.classes()[*class].nodes.iter().find(|id| {
+                egraph[*id].children.iter().all(|child| {
+                    let class = &egraph[child].eclass;
+                    !names.contains_key(class) || bound.contains_key(class)
+                })
+            });
+            if let Some(id) = definition {
+                lets.push_str(&format!(
+                    "(let {name} {})\n",
+                    render(egraph, &names, &egraph[id])
+                ));
+                bound.insert(*class, Some(id));
+                progress = true;
+            }
+        }
+
+        // Everything left is in a loop, so break one with a placeholder.
+        if !progress {
+            let (class, name) = names
+                .iter()
+                .find(|(class, _)| !bound.contains_key(*class))
+                .unwrap();
+            let bw = get_bitwidth(egraph, class)
+                .ok_or_else(|| format!("Can't break a loop through untyped class {:?}.", class))?;
+            placeholders.push_str(&format!(
+                "(let {name} (Wire \"{name}_placeholder\" {bw}))\n"
+            ));
+            bound.insert(*class, None);
+        }
+    }
+
+    let mut unions = String::new();
+    for (class, name) in &names {
+        for id in &egraph.classes()[*class].nodes {
+            if bound[class] != Some(id) {
+                unions.push_str(&format!(
+                    "(union {name} {})\n",
+                    render(egraph, &names, &egraph[id])
+                ));
+            }
+        }
+    }
+
+    let mut facts = String::new();
+    for (class, data) in egraph.classes() {
+        let literal = literal_of(egraph, class);
+        for node in data.nodes.iter().map(|id| &egraph[id]) {
+            if is_fact_class(class) && node.op != "()" {
+                facts.push_str(&format!("{}\n", render(egraph, &names, node)));
+            } else if let Some(literal) = literal.filter(|_| !is_literal(node)) {
+                // Functions with primitive outputs, such as
+                // `ModuleOutputBitwidth`, are set rather than unioned.
+                facts.push_str(&format!(
+                    "(set {} {})\n",
+                    render(egraph, &names, node),
+                    literal.op
+                ));
+            }
+        }
+    }
+
+    Ok(format!(
+        "; placeholders\n{placeholders}\n; definitions\n{lets}\n; unions\n{unions}\n; facts\n{facts}"
+    ))
+}
+
+/// Import the mapping rewrites for the given architecture into the `mapping`
+/// ruleset. [`import_churchroad`] must be called first.
+pub fn import_mapping_rewrites(egraph: &mut EGraph, architecture: &Architecture) {
+    egraph
+        .parse_and_run_program(&format!(
+            r#"(include "egglog_src/mapping_rewrites/{}.egg")"#,
+            architecture
+        ))
+        .unwrap();
+}
+
+/// Delete all remaining `Wire` placeholders from the egraph, returning the
+/// number deleted. Call this once all wires have been unioned with the
+/// expressions that drive them.
+pub fn auto_delete_wires(egraph: &mut EGraph) -> usize {
+    let num_wires = egraph
+        .serialize(SerializeConfig::default())
+        .nodes
+        .values()
+        .filter(|node| node.op == "Wire")
+        .count();
+    egraph
+        .parse_and_run_program("(run-schedule (saturate delete-wires))")
+        .unwrap();
+    num_wires
+}
+
+/// Two inconsistent widths found for the same expression by [`check_widths`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WidthConflict {
+    /// The names of the wires, variables and ports in the expression's
+    /// eclass. Empty if the expression is unnamed.
+    pub names: Vec<String>,
+    /// The two widths, smallest first.
+    pub widths: (u64, u64),
+}
+
+impl std::fmt::Display for WidthConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (w1, w2) = self.widths;
+        if self.names.is_empty() {
+            write!(f, "an unnamed expression is both {w1} and {w2} bits wide")
+        } else {
+            write!(
+                f,
+                "{} is both {w1} and {w2} bits wide",
+                self.names.join(" = ")
+            )
+        }
+    }
+}
+
+/// Run `typing` and report expressions which were given more than one width,
+/// which usually means something was unioned with an expression of the wrong
+/// width. Best run right after importing a design, while its `Wire`s (and
+/// their declared widths) are still around.
+pub fn check_widths(egraph: &mut EGraph) -> Vec<WidthConflict> {
+    egraph
+        .parse_and_run_program("(run-schedule (saturate typing) (saturate width-checks))")
+        .unwrap();
+
+    let serialized = egraph.serialize(SerializeConfig::default());
+    let unquote = |node_id: &NodeId| {
+        let op = serialized[node_id].op.as_str();
+        op.strip_prefix('"')
+            .unwrap()
+            .strip_suffix('"')
+            .unwrap()
+            .to_owned()
+    };
+
+    let mut conflicts: Vec<WidthConflict> = serialized
+        .nodes
+        .values()
+        .filter(|node| node.op == "WidthConflict")
+        .map(|node| {
+            let class = &serialized[&node.children[0]].eclass;
+            let mut names: Vec<String> = serialized[class]
+                .nodes
+                .iter()
+                .map(|node_id| &serialized[node_id])
+                .filter(|node| matches!(node.op.as_str(), "Wire" | "Var"))
+                .map(|node| unquote(&node.children[0]))
+                .chain(
+                    serialized
+                        .nodes
+                        .values()
+                        .filter(|port| {
+                            port.op == "IsPort" && &serialized[&port.children[3]].eclass == class
+                        })
+                        .map(|port| unquote(&port.children[1])),
+                )
+                .collect();
+            names.sort();
+            names.dedup();
+            WidthConflict {
+                names,
+                widths: (
+                    serialized[&node.children[1]].op.parse().unwrap(),
+                    serialized[&node.children[2]].op.parse().unwrap(),
+                ),
+            }
+        })
+        .collect();
+    conflicts.sort_by(|a, b| (&a.names, a.widths).cmp(&(&b.names, b.widths)));
+    conflicts
+}
+
+/// Run the `simplify` ruleset (along with `typing`, which it depends on) to
+/// saturation, removing redundant extensions, extractions and
+/// concatenations.
+pub fn run_simplification(egraph: &mut EGraph) {
+    egraph
+        .parse_and_run_program("(run-schedule (saturate typing simplify))")
+        .unwrap();
+}
+
+/// Run the `algebraic` and `push-ops-through-concat` rulesets for the given
+/// number of iterations, re-running `typing` in between so that rewritten
+/// expressions are typed. The rulesets grow the egraph quickly, so they aren't
+/// run to saturation.
+pub fn run_algebraic_rewrites(egraph: &mut EGraph, iterations: usize) {
+    egraph
+        .parse_and_run_program(&format!(
+            "(run-schedule (repeat {} (saturate typing) algebraic push-ops-through-concat))",
+            iterations
+        ))
+        .unwrap();
+}
+
+/// Run the `boolean` ruleset for the given number of iterations, re-running
+/// `typing` in between. Besides De Morgan's laws and Xor expansion, this
+/// Shannon-expands 1-bit expressions around each of the 1-bit variables
+/// named in `shannon_variables`. Like the algebraic rewrites, these grow the
+/// egraph quickly, so they aren't run to saturation.
+pub fn run_boolean_rewrites(egraph: &mut EGraph, iterations: usize, shannon_variables: &[&str]) {
+    let facts = shannon_variables
+        .iter()
+        .map(|name| format!("(ShannonVariable (Var \"{}\" 1))\n", name))
+        .collect::<String>();
+    egraph
+        .parse_and_run_program(&format!(
+            "{}(run-schedule (repeat {} (saturate typing) boolean))",
+            facts, iterations
+        ))
+        .unwrap();
+}
+
+/// Finds a way to implement the logic cone rooted at `class` with two
+/// `k`-input LUTs: an inner cone with at most `k` inputs, such that the rest
+/// of the cone also has at most `k` inputs once the inner cone's output is
+/// counted as one of them. Returns the inner cone's class.
+///
+/// Each `Var` counts as one input, so this is meant for 1-bit signals. Only
+/// the smallest-support node of each eclass is considered, so this may miss
+/// decompositions.
+pub fn find_lut_decomposition(
+    egraph: &egraph_serialize::EGraph,
+    class: &ClassId,
+    k: usize,
+) -> Option<ClassId> {
+    // The variables the smallest-support node of `class` depends on, with
+    // `cut` (if given) counted as a variable of its own. `None` if every
+    // node of the class is on a cycle.
+    fn support(
+        egraph: &egraph_serialize::EGraph,
+        class: &ClassId,
+        cut: Option<&ClassId>,
+        visiting: &mut HashSet<ClassId>,
+        memo: &mut HashMap<ClassId, Option<HashSet<String>>>,
+    ) -> Option<HashSet<String>> {
+        if Some(class) == cut {
+            return Some(HashSet::from([format!("cut {}", class)]));
+        }
+        if let Some(support) = memo.get(class) {
+            return support.clone();
+        }
+        if !visiting.insert(class.clone()) {
+            return None;
+        }
+        let best = egraph[class]
+            .nodes
+            .iter()
+            .filter_map(|node_id| {
+                let node = &egraph[node_id];
+                if node.op == "Var" {
+                    return Some(HashSet::from([egraph[&node.children[0]].op.clone()]));
+                }
+                node.children
+                    .iter()
+                    .map(|child| support(egraph, &egraph[child].eclass, cut, visiting, memo))
+                    .try_fold(HashSet::new(), |mut acc, child| {
+                        acc.extend(child?);
+                        Some(acc)
+                    })
+            })
+            .min_by_key(|support| support.len());
+        visiting.remove(class);
+        memo.insert(class.clone(), best.clone());
+        best
+    }
+
+    // Every class in the cone, except the root.
+    let mut cone = indexmap::IndexSet::new();
+    let mut queue = VecDeque::from([class.clone()]);
+    while let Some(id) = queue.pop_front() {
+        for node_id in &egraph[&id].nodes {
+            for child in &egraph[node_id].children {
+                let child = &egraph[child].eclass;
+                if child != class && cone.insert(child.clone()) {
+                    queue.push_back(child.clone());
+                }
+            }
+        }
+    }
+
+    let mut memo = HashMap::new();
+    cone.into_iter().find(|inner| {
+        let Some(inner_support) = support(egraph, inner, None, &mut HashSet::new(), &mut memo)
+        else {
+            return false;
+        };
+        // A single variable isn't worth a LUT of its own.
+        if inner_support.len() < 2 || inner_support.len() > k {
+            return false;
+        }
+        support(
+            egraph,
+            class,
+            Some(inner),
+            &mut HashSet::new(),
+            &mut HashMap::new(),
+        )
+        .is_some_and(|outer_support| outer_support.len() <= k)
+    })
+}
+
+/// Recognize chains of registers sharing a clock and init value as
+/// `ShiftReg`s of up to `max_depth` registers, e.g. 32 for Xilinx SRLs. The
+/// registers in a chain are unioned with the shift register, so taps on
+/// intermediate registers are preserved.
+pub fn recognize_shift_registers(egraph: &mut EGraph, max_depth: usize) {
+    egraph
+        .parse_and_run_program(&format!(
+            "(ShiftRegMaxDepth {})\n(run-schedule (saturate shift-registers))",
+            max_depth
+        ))
+        .unwrap();
+}
+
+/// Split multiplies wider than `max_width` bits into shifts and adds of
+/// narrower multiplies, all at most `max_width` bits wide, so that they can
+/// be mapped to DSPs. For an [`Architecture`], `max_width` is one less than
+/// its [`ArchitectureDescription::dsp_multiplier_width`], as hard multipliers
+/// are signed. Runs the `expansion` ruleset, along with `typing`, to
+/// saturation.
+pub fn split_wide_multiplies(egraph: &mut EGraph, max_width: u32) {
+    egraph
+        .parse_and_run_program(&format!(
+            "(MulSplitWidth {})\n(run-schedule (saturate (saturate typing) expansion))",
+            max_width
+        ))
+        .unwrap();
+}
+
+/// Size statistics about an egraph, for diagnosing slow rewriting. See
+/// [`egraph_stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EGraphStats {
+    pub eclass_count: usize,
+    pub enode_count: usize,
+    pub isport_count: usize,
+    pub makemodule_count: usize,
+    /// The number of enodes with each operator.
+    pub distinct_operators: HashMap<String, usize>,
+}
+
+impl EGraphStats {
+    /// The number of eclasses per enode. Lower values mean more merging.
+    pub fn merge_ratio(&self) -> f64 {
+        if self.enode_count == 0 {
+            return 1.0;
+        }
+        self.eclass_count as f64 / self.enode_count as f64
+    }
+}
+
+impl std::fmt::Display for EGraphStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{:<24} {:>10}", "eclasses", self.eclass_count)?;
+        writeln!(f, "{:<24} {:>10}", "enodes", self.enode_count)?;
+        writeln!(f, "{:<24} {:>10.3}", "merge ratio", self.merge_ratio())?;
+        writeln!(f, "{:<24} {:>10}", "IsPort facts", self.isport_count)?;
+        writeln!(
+            f,
+            "{:<24} {:>10}",
+            "MakeModule nodes", self.makemodule_count
+        )?;
+        writeln!(f, "enodes by operator:")?;
+        let mut operators: Vec<_> = self.distinct_operators.iter().collect();
+        operators
+            .sort_by(|(a_op, a_count), (b_op, b_count)| b_count.cmp(a_count).then(a_op.cmp(b_op)));
+        for (op, count) in operators {
+            writeln!(f, "  {:<22} {:>10}", op, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Collect size statistics about the egraph.
+pub fn egraph_stats(egraph: &EGraph) -> EGraphStats {
+    let serialized = egraph.serialize(SerializeConfig::default());
+    let mut distinct_operators: HashMap<String, usize> = HashMap::new();
+    for node in serialized.nodes.values() {
+        *distinct_operators.entry(node.op.clone()).or_default() += 1;
+    }
+    EGraphStats {
+        eclass_count: serialized.classes().len(),
+        enode_count: serialized.nodes.len(),
+        isport_count: distinct_operators.get("IsPort").copied().unwrap_or(0),
+        makemodule_count: distinct_operators.get("MakeModule").copied().unwrap_or(0),
+        distinct_operators,
+    }
+}
+
+/// The size of an egraph after a round of a ruleset, and how long the round
+/// took. See [`benchmark_rewrite_saturation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaturationRound {
+    /// The number of rounds run, counting from one; zero for the egraph
+    /// before any were run.
+    pub iteration: usize,
+    pub enode_count: usize,
+    pub eclass_count: usize,
+    pub duration: std::time::Duration,
+}
+
+/// How an egraph grew as a ruleset was run on it, round by round, as measured
+/// by [`benchmark_rewrite_saturation`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaturationBenchmark {
+    pub ruleset: String,
+    /// The egraph before any rounds were run, then after each round.
+    pub rounds: Vec<SaturationRound>,
+}
+
+impl SaturationBenchmark {
+    /// The number of rounds which changed the egraph, if the ruleset then
+    /// saturated, i.e. a round left the numbers of enodes and eclasses as
+    /// they were.
+    pub fn iterations_until_saturation(&self) -> Option<usize> {
+        self.rounds
+            .windows(2)
+            .find(|rounds| {
+                (rounds[0].enode_count, rounds[0].eclass_count)
+                    == (rounds[1].enode_count, rounds[1].eclass_count)
+            })
+            .map(|rounds| rounds[0].iteration)
+    }
+
+    /// A bar chart of the number of enodes after each round.
+    pub fn plot_growth(&self) -> String {
+        const WIDTH: usize = 50;
+        let max = self
+            .rounds
+            .iter()
+            .map(|round| round.enode_count)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let mut plot = format!(
+            "{:>5} {:>10} {:>10} {:>10}\n",
+            "round", "enodes", "eclasses", "ms"
+        );
+        for round in &self.rounds {
+            plot.push_str(&format!(
+                "{:>5} {:>10} {:>10} {:>10.3} {}\n",
+                round.iteration,
+                round.enode_count,
+                round.eclass_count,
+                round.duration.as_secs_f64() * 1000.0,
+                "#".repeat(round.enode_count * WIDTH / max)
+            ));
+        }
+        plot
+    }
+
+    /// An estimate of how long running the ruleset for `target_iteration`
+    /// rounds would take. Rounds which were run count for the time they took.
+    /// Past those, unless the ruleset saturated, each round is taken to grow
+    /// the egraph, and to take longer, by the same factor as the last round
+    /// run grew the egraph.
+    pub fn estimated_total_time(&self, target_iteration: usize) -> std::time::Duration {
+        let measured = self
+            .rounds
+            .iter()
+            .filter(|round| round.iteration <= target_iteration)
+            .map(|round| round.duration)
+            .sum::<std::time::Duration>();
+        let last = match self.rounds.last() {
+            Some(last) if last.iteration > 0 && last.iteration < target_iteration => last,
+            _ => return measured,
+        };
+        if self.iterations_until_saturation().is_some() {
+            return measured;
+        }
+
+        let previous = &self.rounds[self.rounds.len() - 2];
+        let growth = (last.enode_count as f64 / previous.enode_count.max(1) as f64).max(1.0);
+        let last_secs = last.duration.as_secs_f64();
+        let remaining = (target_iteration - last.iteration) as f64;
+        // The sum of last_secs * growth^k for k from 1 to `remaining`.
+        let extrapolated = if growth == 1.0 {
+            last_secs * remaining
+        } else {
+            last_secs * growth * (growth.powf(remaining) - 1.0) / (growth - 1.0)
+        };
+        measured
+            .checked_add(
+                std::time::Duration::try_from_secs_f64(extrapolated)
+                    .unwrap_or(std::time::Duration::MAX),
+            )
+            .unwrap_or(std::time::Duration::MAX)
+    }
+}
+
+/// Run `ruleset` on a copy of `egraph` a round at a time, for at most
+/// `max_iterations` rounds or until it saturates, recording the size of the
+/// egraph after each round and how long the round took. This helps predict
+/// whether running a ruleset such as `enumerate-modules` to saturation is
+/// feasible for a design.
+///
+/// Panics if egglog fails to run the ruleset, e.g. because it doesn't exist.
+pub fn benchmark_rewrite_saturation(
+    egraph: &EGraph,
+    ruleset: &str,
+    max_iterations: usize,
+) -> SaturationBenchmark {
+    let mut egraph = egraph.clone();
+    let round = |egraph: &EGraph, iteration, duration| {
+        let stats = egraph_stats(egraph);
+        SaturationRound {
+            iteration,
+            enode_count: stats.enode_count,
+            eclass_count: stats.eclass_count,
+            duration,
+        }
+    };
+
+    let mut benchmark = SaturationBenchmark {
+        ruleset: ruleset.to_owned(),
+        rounds: vec![round(&egraph, 0, std::time::Duration::ZERO)],
+    };
+    for iteration in 1..=max_iterations {
+        let start = std::time::Instant::now();
+        egraph
+            .parse_and_run_program(&format!("(run-schedule (repeat 1 {}))", ruleset))
+            .unwrap();
+        benchmark
+            .rounds
+            .push(round(&egraph, iteration, start.elapsed()));
+        if benchmark.iterations_until_saturation().is_some() {
+            break;
+        }
+    }
+    benchmark
+}
+
+/// Generate the rewrites which map Churchroad expressions to the primitives
+/// of the given architecture, as described by [`Architecture::description`].
+///
+/// This function is used to generate the contents of the
+/// `egglog_src/mapping_rewrites/<architecture>.egg` files. A test in this file
+/// ensures that the generated files match what this function produces.
+pub fn generate_mapping_rewrites(architecture: &Architecture) -> String {
+    PrimitiveLibrary::for_architecture(architecture).generate_mapping_ruleset()
+}
+
+/// Generate all module enumeration rewrites used by Churchroad.
+///
+/// This function is used to generate the contents of the the
+/// `egglog_src/module_enumeration_rewrites.egg` file. A test in this file
+/// ensures that the generated file matches what this function produces.
+pub fn generate_module_enumeration_rewrites(enumeration_ruleset_name: &str) -> String {
+    format!(
+            "
+(ruleset {enumeration_ruleset_name})
+{rewrites}",
+            enumeration_ruleset_name = enumeration_ruleset_name,
+            rewrites = vec![
+                // Var
+                // Note that this puts a loop in the graph, because a Var
+                // becomes a hole applied to itself. We just need to be careful
+                // about that during extraction.
+                format!("(rewrite (Var name bw) (apply (MakeModule (Hole) (vec-of 0)) (vec-of (Var_ name bw))) :ruleset {})", enumeration_ruleset_name),
+
+                // 0-ary
+                generate_module_enumeration_rewrite(&[], Some(enumeration_ruleset_name)),
+                // 1-ary
+                generate_module_enumeration_rewrite(&[true], Some(enumeration_ruleset_name)),
+                generate_module_enumeration_rewrite(&[false], Some(enumeration_ruleset_name)),
+                // 2-ary
+                generate_module_enumeration_rewrite(&[true, true], Some(enumeration_ruleset_name)),
+                generate_module_enumeration_rewrite(&[true, false], Some(enumeration_ruleset_name)),
+                generate_module_enumeration_rewrite(&[false, true], Some(enumeration_ruleset_name)),
+                generate_module_enumeration_rewrite(
+                    &[false, false],
+                    Some(enumeration_ruleset_name)
+                ),
+                // 3-ary
+                generate_module_enumeration_rewrite(
+                    &[true, true, true],
+                    Some(enumeration_ruleset_name)
+                ),
+                generate_module_enumeration_rewrite(
+                    &[true, true, false],
+                    Some(enumeration_ruleset_name)
+                ),
+                generate_module_enumeration_rewrite(
+                    &[true, false, true],
+                    Some(enumeration_ruleset_name)
+                ),
+                generate_module_enumeration_rewrite(
+                    &[true, false, false],
+                    Some(enumeration_ruleset_name)
+                ),
+                generate_module_enumeration_rewrite(
+                    &[false, true, true],
+                    Some(enumeration_ruleset_name)
+                ),
+                generate_module_enumeration_rewrite(
+                    &[false, true, false],
+                    Some(enumeration_ruleset_name)
+                ),
+                generate_module_enumeration_rewrite(
+                    &[false, false, true],
+                    Some(enumeration_ruleset_name)
+                ),
+                generate_module_enumeration_rewrite(
+                    &[false, false, false],
+                    Some(enumeration_ruleset_name)
+                ),
+                // clang-format on
+            ]
+            .join("\n"),
+        )
+}
+
+/// Generate module enumeration rewrite.
+///
+/// - hole_indicator: a list of booleans indicating whether the Op's
+///   argument at the given index is a hole. If true, the argument will
+///   become a `(Hole)`. If not, it will expect a module application:
+///   `(apply (MakeModule graph indices) args)`.
+///
+/// ```
+/// use churchroad::generate_module_enumeration_rewrite;
+/// assert_eq!(generate_module_enumeration_rewrite(&[true, false, true], None),
+///           "(rewrite
+///   (Op3 op expr0 (apply (MakeModule graph1 _) args1) expr2)
+///   (apply (MakeModule (Op3_ op (Hole) graph1 (Hole)) (debruijnify (vec-append (vec-empty) (vec-of expr0) args1 (vec-of expr2)))) (vec-append (vec-empty) (vec-of expr0) args1 (vec-of expr2)))
+/// )");
+/// ```
+///
+/// With no holes, every argument must already be a module application, and
+/// the new module's graph is built from the arguments' graphs:
+///
+/// ```
+/// use churchroad::generate_module_enumeration_rewrite;
+/// assert_eq!(generate_module_enumeration_rewrite(&[false, false], None),
+///           "(rewrite
+///   (Op2 op (apply (MakeModule graph0 _) args0) (apply (MakeModule graph1 _) args1))
+///   (apply (MakeModule (Op2_ op graph0 graph1) (debruijnify (vec-append (vec-empty) args0 args1))) (vec-append (vec-empty) args0 args1))
+/// )");
+/// ```
+///
+/// With only holes, the arguments can be any expressions, each of which
+/// becomes an argument of the new module:
+///
+/// ```
+/// use churchroad::generate_module_enumeration_rewrite;
+/// assert_eq!(generate_module_enumeration_rewrite(&[true, true], None),
+///           "(rewrite
+///   (Op2 op expr0 expr1)
+///   (apply (MakeModule (Op2_ op (Hole) (Hole)) (debruijnify (vec-append (vec-empty) (vec-of expr0) (vec-of expr1)))) (vec-append (vec-empty) (vec-of expr0) (vec-of expr1)))
+/// )");
+/// ```
+///
+/// A zero-arity op becomes a module with no arguments, applied to
+/// `(vec-empty)`. This is how constants end up as concrete parts of bigger
+/// modules: the `(Op0_ (BV v w))` graph is matched like any other nested
+/// module, so `x + 1` becomes an instance of a one-argument increment module
+/// as well as of the two-argument adder.
+///
+/// ```
+/// use churchroad::generate_module_enumeration_rewrite;
+/// assert_eq!(generate_module_enumeration_rewrite(&[], None),
+///           "(rewrite
+///   (Op0 op )
+///   (apply (MakeModule (Op0_ op ) (debruijnify (vec-append (vec-empty) ))) (vec-append (vec-empty) ))
+/// )");
+/// ```
+pub fn generate_module_enumeration_rewrite(
+    hole_indicator: &[bool],
+    ruleset: Option<&str>,
+) -> String {
+    let arity: usize = hole_indicator.len();
+
+    fn make_apply_pattern(idx: usize) -> String {
+        format!("(apply (MakeModule graph{idx} _) args{idx})", idx = idx)
+    }
+
+    fn make_opaque_expr_pattern(idx: usize) -> String {
+        format!("expr{idx}", idx = idx)
+    }
+
+    let arg_patterns = hole_indicator
+        .iter()
+        .enumerate()
+        .map(|(idx, is_hole)| {
+            if *is_hole {
+                make_opaque_expr_pattern(idx)
+            } else {
+                make_apply_pattern(idx)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let lhs = format!(
+        "(Op{arity} op {args})",
+        arity = arity,
+        args = arg_patterns.join(" ")
+    );
+
+    let args_rhs_patterns = hole_indicator
+        .iter()
+        .enumerate()
+        .map(|(idx, is_hole)| {
+            if *is_hole {
+                "(Hole)".to_string()
+            } else {
+                format!("graph{idx}", idx = idx).to_string()
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // Creates the list of arguments for the module application. Starting
+    // from `(vec-empty)` gives zero-arity ops an empty, but still typed, list.
+    let args_list_expr = format!(
+        "(vec-append (vec-empty) {args})",
+        args = hole_indicator
+            .iter()
+            .enumerate()
+            .map(|(idx, is_hole)| {
+                if *is_hole {
+                    format!("(vec-of expr{idx})", idx = idx)
+                } else {
+                    format!("args{idx}", idx = idx)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+
+    let rhs = format!(
+        "(apply (MakeModule (Op{arity}_ op {graphs}) (debruijnify {args})) {args})",
+        arity = arity,
+        graphs = args_rhs_patterns.join(" "),
+        args = args_list_expr,
+    );
+
+    format!(
+        "(rewrite
+  {lhs}
+  {rhs}
+{ruleset_flag})",
+        lhs = lhs,
+        rhs = rhs,
+        ruleset_flag = match ruleset {
+            Some(ruleset) => format!(":ruleset {}\n", ruleset),
+            None => "".to_string(),
+        },
+    )
+}
+
+/// Run module enumeration to saturation, after adding the mirror image of
+/// every commutative op with the `commute` ruleset. Without the mirror
+/// images, `and(or(a, b), c)` and `and(c, or(d, e))` would enumerate
+/// different modules, skewing any frequency analysis of the modules.
+///
+/// Returns the nodes which can't become modules, as for
+/// [`run_module_enumeration`].
+pub fn enumerate_modules(egraph: &mut EGraph) -> Vec<NodeId> {
+    egraph
+        .parse_and_run_program("(run-schedule (saturate commute) (saturate enumerate-modules))")
+        .unwrap();
+    warn_unsupported_for_enumeration(egraph)
+}
+
+/// Like [`enumerate_modules`], but only runs `max_size` iterations of
+/// `enumerate-modules` rather than running it to saturation, which blows up on
+/// anything but small designs.
+///
+/// Each iteration wraps existing modules in at most one more op, so no module
+/// will be more than `max_size` ops deep. For chains of ops, that's exactly a
+/// bound on the number of ops in each module.
+///
+/// Returns the nodes whose ops have more arguments than the enumeration
+/// rewrites handle, as found by [`unsupported_for_enumeration`], logging a
+/// warning for each. These nodes never become modules, so any analysis of the
+/// enumerated modules misses them.
+pub fn run_module_enumeration(egraph: &mut EGraph, max_size: usize) -> Vec<NodeId> {
+    egraph
+        .parse_and_run_program(&format!(
+            "(run-schedule (saturate commute) (repeat {max_size} enumerate-modules))"
+        ))
+        .unwrap();
+    warn_unsupported_for_enumeration(egraph)
+}
+
+/// The largest arity of op for which
+/// [`generate_module_enumeration_rewrites`] generates rewrites.
+pub const MAX_ENUMERATION_ARITY: usize = 3;
+
+/// Find the `Op{n}` nodes with more than [`MAX_ENUMERATION_ARITY`] arguments,
+/// which module enumeration can't turn into modules.
+pub fn unsupported_for_enumeration(egraph: &egraph_serialize::EGraph) -> Vec<NodeId> {
+    egraph
+        .nodes
+        .iter()
+        .filter(|(_, node)| {
+            node.op
+                .strip_prefix("Op")
+                .and_then(|arity| arity.parse::<usize>().ok())
+                .is_some_and(|arity| arity > MAX_ENUMERATION_ARITY)
+        })
+        .map(|(id, _)| id.clone())
+        .collect()
+}
+
+fn warn_unsupported_for_enumeration(egraph: &EGraph) -> Vec<NodeId> {
+    let serialized = egraph.serialize(egglog::SerializeConfig::default());
+    let unsupported = unsupported_for_enumeration(&serialized);
+    for id in &unsupported {
+        log::warn!(
+            "{} node {} has too many arguments for module enumeration, so it won't become a module",
+            serialized[id].op,
+            id
+        );
+    }
+    unsupported
+}
+
+/// The differences between two serializations of an egraph, as found by
+/// [`diff_egraphs`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct EGraphDiff {
+    /// The nodes of the second egraph which aren't in the first.
+    pub new_enodes: Vec<NodeId>,
+    /// Pairs of eclasses of the first egraph which are merged in the second.
+    /// Where more than two eclasses are merged, each is paired with the first
+    /// of them.
+    pub new_merges: Vec<(ClassId, ClassId)>,
+    /// The eclasses of the second egraph which hold none of the eclasses of
+    /// the first.
+    pub new_eclasses: Vec<ClassId>,
+    /// The number of nodes of the second egraph which are also in the first.
+    pub unchanged_enodes: usize,
+}
+
+impl EGraphDiff {
+    /// Whether the egraphs hold the same nodes and eclasses.
+    pub fn is_empty(&self) -> bool {
+        self.new_enodes.is_empty() && self.new_merges.is_empty() && self.new_eclasses.is_empty()
+    }
+}
+
+/// Find what changed between two serializations of the same egraph, e.g.
+/// before and after running some rewrites. Nodes can only be added and
+/// eclasses merged, so anything in `before` is assumed to still be in `after`.
+///
+/// Node IDs aren't stable across serializations, as egglog moves nodes when it
+/// canonicalizes them, so nodes are matched by their ops and the eclasses of
+/// their children. An eclass is matched by its ID, if it still exists, or else
+/// by a matching node.
+///
+/// ```
+/// use churchroad::*;
+/// use egglog::{EGraph, SerializeConfig};
+///
+/// let mut egraph = EGraph::default();
+/// import_churchroad(&mut egraph).unwrap();
+/// egraph
+///     .parse_and_run_program(r#"(let a (Op2 (And) (Var "a" 1) (Var "b" 1)))"#)
+///     .unwrap();
+/// let before = egraph.serialize(SerializeConfig::default());
+/// assert!(diff_egraphs(&before, &before).is_empty());
+///
+/// egraph
+///     .parse_and_run_program(r#"(union a (Op2 (And) (Var "b" 1) (Var "a" 1)))"#)
+///     .unwrap();
+/// let diff = diff_egraphs(&before, &egraph.serialize(SerializeConfig::default()));
+/// assert_eq!(diff.new_enodes.len(), 1);
+/// assert!(diff.new_merges.is_empty());
+/// ```
+pub fn diff_egraphs(
+    before: &egraph_serialize::EGraph,
+    after: &egraph_serialize::EGraph,
+) -> EGraphDiff {
+    // The nodes of `after`, by their ops and the eclasses of their children.
+    let after_nodes: HashMap<(&str, Vec<&ClassId>), &NodeId> = after
+        .nodes
+        .iter()
+        .map(|(id, node)| {
+            let children = node.children.iter().map(|child| &after[child].eclass);
+            ((node.op.as_str(), children.collect()), id)
+        })
+        .collect();
+
+    // The eclass of `after` holding each eclass of `before`.
+    let mut class_map: HashMap<&ClassId, &ClassId> = before
+        .classes()
+        .keys()
+        .filter_map(|id| Some((id, after.classes().get_key_value(id)?.0)))
+        .collect();
+    let mut matched_nodes: HashSet<&NodeId> = HashSet::new();
+    loop {
+        let mut progress = false;
+        for node in before.nodes.values() {
+            let Some(children) = node
+                .children
+                .iter()
+                .map(|child| class_map.get(&before[child].eclass).copied())
+                .collect::<Option<Vec<_>>>()
+            else {
+                continue;
+            };
+            let Some(&after_node) = after_nodes.get(&(node.op.as_str(), children)) else {
+                continue;
+            };
+            progress |= matched_nodes.insert(after_node);
+            if !class_map.contains_key(&node.eclass) {
+                class_map.insert(&node.eclass, &after[after_node].eclass);
+                progress = true;
+            }
+        }
+        if !progress {
+            break;
+        }
+    }
+
+    // The eclasses of `before` held by each eclass of `after`.
+    let mut held: IndexMap<&ClassId, Vec<&ClassId>> = IndexMap::new();
+    for id in before.classes().keys() {
+        if let Some(after_id) = class_map.get(id) {
+            held.entry(*after_id).or_default().push(id);
+        }
+    }
+
+    EGraphDiff {
+        new_enodes: after
+            .nodes
+            .keys()
+            .filter(|id| !matched_nodes.contains(id))
+            .cloned()
+            .collect(),
+        new_merges: held
+            .values()
+            .flat_map(|ids| ids[1..].iter().map(|id| (ids[0].clone(), (*id).clone())))
+            .collect(),
+        new_eclasses: after
+            .classes()
+            .keys()
+            .filter(|id| !held.contains_key(id))
+            .cloned()
+            .collect(),
+        unchanged_enodes: matched_nodes.len(),
+    }
+}
+
+/// List all modules present in the egraph.
+pub fn list_modules(egraph: &mut EGraph, num_variants: usize) {
+    for s in egraph
+        .parse_and_run_program(
+            format!("(query-extract :variants {num_variants} (MakeModule mod args))").as_str(),
+        )
+        .unwrap()
+    {
+        println!("{}", s);
+    }
+}
+
+/// Count the `apply` nodes which apply the module in `module_class`, i.e.
+/// how many places in the design could share one implementation of it.
+pub fn count_instances_of_module(
+    egraph: &egraph_serialize::EGraph,
+    module_class: &ClassId,
+) -> usize {
+    egraph
+        .nodes
+        .values()
+        .filter(|node| node.op == "apply" && egraph[&node.children[0]].eclass == *module_class)
+        .count()
+}
+
+/// Count the instances of every module in the egraph, as with
+/// [`count_instances_of_module`], most instantiated first. Useful for
+/// deciding which modules to synthesize first.
+pub fn all_module_instance_counts(egraph: &egraph_serialize::EGraph) -> Vec<(ClassId, usize)> {
+    let mut counts: Vec<(ClassId, usize)> = egraph
+        .classes()
+        .iter()
+        .filter(|(_, class)| {
+            class
+                .nodes
+                .iter()
+                .any(|node_id| egraph[node_id].op == "MakeModule")
+        })
+        .map(|(id, _)| (id.clone(), count_instances_of_module(egraph, id)))
+        .collect();
+    counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+    counts
+}
+
+/// The egglog term for a node of a serialized egraph, following the first
+/// child of each node. Only meant for acyclic parts of the egraph, such as
+/// module graphs.
+fn serialized_term(egraph: &egraph_serialize::EGraph, node_id: &NodeId) -> String {
+    let node = &egraph[node_id];
+    if node.children.is_empty() && (node.op.parse::<i64>().is_ok() || node.op.starts_with('"')) {
+        return node.op.clone();
+    }
+    let children = node
+        .children
+        .iter()
+        .map(|child| format!(" {}", serialized_term(egraph, child)))
+        .collect::<String>();
+    format!("({}{})", node.op, children)
+}
+
+/// A module found by module enumeration, as returned by [`mine_modules`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MinedModule {
+    /// The class of the `MakeModule` node.
+    pub class: ClassId,
+    /// The module's graph, as an egglog term, e.g.
+    /// `(Op2_ (And) (Hole) (Hole))`.
+    pub graph: String,
+    /// The number of distinct arguments the module takes.
+    pub arity: usize,
+    /// The classes which contain an application of the module, i.e. the
+    /// places in the design which one implementation of the module could
+    /// serve.
+    pub application_sites: Vec<ClassId>,
+}
+
+impl MinedModule {
+    /// The number of distinct places the module is applied.
+    pub fn num_application_sites(&self) -> usize {
+        self.application_sites.len()
+    }
+}
+
+/// Find all the modules in the egraph, most applied first. Unlike
+/// [`list_modules`], which just prints them, this describes each module and
+/// where it's applied. Module enumeration should already have been run, e.g.
+/// with [`enumerate_modules`].
+pub fn mine_modules(egraph: &mut EGraph) -> Vec<MinedModule> {
+    let serialized = egraph.serialize(egglog::SerializeConfig::default());
+
+    let mut modules: Vec<MinedModule> = serialized
+        .nodes
+        .values()
+        .filter(|node| node.op == "MakeModule")
+        .map(|node| {
+            let application_sites: Vec<ClassId> = serialized
+                .nodes
+                .values()
+                .filter(|apply| {
+                    apply.op == "apply" && serialized[&apply.children[0]].eclass == node.eclass
+                })
+                .map(|apply| apply.eclass.clone())
+                .collect::<IndexSet<_>>()
+                .into_iter()
+                .collect();
+            // The IVec holds the De Bruijn index of each hole, so the module
+            // has as many arguments as it has distinct indices.
+            let arity = serialized[&node.children[1]]
+                .children
+                .iter()
+                .map(|index| serialized[index].op.as_str())
+                .collect::<HashSet<_>>()
+                .len();
+            MinedModule {
+                class: node.eclass.clone(),
+                graph: serialized_term(&serialized, &node.children[0]),
+                arity,
+                application_sites,
+            }
+        })
+        .collect();
+    modules.sort_by_key(|module| std::cmp::Reverse(module.num_application_sites()));
+    modules
+}
+
+/// Emit a Verilog module definition for each of the modules in `modules`,
+/// e.g. as found by [`mine_modules`], to be read by people or synthesized on
+/// its own. These are the same definitions [`to_verilog_egraph_serialize`]
+/// emits for chosen `apply` nodes: each module is named
+/// `churchroad_module_{class}` and has inputs `in0` to `in{k-1}` and an output
+/// `out`, with widths set by parameters.
+///
+/// Fails on the first module which can't be written as a module definition,
+/// e.g. because it contains a register.
+pub fn emit_module_library(
+    egraph: &egraph_serialize::EGraph,
+    modules: &[ClassId],
+) -> Result<String, ExportError> {
+    Ok(modules
+        .iter()
+        .map(|class| {
+            let make_module = egraph[class]
+                .nodes
+                .iter()
+                .map(|node_id| &egraph[node_id])
+                .find(|node| node.op == "MakeModule")
+                .unwrap();
+            module_definition(egraph, make_module, &format!("churchroad_module_{class}"))
+        })
+        .collect::<Result<Vec<_>, _>>()?
+        .join("\n\n"))
+}
+
+/// A canonical form for the module in `module_class`, equal for any two
+/// modules which compute the same thing up to the numbering of their
+/// arguments and the order of the operands of commutative ops. Can be used to
+/// key a map of modules, e.g. a cache of synthesized modules.
+///
+/// Holes are written `?i`, with the numbering of the arguments chosen to make
+/// the form as small as possible, so the cost grows with the factorial of
+/// the module's arity. Module graphs are expected to be small.
+pub fn module_canonical_form(egraph: &egraph_serialize::EGraph, module_class: &ClassId) -> String {
+    let make_module = egraph[module_class]
+        .nodes
+        .iter()
+        .map(|node_id| &egraph[node_id])
+        .find(|node| node.op == "MakeModule")
+        .unwrap();
+    let argument_indices: Vec<usize> = egraph[&make_module.children[1]]
+        .children
+        .iter()
+        .map(|index| egraph[index].op.parse().unwrap())
+        .collect();
+    let arity = argument_indices.iter().max().map_or(0, |max| max + 1);
+
+    /// Render the graph with argument `i` numbered `numbering[i]`, sorting
+    /// the operands of commutative ops.
+    fn render(
+        egraph: &egraph_serialize::EGraph,
+        graph: &Node,
+        argument_indices: &[usize],
+        numbering: &[usize],
+        next_hole: &mut usize,
+    ) -> String {
+        if graph.op == "Hole" {
+            let index = argument_indices[*next_hole];
+            *next_hole += 1;
+            return format!("?{}", numbering[index]);
+        }
+        let op = serialized_term(egraph, &graph.children[0]);
+        let mut args: Vec<String> = graph.children[1..]
+            .iter()
+            .map(|child| {
+                render(
+                    egraph,
+                    &egraph[child],
+                    argument_indices,
+                    numbering,
+                    next_hole,
+                )
+            })
+            .collect();
+        if matches!(
+            egraph[&graph.children[0]].op.as_str(),
+            "And" | "Or" | "Xor" | "Add" | "Mul" | "Eq" | "Ne"
+        ) {
+            args.sort();
+        }
+        format!(
+            "({} {op}{})",
+            graph.op,
+            args.iter().map(|arg| format!(" {arg}")).collect::<String>()
+        )
+    }
+
+    fn permutations(n: usize) -> Vec<Vec<usize>> {
+        if n == 0 {
+            return vec![vec![]];
+        }
+        permutations(n - 1)
+            .into_iter()
+            .flat_map(|permutation| {
+                (0..n).map(move |position| {
+                    let mut permutation = permutation.clone();
+                    permutation.insert(position, n - 1);
+                    permutation
+                })
+            })
+            .collect()
+    }
+
+    permutations(arity)
+        .iter()
+        .map(|numbering| {
+            render(
+                egraph,
+                &egraph[&make_module.children[0]],
+                &argument_indices,
+                numbering,
+                &mut 0,
+            )
+        })
+        .min()
+        .unwrap()
+}
+
+/// Whether the modules in two classes compute the same thing, up to the
+/// numbering of their arguments and the order of the operands of commutative
+/// ops. See [`module_canonical_form`].
+pub fn modules_isomorphic(
+    egraph: &egraph_serialize::EGraph,
+    module_a_class: &ClassId,
+    module_b_class: &ClassId,
+) -> bool {
+    module_canonical_form(egraph, module_a_class) == module_canonical_form(egraph, module_b_class)
+}
+
+/// Choose, in every class which applies one of the modules in `library`, an
+/// application of that module, and a node in direct form (i.e. not an
+/// `apply`) elsewhere. [`to_verilog_egraph_serialize`] emits the chosen
+/// applications as instances of one module definition per library module.
+///
+/// Applications whose arguments are `Var_`s are never chosen, as they aren't
+/// connected to the design's inputs; module enumeration puts an equivalent
+/// application with the `Var`s themselves as arguments in the same class.
+/// Nor are applications of library modules which can't be written as a module
+/// definition, e.g. because they contain registers; those are left in direct
+/// form, with a warning.
+pub fn extract_with_module_library(
+    egraph: &egraph_serialize::EGraph,
+    _roots: &[ClassId],
+    library: &[ClassId],
+) -> IndexMap<ClassId, NodeId> {
+    let library: Vec<&ClassId> = library
+        .iter()
+        .filter(|class| {
+            let Some(make_module) = egraph[*class]
+                .nodes
+                .iter()
+                .map(|node_id| &egraph[node_id])
+                .find(|node| node.op == "MakeModule")
+            else {
+                return false;
+            };
+            match module_definition(egraph, make_module, "") {
+                Ok(_) => true,
+                Err(err) => {
+                    log::warn!("not using library module {class}: {err}");
+                    false
+                }
+            }
+        })
+        .collect();
+    let applies_library_module = |node: &Node| {
+        node.op == "apply"
+            && library.contains(&&egraph[&node.children[0]].eclass)
+            && egraph[&node.children[1]].children.iter().all(|arg| {
+                egraph[&egraph[arg].eclass]
+                    .nodes
+                    .iter()
+                    .all(|node_id| egraph[node_id].op != "Var_")
+            })
+    };
+
+    egraph
+        .classes()
+        .iter()
+        .map(|(id, class)| {
+            let node_id = class
+                .nodes
+                .iter()
+                .find(|node_id| applies_library_module(&egraph[*node_id]))
+                .or_else(|| {
+                    class
+                        .nodes
+                        .iter()
+                        .find(|node_id| egraph[*node_id].op != "apply")
+                })
+                .unwrap_or(&class.nodes[0]);
+            (id.clone(), node_id.clone())
+        })
+        .collect()
+}
+
+/// Whether a port is an input or an output of the design.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Input,
+    Output,
+}
+
+/// Error returned by [`get_ports`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortError {
+    /// An `IsPort` fact has a direction other than `(Input)` or `(Output)`,
+    /// given here as a term.
+    UnknownDirection(String),
+}
+
+impl std::fmt::Display for PortError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PortError::UnknownDirection(direction) => {
+                write!(f, "Unknown port direction {}.", direction)
+            }
+        }
+    }
+}
+
+/// A port of a design, as described by an `IsPort` fact.
+#[derive(Debug, Clone)]
+pub struct Port {
+    /// The name of the module the port belongs to. Empty for designs which
+    /// don't name their modules.
+    pub module: String,
+    pub name: String,
+    pub direction: Direction,
+    /// The width of the port's expression, taken from the expression itself
+    /// for `Var`s and from its `HasType` otherwise. `None` if the expression
+    /// hasn't been typed, e.g. because the `typing` ruleset hasn't been run.
+    pub width: Option<u64>,
+    /// The sort and value of the port's expression.
+    pub expr: (ArcSort, Value),
+    /// The port's position in its module's declaration, from its `PortIndex`
+    /// fact. `None` if it has none, e.g. if the design wasn't imported from
+    /// Verilog.
+    pub index: Option<usize>,
+}
+
+/// Find the ports of a design, in declaration order. If `module` is given,
+/// only that module's ports are found. Ports without a `PortIndex` fact come
+/// last, in the order egglog stores their `IsPort` facts. Returns
+/// [`PortError::UnknownDirection`] if a port is neither an input nor an
+/// output. Takes `&mut EGraph` because egglog's `EGraph::function_to_dag`,
+/// which reads the `IsPort` facts, does.
+///
+/// ```
+/// use churchroad::*;
+/// use egglog::{EGraph, TermDag};
+///
+/// // Get an egraph, load in a simple design.
+/// let mut egraph = EGraph::default();
+///
+/// import_churchroad(&mut egraph).unwrap();
+/// egraph
+///     .parse_and_run_program(
+///         r#"
+/// ; wire declarations
+/// ; $and$<<EOF:2$1_Y
+/// (let v0 (Wire "v0" 2))
+/// ; a
+/// (let v1 (Wire "v1" 2))
+/// ; b
+/// (let v2 (Wire "v2" 1))
+/// ; o
+/// (let v3 (Wire "v3" 1))
+///
+/// ; cells
+/// ; TODO not handling signedness
+/// (let v4 (Op1 (ZeroExtend 2) v2))
+/// (union v0 (Op2 (And) v1 v4))
+/// (let v5 (Op1 (Extract 0 0) v0))
+/// (union v3 (Op1 (Extract 0 0) v5))
+///
+/// ; inputs
+/// (IsPort "" "a" (Input) (Var "a" 2))
+/// (union v1 (Var "a" 2))
+/// (IsPort "" "b" (Input) (Var "b" 1))
+/// (union v2 (Var "b" 1))
+///
+/// ; outputs
+/// (IsPort "" "o" (Output) v3)
+/// "#,
+///     )
+///     .unwrap();
+/// auto_delete_wires(&mut egraph);
+/// egraph
+///     .parse_and_run_program("(run-schedule (saturate typing))")
+///     .unwrap();
+///
+/// let ports = get_ports(&mut egraph, None).unwrap();
+/// let summary: Vec<(&str, Direction, Option<u64>)> = ports
+///     .iter()
+///     .map(|port| (port.name.as_str(), port.direction, port.width))
+///     .collect();
+/// assert_eq!(
+///     summary,
+///     vec![
+///         ("a", Direction::Input, Some(2)),
+///         ("b", Direction::Input, Some(1)),
+///         ("o", Direction::Output, Some(1)),
+///     ]
+/// );
+///
+/// // The port's expression is still available, e.g. for extraction.
+/// let (sort, value) = ports[2].expr.clone();
+/// let mut termdag = TermDag::default();
+/// let (_, term) = egraph.extract(value, &mut termdag, &sort);
+/// assert_eq!(termdag.to_string(&term), "(Op1 (Extract 0 0) (Op1 (Extract 0 0) (Op2 (And) (Var \"a\" 2) (Op1 (ZeroExtend 2) (Var \"b\" 1)))))");
+/// ```
+pub fn get_ports(egraph: &mut EGraph, module: Option<&str>) -> Result<Vec<Port>, PortError> {
+    let (results, termdag) = egraph.function_to_dag("IsPort".into(), usize::MAX).unwrap();
+
+    let mut ports = vec![];
+    // The ports whose widths must come from `HasType`, by the string of their
+    // expression.
+    let mut untyped_ports: HashMap<String, usize> = HashMap::new();
+    for (term, output) in &results {
+        assert!(
+            matches!(output, Term::Lit(Literal::Unit)),
+            "IsPort relation shouldn't have any outputs."
+        );
+
+        let children = match term {
+            Term::App(_, children) => children,
+            _ => panic!(),
+        };
+
+        let port_module = match termdag.get(children[0]) {
+            Term::Lit(Literal::String(port_module)) => port_module.to_string(),
+            _ => panic!(),
+        };
+        if module.is_some_and(|module| module != port_module) {
+            continue;
+        }
+
+        let direction = match termdag.get(children[2]) {
+            Term::App(direction, v) if v.is_empty() && direction == "Input".into() => {
+                Direction::Input
+            }
+            Term::App(direction, v) if v.is_empty() && direction == "Output".into() => {
+                Direction::Output
+            }
+            direction => return Err(PortError::UnknownDirection(termdag.to_string(&direction))),
+        };
+
+        let name = match termdag.get(children[1]) {
+            Term::Lit(Literal::String(name)) => name.to_string(),
+            _ => panic!(),
+        };
+
+        let expr_string = termdag.to_string(&termdag.get(children[3]));
+        let expr = eval_term_string(egraph, &expr_string);
+
+        // A `Var`'s width is right there in the expression.
+        let width = match termdag.get(children[3]) {
+            Term::App(op, var_children) if op == "Var".into() => {
+                match termdag.get(var_children[1]) {
+                    Term::Lit(Literal::Int(width)) => Some(width as u64),
+                    _ => panic!(),
+                }
+            }
+            _ => {
+                untyped_ports.insert(expr_string, ports.len());
+                None
+            }
+        };
+
+        ports.push(Port {
+            module: port_module,
+            name,
+            direction,
+            width,
+            expr,
+            index: None,
+        });
+    }
+
+    let (indices, termdag) = egraph
+        .function_to_dag("PortIndex".into(), usize::MAX)
+        .unwrap();
+    for (term, _) in &indices {
+        let Term::App(_, children) = term else {
+            panic!()
+        };
+        let (
+            Term::Lit(Literal::String(module)),
+            Term::Lit(Literal::String(name)),
+            Term::Lit(Literal::Int(index)),
+        ) = (
+            termdag.get(children[0]),
+            termdag.get(children[1]),
+            termdag.get(children[2]),
+        )
+        else {
+            panic!()
+        };
+        for port in ports
+            .iter_mut()
+            .filter(|port| port.module == module.as_str() && port.name == name.as_str())
+        {
+            port.index = Some(index as usize);
+        }
+    }
+
+    if !untyped_ports.is_empty() {
+        let (types, termdag) = egraph
+            .function_to_dag("HasType".into(), usize::MAX)
+            .unwrap();
+        for (term, _) in &types {
+            let Term::App(_, children) = term else {
+                panic!()
+            };
+            let Some(&port) = untyped_ports.get(&termdag.to_string(&termdag.get(children[0])))
+            else {
+                continue;
+            };
+            if let Term::App(_, type_children) = termdag.get(children[1]) {
+                if let Term::Lit(Literal::Int(width)) = termdag.get(type_children[0]) {
+                    ports[port].width = Some(width as u64);
+                }
+            }
+        }
+    }
+
+    ports.sort_by_key(|port| port.index.unwrap_or(usize::MAX));
+    Ok(ports)
+}
+
+/// Port name, port type, port value.
+type Ports = Vec<(String, ArcSort, Value)>;
+
+/// Like [`get_ports`], but splits the ports into inputs and outputs, and only
+/// gives the name and expression of each.
+// TODO(@gussmith23): This really shouldn't require mutability.
+pub fn get_inputs_and_outputs(egraph: &mut EGraph) -> Result<(Ports, Ports), PortError> {
+    let (inputs, outputs): (Vec<Port>, Vec<Port>) = get_ports(egraph, None)?
+        .into_iter()
+        .partition(|port| port.direction == Direction::Input);
+    let to_ports = |ports: Vec<Port>| {
+        ports
+            .into_iter()
+            .map(|port| (port.name, port.expr.0, port.expr.1))
+            .collect()
+    };
+    Ok((to_ports(inputs), to_ports(outputs)))
+}
+
+/// Look up the port with the given name, returning its sort and value, or
+/// `None` if there is no such port. Unlike [`get_inputs_and_outputs`], only
+/// the matching port is evaluated. `egraph` is mutable only because egglog
+/// needs `&mut EGraph` to read the `IsPort` facts and evaluate the port.
+///
+/// ```
+/// use churchroad::*;
+/// use egglog::{EGraph, TermDag};
+///
+/// let mut egraph = EGraph::default();
+/// import_churchroad(&mut egraph).unwrap();
+/// egraph
+///     .parse_and_run_program(
+///         r#"
+/// (let a (Var "a" 1))
+/// (IsPort "" "a" (Input) a)
+/// (IsPort "" "O" (Output) (Op1 (Not) a))
+/// "#,
+///     )
+///     .unwrap();
+///
+/// let (sort, value) = get_output_by_name(&mut egraph, "O").unwrap();
+/// let mut termdag = TermDag::default();
+/// let (_, term) = egraph.extract(value, &mut termdag, &sort);
+/// assert_eq!(termdag.to_string(&term), "(Op1 (Not) (Var \"a\" 1))");
+/// assert!(get_port_by_name(&mut egraph, "a").is_some());
+/// assert!(get_output_by_name(&mut egraph, "a").is_none());
+/// assert!(get_port_by_name(&mut egraph, "b").is_none());
+/// ```
+pub fn get_port_by_name(egraph: &mut EGraph, name: &str) -> Option<(ArcSort, Value)> {
+    find_port(egraph, name, None)
+}
+
+/// Like [`get_port_by_name`], but only finds input ports.
+pub fn get_input_by_name(egraph: &mut EGraph, name: &str) -> Option<(ArcSort, Value)> {
+    find_port(egraph, name, Some("Input"))
+}
+
+/// Like [`get_port_by_name`], but only finds output ports.
+pub fn get_output_by_name(egraph: &mut EGraph, name: &str) -> Option<(ArcSort, Value)> {
+    find_port(egraph, name, Some("Output"))
+}
+
+/// Find the port named `name`, optionally requiring it to have the given
+/// direction (`"Input"` or `"Output"`).
+fn find_port(egraph: &mut EGraph, name: &str, direction: Option<&str>) -> Option<(ArcSort, Value)> {
+    let (results, termdag) = egraph.function_to_dag("IsPort".into(), usize::MAX).unwrap();
+    let churchroad_term = results.iter().find_map(|(term, _)| {
+        let children = match term {
+            Term::App(_, children) => children,
+            _ => panic!(),
+        };
+        let name_matches = matches!(
+            termdag.get(children[1]),
+            Term::Lit(Literal::String(port_name)) if port_name.as_str() == name
+        );
+        let direction_matches = match (direction, termdag.get(children[2])) {
+            (None, _) => true,
+            (Some(direction), Term::App(in_or_out, _)) => in_or_out.as_str() == direction,
+            _ => panic!(),
+        };
+        (name_matches && direction_matches).then(|| children[3])
+    })?;
+
+    Some(eval_term_string(
+        egraph,
+        &termdag.to_string(&termdag.get(churchroad_term)),
+    ))
+}
+
+/// The width of the port named `port_name` of `module`, or `None` if there's
+/// no such port or its width isn't known. See [`Port::width`], and
+/// [`get_ports`] for why `egraph` is mutable.
+///
+/// ```
+/// use churchroad::*;
+/// use egglog::EGraph;
+///
+/// let mut egraph = EGraph::default();
+/// import_churchroad(&mut egraph).unwrap();
+/// egraph
+///     .parse_and_run_program(
+///         r#"
+/// (let a (Var "a" 8))
+/// (IsPort "top" "a" (Input) a)
+/// (IsPort "top" "o" (Output) (Op1 (Extract 0 0) a))
+/// (run-schedule (saturate typing))
+/// "#,
+///     )
+///     .unwrap();
+///
+/// assert_eq!(port_width(&mut egraph, "top", "a"), Some(8));
+/// assert_eq!(port_width(&mut egraph, "top", "o"), Some(1));
+/// assert_eq!(port_width(&mut egraph, "other", "a"), None);
+/// ```
+pub fn port_width(egraph: &mut EGraph, module: &str, port_name: &str) -> Option<u64> {
+    get_ports(egraph, Some(module))
+        .ok()?
+        .into_iter()
+        .find(|port| port.name == port_name)?
+        .width
+}
+
+/// The width of an eclass of a serialized egraph: the width of its `Var`, if
+/// it has one, or else its width from its `HasType` fact, as for
+/// [`get_bitwidth`].
+pub fn expr_width(egraph: &egraph_serialize::EGraph, class: &ClassId) -> Option<u64> {
+    egraph
+        .classes()
+        .get(class)?
+        .nodes
+        .iter()
+        .find_map(|id| {
+            let node = &egraph[id];
+            (node.op == "Var").then(|| get_var_name_and_bitwidth(egraph, node).1)
+        })
+        .or_else(|| get_bitwidth(egraph, class))
+}
+
+/// Port name, port eclass.
+pub type SerializedPort = (String, ClassId);
+
+/// Like [`get_inputs_and_outputs`], but reads the `IsPort` facts from a
+/// serialized egraph, such as the one the interpreter and Verilog backend
+/// consume, giving each port's eclass rather than its value. Doesn't need the
+/// egraph to be mutable. As with [`get_ports`], ports come in declaration
+/// order when they have `PortIndex` facts.
+///
+/// ```
+/// use churchroad::*;
+/// use egglog::{EGraph, SerializeConfig};
+///
+/// let mut egraph = EGraph::default();
+/// import_churchroad(&mut egraph).unwrap();
+/// egraph
+///     .parse_and_run_program(
+///         r#"
+///     ; wire declarations
+///     ; $and$<<EOF:2$1_Y
+///     (let v0 (Wire "v0" 2))
+///     ; a
+///     (let v1 (Wire "v1" 2))
+///     ; b
+///     (let v2 (Wire "v2" 1))
+///     ; o
+///     (let v3 (Wire "v3" 1))
+///
+///     ; cells
+///     ; TODO not handling signedness
+///     (let v4 (Op1 (ZeroExtend 2) v2))
+///     (union v0 (Op2 (And) v1 v4))
+///     (let v5 (Op1 (Extract 0 0) v0))
+///     (union v3 (Op1 (Extract 0 0) v5))
+///
+///     ; inputs
+///     (IsPort "" "a" (Input) (Var "a" 2))
+///     (union v1 (Var "a" 2))
+///     (IsPort "" "b" (Input) (Var "b" 1))
+///     (union v2 (Var "b" 1))
+///
+///     ; outputs
+///     (IsPort "" "o" (Output) v3)
+///     "#,
+///     )
+///     .unwrap();
+/// auto_delete_wires(&mut egraph);
+///
+/// let serialized = egraph.serialize(SerializeConfig::default());
+/// let (inputs, outputs) = get_inputs_and_outputs_serialized(&serialized);
+///
+/// // We should have found two inputs, a and b.
+/// assert_eq!(inputs.len(), 2);
+/// assert_eq!(inputs[0].0, "a");
+/// assert_eq!(inputs[1].0, "b");
+///
+/// // We should have found one output, o.
+/// assert_eq!(outputs.len(), 1);
+/// assert_eq!(outputs[0].0, "o");
+/// ```
+pub fn get_inputs_and_outputs_serialized(
+    egraph: &egraph_serialize::EGraph,
+) -> (Vec<SerializedPort>, Vec<SerializedPort>) {
+    // Find IsPort relations.
+    #[derive(Clone)]
+    enum InputOrOutput {
+        Input(String, ClassId),
+        Output(String, ClassId),
+    }
+
+    fn is_port(node: &Node, egraph: &egraph_serialize::EGraph) -> Option<InputOrOutput> {
+        if node.op != "IsPort" {
+            return None;
+        }
+
+        assert_eq!(node.children.len(), 4);
+
+        let inout = &node.children[2];
+
+        let expr = egraph[&node.children[3]].eclass.clone();
+
+        let name = egraph[&node.children[1]]
+            .op
+            .strip_prefix('\"')
+            .unwrap()
+            .strip_suffix('\"')
+            .unwrap()
+            .to_string();
+
+        match egraph[inout].op.as_str() {
+            "Input" => Some(InputOrOutput::Input(name, expr)),
+            "Output" => Some(InputOrOutput::Output(name, expr)),
+            _ => panic!(),
+        }
+    }
+
+    let mut inputs_and_outputs = egraph
+        .nodes
+        .iter()
+        .filter_map(|(_id, node)| is_port(node, egraph))
+        .collect::<Vec<_>>();
+    let indices = port_indices_serialized(egraph, None);
+    inputs_and_outputs.sort_by_key(|io| match io {
+        InputOrOutput::Input(n, _) | InputOrOutput::Output(n, _) => {
+            indices.get(n).copied().unwrap_or(usize::MAX)
+        }
+    });
+
+    let inputs = inputs_and_outputs
+        .iter()
+        .filter_map(|io| match io {
+            InputOrOutput::Input(n, v) => Some((n.clone(), v.clone())),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let outputs = inputs_and_outputs
+        .iter()
+        .filter_map(|io| match io {
+            InputOrOutput::Output(n, v) => Some((n.clone(), v.clone())),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    (inputs, outputs)
+}
+
+/// The index of each port with a `PortIndex` fact in a serialized egraph, by
+/// the port's name, optionally only for the ports of `module`.
+fn port_indices_serialized(
+    egraph: &egraph_serialize::EGraph,
+    module: Option<&str>,
+) -> HashMap<String, usize> {
+    egraph
+        .nodes
+        .values()
+        .filter(|node| {
+            node.op == "PortIndex"
+                && module
+                    .is_none_or(|module| egraph[&node.children[0]].op == format!("\"{module}\""))
+        })
+        .map(|node| {
+            let name = &egraph[&node.children[1]].op;
+            (
+                name[1..name.len() - 1].to_owned(),
+                egraph[&node.children[2]].op.parse().unwrap(),
+            )
+        })
+        .collect()
+}
+
+/// Find the inputs of a design which are clocks, in port order.
+///
+/// An input is a clock if it's only used as the clock of `(Op2 (Reg init)
+/// clk d)` or `(Op2 (RegWithClk init) clk d)` registers. Failing that, e.g.
+/// for designs whose registers don't carry their clocks, 1-bit inputs named
+/// `clk`, `clock` or `aclk` are assumed to be clocks.
+///
+/// ```
+/// use churchroad::*;
+/// use egglog::{EGraph, SerializeConfig};
+///
+/// let mut egraph = EGraph::default();
+/// import_churchroad(&mut egraph).unwrap();
+/// egraph
+///     .parse_and_run_program(
+///         r#"
+/// (let tick (Var "tick" 1))
+/// (IsPort "" "tick" (Input) tick)
+/// (let d (Var "d" 8))
+/// (IsPort "" "d" (Input) d)
+/// (IsPort "" "q" (Output) (Op2 (Reg 0) tick d))
+/// "#,
+///     )
+///     .unwrap();
+///
+/// let serialized = egraph.serialize(SerializeConfig::default());
+/// assert_eq!(detect_clocks(&serialized), vec!["tick"]);
+/// ```
+pub fn detect_clocks(egraph: &egraph_serialize::EGraph) -> Vec<String> {
+    let (inputs, _) = get_inputs_and_outputs_serialized(egraph);
+
+    // The uses of each eclass as an argument of an op, as (op, argument
+    // position, number of arguments).
+    let mut uses: HashMap<&ClassId, Vec<(&str, usize, usize)>> = HashMap::new();
+    for node in egraph.nodes.values() {
+        if !matches!(node.op.as_str(), "Op0" | "Op1" | "Op2" | "Op3") {
+            continue;
+        }
+        let op = &egraph[&node.children[0]];
+        for (i, child) in node.children.iter().enumerate().skip(1) {
+            uses.entry(&egraph[child].eclass).or_default().push((
+                op.op.as_str(),
+                i,
+                node.children.len() - 1,
+            ));
+        }
+    }
+
+    let clocks: Vec<String> = inputs
+        .iter()
+        .filter(|(name, class)| {
+            input_width(egraph, class) == Some(1)
+                && uses.get(class).is_some_and(|uses| {
+                    uses.iter()
+                        .all(|&use_| matches!(use_, ("Reg" | "RegWithClk", 1, 2)))
+                })
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+    if !clocks.is_empty() {
+        return clocks;
+    }
+
+    inputs
+        .into_iter()
+        .filter(|(name, class)| {
+            ["clk", "clock", "aclk"].contains(&name.to_lowercase().as_str())
+                && input_width(egraph, class) == Some(1)
+        })
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// Find the inputs of a design which are resets, in port order.
+///
+/// An input is a reset if it selects, either directly or through a `Not` or
+/// `LogicNot`, between a constant and something else in a `Mux` feeding the
+/// data input of a register. 1-bit inputs named `rst`, `reset`, `areset`, or
+/// one of those with an `_n` or `n` suffix, are also assumed to be resets.
+///
+/// ```
+/// use churchroad::*;
+/// use egglog::{EGraph, SerializeConfig};
+///
+/// let mut egraph = EGraph::default();
+/// import_churchroad(&mut egraph).unwrap();
+/// egraph
+///     .parse_and_run_program(
+///         r#"
+/// (let clk (Var "clk" 1))
+/// (IsPort "" "clk" (Input) clk)
+/// (let clear (Var "clear" 1))
+/// (IsPort "" "clear" (Input) clear)
+/// (let d (Var "d" 8))
+/// (IsPort "" "d" (Input) d)
+/// (IsPort "" "q" (Output) (Op2 (Reg 0) clk (Op3 (Mux) clear d (Op0 (BV 0 8)))))
+/// "#,
+///     )
+///     .unwrap();
+///
+/// let serialized = egraph.serialize(SerializeConfig::default());
+/// assert_eq!(detect_resets(&serialized), vec!["clear"]);
+/// ```
+pub fn detect_resets(egraph: &egraph_serialize::EGraph) -> Vec<String> {
+    let op_of = |node: &Node| match node.op.as_str() {
+        "Op0" | "Op1" | "Op2" | "Op3" => Some(egraph[&node.children[0]].op.as_str()),
+        _ => None,
+    };
+    let nodes_in = |class: &ClassId| {
+        egraph.classes()[class]
+            .nodes
+            .iter()
+            .map(|id| &egraph[id])
+            .collect::<Vec<_>>()
+    };
+    let is_constant = |class: &ClassId| {
+        nodes_in(class)
+            .iter()
+            .any(|node| matches!(op_of(node), Some("BV" | "SignedBV")))
+    };
+
+    // The eclasses selecting between a constant and something else in muxes
+    // feeding registers, looking through inversions.
+    let mut selects = HashSet::new();
+    for node in egraph.nodes.values() {
+        if !matches!(op_of(node), Some("Reg" | "RegWithClk")) {
+            continue;
+        }
+        let d = &egraph[node.children.last().unwrap()].eclass;
+        for mux in nodes_in(d) {
+            if op_of(mux) != Some("Mux") {
+                continue;
+            }
+            let [select, a, b] = [1, 2, 3].map(|i| &egraph[&mux.children[i]].eclass);
+            if is_constant(a) == is_constant(b) {
+                continue;
+            }
+            selects.insert(select.clone());
+            for inversion in nodes_in(select) {
+                if matches!(op_of(inversion), Some("Not" | "LogicNot")) {
+                    selects.insert(egraph[&inversion.children[1]].eclass.clone());
+                }
+            }
+        }
+    }
+
+    let (inputs, _) = get_inputs_and_outputs_serialized(egraph);
+    inputs
+        .into_iter()
+        .filter(|(name, class)| {
+            let name = name.to_lowercase();
+            let base = name
+                .strip_suffix("_n")
+                .or_else(|| name.strip_suffix('n'))
+                .unwrap_or(&name);
+            selects.contains(class)
+                || (["rst", "reset", "areset"].contains(&base)
+                    && input_width(egraph, class) == Some(1))
+        })
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// The width of an input's `Var`, or `None` if its eclass has no `Var`.
+fn input_width(egraph: &egraph_serialize::EGraph, class: &ClassId) -> Option<u64> {
+    egraph.classes()[class]
+        .nodes
+        .iter()
+        .map(|id| &egraph[id])
+        .find(|node| node.op == "Var")
+        .map(|node| get_var_name_and_bitwidth(egraph, node).1)
+}
+
+/// Finds a spec for a primitive interface node: another node in its eclass
+/// which can be written out without any primitive interfaces, e.g. the
+/// `(Op2 (Mul) a b)` next to a `(PrimitiveInterfaceDSP a b)`.
+///
+/// Returns the spec's node, along with a choice of node for each eclass in
+/// the spec's subterms, or `None` if there's no such spec, e.g. because
+/// mapping rewrites fired before the spec was added.
+pub fn find_spec_for_primitive_interface(
+    egraph: &egraph_serialize::EGraph,
+    interface: &NodeId,
+) -> Option<(IndexMap<ClassId, NodeId>, NodeId)> {
+    // Chooses a node for `class_id` and its descendants, if it has a node
+    // without primitive interfaces or cycles below it.
+    fn choose(
+        egraph: &egraph_serialize::EGraph,
+        class_id: &ClassId,
+        visiting: &mut HashSet<ClassId>,
+        choices: &mut IndexMap<ClassId, NodeId>,
+    ) -> Option<NodeId> {
+        if let Some(node_id) = choices.get(class_id) {
+            return Some(node_id.clone());
+        }
+        if !visiting.insert(class_id.clone()) {
+            return None;
+        }
+        let chosen = egraph[class_id]
+            .nodes
+            .iter()
+            .find(|node_id| {
+                let node = &egraph[*node_id];
+                !node.op.starts_with("PrimitiveInterface")
+                    && node.children.iter().all(|child| {
+                        choose(egraph, &egraph[child].eclass, visiting, choices).is_some()
+                    })
+            })
+            .cloned();
+        visiting.remove(class_id);
+        if let Some(node_id) = &chosen {
+            choices.insert(class_id.clone(), node_id.clone());
+        }
+        chosen
+    }
+
+    let mut choices = IndexMap::new();
+    let spec = choose(
+        egraph,
+        &egraph[interface].eclass,
+        &mut HashSet::new(),
+        &mut choices,
+    )?;
+    Some((choices, spec))
+}
+
+/// Error returned by [`insert_lakeroad_result`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InsertError {
+    /// The original class isn't in the egraph.
+    UnknownClass(ClassId),
+    /// The original class can't be written as a finite term, e.g. because
+    /// every node in it depends on the class itself through a register.
+    CyclicClass(ClassId),
+    /// egglog failed to parse or run the union.
+    Egglog(String),
+}
+
+impl std::fmt::Display for InsertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InsertError::UnknownClass(id) => write!(f, "No class with ID {}.", id),
+            InsertError::CyclicClass(id) => {
+                write!(f, "Class {} can't be written as a finite term.", id)
+            }
+            InsertError::Egglog(err) => write!(f, "egglog failed: {}", err),
+        }
+    }
+}
+
+/// Inserts an implementation found by Lakeroad into the egraph.
+///
+/// `lakeroad_egglog` is the Churchroad expression produced by Lakeroad,
+/// usually a `GetOutput` of a `ModuleInstance` of some primitive.
+/// `original_class` is the class of the spec that Lakeroad implemented, as
+/// found in a serialized version of `egraph`. The two are unioned, so that
+/// extraction can choose the mapped implementation.
+///
+/// egglog has no way to refer to a class by its ID, so we refer to the
+/// original class by writing out a term in it, which is then found through
+/// hashconsing.
+pub fn insert_lakeroad_result(
+    egraph: &mut EGraph,
+    original_class: &ClassId,
+    lakeroad_egglog: &str,
+) -> Result<(), InsertError> {
+    let serialized = egraph.serialize(SerializeConfig::default());
+    let original_term = class_term(&serialized, original_class).map_err(|err| match err {
+        ExtractionError::UnknownClass(id) => InsertError::UnknownClass(id),
+        ExtractionError::Cyclic(_) => InsertError::CyclicClass(original_class.clone()),
+    })?;
+
+    egraph
+        .parse_and_run_program(&format!(
+            "(union {} {})",
+            original_term,
+            lakeroad_egglog.trim()
+        ))
+        .map_err(|err| InsertError::Egglog(err.to_string()))?;
+
+    Ok(())
+}
+
+/// The result of [`verify_lakeroad_result`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerificationResult {
+    /// The result matches the spec on every input.
+    Verified,
+    /// The result differs from the spec on these inputs, given by name.
+    Counterexample(HashMap<String, u64>),
+    /// The solver couldn't decide in time or failed, or the spec or result
+    /// couldn't be written as an SMT formula, e.g. because it contains
+    /// registers or primitive instances.
+    Unknown,
+}
+
+/// Check an implementation found by Lakeroad against the spec it was asked
+/// to implement, as Lakeroad itself may have bugs.
+///
+/// The spec is the expression given by `spec_choices`, whose root is the one
+/// eclass no other chosen node depends on, as returned by
+/// [`find_spec_for_primitive_interface`]. `result_egglog` is a Churchroad
+/// expression over the same `Var`s. The two are written as an SMT formula
+/// asserting that they differ, which is handed to Z3 with the given timeout.
+/// Requires `z3` on the `PATH`, as in the Docker image.
+pub fn verify_lakeroad_result(
+    spec_egraph: &egraph_serialize::EGraph,
+    spec_choices: &IndexMap<ClassId, NodeId>,
+    result_egglog: &str,
+    timeout: std::time::Duration,
+) -> VerificationResult {
+    // Find the root of the spec.
+    let children: HashSet<&ClassId> = spec_choices
+        .values()
+        .flat_map(|node_id| &spec_egraph[node_id].children)
+        .map(|child| &spec_egraph[child].eclass)
+        .collect();
+    let Some(spec_root) = spec_choices.keys().find(|class| !children.contains(class)) else {
+        return VerificationResult::Unknown;
+    };
+
+    // Load the result into its own egraph, marking it as an output so we can
+    // find it again.
+    let mut result_egraph = EGraph::default();
+    import_churchroad(&mut result_egraph).unwrap();
+    if result_egraph
+        .parse_and_run_program(&format!(
+            r#"
+            (let churchroad-result {result_egglog})
+            (IsPort "" "churchroad-result" (Output) churchroad-result)
+            (run-schedule (saturate typing))
+            "#
+        ))
+        .is_err()
+    {
+        return VerificationResult::Unknown;
+    }
+    let result_egraph = result_egraph.serialize(egglog::SerializeConfig::default());
+    let result_choices = AnythingExtractor.extract(&result_egraph, &[]);
+    let result_root = get_inputs_and_outputs_serialized(&result_egraph).1[0]
+        .1
+        .clone();
+
+    if !interop::supported_by_smtlib(spec_egraph, spec_choices, spec_root)
+        || !interop::supported_by_smtlib(&result_egraph, &result_choices, &result_root)
+        || get_bitwidth(spec_egraph, spec_root).is_none()
+        || get_bitwidth(spec_egraph, spec_root) != get_bitwidth(&result_egraph, &result_root)
+    {
+        return VerificationResult::Unknown;
+    }
+
+    let (Ok((mut inputs, spec_definitions)), Ok((result_inputs, result_definitions))) = (
+        interop::smtlib_definitions(spec_egraph, spec_choices, vec![spec_root.clone()], "spec_"),
+        interop::smtlib_definitions(
+            &result_egraph,
+            &result_choices,
+            vec![result_root.clone()],
+            "result_",
+        ),
+    ) else {
+        return VerificationResult::Unknown;
+    };
+    for (name, bw) in result_inputs {
+        if *inputs.entry(name).or_insert(bw) != bw {
+            return VerificationResult::Unknown;
+        }
+    }
+
+    let mut query = String::from("(set-logic QF_BV)\n");
+    for (name, bw) in &inputs {
+        query.push_str(&format!("(declare-const {name} (_ BitVec {bw}))\n"));
+    }
+    query.push_str(&spec_definitions);
+    query.push_str(&result_definitions);
+    query.push_str(&format!(
+        "(assert (distinct spec_wire_{spec_root} result_wire_{result_root}))\n(check-sat)\n"
+    ));
+    if !inputs.is_empty() {
+        query.push_str(&format!(
+            "(get-value ({}))\n",
+            inputs.keys().cloned().collect::<Vec<_>>().join(" ")
+        ));
+    }
+
+    match run_z3(&query, timeout) {
+        SatResult::Unsat => VerificationResult::Verified,
+        SatResult::Sat(model) => VerificationResult::Counterexample(model),
+        SatResult::Unknown | SatResult::Error(_) => VerificationResult::Unknown,
+    }
+}
+
+/// The answer to an SMT query, as given by [`run_z3`].
+enum SatResult {
+    Unsat,
+    /// The values of the query's `get-value` constants.
+    Sat(HashMap<String, u64>),
+    /// Z3 couldn't decide in time.
+    Unknown,
+    /// Z3 couldn't be run, e.g. because it isn't installed, or rejected the
+    /// query.
+    Error(String),
+}
+
+/// Hand an SMT-LIB query ending in `(check-sat)`, and optionally a
+/// `(get-value ...)` of bitvector constants, to Z3 with the given timeout.
+fn run_z3(query: &str, timeout: std::time::Duration) -> SatResult {
+    let output = std::process::Command::new("z3")
+        .arg("-smt2")
+        .arg("-in")
+        .arg(format!("-T:{}", timeout.as_secs().max(1)))
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .and_then(|mut z3| {
+            use std::io::Write;
+            z3.stdin.take().unwrap().write_all(query.as_bytes())?;
+            z3.wait_with_output()
+        });
+    let output = match output {
+        Ok(output) => output,
+        Err(err) => return SatResult::Error(format!("Couldn't run z3: {err}")),
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+    match lines.next().map(str::trim) {
+        Some("unsat") => SatResult::Unsat,
+        Some("sat") => {
+            // The model looks like `((a #x05)\n (b #b1))`.
+            let model: HashMap<String, u64> = lines
+                .collect::<String>()
+                .split(')')
+                .filter_map(|pair| {
+                    let mut pair = pair
+                        .trim_matches(|c: char| c == '(' || c.is_whitespace())
+                        .split_whitespace();
+                    let name = pair.next()?;
+                    let value = pair.next()?;
+                    let value = if let Some(bits) = value.strip_prefix("#b") {
+                        u64::from_str_radix(bits, 2).ok()?
+                    } else {
+                        u64::from_str_radix(value.strip_prefix("#x")?, 16).ok()?
+                    };
+                    Some((name.to_owned(), value))
+                })
+                .collect();
+            SatResult::Sat(model)
+        }
+        Some(line) if line.starts_with("(error") => SatResult::Error(stdout.into_owned()),
+        _ => SatResult::Unknown,
+    }
+}
+
+/// The result of [`VerificationHarness::check_equivalence`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EquivalenceResult {
+    /// The two designs have the same outputs on every input.
+    Equivalent,
+    /// Some output differs on these inputs, given by name.
+    NotEquivalent {
+        counterexample: HashMap<String, u64>,
+    },
+    /// The solver couldn't decide in time.
+    Timeout,
+}
+
+/// Checks that a design is still equivalent to the original after a change,
+/// e.g. after synthesis. Outputs and inputs are matched by name.
+///
+/// ```no_run
+/// use churchroad::*;
+/// use egglog::EGraph;
+///
+/// let design = |src: &str| {
+///     let mut egraph = EGraph::default();
+///     import_churchroad(&mut egraph).unwrap();
+///     egraph.parse_and_run_program(src).unwrap();
+///     egraph
+/// };
+///
+/// let mut harness = VerificationHarness::default();
+/// harness.add_before(design(
+///     r#"(IsPort "" "out" (Output) (Op2 (Add) (Var "a" 8) (Var "b" 8)))
+///        (run-schedule (saturate typing))"#,
+/// ));
+/// harness.add_after(design(
+///     r#"(IsPort "" "out" (Output) (Op2 (Add) (Var "b" 8) (Var "a" 8)))
+///        (run-schedule (saturate typing))"#,
+/// ));
+/// assert_eq!(
+///     harness.check_equivalence(std::time::Duration::from_secs(10)),
+///     Ok(EquivalenceResult::Equivalent)
+/// );
+/// ```
+#[derive(Default)]
+pub struct VerificationHarness {
+    before: Option<egraph_serialize::EGraph>,
+    after: Option<egraph_serialize::EGraph>,
+}
+
+impl VerificationHarness {
+    /// Set the original design.
+    pub fn add_before(&mut self, egraph: EGraph) {
+        self.before = Some(egraph.serialize(SerializeConfig::default()));
+    }
+
+    /// Set the changed design.
+    pub fn add_after(&mut self, egraph: EGraph) {
+        self.after = Some(egraph.serialize(SerializeConfig::default()));
+    }
+
+    /// Ask Z3 whether any output of the two designs can differ, giving up
+    /// after `timeout`. Returns an error if either design is missing, their
+    /// ports don't match, they can't be written as SMT formulas, e.g.
+    /// because they contain registers or module instances, or Z3 can't be run
+    /// or rejects the query.
+    pub fn check_equivalence(
+        &self,
+        timeout: std::time::Duration,
+    ) -> Result<EquivalenceResult, String> {
+        let (Some(before), Some(after)) = (&self.before, &self.after) else {
+            return Err("Both designs must be added first.".to_string());
+        };
+
+        // The inputs, definitions and outputs of a design, with every name
+        // prefixed to keep the two designs apart.
+        let smtlib = |egraph: &egraph_serialize::EGraph, prefix: &str| {
+            let choices = AnythingExtractor.extract(egraph, &[]);
+            let (_, outputs) = get_inputs_and_outputs_serialized(egraph);
+            if let Some((name, _)) = outputs
+                .iter()
+                .find(|(_, class)| !interop::supported_by_smtlib(egraph, &choices, class))
+            {
+                return Err(format!(
+                    "Output {name:?} can't be written as an SMT formula."
+                ));
+            }
+            let (inputs, definitions) = interop::smtlib_definitions(
+                egraph,
+                &choices,
+                outputs.iter().map(|(_, class)| class.clone()).collect(),
+                prefix,
+            )
+            .map_err(|err| err.to_string())?;
+            Ok((inputs, definitions, outputs))
+        };
+        let (mut inputs, before_definitions, before_outputs) = smtlib(before, "before_")?;
+        let (after_inputs, after_definitions, after_outputs) = smtlib(after, "after_")?;
+
+        for (name, bw) in after_inputs {
+            if *inputs.entry(name.clone()).or_insert(bw) != bw {
+                return Err(format!("Input {name:?} has different widths."));
+            }
+        }
+
+        let mut differences = vec![];
+        for (name, before_class) in &before_outputs {
+            let Some((_, after_class)) = after_outputs.iter().find(|(n, _)| n == name) else {
+                return Err(format!("Output {name:?} is missing after the change."));
+            };
+            if get_bitwidth(before, before_class) != get_bitwidth(after, after_class) {
+                return Err(format!("Output {name:?} has different widths."));
+            }
+            differences.push(format!(
+                "(distinct before_wire_{before_class} after_wire_{after_class})"
+            ));
+        }
+        if let Some((name, _)) = after_outputs
+            .iter()
+            .find(|(name, _)| before_outputs.iter().all(|(n, _)| n != name))
+        {
+            return Err(format!("Output {name:?} is new after the change."));
+        }
+        if differences.is_empty() {
+            return Err("The designs have no outputs.".to_string());
+        }
+
+        let mut query = String::from("(set-logic QF_BV)\n");
+        for (name, bw) in &inputs {
+            query.push_str(&format!("(declare-const {name} (_ BitVec {bw}))\n"));
+        }
+        query.push_str(&before_definitions);
+        query.push_str(&after_definitions);
+        query.push_str(&format!(
+            "(assert (or false {}))\n(check-sat)\n",
+            differences.join(" ")
+        ));
+        if !inputs.is_empty() {
+            query.push_str(&format!(
+                "(get-value ({}))\n",
+                inputs.keys().cloned().collect::<Vec<_>>().join(" ")
+            ));
+        }
+
+        match run_z3(&query, timeout) {
+            SatResult::Unsat => Ok(EquivalenceResult::Equivalent),
+            SatResult::Sat(counterexample) => {
+                Ok(EquivalenceResult::NotEquivalent { counterexample })
+            }
+            SatResult::Unknown => Ok(EquivalenceResult::Timeout),
+            SatResult::Error(err) => Err(err),
+        }
+    }
+}
+
+/// A library of known module implementations, by module name, e.g. of the
+/// primitives of an FPGA architecture, for [`flatten_hierarchy`].
+///
+/// Each implementation is a Churchroad program whose `IsPort` inputs and
+/// outputs are the module's ports. Parameters are referred to as `Var`s named
+/// after the parameter.
+#[derive(Debug, Clone, Default)]
+pub struct HardwareDB {
+    implementations: HashMap<String, String>,
+}
+
+impl HardwareDB {
+    /// Add an implementation of `module_name`, replacing any earlier one.
+    pub fn register(&mut self, module_name: &str, implementation: &str) {
+        self.implementations
+            .insert(module_name.to_owned(), implementation.to_owned());
+    }
+
+    /// The implementation of `module_name`, if there is one.
+    pub fn lookup(&self, module_name: &str) -> Option<&str> {
+        self.implementations.get(module_name).map(String::as_str)
+    }
+
+    /// Load each `.egg` file in `dir` as the implementation of the module
+    /// named by the file's stem, e.g. `LUT2.egg` for `LUT2`.
+    pub fn from_directory(dir: &std::path::Path) -> std::io::Result<Self> {
+        let mut db = HardwareDB::default();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "egg") {
+                if let Some(module_name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    db.register(module_name, &std::fs::read_to_string(&path)?);
+                }
+            }
+        }
+        Ok(db)
+    }
+
+    /// Implementations of the LUTs (`LUT1` to `LUT6`), wide muxes (`MUXF7`
+    /// and `MUXF8`) and inverter (`INV`) of Xilinx UltraScale+ FPGAs.
+    pub fn xilinx_ultrascale_plus() -> Self {
+        let mut db = HardwareDB::default();
+        let input = |name: &str| format!("(IsPort \"\" \"{name}\" (Input) (Var \"{name}\" 1))\n");
+
+        for k in 1..=6 {
+            let inputs = (0..k).map(|i| input(&format!("I{i}"))).collect::<String>();
+            // The inputs select a bit of INIT, with I0 as the low bit.
+            let select = (1..k).fold("(Var \"I0\" 1)".to_owned(), |low, i| {
+                format!("(Op2 (Concat) (Var \"I{i}\" 1) {low})")
+            });
+            db.register(
+                &format!("LUT{k}"),
+                &format!(
+                    "{inputs}(IsPort \"\" \"O\" (Output) (Op1 (Extract 0 0) (Op2 (Shr) (Var \"INIT\" {}) {select})))\n",
+                    1 << k
+                ),
+            );
+        }
+        for mux in ["MUXF7", "MUXF8"] {
+            db.register(
+                mux,
+                &format!(
+                    "{}{}{}(IsPort \"\" \"O\" (Output) (Op3 (Mux) (Var \"S\" 1) (Var \"I0\" 1) (Var \"I1\" 1)))\n",
+                    input("I0"),
+                    input("I1"),
+                    input("S")
+                ),
+            );
+        }
+        db.register(
+            "INV",
+            &format!(
+                "{}(IsPort \"\" \"O\" (Output) (Op1 (Not) (Var \"I\" 1)))\n",
+                input("I")
+            ),
+        );
+        db
+    }
+}
+
+/// Flattens a hierarchical design by inlining module instances.
+///
+/// Each `GetOutput` of an instance of a module with an implementation in `db`
+/// is unioned with the implementation of that output, with the instance's
+/// inputs and parameters substituted in. Modules instantiated by an
+/// implementation are inlined in turn, so implementations mustn't be
+/// recursive. Inlined outputs are marked with `Inlined` facts, so running this
+/// again only inlines outputs added since.
+///
+/// Returns the number of module instances inlined. Panics if an
+/// implementation fails to parse or lacks an output that the design uses.
+pub fn flatten_hierarchy(egraph: &mut EGraph, db: &HardwareDB) -> usize {
+    // Each run of this function gets its own ruleset, as egglog won't let us
+    // add the same rule to a ruleset twice.
+    static NEXT_RULESET_ID: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    let ruleset = format!(
+        "flatten-{}",
+        NEXT_RULESET_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+    );
+    egraph
+        .parse_and_run_program(&format!("(ruleset {ruleset})"))
+        .unwrap();
+
+    fn unquote(s: &str) -> &str {
+        s.strip_prefix('"').unwrap().strip_suffix('"').unwrap()
+    }
+
+    fn string_cons_list_to_vec(egraph: &egraph_serialize::EGraph, node_id: &NodeId) -> Vec<String> {
+        let node = &egraph[&egraph[&egraph[node_id].eclass].nodes[0]];
+        match node.op.as_str() {
+            "StringCons" => std::iter::once(unquote(&egraph[&node.children[0]].op).to_owned())
+                .chain(string_cons_list_to_vec(egraph, &node.children[1]))
+                .collect(),
+            "StringNil" => vec![],
+            _ => unreachable!(),
+        }
+    }
+
+    // Replaces each `(Var "name" bw)` in `term` with `replacement`.
+    fn substitute_var(term: &str, name: &str, replacement: &str) -> String {
+        let prefix = format!("(Var \"{name}\" ");
+        let mut out = String::new();
+        let mut rest = term;
+        while let Some(start) = rest.find(&prefix) {
+            out.push_str(&rest[..start]);
+            out.push_str(replacement);
+            let after = &rest[start + prefix.len()..];
+            rest = &after[after.find(')').unwrap() + 1..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    // The outputs of each module, as terms over the module's inputs.
+    let mut module_outputs: HashMap<String, HashMap<String, String>> = HashMap::new();
+    let mut rules = HashSet::new();
+    let mut num_inlined = 0;
+
+    loop {
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let mut new_rules = vec![];
+        let mut new_instances = HashSet::new();
+
+        // The instance and output name of each output inlined so far.
+        let inlined: HashSet<(&ClassId, &str)> = serialized
+            .nodes
+            .values()
+            .filter(|node| node.op == "Inlined")
+            .map(|node| {
+                (
+                    &serialized[&node.children[0]].eclass,
+                    unquote(&serialized[&node.children[1]].op),
+                )
+            })
+            .collect();
+
+        for get_output in serialized
+            .nodes
+            .values()
+            .filter(|node| node.op == "GetOutput")
+        {
+            let instance_class = &serialized[&get_output.children[0]].eclass;
+            for instance in serialized[instance_class]
+                .nodes
+                .iter()
+                .map(|node_id| &serialized[node_id])
+                .filter(|node| node.op == "ModuleInstance")
+            {
+                let module_name = unquote(&serialized[&instance.children[0]].op);
+                let output_name = unquote(&serialized[&get_output.children[1]].op);
+                let Some(implementation) = db.lookup(module_name) else {
+                    continue;
+                };
+                if inlined.contains(&(instance_class, output_name)) {
+                    continue;
+                }
+                new_instances.insert(instance_class.clone());
+
+                let outputs = module_outputs
+                    .entry(module_name.to_owned())
+                    .or_insert_with(|| {
+                        let mut module_egraph = EGraph::default();
+                        import_churchroad(&mut module_egraph).unwrap();
+                        module_egraph.parse_and_run_program(implementation).unwrap();
+                        let (_, outputs) = get_inputs_and_outputs(&mut module_egraph).unwrap();
+                        outputs
+                            .into_iter()
+                            .map(|(name, sort, value)| {
+                                let mut termdag = TermDag::default();
+                                let (_, term) = module_egraph.extract(value, &mut termdag, &sort);
+                                (name, termdag.to_string(&term))
+                            })
+                            .collect()
+                    });
+
+                let mut implementation = outputs
+                    .get(output_name)
+                    .unwrap_or_else(|| {
+                        panic!("Module {} has no output {}.", module_name, output_name)
+                    })
+                    .clone();
+
+                // Match the instance's parameter and input expressions with
+                // pattern variables, and substitute those for the
+                // implementation's Vars.
+                let mut pattern_lists = vec![];
+                for (names_child, kind) in [(1, "param"), (3, "input")] {
+                    let names =
+                        string_cons_list_to_vec(&serialized, &instance.children[names_child]);
+                    let mut names_list = "(StringNil)".to_string();
+                    let mut exprs_list = "(ExprNil)".to_string();
+                    for (i, name) in names.iter().enumerate().rev() {
+                        let var = format!("flatten-{kind}-{i}");
+                        names_list = format!("(StringCons \"{name}\" {names_list})");
+                        exprs_list = format!("(ExprCons {var} {exprs_list})");
+                        implementation = substitute_var(&implementation, name, &var);
+                    }
+                    pattern_lists.push(format!("{names_list} {exprs_list}"));
+                }
+
+                let rule = format!(
+                    "(rule ((= instance (ModuleInstance \"{module_name}\" {params} {inputs})) (= out (GetOutput instance \"{output_name}\"))) ((union out {implementation}) (Inlined instance \"{output_name}\")) :ruleset {ruleset})",
+                    params = pattern_lists[0],
+                    inputs = pattern_lists[1],
+                );
+                if rules.insert(rule.clone()) {
+                    new_rules.push(rule);
+                }
+            }
+        }
+
+        if new_instances.is_empty() {
+            break;
+        }
+
+        egraph.parse_and_run_program(&new_rules.join("\n")).unwrap();
+        egraph
+            .parse_and_run_program(&format!("(run-schedule (saturate {ruleset}))"))
+            .unwrap();
+        num_inlined += new_instances.len();
+    }
+
+    num_inlined
+}
+
+/// The outcome of trying to synthesize one primitive interface with Lakeroad.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SynthesisOutcome {
+    /// Lakeroad found an implementation.
+    Synthesized,
+    /// Lakeroad proved that no implementation exists.
+    Unsat,
+    /// Lakeroad ran out of time.
+    Timeout,
+    /// Lakeroad (or inserting its result) failed.
+    Error(String),
+}
+
+/// A summary of the synthesis loop: how many primitive interfaces were found,
+/// and what happened to them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MappingReport {
+    pub found: usize,
+    pub synthesized: usize,
+    pub unsat: usize,
+    pub timeout: usize,
+    pub errors: Vec<String>,
+}
+
+impl MappingReport {
+    /// Record the outcome of synthesizing one primitive interface.
+    pub fn record(&mut self, outcome: SynthesisOutcome) {
+        self.found += 1;
+        match outcome {
+            SynthesisOutcome::Synthesized => self.synthesized += 1,
+            SynthesisOutcome::Unsat => self.unsat += 1,
+            SynthesisOutcome::Timeout => self.timeout += 1,
+            SynthesisOutcome::Error(err) => self.errors.push(err),
+        }
+    }
+
+    /// Write the report as a JSON object with one field per counter.
+    pub fn write_json<W: std::io::Write>(&self, sink: W) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(
+            sink,
+            &serde_json::json!({
+                "found": self.found,
+                "synthesized": self.synthesized,
+                "unsat": self.unsat,
+                "timeout": self.timeout,
+                "errors": self.errors,
+            }),
+        )
+    }
+}
+
+/// The result of [`synthesis_exploration`]: the best strategy found, its
+/// report, and the egraph it produced.
+pub struct ExplorationResult {
+    pub strategy: String,
+    pub report: MappingReport,
+    pub egraph: EGraph,
+}
+
+/// Try several rewrite strategies on a design and return the one which
+/// exposes the most primitive interfaces.
+///
+/// Each strategy is an egglog program, e.g. `"(run-schedule (saturate
+/// typing mapping))"`, which is run on a copy of `egraph` after importing
+/// the mapping rewrites for `architecture` (so `egraph` shouldn't already
+/// contain them). Strategies which fail are recorded as errors in their
+/// report and are only chosen if every strategy fails. Ties go to the
+/// earliest strategy.
+pub fn synthesis_exploration(
+    egraph: &EGraph,
+    strategies: &[&str],
+    architecture: &Architecture,
+) -> ExplorationResult {
+    assert!(!strategies.is_empty(), "no strategies to explore");
+
+    strategies
+        .iter()
+        .map(|strategy| {
+            let mut egraph = egraph.clone();
+            import_mapping_rewrites(&mut egraph, architecture);
+
+            let mut report = MappingReport::default();
+            match egraph.parse_and_run_program(strategy) {
+                Ok(_) => {
+                    let serialized = egraph.serialize(egglog::SerializeConfig::default());
+                    report.found = serialized
+                        .nodes
+                        .values()
+                        .filter(|node| node.op.starts_with("PrimitiveInterface"))
+                        .count();
+                }
+                Err(err) => report.errors.push(err.to_string()),
+            }
+
+            ExplorationResult {
+                strategy: strategy.to_string(),
+                report,
+                egraph,
+            }
+        })
+        .reduce(|best, result| {
+            let key = |result: &ExplorationResult| {
+                (
+                    result.report.errors.is_empty(),
+                    result.report.synthesized,
+                    result.report.found,
+                )
+            };
+            if key(&result) > key(&best) {
+                result
+            } else {
+                best
+            }
+        })
+        .unwrap()
+}
+
+/// An FPGA architecture which Churchroad can target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Architecture {
+    XilinxUltrascalePlus,
+    LatticeEcp5,
+    IntelCyclone10Lp,
+}
+
+/// Machine-readable information about the primitives an [`Architecture`]
+/// provides, as returned by [`Architecture::description`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchitectureDescription {
+    pub name: String,
+    /// Number of inputs to the architecture's LUTs, if it has LUTs.
+    pub lut_inputs: Option<u32>,
+    /// Width of the operands of the architecture's hard multipliers, if it has
+    /// them.
+    pub dsp_multiplier_width: Option<u32>,
+    pub has_block_ram: bool,
+    pub has_distributed_ram: bool,
+    pub primitive_names: Vec<String>,
+}
+
+impl Architecture {
+    pub fn description(&self) -> ArchitectureDescription {
+        let (lut_inputs, dsp_multiplier_width, has_block_ram, has_distributed_ram, primitives) =
+            match self {
+                Architecture::XilinxUltrascalePlus => (
+                    Some(6),
+                    Some(18),
+                    true,
+                    true,
+                    vec!["LUT6", "CARRY8", "DSP48E2", "RAMB36E2"],
+                ),
+                Architecture::LatticeEcp5 => (
+                    Some(4),
+                    Some(18),
+                    true,
+                    true,
+                    vec!["LUT4", "CCU2C", "MULT18X18D", "ALU54B", "DP16KD"],
+                ),
+                Architecture::IntelCyclone10Lp => (
+                    Some(4),
+                    Some(18),
+                    true,
+                    false,
+                    vec![
+                        "cyclone10lp_lcell_comb",
+                        "cyclone10lp_mac_mult",
+                        "altsyncram",
+                    ],
+                ),
+            };
+
+        ArchitectureDescription {
+            name: self.to_string(),
+            lut_inputs,
+            dsp_multiplier_width,
+            has_block_ram,
+            has_distributed_ram,
+            primitive_names: primitives.into_iter().map(String::from).collect(),
+        }
+    }
+}
+
+/// Displays the architecture's name as understood by Lakeroad's
+/// `--architecture` flag.
+impl std::fmt::Display for Architecture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Architecture::XilinxUltrascalePlus => "xilinx-ultrascale-plus",
+            Architecture::LatticeEcp5 => "lattice-ecp5",
+            Architecture::IntelCyclone10Lp => "intel-cyclone10lp",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Parses the names produced by [`Architecture`]'s `Display` impl.
+impl std::str::FromStr for Architecture {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "xilinx-ultrascale-plus" => Ok(Architecture::XilinxUltrascalePlus),
+            "lattice-ecp5" => Ok(Architecture::LatticeEcp5),
+            "intel-cyclone10lp" => Ok(Architecture::IntelCyclone10Lp),
+            _ => Err(format!("Unknown architecture {}.", s)),
+        }
+    }
+}
+
+/// Error returned by [`LakeroadConfig::from_env`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// A required environment variable isn't set.
+    Missing(&'static str),
+    /// An environment variable is set, but to something we can't use.
+    Invalid { var: &'static str, reason: String },
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Missing(var) => write!(f, "{} is not set.", var),
+            ConfigError::Invalid { var, reason } => write!(f, "{} is invalid: {}", var, reason),
+        }
+    }
+}
+
+/// Everything needed to invoke Lakeroad.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LakeroadConfig {
+    /// The Lakeroad executable. In tests, this can be a script which mocks
+    /// Lakeroad.
+    pub binary_path: std::path::PathBuf,
+    pub architecture: Architecture,
+    /// Passed to Lakeroad's `--timeout`, in whole seconds.
+    pub timeout: std::time::Duration,
+    /// Appended to every Lakeroad invocation.
+    pub extra_args: Vec<String>,
+}
+
+impl LakeroadConfig {
+    /// Reads the configuration from the environment:
+    ///
+    /// - `LAKEROAD_PATH` (required): the Lakeroad executable.
+    /// - `CHURCHROAD_ARCHITECTURE` (required): e.g. `xilinx-ultrascale-plus`.
+    /// - `LAKEROAD_TIMEOUT` (optional): in seconds, defaulting to 120.
+    /// - `LAKEROAD_EXTRA_ARGS` (optional): whitespace-separated arguments.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_vars(|var| std::env::var(var).ok())
+    }
+
+    /// Like [`LakeroadConfig::from_env`], but looks variables up with `get`.
+    pub fn from_vars(get: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        const PATH: &str = "LAKEROAD_PATH";
+        const ARCHITECTURE: &str = "CHURCHROAD_ARCHITECTURE";
+        const TIMEOUT: &str = "LAKEROAD_TIMEOUT";
+        const EXTRA_ARGS: &str = "LAKEROAD_EXTRA_ARGS";
+
+        let binary_path = get(PATH).ok_or(ConfigError::Missing(PATH))?.into();
+        let architecture = get(ARCHITECTURE)
+            .ok_or(ConfigError::Missing(ARCHITECTURE))?
+            .parse::<Architecture>()
+            .map_err(|reason| ConfigError::Invalid {
+                var: ARCHITECTURE,
+                reason,
+            })?;
+        let timeout = match get(TIMEOUT) {
+            Some(secs) => std::time::Duration::from_secs(secs.trim().parse().map_err(
+                |err: std::num::ParseIntError| ConfigError::Invalid {
+                    var: TIMEOUT,
+                    reason: err.to_string(),
+                },
+            )?),
+            None => std::time::Duration::from_secs(120),
+        };
+        let extra_args = get(EXTRA_ARGS)
+            .map(|args| args.split_whitespace().map(str::to_owned).collect())
+            .unwrap_or_default();
+
+        Ok(LakeroadConfig {
+            binary_path,
+            architecture,
+            timeout,
+            extra_args,
+        })
+    }
+
+    /// A command invoking Lakeroad with the architecture, timeout and extra
+    /// arguments from this config. Callers add the per-primitive arguments.
+    pub fn command(&self) -> std::process::Command {
+        let mut command = std::process::Command::new(&self.binary_path);
+        command
+            .arg("--architecture")
+            .arg(self.architecture.to_string())
+            .arg("--timeout")
+            .arg(self.timeout.as_secs().to_string())
+            .args(&self.extra_args);
+        command
+    }
+}
+
+/// What a primitive can implement. This determines the mapping rewrites
+/// generated for the primitive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrimitiveFunction {
+    /// The given Churchroad expression over the primitive's input ports, e.g.
+    /// `(Op2 (Mul) a b)`. Matching expressions are unioned with
+    /// `(PrimitiveInterface<name> <input ports>...)`.
+    Expr(String),
+    /// Any combinational function with a 1-bit output and at most as many
+    /// input bits as the primitive has input ports. Matching expressions are
+    /// marked with `(PrimitiveInterface<name> expr)`.
+    Lut,
+    /// Addition of up to `width` bits with a carry in, as
+    /// `(PrimitiveInterface<name> ci a b)`, along with its carry out,
+    /// `(PrimitiveInterface<name>_CO ci a b)`. Wider additions are split into
+    /// `width`-bit slices, chained through their carries.
+    CarryChain { width: u32 },
+}
+
+/// A primitive which Churchroad can map expressions to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrimitiveSpec {
+    /// The name of the primitive interface, e.g. `DSP` for
+    /// `PrimitiveInterfaceDSP`.
+    pub name: String,
+    /// The name of the primitive as understood by Lakeroad.
+    pub lakeroad_name: String,
+    pub input_ports: Vec<(String, u32)>,
+    pub output_ports: Vec<(String, u32)>,
+    /// An egglog condition under which expressions map to this primitive,
+    /// where `n` is bound to the bitwidth of the expression, e.g. `(< n 18)`.
+    pub trigger_condition: Option<String>,
+    pub function: PrimitiveFunction,
+}
+
+/// The primitives available on an [`Architecture`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrimitiveLibrary {
+    primitives: Vec<PrimitiveSpec>,
+}
+
+impl PrimitiveLibrary {
+    pub fn for_architecture(architecture: &Architecture) -> Self {
+        let description = architecture.description();
+        let mut primitives = vec![];
+
+        if let Some(width) = description.dsp_multiplier_width {
+            let lakeroad_name = match architecture {
+                Architecture::XilinxUltrascalePlus => "DSP48E2",
+                Architecture::LatticeEcp5 => "MULT18X18D",
+                Architecture::IntelCyclone10Lp => "cyclone10lp_mac_mult",
+            };
+            // Hard multipliers are signed, so an unsigned multiply fits only
+            // if it's narrower than the multiplier.
+            primitives.push(PrimitiveSpec {
+                name: "DSP".to_string(),
+                lakeroad_name: lakeroad_name.to_string(),
+                input_ports: vec![("a".to_string(), width - 1), ("b".to_string(), width - 1)],
+                output_ports: vec![("out".to_string(), width - 1)],
+                trigger_condition: Some(format!("(< n {width})")),
+                function: PrimitiveFunction::Expr("(Op2 (Mul) a b)".to_string()),
+            });
+
+            // DSP48E2s can also absorb an adder after the multiplier, and a
+            // register on its output.
+            if *architecture == Architecture::XilinxUltrascalePlus {
+                primitives.push(PrimitiveSpec {
+                    name: "DSP_MulAdd".to_string(),
+                    lakeroad_name: lakeroad_name.to_string(),
+                    input_ports: vec![
+                        ("a".to_string(), width - 1),
+                        ("b".to_string(), width - 1),
+                        ("c".to_string(), width - 1),
+                    ],
+                    output_ports: vec![("out".to_string(), width - 1)],
+                    trigger_condition: Some(format!("(< n {width})")),
+                    function: PrimitiveFunction::Expr("(Op2 (Add) (Op2 (Mul) a b) c)".to_string()),
+                });
+                primitives.push(PrimitiveSpec {
+                    name: "DSP_MulReg".to_string(),
+                    lakeroad_name: lakeroad_name.to_string(),
+                    input_ports: vec![
+                        ("clk".to_string(), 1),
+                        ("a".to_string(), width - 1),
+                        ("b".to_string(), width - 1),
+                    ],
+                    output_ports: vec![("out".to_string(), width - 1)],
+                    trigger_condition: Some(format!("(< n {width})")),
+                    function: PrimitiveFunction::Expr(
+                        "(Op2 (Reg 0) clk (Op2 (Mul) a b))".to_string(),
+                    ),
+                });
+            }
+        }
+
+        if let Some(lut_inputs) = description.lut_inputs {
+            let lakeroad_name = match architecture {
+                Architecture::XilinxUltrascalePlus => "LUT6",
+                Architecture::LatticeEcp5 => "LUT4",
+                Architecture::IntelCyclone10Lp => "cyclone10lp_lcell_comb",
+            };
+            primitives.push(PrimitiveSpec {
+                name: "LUT".to_string(),
+                lakeroad_name: lakeroad_name.to_string(),
+                input_ports: (0..lut_inputs).map(|i| (format!("I{i}"), 1)).collect(),
+                output_ports: vec![("O".to_string(), 1)],
+                trigger_condition: None,
+                function: PrimitiveFunction::Lut,
+            });
+        }
+
+        if *architecture == Architecture::XilinxUltrascalePlus {
+            primitives.push(PrimitiveSpec {
+                name: "CARRY8".to_string(),
+                lakeroad_name: "CARRY8".to_string(),
+                input_ports: vec![
+                    ("CI".to_string(), 1),
+                    ("DI".to_string(), 8),
+                    ("S".to_string(), 8),
+                ],
+                output_ports: vec![("O".to_string(), 8), ("CO".to_string(), 8)],
+                trigger_condition: None,
+                function: PrimitiveFunction::CarryChain { width: 8 },
+            });
+        }
+
+        Self { primitives }
+    }
+
+    pub fn primitives(&self) -> &[PrimitiveSpec] {
+        &self.primitives
+    }
+
+    /// Generate the `mapping` ruleset, which proposes mappings of Churchroad
+    /// expressions to the primitives in this library.
+    pub fn generate_mapping_ruleset(&self) -> String {
+        let mut rewrites = vec![];
+
+        for primitive in &self.primitives {
+            let name = &primitive.name;
+            let trigger_condition = primitive
+                .trigger_condition
+                .as_ref()
+                .map(|condition| format!("\n  {condition}"))
+                .unwrap_or_default();
+
+            match &primitive.function {
+                PrimitiveFunction::Expr(pattern) => {
+                    let args = primitive
+                        .input_ports
+                        .iter()
+                        .map(|(port, _)| port.as_str())
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    rewrites.push(format!(
+                        "(rule
+ ((= expr {pattern})
+  (HasType expr (Bitvector n)){trigger_condition})
+ ((union expr (PrimitiveInterface{name} {args})))
+ :ruleset mapping)"
+                    ));
+                }
+                PrimitiveFunction::Lut => {
+                    let lut_inputs = primitive.input_ports.len();
+                    for (op, arity) in [
+                        ("Not", 1),
+                        ("ReduceOr", 1),
+                        ("ReduceAnd", 1),
+                        ("ReduceXor", 1),
+                        ("LogicNot", 1),
+                        ("And", 2),
+                        ("Or", 2),
+                        ("Xor", 2),
+                        ("Eq", 2),
+                        ("Ne", 2),
+                        ("LogicAnd", 2),
+                        ("LogicOr", 2),
+                        ("Mux", 3),
+                    ] {
+                        let args = (0..arity).map(|i| format!("e{i}")).collect::<Vec<_>>();
+                        let has_types = args
+                            .iter()
+                            .map(|arg| format!("\n  (HasType {arg} (Bitvector {arg}-bw))"))
+                            .collect::<String>();
+                        let total_bw = args
+                            .iter()
+                            .skip(1)
+                            .fold(format!("{}-bw", args[0]), |acc, arg| {
+                                format!("(+ {acc} {arg}-bw)")
+                            });
+                        rewrites.push(format!(
+                            "(rule
+ ((= expr (Op{arity} ({op}) {args}))
+  (HasType expr (Bitvector 1)){has_types}{trigger_condition}
+  (<= {total_bw} {lut_inputs}))
+ ((PrimitiveInterface{name} expr))
+ :ruleset mapping)",
+                            args = args.join(" ")
+                        ));
+                    }
+                }
+                PrimitiveFunction::CarryChain { width } => {
+                    let high = width - 1;
+                    rewrites.push(format!(
+                        "(rule
+ ((= expr (Op2 (Add) a b))
+  (HasType expr (Bitvector n))
+  (<= n {width}))
+ ((union expr (PrimitiveInterface{name} (Op0 (BV 0 1)) a b)))
+ :ruleset mapping)"
+                    ));
+                    rewrites.push(format!(
+                        "(rule
+ ((= expr (Op2 (Add) (Op2 (Add) a b) (Op1 (ZeroExtend n) ci)))
+  (HasType expr (Bitvector n))
+  (HasType ci (Bitvector 1))
+  (<= n {width}))
+ ((union expr (PrimitiveInterface{name} ci a b)))
+ :ruleset mapping)"
+                    ));
+                    // The high slice adds in the carry out of the low slice,
+                    // which the rule above maps to a chained carry in.
+                    rewrites.push(format!(
+                        "(rule
+ ((= expr (Op2 (Add) a b))
+  (HasType expr (Bitvector n))
+  (> n {width}))
+ ((union expr
+   (Op2 (Concat)
+    (Op2 (Add)
+     (Op2 (Add) (Op1 (Extract (- n 1) {width}) a) (Op1 (Extract (- n 1) {width}) b))
+     (Op1 (ZeroExtend (- n {width}))
+      (PrimitiveInterface{name}_CO (Op0 (BV 0 1)) (Op1 (Extract {high} 0) a) (Op1 (Extract {high} 0) b))))
+    (Op2 (Add) (Op1 (Extract {high} 0) a) (Op1 (Extract {high} 0) b)))))
+ :ruleset mapping)"
+                    ));
+                }
+            }
+        }
+
+        format!("(ruleset mapping)\n{}\n", rewrites.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::Path;
+
+    use egglog::{EGraph, SerializeConfig};
+
+    /// Doing some exploration of where cyclic extraction breaks in egglog with
+    /// Andrew and Vishal.
+    #[test]
+    fn generate_loop() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let placeholder (Wire "placeholder" 8))
+                (let reg (Op1 (Reg 0) placeholder))
+                (union placeholder reg)
+            "#,
+            )
+            .unwrap();
+        auto_delete_wires(&mut egraph);
+
+        // Uncomment to write out the SVG.
+        // let serialized = egraph.serialize_for_graphviz(true);
+        // let svg_path = Path::new("tmp").with_extension("svg");
+        // serialized.to_svg_file(svg_path).unwrap();
+
+        // Extract reg from Egraph.
+        let mut _termdag = TermDag::default();
+        let (_sort, _value) = egraph
+            .eval_expr(&egglog::ast::Expr::Var((), "reg".into()))
+            .unwrap();
+        // This will panic, which is what we were trying to get to.
+        // It panics with `No cost for Value { tag: "Expr", bits: 6 }`
+        // which is basically egglog saying that it can't get a cost because
+        // of the cycle. I expected it to loop infinitely, but it's smarter than
+        // that.
+        // let (_, extracted) = egraph.extract(_value, &mut _termdag, &_sort);
+
+        // Next: can we serialize the egraph? That's the first step to building
+        // a new extraction algorithm.
+
+        // The loop passes through a register, so it isn't a combinational
+        // cycle.
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let choices = AnythingExtractor.extract(&serialized, &[]);
+        assert_eq!(check_acyclicity(&serialized, &choices), Ok(()));
+    }
+
+    #[test]
+    fn check_acyclicity_finds_combinational_cycle() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let placeholder (Wire "placeholder" 8))
+                (let b (Var "b" 8))
+                (let a (Op2 (And) placeholder b))
+                (union placeholder a)
+            "#,
+            )
+            .unwrap();
+        auto_delete_wires(&mut egraph);
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let choices = AnythingExtractor.extract(&serialized, &[]);
+        let and_class = serialized
+            .nodes
+            .values()
+            .find(|node| node.op == "Op2" && serialized[&node.children[0]].op == "And")
+            .unwrap()
+            .eclass
+            .clone();
+        assert_eq!(
+            check_acyclicity(&serialized, &choices),
+            Err(vec![vec![and_class]])
+        );
+    }
+
+    #[test]
+    fn check_acyclicity_counter() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let placeholder (Wire "placeholder" 4))
+                (let clk (Var "clk" 1))
+                (let count (Op2 (Reg 0) clk (Op2 (Add) placeholder (Op0 (BV 1 4)))))
+                (union placeholder count)
+                (IsPort "" "count" (Output) count)
+            "#,
+            )
+            .unwrap();
+        auto_delete_wires(&mut egraph);
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let choices = AnythingExtractor.extract(&serialized, &[]);
+        assert_eq!(check_acyclicity(&serialized, &choices), Ok(()));
+    }
+
+    #[test]
+    fn churchroad_egraph_from_verilog_string() {
+        let config = ImportConfig {
+            yosys_plugin: Path::new(&std::env::var("CHURCHROAD_DIR").unwrap())
+                .join("yosys-plugin")
+                .join("churchroad.so"),
+            ..Default::default()
+        };
+        let mut egraph = ChurchroadEGraph::from_verilog_string(
+            "module and_gate(input a, input b, output out); assign out = a & b; endmodule",
+            "and_gate",
+            &config,
+        )
+        .unwrap()
+        .egraph;
+
+        egraph
+            .parse_and_run_program(
+                r#"
+                (check (IsPort "and_gate" "a" (Input) a))
+                (check (IsPort "and_gate" "b" (Input) b))
+                (check (IsPort "and_gate" "out" (Output) (Op2 (And) a b)))
+                (check (HasType (Op2 (And) a b) (Bitvector 1)))
+                ; Module enumeration is off by default.
+                (fail (check (MakeModule graph indices)))
+            "#,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn from_verilog_string_ports_and_interpret() {
+        let yosys_plugin = Path::new(&std::env::var("CHURCHROAD_DIR").unwrap())
+            .join("yosys-plugin")
+            .join("churchroad.so");
+        let mut egraph = from_verilog_string(
+            "module mux2(input s, input [1:0] a, input [1:0] b, output [1:0] out);
+               assign out = s ? b : a;
+             endmodule",
+            "mux2",
+            &yosys_plugin,
+            false,
+            &HashMap::new(),
+        )
+        .unwrap();
+        egraph
+            .parse_and_run_program("(run-schedule (saturate typing))")
+            .unwrap();
+        auto_delete_wires(&mut egraph);
+
+        let ports = get_ports(&mut egraph, None).unwrap();
+        assert_eq!(
+            ports
+                .iter()
+                .map(|port| (port.name.as_str(), port.direction, port.width))
+                .collect::<Vec<_>>(),
+            vec![
+                ("s", Direction::Input, Some(1)),
+                ("a", Direction::Input, Some(2)),
+                ("b", Direction::Input, Some(2)),
+                ("out", Direction::Output, Some(2)),
+            ]
+        );
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let (_, outputs) = get_inputs_and_outputs_serialized(&serialized);
+        for (s, expected) in [(0, 1), (1, 2)] {
+            let env = [("s", vec![s]), ("a", vec![1]), ("b", vec![2])].into();
+            assert_eq!(
+                interpret(&serialized, &outputs[0].1, 0, &env),
+                Ok(InterpreterResult::Bitvector(expected, 2))
+            );
+        }
+    }
+
+    #[test]
+    fn from_verilog_string_keep_hierarchy() {
+        let yosys_plugin = Path::new(&std::env::var("CHURCHROAD_DIR").unwrap())
+            .join("yosys-plugin")
+            .join("churchroad.so");
+        let import = |verilog: &str, keep_hierarchy: bool| {
+            let mut egraph = from_verilog_string(
+                verilog,
+                "top",
+                &yosys_plugin,
+                keep_hierarchy,
+                &HashMap::new(),
+            )
+            .unwrap();
+            egraph
+                .parse_and_run_program("(run-schedule (saturate typing))")
+                .unwrap();
+            auto_delete_wires(&mut egraph);
+            egraph
+        };
+        let port_names = |egraph: &mut EGraph, module: &str| {
+            get_ports(egraph, Some(module))
+                .unwrap()
+                .into_iter()
+                .map(|port| port.name)
+                .collect::<Vec<_>>()
+        };
+        let interpret_out = |egraph: &mut EGraph| {
+            let serialized = egraph.serialize(SerializeConfig::default());
+            let (_, outputs) = get_inputs_and_outputs_serialized(&serialized);
+            let out = &outputs.iter().find(|(name, _)| name == "out").unwrap().1;
+            [(1, 2, 3), (7, 8, 9), (15, 15, 15)]
+                .into_iter()
+                .map(|(a, b, c)| {
+                    let env = [("a", vec![a]), ("b", vec![b]), ("c", vec![c])].into();
+                    interpret(&serialized, out, 0, &env).unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let verilog = "module add2(input [3:0] x, input [3:0] y, output [3:0] s);
+               assign s = x + y;
+             endmodule
+             module top(input [3:0] a, input [3:0] b, input [3:0] c, output [3:0] out);
+               wire [3:0] t;
+               add2 u0(.x(a), .y(b), .s(t));
+               add2 u1(.x(t), .y(c), .s(out));
+             endmodule";
+        let mut hierarchical = import(verilog, true);
+        let mut flattened = import(verilog, false);
+
+        assert_eq!(port_names(&mut hierarchical, "add2"), vec!["x", "y", "s"]);
+        assert_eq!(
+            port_names(&mut hierarchical, "top"),
+            vec!["a", "b", "c", "out"]
+        );
+        assert!(port_names(&mut flattened, "add2").is_empty());
+        hierarchical
+            .parse_and_run_program(
+                r#"
+                (check (= top-out (GetOutput top-u1 "s")))
+                (check (= top-u0 (ModuleInstance "add2" (StringNil) (ExprNil) (StringCons "x" (StringCons "y" (StringNil))) (ExprCons top-a (ExprCons top-b (ExprNil))))))
+                (check (= top-u1 (ModuleInstance "add2" (StringNil) (ExprNil) (StringCons "x" (StringCons "y" (StringNil))) (ExprCons (GetOutput top-u0 "s") (ExprCons top-c (ExprNil))))))
+                (check (IsPort "add2" "s" (Output) (Op2 (Add) add2-x add2-y)))
+                (check (HasType top-out (Bitvector 4)))
+            "#,
+            )
+            .unwrap();
+
+        let expected = [6, 8, 13]
+            .map(|value| InterpreterResult::Bitvector(value, 4))
+            .to_vec();
+        assert_eq!(interpret_out(&mut hierarchical), expected);
+        assert_eq!(interpret_out(&mut flattened), expected);
+
+        // The Verilog keeps the hierarchy, and imports to the same design.
+        let serialized = hierarchical.serialize(SerializeConfig::default());
+        let choices = AnythingExtractor.extract(&serialized, &[]);
+        let verilog = to_verilog_egraph_serialize_hierarchy(&serialized, &choices, "top", "");
+        assert!(verilog.contains("module top("));
+        assert!(verilog.contains("module add2("));
+        assert_eq!(verilog.matches("  add2 module_").count(), 2);
+
+        let mut round_tripped = import(&verilog, true);
+        assert_eq!(port_names(&mut round_tripped, "add2"), vec!["x", "y", "s"]);
+        assert_eq!(
+            port_names(&mut round_tripped, "top"),
+            vec!["a", "b", "c", "out"]
+        );
+        assert_eq!(interpret_out(&mut round_tripped), expected);
+    }
+
+    #[test]
+    fn from_verilog_string_parameters() {
+        let yosys_plugin = Path::new(&std::env::var("CHURCHROAD_DIR").unwrap())
+            .join("yosys-plugin")
+            .join("churchroad.so");
+        let lut6 = "module lut6 #(parameter [63:0] INIT = 64'h0) (input [5:0] I, output O);
+               assign O = INIT[I];
+             endmodule";
+        let import = |verilog: &str, top: &str, keep_hierarchy: bool, init: Option<&str>| {
+            let parameters = init
+                .map(|init| [("INIT".to_owned(), init.to_owned())].into())
+                .unwrap_or_default();
+            let mut egraph =
+                from_verilog_string(verilog, top, &yosys_plugin, keep_hierarchy, &parameters)
+                    .unwrap();
+            egraph
+                .parse_and_run_program("(run-schedule (saturate typing))")
+                .unwrap();
+            auto_delete_wires(&mut egraph);
+            egraph
+        };
+        // The value of the output `name` for I = 3 and I = 40.
+        let interpret_output = |egraph: &EGraph, name: &str| {
+            let serialized = egraph.serialize(SerializeConfig::default());
+            let (_, outputs) = get_inputs_and_outputs_serialized(&serialized);
+            let output = &outputs.iter().find(|(n, _)| n == name).unwrap().1;
+            [3, 40]
+                .map(|i| interpret(&serialized, output, 0, &[("I", vec![i])].into()).unwrap())
+                .to_vec()
+        };
+        let bits = |bits: [u64; 2]| {
+            bits.map(|bit| InterpreterResult::Bitvector(bit, 1))
+                .to_vec()
+        };
+
+        // The top module's parameters can be overridden.
+        let high = import(lut6, "lut6", false, Some("64'hFFFFFFFF00000000"));
+        let low = import(lut6, "lut6", false, Some("64'h00000000FFFFFFFF"));
+        let default = import(lut6, "lut6", false, None);
+        assert_eq!(interpret_output(&high, "O"), bits([0, 1]));
+        assert_eq!(interpret_output(&low, "O"), bits([1, 0]));
+        assert_eq!(interpret_output(&default, "O"), bits([0, 0]));
+
+        // With the hierarchy kept, each instance gets a module of its own,
+        // which its parameters are bound in.
+        let verilog = format!(
+            "{lut6}
+             module top(input [5:0] I, output O0, output O1);
+               lut6 #(.INIT(64'hFFFFFFFF00000000)) u0(.I(I), .O(O0));
+               lut6 #(.INIT(64'h00000000FFFFFFFF)) u1(.I(I), .O(O1));
+             endmodule"
+        );
+        let mut hierarchical = import(&verilog, "top", true, None);
+        let serialized = hierarchical.serialize(SerializeConfig::default());
+        let modules = serialized
+            .nodes
+            .values()
+            .filter(|node| node.op == "IsPort")
+            .map(|node| serialized[&node.children[0]].op.clone())
+            .collect::<HashSet<_>>();
+        assert_eq!(modules.len(), 3);
+        hierarchical
+            .parse_and_run_program(
+                r#"
+                (check (= (GetOutput (ModuleInstance name (StringCons "INIT" (StringNil)) (ExprCons (Op0 (BV -4294967296 64)) (ExprNil)) inputs input-exprs) "O") top-O0))
+                (check (= (GetOutput (ModuleInstance name (StringCons "INIT" (StringNil)) (ExprCons (Op0 (BV 4294967295 64)) (ExprNil)) inputs input-exprs) "O") top-O1))
+            "#,
+            )
+            .unwrap();
+        assert_eq!(interpret_output(&hierarchical, "O0"), bits([0, 1]));
+        assert_eq!(interpret_output(&hierarchical, "O1"), bits([1, 0]));
+
+        // The Verilog declares the parameters which the instances bind.
+        let choices = AnythingExtractor.extract(&serialized, &[]);
+        let verilog = to_verilog_egraph_serialize_hierarchy(&serialized, &choices, "top", "");
+        assert_eq!(
+            verilog
+                .matches("  parameter INIT = 64'hffffffff00000000;")
+                .count(),
+            1
+        );
+        let round_tripped = import(&verilog, "top", true, None);
+        assert_eq!(interpret_output(&round_tripped, "O0"), bits([0, 1]));
+        assert_eq!(interpret_output(&round_tripped, "O1"), bits([1, 0]));
+    }
+
+    #[test]
+    fn extract_one_errors() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let w (Wire "w" 8))
+                (union w (Op1 (Not) w))
+                (delete (Wire "w" 8))
+            "#,
+            )
+            .unwrap();
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let not = &serialized
+            .nodes
+            .values()
+            .find(|node| node.op == "Op1")
+            .unwrap()
+            .eclass;
+        assert!(matches!(
+            extract_one(&mut egraph, not),
+            Err(ExtractionError::Cyclic(id)) if id == *not
+        ));
+        assert!(matches!(
+            extract_one(&mut egraph, &ClassId::from("missing")),
+            Err(ExtractionError::UnknownClass(id)) if id == ClassId::from("missing")
+        ));
+    }
+
+    #[test]
+    fn apply_rewrite_to_one_class() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let x (Op2 (And) (Var "a" 8) (Var "b" 8)))
+                (let y (Op2 (And) (Var "c" 8) (Var "d" 8)))
+            "#,
+            )
+            .unwrap();
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let var_class = |name: &str| {
+            let name = format!("\"{}\"", name);
+            let literal = serialized
+                .nodes
+                .values()
+                .find(|node| node.op == name)
+                .unwrap();
+            serialized
+                .nodes
+                .values()
+                .find(|node| node.op == "Var" && node.children[0] == literal.id)
+                .unwrap()
+                .eclass
+                .clone()
+        };
+        let x = &serialized
+            .nodes
+            .values()
+            .find(|node| node.op == "Op2" && serialized[&node.children[1]].eclass == var_class("a"))
+            .unwrap()
+            .eclass;
+
+        let changed = apply_rewrite(
+            &mut egraph,
+            "(rewrite (Op2 (And) a b) (Op2 (And) b a) :ruleset commute)",
+            x,
+        )
+        .unwrap();
+        assert_eq!(changed.len(), 1);
+        egraph
+            .parse_and_run_program(
+                r#"
+                (check (= x (Op2 (And) (Var "b" 8) (Var "a" 8))))
+                (fail (check (= y (Op2 (And) (Var "d" 8) (Var "c" 8)))))
+            "#,
+            )
+            .unwrap();
+
+        // A rewrite which doesn't match changes nothing.
+        assert_eq!(
+            apply_rewrite(&mut egraph, "(rewrite (Op2 (Or) a b) (Op2 (Or) b a))", x).unwrap(),
+            vec![]
+        );
+        assert!(matches!(
+            apply_rewrite(&mut egraph, "(rule () ())", x),
+            Err(RewriteError::NotARewrite(_))
+        ));
+        assert!(matches!(
+            apply_rewrite(
+                &mut egraph,
+                "(rewrite (Op2 (And) a b) (Op2 (And) b a))",
+                &ClassId::from("missing")
+            ),
+            Err(RewriteError::Extraction(ExtractionError::UnknownClass(_)))
+        ));
+    }
+
+    #[test]
+    fn import_churchroad_twice() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        assert!(matches!(
+            import_churchroad(&mut egraph),
+            Err(ChurchroadImportError::AlreadyLoaded)
+        ));
+
+        // The egraph is still usable.
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 8))
+                (run-schedule (saturate typing))
+                (check (HasType a (Bitvector 8)))
+            "#,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_module_enumeration_rewrites_up_to_date() {
+        // Read in egglog_src/module_enumeration_rewrites.egg and check that it
+        // matches the output of generate_module_enumeration_rewrites.
+        let actual = std::fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("egglog_src")
+                .join("module_enumeration_rewrites.egg"),
+        )
+        .unwrap();
+        let expected = super::generate_module_enumeration_rewrites("enumerate-modules");
+        assert_eq!(
+            expected, actual,
+            "Copy and paste this up-to-date source into module_enumeartion_rewrites.egg:\n{}",
+            expected
+        );
+    }
+
+    #[test]
+    fn zero_arity_module_enumeration_rewrite() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(&format!(
+                "(ruleset zero-arity)\n{}",
+                generate_module_enumeration_rewrite(&[], Some("zero-arity"))
+            ))
+            .unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let const (Op0 (BV 23 8)))
+                (run-schedule (saturate zero-arity))
+                (check (= const (apply (MakeModule (Op0_ (BV 23 8)) (vec-empty)) (vec-empty))))
+            "#,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn independently_derived_modules_share_an_eclass() {
+        // MakeModule is an egglog function, so it's hashconsed: modules with
+        // the same graph and debruijn indices are the same term, no matter
+        // which rewrite firing created them.
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let and1 (Op2 (And) (Var "a" 1) (Var "b" 1)))
+                (let and2 (Op2 (And) (Var "c" 1) (Var "d" 1)))
+                (run-schedule (saturate enumerate-modules))
+                (check
+                 (= and1 (apply m1 args1))
+                 (= and2 (apply m2 args2))
+                 (= m1 (MakeModule (Op2_ (And) (Hole) (Hole)) (vec-of 0 1)))
+                 (= m1 m2))
+            "#,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn enumerate_modules_modulo_commutativity() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let ab (Op2 (And) (Var "a" 1) (Var "b" 1)))
+                (let ba (Op2 (And) (Var "b" 1) (Var "a" 1)))
+                (let left (Op2 (And) (Op2 (Or) (Var "a" 1) (Var "b" 1)) (Var "c" 1)))
+                (let right (Op2 (And) (Var "c" 1) (Op2 (Or) (Var "d" 1) (Var "e" 1))))
+            "#,
+            )
+            .unwrap();
+        enumerate_modules(&mut egraph);
+
+        // and(a, b) and and(b, a) are instances of a single two-input AND
+        // module.
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let and_modules = serialized
+            .nodes
+            .values()
+            .filter(|node| node.op == "MakeModule")
+            .filter(|node| {
+                let graph = &serialized[&node.children[0]];
+                graph.op == "Op2_"
+                    && serialized[&graph.children[0]].op == "And"
+                    && graph.children[1..]
+                        .iter()
+                        .all(|child| serialized[child].op == "Hole")
+            })
+            .count();
+        assert_eq!(and_modules, 1);
+
+        // The mirror-image patterns share a module.
+        egraph
+            .parse_and_run_program(
+                r#"
+                (check
+                 (= left (apply m left-args))
+                 (= right (apply m right-args))
+                 (= m (MakeModule (Op2_ (And) (Op2_ (Or) (Hole) (Hole)) (Hole)) (vec-of 0 1 2))))
+            "#,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn run_module_enumeration_bounds_module_size() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let chain (Op1 (Not) (Op1 (ReduceOr) (Op1 (Not) (Var "a" 1)))))
+            "#,
+            )
+            .unwrap();
+        run_module_enumeration(&mut egraph, 2);
+
+        // Both two-op subchains become modules...
+        egraph
+            .parse_and_run_program(
+                r#"
+                (check (= chain (apply (MakeModule (Op1_ (Not) (Op1_ (ReduceOr) (Hole))) (vec-of 0)) args)))
+                (check (= (Op1 (ReduceOr) (Op1 (Not) (Var "a" 1)))
+                          (apply (MakeModule (Op1_ (ReduceOr) (Op1_ (Not) (Hole))) (vec-of 0)) args)))
+                (fail (check (MakeModule (Op1_ (Not) (Op1_ (ReduceOr) (Op1_ (Not) (Hole)))) (vec-of 0))))
+            "#,
+            )
+            .unwrap();
+
+        // ...but the whole chain only does when running to saturation.
+        enumerate_modules(&mut egraph);
+        egraph
+            .parse_and_run_program(
+                r#"
+                (check (MakeModule (Op1_ (Not) (Op1_ (ReduceOr) (Op1_ (Not) (Hole)))) (vec-of 0)))
+            "#,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn module_indices_stable_across_egraphs() {
+        let design = r#"
+            (let x (Var "x" 8))
+            (let y (Var "y" 8))
+            (let out (Op2 (Or) (Op2 (And) y x) (Op2 (Xor) x (Op2 (And) y x))))
+        "#;
+        // Values unrelated to the design. Both egraphs get them, so that both
+        // enumerate the same modules from them; only the order differs.
+        let unrelated = r#"(let z (Var "z" 8)) (let w (Op1 (Not) z))"#;
+        let module_terms = |unrelated_first: bool| {
+            let mut egraph = EGraph::default();
+            import_churchroad(&mut egraph).unwrap();
+            if unrelated_first {
+                egraph.parse_and_run_program(unrelated).unwrap();
+                egraph.parse_and_run_program(design).unwrap();
+            } else {
+                egraph.parse_and_run_program(design).unwrap();
+                egraph.parse_and_run_program(unrelated).unwrap();
+            }
+            run_module_enumeration(&mut egraph, 2);
+            let serialized = egraph.serialize(SerializeConfig::default());
+            let mut terms = serialized
+                .nodes
+                .iter()
+                .filter(|(_, node)| node.op == "MakeModule")
+                .map(|(id, _)| serialized_term(&serialized, id))
+                .collect::<Vec<_>>();
+            terms.sort();
+            terms
+        };
+
+        let terms = module_terms(false);
+        assert!(!terms.is_empty());
+        assert_eq!(module_terms(false), terms);
+        // Creating the unrelated values first changes how egglog numbers the
+        // design's values, but not how the modules are numbered.
+        assert_eq!(module_terms(true), terms);
+    }
+
+    #[test]
+    fn run_module_enumeration_reports_unsupported_arity() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (function Op4 (Op Expr Expr Expr Expr) Expr)
+                (let a (Var "a" 1))
+                (let wide (Op4 (And) a a a a))
+                (let narrow (Op3 (Mux) a a a))
+            "#,
+            )
+            .unwrap();
+
+        let unsupported = run_module_enumeration(&mut egraph, 2);
+        let serialized = egraph.serialize(SerializeConfig::default());
+        assert_eq!(unsupported.len(), 1);
+        assert_eq!(serialized[&unsupported[0]].op, "Op4");
+
+        egraph
+            .parse_and_run_program(
+                r#"
+                (check (= narrow (apply (MakeModule graph _) args)))
+                (fail (check (= wide (apply (MakeModule graph _) args))))
+            "#,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn enumeration_adds_no_placeholder_vars() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let sum (Op2 (Add) (Var "x" 8) (Op0 (BV 1 8))))
+                (run-schedule (saturate enumerate-modules))
+            "#,
+            )
+            .unwrap();
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        assert!(serialized
+            .nodes
+            .values()
+            .all(|node| node.op != "\"unused\""));
+    }
+
+    #[test]
+    fn enumerate_modules_with_concrete_constants() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let x-plus-one (Op2 (Add) (Var "x" 8) (Op0 (BV 1 8))))
+                (let y-plus-one (Op2 (Add) (Var "y" 8) (Op0 (BV 1 8))))
+            "#,
+            )
+            .unwrap();
+        enumerate_modules(&mut egraph);
+
+        // Both are instances of a single increment module, whose graph holds
+        // the constant rather than a hole for it.
+        egraph
+            .parse_and_run_program(
+                r#"
+                (check
+                 (= x-plus-one (apply m x-args))
+                 (= y-plus-one (apply m y-args))
+                 (= m (MakeModule (Op2_ (Add) (Hole) (Op0_ (BV 1 8))) (vec-of 0))))
+            "#,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn count_and_module_instances() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let and1 (Op2 (And) (Var "a" 1) (Var "b" 1)))
+                (let and2 (Op2 (And) (Var "c" 1) (Var "d" 1)))
+                (let and3 (Op2 (And) (Var "e" 1) (Var "f" 1)))
+                (let or (Op2 (Or) (Var "a" 1) (Var "c" 1)))
+                (run-schedule (saturate enumerate-modules))
+            "#,
+            )
+            .unwrap();
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let make_module_class = |graph_op: &str| {
+            serialized
+                .nodes
+                .values()
+                .find(|node| {
+                    node.op == "MakeModule" && {
+                        let graph = &serialized[&node.children[0]];
+                        graph.op == "Op2_" && serialized[&graph.children[0]].op == graph_op
+                    }
+                })
+                .unwrap()
+                .eclass
+                .clone()
+        };
+        let and_module = make_module_class("And");
+        let or_module = make_module_class("Or");
+
+        assert_eq!(count_instances_of_module(&serialized, &and_module), 3);
+        assert_eq!(count_instances_of_module(&serialized, &or_module), 1);
+
+        let counts = all_module_instance_counts(&serialized);
+        assert!(counts.contains(&(and_module, 3)));
+        assert!(counts.contains(&(or_module, 1)));
+        assert!(counts.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+    }
+
+    #[test]
+    fn mine_modules_and_of_and() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let anded (Op2 (And) (Var "a" 1) (Op2 (And) (Var "b" 1) (Var "c" 1))))
+                (run-schedule (saturate enumerate-modules))
+            "#,
+            )
+            .unwrap();
+
+        let modules = mine_modules(&mut egraph);
+        let and_module = modules
+            .iter()
+            .find(|module| module.graph == "(Op2_ (And) (Hole) (Hole))")
+            .unwrap();
+        assert_eq!(and_module.arity, 2);
+        assert_eq!(and_module.num_application_sites(), 2);
+
+        assert!(modules.iter().any(|module| module.graph
+            == "(Op2_ (And) (Hole) (Op2_ (And) (Hole) (Hole)))"
+            && module.arity == 3
+            && module.num_application_sites() == 1));
+        assert!(modules
+            .windows(2)
+            .all(|pair| pair[0].num_application_sites() >= pair[1].num_application_sites()));
+    }
+
+    #[test]
+    fn emit_module_library_and_of_and() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let anded (Op2 (And) (Var "a" 1) (Op2 (And) (Var "b" 1) (Var "c" 1))))
+                (run-schedule (saturate enumerate-modules))
+            "#,
+            )
+            .unwrap();
+
+        let and_module = mine_modules(&mut egraph)
+            .into_iter()
+            .find(|module| module.graph == "(Op2_ (And) (Hole) (Hole))")
+            .unwrap();
+        let serialized = egraph.serialize(SerializeConfig::default());
+        assert_eq!(
+            emit_module_library(&serialized, &[and_module.class.clone()]),
+            Ok(format!(
+                "module churchroad_module_{} #(parameter IN0_WIDTH = 1, parameter IN1_WIDTH = 1, parameter OUT_WIDTH = 1) (
+  input [IN0_WIDTH-1:0] in0,
+  input [IN1_WIDTH-1:0] in1,
+  output [OUT_WIDTH-1:0] out
+);
+  assign out = (in0 & in1);
+endmodule",
+                and_module.class
+            ))
+        );
+    }
+
+    #[test]
+    fn emit_module_library_rejects_registers() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let registered (Op1 (Reg 0) (Op2 (And) (Var "a" 1) (Var "b" 1))))
+                (run-schedule (saturate enumerate-modules))
+            "#,
+            )
+            .unwrap();
+
+        let modules = mine_modules(&mut egraph);
+        let and_module = modules
+            .iter()
+            .find(|module| module.graph == "(Op2_ (And) (Hole) (Hole))")
+            .unwrap();
+        let reg_module = modules
+            .iter()
+            .find(|module| module.graph == "(Op1_ (Reg 0) (Op2_ (And) (Hole) (Hole)))")
+            .unwrap();
+        let serialized = egraph.serialize(SerializeConfig::default());
+        assert!(emit_module_library(&serialized, &[and_module.class.clone()]).is_ok());
+        assert_eq!(
+            emit_module_library(
+                &serialized,
+                &[and_module.class.clone(), reg_module.class.clone()]
+            ),
+            Err(ExportError::UnsupportedOp {
+                op: "Reg".to_owned(),
+                format: "a Verilog module"
+            })
+        );
+    }
+
+    #[test]
+    fn modules_isomorphic_modulo_numbering_and_commutativity() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (MakeModule (Op2_ (And) (Hole) (Hole)) (vec-of 0 1))
+                (MakeModule (Op2_ (And) (Hole) (Hole)) (vec-of 1 0))
+                (MakeModule (Op2_ (Or) (Hole) (Hole)) (vec-of 0 1))
+                (MakeModule (Op2_ (Sub) (Op2_ (And) (Hole) (Hole)) (Hole)) (vec-of 0 1 0))
+                (MakeModule (Op2_ (Sub) (Op2_ (And) (Hole) (Hole)) (Hole)) (vec-of 1 0 1))
+                (MakeModule (Op2_ (Sub) (Op2_ (And) (Hole) (Hole)) (Hole)) (vec-of 0 1 1))
+                (MakeModule (Op2_ (Sub) (Hole) (Op2_ (And) (Hole) (Hole))) (vec-of 0 0 1))
+            "#,
+            )
+            .unwrap();
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let module = |graph: &str, indices: &[&str]| {
+            serialized
+                .nodes
+                .values()
+                .find(|node| {
+                    node.op == "MakeModule"
+                        && serialized_term(&serialized, &node.children[0]) == graph
+                        && serialized[&node.children[1]]
+                            .children
+                            .iter()
+                            .map(|index| serialized[index].op.as_str())
+                            .eq(indices.iter().copied())
+                })
+                .unwrap()
+                .eclass
+                .clone()
+        };
+
+        let and_ab = module("(Op2_ (And) (Hole) (Hole))", &["0", "1"]);
+        let and_ba = module("(Op2_ (And) (Hole) (Hole))", &["1", "0"]);
+        let or_ab = module("(Op2_ (Or) (Hole) (Hole))", &["0", "1"]);
+        assert!(modules_isomorphic(&serialized, &and_ab, &and_ba));
+        assert!(!modules_isomorphic(&serialized, &and_ab, &or_ab));
+
+        // (a & b) - a, with the arguments numbered either way round.
+        let nested = "(Op2_ (Sub) (Op2_ (And) (Hole) (Hole)) (Hole))";
+        let and_minus_first = module(nested, &["0", "1", "0"]);
+        let and_minus_first_swapped = module(nested, &["1", "0", "1"]);
+        let and_minus_second = module(nested, &["0", "1", "1"]);
+        assert!(modules_isomorphic(
+            &serialized,
+            &and_minus_first,
+            &and_minus_first_swapped
+        ));
+        // (a & b) - b is (b & a) - b, which is (a & b) - a renumbered.
+        assert!(modules_isomorphic(
+            &serialized,
+            &and_minus_first,
+            &and_minus_second
+        ));
+        // But a - (a & b) isn't, as Sub isn't commutative.
+        let first_minus_and = module(
+            "(Op2_ (Sub) (Hole) (Op2_ (And) (Hole) (Hole)))",
+            &["0", "0", "1"],
+        );
+        assert!(!modules_isomorphic(
+            &serialized,
+            &and_minus_first,
+            &first_minus_and
+        ));
+        assert_eq!(
+            module_canonical_form(&serialized, &and_ab),
+            "(Op2_ (And) ?0 ?1)"
+        );
+    }
+
+    #[test]
+    fn extract_with_module_library_three_slices() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (IsPort "" "out0" (Output) (Op2 (Xor) (Op2 (And) (Var "a0" 4) (Var "b0" 4)) (Var "c0" 4)))
+                (IsPort "" "out1" (Output) (Op2 (Xor) (Op2 (And) (Var "a1" 4) (Var "b1" 4)) (Var "c1" 4)))
+                (IsPort "" "out2" (Output) (Op2 (Xor) (Op2 (And) (Var "a2" 4) (Var "b2" 4)) (Var "c2" 4)))
+            "#,
+            )
+            .unwrap();
+        enumerate_modules(&mut egraph);
+        egraph
+            .parse_and_run_program("(run-schedule (saturate typing))")
+            .unwrap();
+
+        let slice = mine_modules(&mut egraph)
+            .into_iter()
+            .find(|module| module.graph == "(Op2_ (Xor) (Op2_ (And) (Hole) (Hole)) (Hole))")
+            .unwrap();
+        assert_eq!(slice.num_application_sites(), 3);
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let choices = extract_with_module_library(&serialized, &[], &[slice.class.clone()]);
+        let verilog = to_verilog_egraph_serialize(&serialized, &choices, "");
+
+        let module_name = format!("churchroad_module_{}", slice.class);
+        assert_eq!(
+            verilog.matches(&format!("module {module_name} ")).count(),
+            1
+        );
+        assert!(verilog.contains("  assign out = ((in0 & in1) ^ in2);"));
+        assert_eq!(
+            verilog
+                .matches(&format!(
+                    "{module_name} #(.IN0_WIDTH(4), .IN1_WIDTH(4), .IN2_WIDTH(4), .OUT_WIDTH(4))"
+                ))
+                .count(),
+            3
+        );
+        assert!(!verilog.contains("&wire_"));
+    }
+
+    #[test]
+    fn module_library_falls_back_for_register_modules() {
+        // Module definitions have no clock, so a module with a register can
+        // only be emitted in direct form.
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 4))
+                (IsPort "" "a" (Input) a)
+                (let b (Var "b" 4))
+                (IsPort "" "b" (Input) b)
+                (let out (Op1 (Reg 0) (Op2 (And) a b)))
+                (IsPort "" "out" (Output) out)
+                (let reg-module (MakeModule (Op1_ (Reg 0) (Op2_ (And) (Hole) (Hole))) (vec-of 0 1)))
+                (union out (apply reg-module (vec-of a b)))
+                (run-schedule (saturate typing))
+            "#,
+            )
+            .unwrap();
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let make_module = serialized
+            .nodes
+            .values()
+            .find(|node| node.op == "MakeModule")
+            .unwrap();
+        assert_eq!(
+            module_definition(&serialized, make_module, "m"),
+            Err(ExportError::UnsupportedOp {
+                op: "Reg".to_owned(),
+                format: "a Verilog module"
+            })
+        );
+
+        let mut choices =
+            extract_with_module_library(&serialized, &[], &[make_module.eclass.clone()]);
+        let verilog = to_verilog_egraph_serialize(&serialized, &choices, "clk");
+        assert!(!verilog.contains("churchroad_module_"), "{verilog}");
+        assert!(verilog.contains("always @(posedge clk)"), "{verilog}");
+
+        // Choosing the application anyway gives the same Verilog.
+        let (apply_id, apply) = serialized
+            .nodes
+            .iter()
+            .find(|(_, node)| node.op == "apply")
+            .unwrap();
+        choices.insert(apply.eclass.clone(), apply_id.clone());
+        assert_eq!(
+            to_verilog_egraph_serialize(&serialized, &choices, "clk"),
+            verilog
+        );
+    }
+
+    #[test]
+    fn module_definition_signed_ops_and_negative_constants() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                "(MakeModule (Op2_ (SLt) (Op1_ (SignExtend 8) (Hole)) (Op0_ (BV -1 8))) (vec-of 0))",
+            )
+            .unwrap();
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let make_module = serialized
+            .nodes
+            .values()
+            .find(|node| node.op == "MakeModule")
+            .unwrap();
+        let definition = module_definition(&serialized, make_module, "m").unwrap();
+        assert!(
+            definition.contains("  assign out = ($signed(8'($signed(in0))) < $signed(8'd255));"),
+            "{definition}"
+        );
+    }
+
+    #[test]
+    fn test_mapping_rewrites_up_to_date() {
+        for architecture in [
+            Architecture::XilinxUltrascalePlus,
+            Architecture::LatticeEcp5,
+            Architecture::IntelCyclone10Lp,
+        ] {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("egglog_src")
+                .join("mapping_rewrites")
+                .join(format!("{}.egg", architecture));
+            let actual = std::fs::read_to_string(&path).unwrap();
+            let expected = super::generate_mapping_rewrites(&architecture);
+            assert_eq!(
+                expected,
+                actual,
+                "Copy and paste this up-to-date source into {}:\n{}",
+                path.display(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn primitive_library_xilinx_ultrascale_plus() {
+        let library = PrimitiveLibrary::for_architecture(&Architecture::XilinxUltrascalePlus);
+        let dsp = library
+            .primitives()
+            .iter()
+            .find(|primitive| primitive.lakeroad_name == "DSP48E2")
+            .unwrap();
+        assert_eq!(dsp.trigger_condition.as_deref(), Some("(< n 18)"));
+
+        let lut = library
+            .primitives()
+            .iter()
+            .find(|primitive| primitive.function == PrimitiveFunction::Lut)
+            .unwrap();
+        assert_eq!(lut.input_ports.len(), 6);
+    }
+
+    #[test]
+    fn mapping_rewrites_xilinx_ultrascale_plus() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        import_mapping_rewrites(&mut egraph, &Architecture::XilinxUltrascalePlus);
+
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let small (Op2 (Mul) (Var "a" 8) (Var "b" 8)))
+                (let large (Op2 (Mul) (Var "c" 32) (Var "d" 32)))
+                (let bit (Op2 (And) (Var "e" 1) (Var "f" 1)))
+                (run-schedule (saturate typing))
+                (run-schedule (saturate mapping))
+                (check (= small (PrimitiveInterfaceDSP (Var "a" 8) (Var "b" 8))))
+                (fail (check (= large (PrimitiveInterfaceDSP (Var "c" 32) (Var "d" 32)))))
+                (check (PrimitiveInterfaceLUT bit))
+            "#,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn split_wide_multiplies_into_dsps() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        import_mapping_rewrites(&mut egraph, &Architecture::LatticeEcp5);
+        egraph
+            .parse_and_run_program(r#"(let out (Op2 (Mul) (Var "a" 32) (Var "b" 32)))"#)
+            .unwrap();
+
+        let dsp_width = Architecture::LatticeEcp5
+            .description()
+            .dsp_multiplier_width
+            .unwrap();
+        split_wide_multiplies(&mut egraph, dsp_width - 1);
+        egraph
+            .parse_and_run_program("(run-schedule (saturate typing mapping))")
+            .unwrap();
+
+        // Splitting the 32-bit multiply gives a 16-bit product of the low
+        // bytes and two 24-bit cross products, each of which splits into
+        // three 16-bit products.
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let dsps = serialized
+            .nodes
+            .values()
+            .filter(|node| node.op == "PrimitiveInterfaceDSP")
+            .count();
+        assert_eq!(dsps, 7);
+    }
+
+    #[test]
+    fn mapping_rewrites_multiply_accumulate() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        import_mapping_rewrites(&mut egraph, &Architecture::XilinxUltrascalePlus);
+
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 8))
+                (let b (Var "b" 8))
+                (let clk (Var "clk" 1))
+                (let placeholder (Wire "acc" 8))
+                (let acc (Op2 (Add) (Op2 (Mul) a b) placeholder))
+                (let reg (Op2 (Reg 0) clk acc))
+                (union placeholder reg)
+                (let registered-product (Op2 (Reg 0) clk (Op2 (Mul) a b)))
+                (run-schedule (saturate typing))
+            "#,
+            )
+            .unwrap();
+        auto_delete_wires(&mut egraph);
+        egraph
+            .parse_and_run_program(
+                r#"
+                (run-schedule (saturate mapping))
+                (check (= acc (PrimitiveInterfaceDSP_MulAdd a b reg)))
+                (check (= registered-product (PrimitiveInterfaceDSP_MulReg clk a b)))
+            "#,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn mapping_rewrites_carry_chain() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        import_mapping_rewrites(&mut egraph, &Architecture::XilinxUltrascalePlus);
+
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 16))
+                (let b (Var "b" 16))
+                (let sum (Op2 (Add) a b))
+                (run-schedule (repeat 3 (saturate typing) (saturate mapping)))
+
+                ; The low byte is a CARRY8 on its own...
+                (let low-carry
+                 (PrimitiveInterfaceCARRY8_CO
+                  (Op0 (BV 0 1)) (Op1 (Extract 7 0) a) (Op1 (Extract 7 0) b)))
+                (check
+                 (= (Op2 (Add) (Op1 (Extract 7 0) a) (Op1 (Extract 7 0) b))
+                    (PrimitiveInterfaceCARRY8
+                     (Op0 (BV 0 1)) (Op1 (Extract 7 0) a) (Op1 (Extract 7 0) b))))
+                ; ...and the high byte is a CARRY8 chained from it.
+                (check
+                 (= sum
+                    (Op2 (Concat)
+                     (PrimitiveInterfaceCARRY8
+                      low-carry (Op1 (Extract 15 8) a) (Op1 (Extract 15 8) b))
+                     (Op2 (Add) (Op1 (Extract 7 0) a) (Op1 (Extract 7 0) b)))))
+            "#,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn demo_2024_02_06() {
+        // Set the environment variable DEMO_2024_02_06_WRITE_SVGS to anything
+        // to produce SVGs.
+        fn write_svg(egraph: &EGraph, path: &str) {
+            if std::env::var("DEMO_2024_02_06_WRITE_SVGS").is_err() {
+                return;
+            }
+            let serialized = egraph.serialize_for_graphviz(true);
+            let svg_path = Path::new(path).with_extension("svg");
+            serialized.to_svg_file(svg_path).unwrap();
+        }
+
+        ///////////////////////////// BEGIN DEMO ///////////////////////////////
+
+        // We currently need to import Churchroad via Rust (rather than using an
+        // egglog `include`) because it depends on a custom primitive.
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+
+        // Churchroad programs can be very simple circuits, e.g. this one-bit and:
+        egraph
+            .parse_and_run_program(
+                r#"
+
+                (let one-bit-and (Op2 (And) (Var "a" 1) (Var "b" 1)))
+
+            "#,
+            )
+            .unwrap();
+        write_svg(&egraph, "1.svg");
+
+        // Clean up the last example...
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+
+        // The first interesting feature of Churchroad is that it can represent
+        // cyclic circuits using the native features of the egraph. For example,
+        // a simple counter circuit looks like this:
+        //
+        //        ┌────┐
+        //      ┌─▼─┐ ┌┴─┐
+        //      │reg│ │+1│
+        //      └─┬─┘ └▲─┘
+        //        └────┘
+        //
+        // In Churchroad, we can capture this easily using the following
+        // commands:
+        egraph
+            .parse_and_run_program(
+                r#"
+
+                ; Instantiate a placeholder wire, which will be connected later.
+                (let placeholder (Wire "placeholder" 8))
+
+                ; Generate the +1 box, but feed it with a temporary placeholder.
+                (let plusone  (Op2 (Add) placeholder (Op0 (BV 1 8))))
+
+                ; Generate the register, whose input is the output of +1.
+                (let reg (Op1 (Reg 0) plusone))
+
+                ; Finally, connect the placeholder to the output of the register.
+                (union placeholder reg)
+            "#,
+            )
+            .unwrap();
+        auto_delete_wires(&mut egraph);
+        write_svg(&egraph, "2.svg");
+
+        // Clean up the last example...
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+
+        // The next interesting feature of Churchroad is that the representation
+        // and its rewrites allow it to find repeated patterns across the
+        // egraph.
+        //
+        // First, let's discuss the underlying representation that allows this.
+        // As we saw in the first example, Churchroad can represent circuits
+        // directly. However, Churchroad can also represent circuits as
+        // applications of abstract modules to concrete inputs:
+        egraph
+            .parse_and_run_program(
+                r#"
+
+                ; An abstract `and` module.
+                (let and-module (MakeModule (Op2_ (And) (Hole) (Hole)) (vec-of 0 1)))
+
+                ; We can represent a concrete `and` by applying the abstract
+                ; module to concrete inputs.
+                (let and (apply and-module (vec-of (Var "a" 1) (Var "b" 1))))
+
+            "#,
+            )
+            .unwrap();
+        write_svg(&egraph, "3.svg");
+
+        // Clean up the last example...
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+
+        // Translating from the first form to the second (`apply`-based) form is
+        // achieved simply with rewrites!
+        egraph
+            .parse_and_run_program(
+                r#"
+
+                ; First, "direct" form.
+                (let anded (Op2 (And) (Var "a" 1) (Var "b" 1)))
+
+                ; Run module enumeration rewrites to convert to "apply" form.
+                (run-schedule (repeat 1 enumerate-modules))
+    
+            "#,
+            )
+            .unwrap();
+        write_svg(&egraph, "4.svg");
+
+        // Clean up the last example...
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+
+        // So why do this? Well the `apply`-based form allows us to find
+        // repeated patterns in the egraph. As a simple example, imagine we have
+        // a series of two `and` gates in a row. This form will allow us to
+        // discover that the two `and` gates are the same:
+        egraph
+            .parse_and_run_program(
+                r#"
+
+                ; First, "direct" form.
+                (let anded (Op2 (And) (Var "a" 1) (Op2 (And) (Var "b" 1) (Var "c" 1))))
+
+                ; Run module enumeration rewrites to convert to "apply" form.
+                (run-schedule (saturate enumerate-modules))
+    
+            "#,
+            )
+            .unwrap();
+        write_svg(&egraph, "5.svg");
+    }
+
+    #[test]
+    fn benchmark_rewrite_saturation_two_ands() {
+        // The two `and` gates from `demo_2024_02_06`.
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"(let anded (Op2 (And) (Var "a" 1) (Op2 (And) (Var "b" 1) (Var "c" 1))))"#,
+            )
+            .unwrap();
+        let before = egraph_stats(&egraph);
+
+        let benchmark = benchmark_rewrite_saturation(&egraph, "enumerate-modules", 100);
+        assert_eq!(egraph_stats(&egraph), before);
+        let iterations = benchmark.iterations_until_saturation().unwrap();
+        assert!(iterations > 0);
+        assert_eq!(benchmark.rounds.len(), iterations + 2);
+        assert_eq!(benchmark.rounds[0].enode_count, before.enode_count);
+        assert!(benchmark.rounds[1].enode_count > before.enode_count);
+
+        // The last round sees the egraph as it is once saturated.
+        egraph
+            .parse_and_run_program("(run-schedule (saturate enumerate-modules))")
+            .unwrap();
+        let saturated = egraph_stats(&egraph);
+        let last = benchmark.rounds.last().unwrap();
+        assert_eq!(
+            (last.enode_count, last.eclass_count),
+            (saturated.enode_count, saturated.eclass_count)
+        );
+
+        // Nothing is left to run once saturated.
+        let total = benchmark
+            .rounds
+            .iter()
+            .map(|round| round.duration)
+            .sum::<std::time::Duration>();
+        assert_eq!(benchmark.estimated_total_time(1000), total);
+        let plot = benchmark.plot_growth();
+        assert_eq!(plot.lines().count(), benchmark.rounds.len() + 1);
+        assert!(plot.contains(&"#".repeat(50)));
+
+        // Stopping early leaves the rest to be estimated.
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"(let anded (Op2 (And) (Var "a" 1) (Op2 (And) (Var "b" 1) (Var "c" 1))))"#,
+            )
+            .unwrap();
+        let benchmark = benchmark_rewrite_saturation(&egraph, "enumerate-modules", 1);
+        assert_eq!(benchmark.rounds.len(), 2);
+        assert_eq!(benchmark.iterations_until_saturation(), None);
+        assert!(benchmark.estimated_total_time(10) >= benchmark.rounds[1].duration);
+    }
+
+    #[test]
+    fn test_module_instance() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph.parse_and_run_program(r#"
+            ; wire declarations
+            ; a
+            (let v0 (Wire "v0" 1))
+            ; b
+            (let v1 (Wire "v1" 1))
+            ; out
+            (let v2 (Wire "v2" 1))
+
+            ; cells
+            (let some_module_instance (ModuleInstance "some_module" (StringCons "p" (StringNil)) (ExprCons (Op0 (BV 4 4)) (ExprNil)) (StringCons "a" (StringCons "b" (StringNil))) (ExprCons v0 (ExprCons v1 (ExprNil)))))
+            (union (GetOutput some_module_instance "out") v2)
+
+            ; inputs
+            (IsPort "" "a" (Input) (Var "a" 1))
+            (union v0 (Var "a" 1))
+            (IsPort "" "b" (Input) (Var "b" 1))
+            (union v1 (Var "b" 1))
+
+            ; outputs
+            (IsPort "" "out" (Output) v2)
+            "#).unwrap();
+        assert_eq!(auto_delete_wires(&mut egraph), 3);
+        assert_eq!(auto_delete_wires(&mut egraph), 0);
+    }
+
+    #[test]
+    fn extract_cycle() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let placeholder (Wire "placeholder" 8))
+                (let reg (Op1 (Reg 0) placeholder))
+                (union placeholder reg)
+                (IsPort "" "out" (Output) reg)
+            "#,
+            )
+            .unwrap();
+        auto_delete_wires(&mut egraph);
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let out = AnythingExtractor.extract(&serialized, &[]);
+
+        // TODO(@gussmith23) terrible assertion, but it's a start.
+        assert_eq!(
+            "module top(
+  output out
+);
+  assign out = wire_10;
+  logic wire_10 = 0;
+  
+always @(posedge clk) begin
+                            wire_10 <= wire_10;
+                        end
+
+
+endmodule",
+            to_verilog_egraph_serialize(&serialized, &out, "clk")
+        );
+    }
+
+    #[test]
+    fn recognize_shift_registers_up_to_max_depth() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let clk (Var "clk" 1))
+                (let x (Var "x" 8))
+                (let r1 (Op2 (Reg 0) clk x))
+                (let r2 (Op2 (Reg 0) clk r1))
+                (let r3 (Op2 (Reg 0) clk r2))
+                (let r4 (Op2 (Reg 0) clk r3))
+            "#,
+            )
+            .unwrap();
+
+        recognize_shift_registers(&mut egraph, 3);
+        egraph
+            .parse_and_run_program(
+                r#"
+                (check (= r2 (Op2 (ShiftReg 2 0) clk x)))
+                (check (= r3 (Op2 (ShiftReg 3 0) clk x)))
+                (check (= r4 (Op2 (Reg 0) clk (Op2 (ShiftReg 3 0) clk x))))
+            "#,
+            )
+            .unwrap();
+        assert!(egraph
+            .parse_and_run_program("(check (Op2 (ShiftReg 4 0) clk x))")
+            .is_err());
+
+        recognize_shift_registers(&mut egraph, 32);
+        egraph
+            .parse_and_run_program("(check (= r4 (Op2 (ShiftReg 4 0) clk x)))")
+            .unwrap();
+    }
+
+    #[test]
+    fn check_widths_finds_mismatched_wire() {
+        // Reduced from a ripple-carry adder whose Concats built 4-bit sums
+        // which were unioned into 2-bit wires.
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Wire "a" 2))
+                (let b (Wire "b" 2))
+                (let sum (Wire "sum" 2))
+                (union a (Var "a" 2))
+                (union b (Var "b" 2))
+                (union sum (Op2 (Concat) a b))
+                (IsPort "" "sum" (Output) sum)
+            "#,
+            )
+            .unwrap();
+
+        let conflicts = check_widths(&mut egraph);
+        assert_eq!(
+            conflicts,
+            vec![WidthConflict {
+                names: vec!["sum".to_string()],
+                widths: (2, 4),
+            }]
+        );
+        assert_eq!(conflicts[0].to_string(), "sum is both 2 and 4 bits wide");
+
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let sum (Wire "sum" 4))
+                (union sum (Op2 (Concat) (Var "a" 2) (Var "b" 2)))
+            "#,
+            )
+            .unwrap();
+        assert!(check_widths(&mut egraph).is_empty());
+    }
+
+    #[test]
+    fn egraph_stats_counts_nodes() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 8))
+                (IsPort "" "a" (Input) a)
+                (let out (Op2 (And) a a))
+                (IsPort "" "out" (Output) out)
+                (union out a)
+            "#,
+            )
+            .unwrap();
+
+        let stats = egraph_stats(&egraph);
+        assert_eq!(stats.isport_count, 2);
+        assert_eq!(stats.makemodule_count, 0);
+        assert_eq!(stats.distinct_operators["Var"], 1);
+        assert_eq!(stats.distinct_operators["Op2"], 1);
+        assert_eq!(
+            stats.enode_count,
+            stats.distinct_operators.values().sum::<usize>()
+        );
+        // The And was merged into a's class.
+        assert!(stats.merge_ratio() < 1.0);
+        assert!(stats.to_string().contains("IsPort facts"));
+    }
+
+    #[test]
+    fn flatten_hierarchy_inlines_nested_modules() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let x (Var "x" 8))
+                (let y (Var "y" 8))
+                (let instance (ModuleInstance "wrapper" (StringNil) (ExprNil) (StringCons "a" (StringCons "b" (StringNil))) (ExprCons x (ExprCons y (ExprNil)))))
+                (let out (GetOutput instance "out"))
+                (IsPort "" "out" (Output) out)
+            "#,
+            )
+            .unwrap();
+
+        // wrapper instantiates some_module, which masks the AND of its inputs
+        // with its parameter.
+        let mut db = HardwareDB::default();
+        db.register(
+            "wrapper",
+            r#"
+                (let a (Var "a" 8))
+                (IsPort "" "a" (Input) a)
+                (let b (Var "b" 8))
+                (IsPort "" "b" (Input) b)
+                (IsPort "" "out" (Output) (GetOutput (ModuleInstance "some_module" (StringCons "p" (StringNil)) (ExprCons (Op0 (BV 15 8)) (ExprNil)) (StringCons "b" (StringCons "a" (StringNil))) (ExprCons a (ExprCons b (ExprNil)))) "out"))
+                "#,
+        );
+        db.register(
+            "some_module",
+            r#"
+                (let a (Var "a" 8))
+                (IsPort "" "a" (Input) a)
+                (let b (Var "b" 8))
+                (IsPort "" "b" (Input) b)
+                (IsPort "" "out" (Output) (Op2 (And) (Op2 (Sub) a b) (Var "p" 8)))
+                "#,
+        );
+
+        assert_eq!(flatten_hierarchy(&mut egraph, &db), 2);
+        // The wrapper swaps its inputs when instantiating some_module.
+        egraph
+            .parse_and_run_program("(check (= out (Op2 (And) (Op2 (Sub) y x) (Op0 (BV 15 8)))))")
+            .unwrap();
+
+        // Everything has already been inlined.
+        assert_eq!(flatten_hierarchy(&mut egraph, &db), 0);
+    }
+
+    #[test]
+    fn hardware_db_xilinx_luts() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        // A LUT2 with INIT 6 is an XOR.
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let x (Var "x" 1))
+                (let y (Var "y" 1))
+                (let lut (ModuleInstance "LUT2" (StringCons "INIT" (StringNil)) (ExprCons (Op0 (BV 6 4)) (ExprNil)) (StringCons "I0" (StringCons "I1" (StringNil))) (ExprCons x (ExprCons y (ExprNil)))))
+                (IsPort "" "out" (Output) (GetOutput lut "O"))
+            "#,
+            )
+            .unwrap();
+        assert_eq!(
+            flatten_hierarchy(&mut egraph, &HardwareDB::xilinx_ultrascale_plus()),
+            1
+        );
+        egraph
+            .parse_and_run_program(
+                r#"
+                (check (= (GetOutput lut "O") (Op1 (Extract 0 0) (Op2 (Shr) (Op0 (BV 6 4)) (Op2 (Concat) y x)))))
+                (delete (GetOutput lut "O"))
+                (run-schedule (saturate typing))
+            "#,
+            )
+            .unwrap();
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let (_, outputs) = get_inputs_and_outputs_serialized(&serialized);
+        for (x, y) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+            assert_eq!(
+                interpret(
+                    &serialized,
+                    &outputs[0].1,
+                    0,
+                    &[("x", vec![x]), ("y", vec![y])].into()
+                ),
+                Ok(InterpreterResult::Bitvector(x ^ y, 1))
+            );
+        }
+    }
+
+    #[test]
+    fn hardware_db_from_directory() {
+        let dir = std::env::temp_dir().join("hardware_db_from_directory");
+        std::fs::create_dir_all(&dir).unwrap();
+        let buffer = r#"(IsPort "" "O" (Output) (Var "I" 1))"#;
+        std::fs::write(dir.join("BUF.egg"), buffer).unwrap();
+        std::fs::write(dir.join("README.md"), "Not a module.").unwrap();
+
+        let db = HardwareDB::from_directory(&dir).unwrap();
+        assert_eq!(db.lookup("BUF"), Some(buffer));
+        assert_eq!(db.lookup("README"), None);
+    }
+
+    #[test]
+    fn compile_module_instance() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 8))
+                (IsPort "" "a" (Input) a)
+                (let b (Var "b" 8))
+                (IsPort "" "b" (Input) b)
+                (IsPort "" "out" (Output) (GetOutput (ModuleInstance "some_module" (StringCons "p" (StringNil)) (ExprCons (Op0 (BV 4 4)) (ExprNil)) (StringCons "a" (StringCons "b" (StringNil))) (ExprCons a (ExprCons b (ExprNil)))) "out"))
+            "#,
+            )
+            .unwrap();
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let out = AnythingExtractor.extract(&serialized, &[]);
+
+        assert_eq!(
+            "module top(
+  input [8-1:0] a,
+  input [8-1:0] b,
+  output out
+);
+  assign out = wire_27;
+  logic wire_27;
+  wire [4-1:0] wire_19 = 4'd4;
+  wire [8-1:0] wire_13 = b;
+  wire [8-1:0] wire_10 = a;
+  
+
+  some_module #(
+    .p(4'h4)
+) module_26 (
+    .a(wire_10),
+    .b(wire_13),
+    .out(wire_27));
+endmodule",
+            to_verilog_egraph_serialize(&serialized, &out, "")
+        );
+    }
+
+    #[test]
+    fn compile_module_instance_lut6_init() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let i (Var "i" 6))
+                (IsPort "" "i" (Input) i)
+                (IsPort "" "out" (Output)
+                 (GetOutput
+                  (ModuleInstance "LUT6"
+                   (StringCons "INIT" (StringNil))
+                   (ExprCons (Op0 (BV 7608434000728254870 64)) (ExprNil))
+                   (StringCons "I0" (StringCons "I1" (StringCons "I2" (StringCons "I3" (StringCons "I4" (StringCons "I5" (StringNil)))))))
+                   (ExprCons (Op1 (Extract 0 0) i) (ExprCons (Op1 (Extract 1 1) i) (ExprCons (Op1 (Extract 2 2) i) (ExprCons (Op1 (Extract 3 3) i) (ExprCons (Op1 (Extract 4 4) i) (ExprCons (Op1 (Extract 5 5) i) (ExprNil))))))))
+                  "O"))
+                (run-schedule (saturate typing))
+            "#,
+            )
+            .unwrap();
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let out = AnythingExtractor.extract(&serialized, &[]);
+        let verilog = to_verilog_egraph_serialize(&serialized, &out, "");
+
+        assert!(verilog.contains("  LUT6 #(\n    .INIT(64'h6996966996696996)\n) module_"));
+        assert!(verilog.contains(".I5(wire_"));
+        assert!(verilog.contains(".O(wire_"));
+    }
+
+    #[test]
+    fn compile_shr() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 8))
+                (IsPort "" "a" (Input) a)
+                (let b (Var "b" 8))
+                (IsPort "" "b" (Input) b)
+                (IsPort "" "out" (Output) (Op2 (Shr) a b))
+            "#,
+            )
+            .unwrap();
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let out = AnythingExtractor.extract(&serialized, &[]);
+        let verilog = to_verilog_egraph_serialize(&serialized, &out, "");
+
+        // Shr should be emitted as a logical shift, never an arithmetic one.
+        assert!(verilog
+            .lines()
+            .any(|line| line.trim().starts_with("wire wire_") && line.contains(">>")));
+        assert!(!verilog.contains(">>>"));
+    }
+
+    #[test]
+    fn compile_carry8() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 6))
+                (IsPort "" "a" (Input) a)
+                (let b (Var "b" 6))
+                (IsPort "" "b" (Input) b)
+                (IsPort "" "sum" (Output) (PrimitiveInterfaceCARRY8 (Op0 (BV 0 1)) a b))
+                (IsPort "" "carry" (Output) (PrimitiveInterfaceCARRY8_CO (Op0 (BV 0 1)) a b))
+                (run-schedule (saturate typing))
+            "#,
+            )
+            .unwrap();
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let out = AnythingExtractor.extract(&serialized, &[]);
+        let verilog = to_verilog_egraph_serialize(&serialized, &out, "");
+
+        assert_eq!(
+            verilog
+                .lines()
+                .filter(|line| line.trim().starts_with("CARRY8 "))
+                .count(),
+            2
+        );
+        assert!(verilog.contains("_o[5:0];"));
+        assert!(verilog.contains("_co[5];"));
+        assert!(verilog.contains("output [6-1:0] sum"));
+        assert!(verilog.contains("output [1-1:0] carry"));
+    }
+
+    #[test]
+    fn compile_signed_bv() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+
+        egraph
+            .parse_and_run_program(
+                r#"
+                (IsPort "" "out" (Output) (Op0 (SignedBV -128 8)))
+                (run-schedule (saturate typing))
+            "#,
+            )
+            .unwrap();
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let out = AnythingExtractor.extract(&serialized, &[]);
+        let verilog = to_verilog_egraph_serialize(&serialized, &out, "");
+
+        assert!(verilog.contains("= 8'sh80;"));
+        assert!(verilog.contains("output [8-1:0] out"));
+    }
+
+    #[test]
+    fn compile_rotl() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 8))
+                (IsPort "" "a" (Input) a)
+                (let b (Var "b" 4))
+                (IsPort "" "b" (Input) b)
+                (IsPort "" "out" (Output) (Op2 (Rotl) a b))
+                (run-schedule (saturate typing))
+            "#,
+            )
+            .unwrap();
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let out = AnythingExtractor.extract(&serialized, &[]);
+        let verilog = to_verilog_egraph_serialize(&serialized, &out, "");
+
+        let rotation = verilog.lines().find(|line| line.contains("% 8")).unwrap();
+        assert!(rotation.contains(" << (") && rotation.contains(" >> (8 - ("));
+    }
+
+    #[test]
+    fn simplify_recognizes_rotations() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 8))
+                (let rotl (Op2 (Or) (Op2 (Shl) a (Op0 (BV 3 4))) (Op2 (Shr) a (Op0 (BV 5 4)))))
+                (let rotr (Op2 (Or) (Op2 (Shr) a (Op0 (BV 2 4))) (Op2 (Shl) a (Op0 (BV 6 4)))))
+                (let not-a-rotation (Op2 (Or) (Op2 (Shl) a (Op0 (BV 3 4))) (Op2 (Shr) a (Op0 (BV 3 4)))))
+            "#,
+            )
+            .unwrap();
+        run_simplification(&mut egraph);
+        egraph
+            .parse_and_run_program(
+                r#"
+                (check (= rotl (Op2 (Rotl) a (Op0 (BV 3 4)))))
+                (check (= rotl (Op2 (Rotr) a (Op0 (BV 5 4)))))
+                (check (= rotr (Op2 (Rotr) a (Op0 (BV 2 4)))))
+            "#,
+            )
+            .unwrap();
+        assert!(egraph
+            .parse_and_run_program("(check (= not-a-rotation (Op2 (Rotl) a (Op0 (BV 3 4)))))")
+            .is_err());
+    }
+
+    #[test]
+    fn simplify_constant_shifts_in_lut6_index() {
+        // LUT6's INIT[{I5, ..., I0}], with the shift lowered to a barrel
+        // shifter of constant shifts.
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let INIT (Var "INIT" 64))
+                (let s0 INIT)
+                (let s1 (Op3 (Mux) (Var "I0" 1) s0 (Op2 (Shr) s0 (Op0 (BV 1 64)))))
+                (let s2 (Op3 (Mux) (Var "I1" 1) s1 (Op2 (Shr) s1 (Op0 (BV 2 64)))))
+                (let s3 (Op3 (Mux) (Var "I2" 1) s2 (Op2 (Shr) s2 (Op0 (BV 4 64)))))
+                (let s4 (Op3 (Mux) (Var "I3" 1) s3 (Op2 (Shr) s3 (Op0 (BV 8 64)))))
+                (let s5 (Op3 (Mux) (Var "I4" 1) s4 (Op2 (Shr) s4 (Op0 (BV 16 64)))))
+                (let s6 (Op3 (Mux) (Var "I5" 1) s5 (Op2 (Shr) s5 (Op0 (BV 32 64)))))
+                (let O (Op1 (Extract 0 0) s6))
+                (IsPort "" "O" (Output) O)
+                (let shl (Op2 (Shl) INIT (Op0 (BV 8 64))))
+            "#,
+            )
+            .unwrap();
+        run_simplification(&mut egraph);
+        egraph
+            .parse_and_run_program(
+                r#"
+                (check (= (Op1 (Extract 0 0) (Op2 (Shr) s5 (Op0 (BV 32 64))))
+                          (Op1 (Extract 32 32) s5)))
+                (check (= shl (Op2 (Concat) (Op1 (Extract 55 0) INIT) (Op0 (BV 0 8)))))
+            "#,
+            )
+            .unwrap();
+
+        // Returns the number of INIT bits at the leaves of a mux tree in
+        // `class`, if there is one.
+        fn mux_tree_leaves(
+            egraph: &egraph_serialize::EGraph,
+            class: &ClassId,
+            init: &ClassId,
+        ) -> Option<usize> {
+            egraph[class].nodes.iter().find_map(|node_id| {
+                let node = &egraph[node_id];
+                let op = &egraph[node.children.first()?];
+                match (node.op.as_str(), op.op.as_str()) {
+                    ("Op1", "Extract")
+                        if egraph[&node.children[1]].eclass == *init
+                            && egraph[&op.children[0]].op == egraph[&op.children[1]].op =>
+                    {
+                        Some(1)
+                    }
+                    ("Op3", "Mux") => Some(
+                        mux_tree_leaves(egraph, &egraph[&node.children[2]].eclass, init)?
+                            + mux_tree_leaves(egraph, &egraph[&node.children[3]].eclass, init)?,
+                    ),
+                    _ => None,
+                }
+            })
+        }
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let (_, outputs) = get_inputs_and_outputs_serialized(&serialized);
+        let init = serialized
+            .nodes
+            .values()
+            .find(|node| node.op == "Var" && serialized[&node.children[0]].op == "\"INIT\"")
+            .unwrap()
+            .eclass
+            .clone();
+        assert_eq!(mux_tree_leaves(&serialized, &outputs[0].1, &init), Some(64));
+    }
+
+    #[test]
+    fn boolean_rewrites_expose_lut6_decomposition() {
+        // x ? p : q, where q is the complement of p written with De Morgan's
+        // laws. As written, each of p and q depends on all of a-f, so neither
+        // can be split off into a LUT6 of its own.
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let x (Var "x" 1))
+                (let a (Var "a" 1))
+                (let b (Var "b" 1))
+                (let c (Var "c" 1))
+                (let d (Var "d" 1))
+                (let e (Var "e" 1))
+                (let f (Var "f" 1))
+                (let p (Op2 (And) a (Op2 (And) b (Op2 (And) c (Op2 (And) d (Op2 (And) e f))))))
+                (let q (Op2 (Or) (Op1 (Not) a)
+                        (Op2 (Or) (Op1 (Not) b)
+                         (Op2 (Or) (Op1 (Not) c)
+                          (Op2 (Or) (Op1 (Not) d)
+                           (Op2 (Or) (Op1 (Not) e) (Op1 (Not) f)))))))
+                (let out (Op2 (Or) (Op2 (And) x p) (Op2 (And) (Op1 (Not) x) q)))
+                (IsPort "" "out" (Output) out)
+                (run-schedule (saturate typing))
+            "#,
+            )
+            .unwrap();
+
+        let out_class = |egraph: &mut EGraph| {
+            let serialized = egraph.serialize(SerializeConfig::default());
+            let (_, outputs) = get_inputs_and_outputs_serialized(&serialized);
+            (outputs[0].1.clone(), serialized)
+        };
+        let (out, serialized) = out_class(&mut egraph);
+        assert_eq!(find_lut_decomposition(&serialized, &out, 6), None);
+
+        run_boolean_rewrites(&mut egraph, 8, &[]);
+        egraph
+            .parse_and_run_program("(check (= q (Op1 (Not) p)))")
+            .unwrap();
+        let (out, serialized) = out_class(&mut egraph);
+        assert!(find_lut_decomposition(&serialized, &out, 6).is_some());
+    }
+
+    #[test]
+    fn boolean_rewrites_shannon_expansion() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let x (Var "x" 1))
+                (let a (Var "a" 1))
+                (let out (Op2 (Xor) x (Op2 (Xor) x a)))
+            "#,
+            )
+            .unwrap();
+        run_boolean_rewrites(&mut egraph, 8, &["x"]);
+        egraph
+            .parse_and_run_program("(check (= out (Op3 (Mux) x a a)))")
+            .unwrap();
+    }
+
+    #[test]
+    fn boolean_rewrites_shannon_expansion_keeps_registers() {
+        // The register's output depends on x in the previous cycle, so it
+        // mustn't be restricted along with the current x.
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let clk (Var "clk" 1))
+                (IsPort "" "clk" (Input) clk)
+                (let x (Var "x" 1))
+                (IsPort "" "x" (Input) x)
+                (let a (Var "a" 1))
+                (IsPort "" "a" (Input) a)
+                (let r (Op2 (Reg 0) clk (Op2 (Xor) x a)))
+                (let out (Op2 (Xor) x r))
+                (IsPort "" "out" (Output) out)
+                (run-schedule (saturate typing))
+            "#,
+            )
+            .unwrap();
+        let original = egraph.serialize(SerializeConfig::default());
+
+        run_boolean_rewrites(&mut egraph, 8, &["x"]);
+        egraph
+            .parse_and_run_program("(check (= out (Op3 (Mux) x r (Op1 (Not) r))))")
+            .unwrap();
+
+        // Write out the expansion of the output, choosing the smallest
+        // implementation of every other eclass.
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let out = get_inputs_and_outputs_serialized(&serialized).1[0]
+            .1
+            .clone();
+        let mut choices: HashMap<ClassId, (usize, NodeId)> = HashMap::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (id, node) in &serialized.nodes {
+                let children = match node.op.as_str() {
+                    "Var" => &node.children[..0],
+                    "Op0" | "Op1" | "Op2" | "Op3" => &node.children[1..],
+                    _ => continue,
+                };
+                let is_mux = node.op == "Op3" && serialized[&node.children[0]].op == "Mux";
+                if (node.eclass == out) != is_mux {
+                    continue;
+                }
+                let Some(cost) = children
+                    .iter()
+                    .map(|child| {
+                        choices
+                            .get(&serialized[child].eclass)
+                            .map(|(cost, _)| cost + 1)
+                    })
+                    .sum::<Option<usize>>()
+                else {
+                    continue;
+                };
+                if choices
+                    .get(&node.eclass)
+                    .map_or(true, |(best, _)| cost < *best)
+                {
+                    choices.insert(node.eclass.clone(), (cost, id.clone()));
+                    changed = true;
+                }
+            }
+        }
+        let choices = choices
+            .into_iter()
+            .map(|(class, (_, node))| (class, node))
+            .collect();
+
+        let mut rewritten = EGraph::default();
+        import_churchroad(&mut rewritten).unwrap();
+        rewritten
+            .parse_and_run_program(&to_egglog(&serialized, &choices).unwrap())
+            .unwrap();
+        auto_delete_wires(&mut rewritten);
+        rewritten
+            .parse_and_run_program("(run-schedule (saturate typing))")
+            .unwrap();
+        let rewritten = rewritten.serialize(SerializeConfig::default());
+
+        let env: HashMap<&str, Vec<u64>> = [
+            ("clk", vec![0, 1, 0, 1, 0, 1, 0, 1]),
+            ("x", vec![1, 1, 0, 0, 1, 0, 0, 1]),
+            ("a", vec![0, 0, 0, 1, 1, 1, 0, 0]),
+        ]
+        .into();
+        let output = |egraph: &egraph_serialize::EGraph| {
+            get_inputs_and_outputs_serialized(egraph).1[0].1.clone()
+        };
+        for time in 0..8 {
+            assert_eq!(
+                interpret(&rewritten, &output(&rewritten), time, &env),
+                interpret(&original, &output(&original), time, &env),
+                "time {time}"
+            );
+        }
+    }
+
+    #[test]
+    fn pmux() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let sel (Var "sel" 3))
+                (IsPort "" "sel" (Input) sel)
+                (let default (Var "default" 4))
+                (IsPort "" "default" (Input) default)
+                (let cases (Var "cases" 12))
+                (IsPort "" "cases" (Input) cases)
+                (let out (Op3 (Pmux) sel default cases))
+                (IsPort "" "out" (Output) out)
+                (run-schedule (saturate typing))
+            "#,
+            )
+            .unwrap();
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let (_, outputs) = get_inputs_and_outputs_serialized(&serialized);
+        let env = |sel: u64| -> HashMap<&'static str, Vec<u64>> {
+            [
+                ("sel", vec![sel]),
+                ("default", vec![0xf]),
+                ("cases", vec![0x321]),
+            ]
+            .into()
+        };
+        assert_eq!(
+            interpret(&serialized, &outputs[0].1, 0, &env(0b100)),
+            Ok(InterpreterResult::Bitvector(3, 4))
+        );
+        assert!(interpret(&serialized, &outputs[0].1, 0, &env(0b101)).is_err());
+
+        let out = AnythingExtractor.extract(&serialized, &[]);
+        let verilog = to_verilog_egraph_serialize(&serialized, &out, "");
+        assert!(verilog.contains("[2] ? ") && verilog.contains("[8 +: 4]"));
+
+        // Lowering gives a tree of two-input muxes, highest select bit first.
+        egraph
+            .parse_and_run_program(
+                r#"
+                (run-schedule (saturate typing lower-pmux))
+                (check (= out
+                  (Op3 (Mux) (Op1 (Extract 2 2) sel)
+                   (Op3 (Mux) (Op1 (Extract 1 1) (Op1 (Extract 1 0) sel))
+                    (Op3 (Mux) (Op1 (Extract 0 0) (Op1 (Extract 1 0) sel))
+                     default
+                     (Op1 (Extract 3 0) (Op1 (Extract 7 0) cases)))
+                    (Op1 (Extract 7 4) (Op1 (Extract 7 0) cases)))
+                   (Op1 (Extract 11 8) cases))))
+            "#,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn compile_shift_reg() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let clk (Var "clk" 1))
+                (IsPort "" "clk" (Input) clk)
+                (let x (Var "x" 8))
+                (IsPort "" "x" (Input) x)
+                (IsPort "" "out" (Output) (Op2 (ShiftReg 3 0) clk x))
+                (run-schedule (saturate typing))
+            "#,
+            )
+            .unwrap();
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let out = AnythingExtractor.extract(&serialized, &[]);
+        let verilog = to_verilog_egraph_serialize(&serialized, &out, "");
+
+        assert_eq!(verilog.matches("logic [8-1:0] ").count(), 3);
+        assert!(verilog.contains("_stage0 <= wire_"));
+        assert!(verilog.contains(" <= wire_") && verilog.contains("_stage1;"));
+    }
+
+    #[test]
+    fn verilog_testbench_drives_clock() {
+        let testbench = VerilogTestbench::new("counter")
+            .add_input("en", 1)
+            .add_output("count", 4)
+            .set_clock("clk")
+            .generate();
+
+        assert!(!testbench.contains("`include"));
+        assert!(testbench.contains("logic [4-1:0] count;"));
+        assert!(testbench.contains("logic clk = 0;"));
+        assert!(testbench.contains(
+            "counter simulate_with_verilator_test_module(.en(inputs[0]), .clk(clk), .count(count));"
+        ));
+        // The clock rises only after the outputs are printed.
+        let display = testbench.find("$display(\"output: %d").unwrap();
+        assert!(testbench[display..].contains("clk = 1;"));
+        assert!(!testbench[..display].contains("clk = 1;"));
+    }
+
+    #[test]
+    fn compile_signed_ops() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 8))
+                (IsPort "" "a" (Input) a)
+                (let b (Var "b" 4))
+                (IsPort "" "b" (Input) b)
+                (let wide-b (Op1 (SignExtend 8) b))
+                (IsPort "" "product" (Output) (Op2 (SMul) a wide-b))
+                (IsPort "" "lt" (Output) (Op2 (SLt) a wide-b))
+                (IsPort "" "shifted" (Output) (Op2 (Ashr) a b))
+                (run-schedule (saturate typing))
+            "#,
+            )
+            .unwrap();
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let out = AnythingExtractor.extract(&serialized, &[]);
+        let verilog = to_verilog_egraph_serialize(&serialized, &out, "");
+
+        assert!(verilog.contains("= 8'($signed(wire_"));
+        assert!(verilog.contains(") * $signed(wire_"));
+        assert!(verilog.contains(") < $signed(wire_"));
+        assert!(verilog.contains(") >>> wire_"));
+    }
+
+    #[test]
+    fn compile_division() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 8))
+                (IsPort "" "a" (Input) a)
+                (let b (Var "b" 8))
+                (IsPort "" "b" (Input) b)
+                (IsPort "" "quotient" (Output) (Op2 (UDiv) a b))
+                (IsPort "" "remainder" (Output) (Op2 (URem) a b))
+                (IsPort "" "signed_quotient" (Output) (Op2 (SDiv) a b))
+                (IsPort "" "signed_remainder" (Output) (Op2 (SRem) a b))
+                (run-schedule (saturate typing))
+            "#,
+            )
+            .unwrap();
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let out = AnythingExtractor.extract(&serialized, &[]);
+        let verilog = to_verilog_egraph_serialize(&serialized, &out, "");
+
+        // Division by zero is guarded, as Verilog leaves it undefined.
+        assert!(verilog.contains(" == 0) ? 0 : wire_"));
+        assert!(verilog.contains(" / wire_"));
+        assert!(verilog.contains(" % wire_"));
+        assert!(verilog.contains(" == 0) ? 0 : $signed(wire_"));
+        assert!(verilog.contains(") / $signed(wire_"));
+        assert!(verilog.contains(" == 0) ? $signed(wire_"));
+        assert!(verilog.contains(") % $signed(wire_"));
+    }
+
+    #[test]
+    fn compile_lut() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 1))
+                (IsPort "" "a" (Input) a)
+                (let b (Var "b" 1))
+                (IsPort "" "b" (Input) b)
+                (IsPort "" "out" (Output) (Op1 (LUT 2 6) (Op2 (Concat) b a)))
+                (run-schedule (saturate typing))
+            "#,
+            )
+            .unwrap();
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let out = AnythingExtractor.extract(&serialized, &[]);
+        let verilog = to_verilog_egraph_serialize(&serialized, &out, "");
+
+        let lut = verilog
+            .lines()
+            .find(|line| line.trim().starts_with("LUT6"))
+            .unwrap();
+        assert!(lut.contains(".INIT(64'h0000000000000006)"));
+        assert!(lut.contains(".I2(1'b0)") && lut.contains(".I5(1'b0)"));
+    }
+
+    #[test]
+    fn compile_lut4() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let i (Var "i" 4))
+                (IsPort "" "i" (Input) i)
+                (IsPort "" "out" (Output) (Op1 (LUT 4 27030) i))
+                (run-schedule (saturate typing))
+            "#,
+            )
+            .unwrap();
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let out = AnythingExtractor.extract(&serialized, &[]);
+        let verilog = to_verilog_egraph_serialize(&serialized, &out, "");
 
-                let outputs = {let mut out = outputs
-                    .iter()
-                    .map(|(name, id)| format!("    .{}({})", name, id_to_wire_name(id)))
-                    .collect::<Vec<_>>();
-                    out.sort();
-                    out.join(",\n")};
+        let lut = verilog
+            .lines()
+            .find(|line| line.trim().starts_with("LUT4"))
+            .unwrap();
+        assert!(lut.contains(".INIT(16'h6996)"));
+        assert!(lut.contains(".I3(") && !lut.contains(".I4("));
+    }
 
-                format!("  {module_class_name} #(\n{parameters}\n) {instance_name} (\n{inputs},\n{outputs});")
-            },
-        )
-        .collect::<Vec<_>>()
-        .join("\n");
+    #[test]
+    fn compile_add_sub_mul() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
 
-    format!(
-        "module top(
-{inputs}
-{outputs}
-);
-{logic_declarations}
-{registers}
-{module_instantiations}
-endmodule",
-        inputs = inputs,
-        logic_declarations = logic_declarations,
-        registers = registers,
-    )
-}
-pub fn to_verilog(term_dag: &TermDag, id: usize) -> String {
-    // let mut wires = HashMap::default();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 8))
+                (IsPort "" "a" (Input) a)
+                (let b (Var "b" 8))
+                (IsPort "" "b" (Input) b)
+                (IsPort "" "sum" (Output) (Op2 (Add) a b))
+                (IsPort "" "difference" (Output) (Op2 (Sub) a b))
+                (IsPort "" "product" (Output) (Op2 (Mul) a b))
+            "#,
+            )
+            .unwrap();
 
-    fn id_to_wire_name(id: usize) -> String {
-        format!("wire_{}", id)
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let out = AnythingExtractor.extract(&serialized, &[]);
+        let verilog = to_verilog_egraph_serialize(&serialized, &out, "");
+
+        let a_wire = verilog
+            .lines()
+            .find_map(|line| line.trim().strip_suffix(" = a;"))
+            .unwrap()
+            .split(' ')
+            .last()
+            .unwrap();
+        let b_wire = verilog
+            .lines()
+            .find_map(|line| line.trim().strip_suffix(" = b;"))
+            .unwrap()
+            .split(' ')
+            .last()
+            .unwrap();
+        for op in ["+", "-", "*"] {
+            assert!(verilog.contains(&format!(" = {}{}{};", a_wire, op, b_wire)));
+        }
     }
 
-    let mut inputs = String::new();
-    let mut logic_declarations = String::new();
-    let mut registers = String::new();
-    let mut module_declarations = String::new();
+    #[test]
+    fn to_verilog_add_sub_mul() {
+        for (op, verilog_op) in [("Add", "+"), ("Sub", "-"), ("Mul", "*")] {
+            let mut termdag = TermDag::default();
+            let mut var = |name: &str| {
+                let name = termdag.lit(Literal::String(name.into()));
+                let bw = termdag.lit(Literal::Int(8));
+                termdag.app("Var".into(), vec![name, bw])
+            };
+            let a = var("a");
+            let b = var("b");
+            let term = termdag.app(op.into(), vec![a.clone(), b.clone()]);
+
+            let verilog = to_verilog(&termdag, termdag.lookup(&term));
+            assert!(verilog.contains(&format!(
+                " = wire_{}{}wire_{};",
+                termdag.lookup(&a),
+                verilog_op,
+                termdag.lookup(&b)
+            )));
+        }
+    }
 
-    let mut queue = vec![id];
-    let mut done = HashSet::new();
+    #[test]
+    fn to_verilog_port_lists() {
+        let mut termdag = TermDag::default();
+        let mut var = |name: &str| {
+            let name = termdag.lit(Literal::String(name.into()));
+            let bw = termdag.lit(Literal::Int(8));
+            termdag.app("Var".into(), vec![name, bw])
+        };
+        let a = var("a");
+        let b = var("b");
+        let term = termdag.app("Add".into(), vec![a, b]);
+        let verilog = to_verilog(&termdag, termdag.lookup(&term));
+        assert!(
+            verilog.starts_with("module top(a, b);") || verilog.starts_with("module top(b, a);"),
+            "{verilog}"
+        );
 
-    while let Some(id) = queue.pop() {
-        done.insert(id);
-        let term = term_dag.get(id);
+        let mut termdag = TermDag::default();
+        let val = termdag.lit(Literal::Int(5));
+        let bw = termdag.lit(Literal::Int(8));
+        let term = termdag.app("BV".into(), vec![val, bw]);
+        let verilog = to_verilog(&termdag, termdag.lookup(&term));
+        assert!(verilog.starts_with("module top();"), "{verilog}");
 
-        match term {
-            Term::Lit(Literal::String(_)) => (),
-            Term::Lit(Literal::Int(v)) => {
-                logic_declarations.push_str(&format!(
-                    "logic [31:0] {this_wire} = {val};\n",
-                    this_wire = id_to_wire_name(id),
-                    val = v
-                ));
-            }
-            Term::Var(_) => todo!(),
-            Term::App(s, v) => match (s.as_str(), v.as_slice()) {
-                ("Reg", &[default_id, clk_id, d_id]) => {
-                    let default_val = match term_dag.get(default_id) {
-                        Term::Lit(Literal::Int(default_val)) => default_val,
-                        _ => panic!(),
-                    };
+        // A design with only outputs has no trailing comma in its port list.
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (IsPort "" "out" (Output) (Op0 (BV 5 8)))
+                (run-schedule (saturate typing))
+            "#,
+            )
+            .unwrap();
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let choices = AnythingExtractor.extract(&serialized, &[]);
+        let verilog = to_verilog_egraph_serialize(&serialized, &choices, "");
+        assert!(
+            verilog.starts_with("module top(\n  output [8-1:0] out\n);"),
+            "{verilog}"
+        );
+    }
 
-                    logic_declarations.push_str(
-                        format!(
-                            "logic {this_wire} = {default};\n",
-                            this_wire = id_to_wire_name(id),
-                            default = default_val
-                        )
-                        .as_str(),
-                    );
+    #[test]
+    fn check_single_driver_finds_second_driver() {
+        let declarations = [
+            "wire [8-1:0] wire_1 = a;",
+            "logic [16-1:0] mem [0:4-1];",
+            "wire wire_2;",
+            "assign out = wire_1;",
+            "assign out = wire_2;",
+        ]
+        .map(str::to_owned);
+        assert_eq!(check_single_driver(&declarations[..4]), Ok(()));
+        assert_eq!(
+            check_single_driver(&declarations),
+            Err(MultipleDriverError {
+                wire: "out".to_owned(),
+                first: "wire_1".to_owned(),
+                second: "wire_2".to_owned(),
+            })
+        );
+    }
 
-                    registers.push_str(&format!(
-                        "always @(posedge {clk}) begin
-                            {this_wire} <= {d};
-                        end\n",
-                        clk = id_to_wire_name(clk_id),
-                        this_wire = id_to_wire_name(id),
-                        d = id_to_wire_name(d_id)
-                    ));
+    #[test]
+    fn check_single_driver_always_blocks() {
+        let declarations = [
+            "logic [8-1:0] wire_3 = 8'd0;",
+            "always @(posedge clk) begin",
+            "    wire_3 <= wire_1;",
+            "    if (wire_2 != 0) mem[wire_1] <= wire_1;",
+            "    if (wire_2 != 0) mem[wire_1] <= wire_3;",
+            "end",
+            "assign wire_3 = wire_2;",
+        ]
+        .map(str::to_owned);
+        // The register's initial value and the memory writes aren't drivers.
+        assert_eq!(check_single_driver(&declarations[..6]), Ok(()));
+        assert_eq!(
+            check_single_driver(&declarations),
+            Err(MultipleDriverError {
+                wire: "wire_3".to_owned(),
+                first: "wire_1".to_owned(),
+                second: "wire_2".to_owned(),
+            })
+        );
+    }
 
-                    if !done.contains(&d_id) {
-                        queue.push(d_id);
-                    }
-                    if !done.contains(&clk_id) {
-                        queue.push(clk_id);
-                    }
-                }
-                ("Var", [name_id, bw_id]) => {
-                    let name = match term_dag.get(*name_id) {
-                        Term::Lit(Literal::String(name)) => name,
-                        _ => panic!(),
-                    };
-                    let bw = match term_dag.get(*bw_id) {
-                        Term::Lit(Literal::Int(bw)) => bw,
-                        _ => panic!(),
-                    };
+    /// Two modules whose outputs share a name, so generating every module at
+    /// once would assign to `out` twice.
+    fn multiple_drivers_egraph() -> egraph_serialize::EGraph {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (IsPort "inverter" "out" (Output) (Op1 (Not) (Var "a" 1)))
+                (IsPort "buffer" "out" (Output) (Var "b" 1))
+                (run-schedule (saturate typing))
+            "#,
+            )
+            .unwrap();
+        egraph.serialize(SerializeConfig::default())
+    }
 
-                    inputs.push_str(
-                        format!("input [{bw}-1:0] {name};\n", bw = bw, name = name).as_str(),
-                    );
+    #[test]
+    #[should_panic = "out is driven by both"]
+    fn to_verilog_egraph_serialize_multiple_drivers() {
+        let serialized = multiple_drivers_egraph();
+        let choices = AnythingExtractor.extract(&serialized, &[]);
 
-                    logic_declarations.push_str(
-                        format!(
-                            "logic [{bw}-1:0] {this_wire} = {name};\n",
-                            bw = bw,
-                            this_wire = id_to_wire_name(id),
-                            name = name
-                        )
-                        .as_str(),
-                    );
-                }
-                ("Mux", []) => (),
-                ("LUT4", []) => (),
-                ("Or", []) => (),
-                ("Bitvector", [_]) => (),
-                ("Eq", []) => (),
-                ("BV", [val_id, bw_id]) => {
-                    let val = match term_dag.get(*val_id) {
-                        Term::Lit(Literal::Int(val)) => val,
-                        _ => panic!(),
-                    };
-                    let bw = match term_dag.get(*bw_id) {
-                        Term::Lit(Literal::Int(bw)) => bw,
-                        _ => panic!(),
-                    };
-                    logic_declarations.push_str(
-                        format!(
-                            "logic [{bw}-1:0] {this_wire} = {bw}'d{val};\n",
-                            bw = bw,
-                            this_wire = id_to_wire_name(id),
-                            val = val
-                        )
-                        .as_str(),
-                    );
-                }
-                ("Extract", [hi_id, lo_id, expr_id]) => {
-                    let hi = match term_dag.get(*hi_id) {
-                        Term::Lit(Literal::Int(hi)) => hi,
-                        _ => panic!(),
-                    };
-                    let lo = match term_dag.get(*lo_id) {
-                        Term::Lit(Literal::Int(lo)) => lo,
-                        _ => panic!(),
-                    };
-                    logic_declarations.push_str(&format!(
-                        "logic {this_wire} = {expr}[{hi}:{lo}];\n",
-                        hi = hi,
-                        lo = lo,
-                        this_wire = id_to_wire_name(id),
-                        expr = id_to_wire_name(*expr_id),
-                    ));
+        to_verilog_egraph_serialize(&serialized, &choices, "");
+    }
 
-                    if !done.contains(expr_id) {
-                        queue.push(*expr_id);
-                    }
-                }
-                ("Concat", [expr0_id, expr1_id]) => {
-                    logic_declarations.push_str(&format!(
-                        "logic {this_wire} = {{ {expr0}, {expr1} }};\n",
-                        this_wire = id_to_wire_name(id),
-                        expr0 = id_to_wire_name(*expr0_id),
-                        expr1 = id_to_wire_name(*expr1_id),
-                    ));
+    #[test]
+    fn try_to_verilog_egraph_serialize_multiple_drivers() {
+        let serialized = multiple_drivers_egraph();
+        let choices = AnythingExtractor.extract(&serialized, &[]);
 
-                    if !done.contains(expr0_id) {
-                        queue.push(*expr0_id);
-                    }
-                    if !done.contains(expr1_id) {
-                        queue.push(*expr1_id);
-                    }
-                }
-                ("ZeroExtend", [expr_id, bw_id]) => {
-                    let bw = match term_dag.get(*bw_id) {
-                        Term::Lit(Literal::Int(bw)) => bw,
-                        _ => panic!(),
-                    };
-                    logic_declarations.push_str(&format!(
-                        "logic {this_wire} = {{ {bw}'d0, {expr} }};\n",
-                        this_wire = id_to_wire_name(id),
-                        bw = bw,
-                        expr = id_to_wire_name(*expr_id),
-                    ));
+        match try_to_verilog_egraph_serialize(&serialized, &choices, "") {
+            Err(VerilogError::MultipleDrivers(err)) => assert_eq!(err.wire, "out"),
+            result => panic!("expected multiple drivers, got {result:?}"),
+        }
+    }
 
-                    if !done.contains(expr_id) {
-                        queue.push(*expr_id);
-                    }
-                }
-                ("Sketch1", [op_id, expr_id])
-                    if match term_dag.get(*op_id) {
-                        Term::App(s, v) => s.as_str() == "LUT4" && v.is_empty(),
-                        _ => false,
-                    } =>
-                {
-                    logic_declarations.push_str(&format!(
-                        "logic {this_wire};\n",
-                        this_wire = id_to_wire_name(id),
-                    ));
+    #[test]
+    fn try_to_verilog_egraph_serialize_malformed_parameters() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 1))
+                (IsPort "lut" "a" (Input) a)
+                (IsPort "lut" "out" (Output) (Op1 (Not) a))
+                (let names (StringCons "INIT" (StringNil)))
+                (union names (StringCons "WIDTH" (StringNil)))
+                (let instance (ModuleInstance "lut" names (ExprCons (Op0 (BV 1 1)) (ExprNil))
+                  (StringCons "a" (StringNil)) (ExprCons (Var "b" 1) (ExprNil))))
+                (IsPort "" "out" (Output) (GetOutput instance "out"))
+                (run-schedule (saturate typing))
+            "#,
+            )
+            .unwrap();
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let choices = AnythingExtractor.extract(&serialized, &[]);
+
+        let result = to_verilog_egraph_serialize_impl(
+            &serialized,
+            &choices,
+            &HashMap::default(),
+            "",
+            Some("lut"),
+        );
+        assert!(
+            matches!(result, Err(VerilogError::MalformedParameters(_))),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn to_verilog_egraph_serialize_no_outputs() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 8))
+                (IsPort "" "a" (Input) a)
+            "#,
+            )
+            .unwrap();
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let choices = AnythingExtractor.extract(&serialized, &[]);
 
-                    module_declarations.push_str(&format!(
-                        "lut4 lut4_{id} (.in({expr}), .out({y}));\n",
-                        id = id,
-                        expr = id_to_wire_name(*expr_id),
-                        y = id_to_wire_name(id),
-                    ));
+        let verilog = to_verilog_egraph_serialize(&serialized, &choices, "");
+        assert!(
+            verilog.starts_with("// Warning: this design has no outputs."),
+            "{verilog}"
+        );
+        assert!(verilog.contains("module top("));
+    }
 
-                    if !done.contains(expr_id) {
-                        queue.push(*expr_id);
-                    }
-                }
-                _ => todo!("{:?}", (s, v)),
-            },
-            _ => todo!("{:?}", term),
-        }
+    #[test]
+    fn to_verilog_extract_width() {
+        let mut termdag = TermDag::default();
+        let name = termdag.lit(Literal::String("a".into()));
+        let bw = termdag.lit(Literal::Int(8));
+        let a = termdag.app("Var".into(), vec![name, bw]);
+        let extract = |termdag: &mut TermDag, hi: i64, lo: i64| {
+            let hi = termdag.lit(Literal::Int(hi));
+            let lo = termdag.lit(Literal::Int(lo));
+            termdag.app("Extract".into(), vec![hi, lo, a.clone()])
+        };
+
+        let slice = extract(&mut termdag, 5, 2);
+        let verilog = to_verilog(&termdag, termdag.lookup(&slice));
+        assert!(verilog.contains(&format!(
+            "logic [4-1:0] wire_{} = wire_{}[5:2];",
+            termdag.lookup(&slice),
+            termdag.lookup(&a)
+        )));
+
+        let bit = extract(&mut termdag, 3, 3);
+        let verilog = to_verilog(&termdag, termdag.lookup(&bit));
+        assert!(verilog.contains(&format!(
+            "logic wire_{} = wire_{}[3:3];",
+            termdag.lookup(&bit),
+            termdag.lookup(&a)
+        )));
     }
 
-    format!(
-        "module top({inputs});
-            {inputs}
-            {logic_declarations}
-            {registers}
-            {module_declarations}
-        endmodule",
-        inputs = inputs,
-        logic_declarations = logic_declarations,
-        registers = registers,
-        module_declarations = module_declarations,
-    )
-}
+    #[test]
+    fn compile_reg_with_clock_port() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
 
-/// Import Churchroad language into an EGraph.
-///
-/// TODO(@gussmith23): Ideally, this would be done via an `import` statement.
-/// That's not currently possible because of the Rust-defined primitive
-/// `debruijnify` in Churchroad.
-pub fn import_churchroad(egraph: &mut EGraph) {
-    // STEP 1: import primary language definitions.
-    egraph
-        .parse_and_run_program(r#"(include "egglog_src/churchroad.egg")"#)
-        .unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let clk (Var "clk" 1))
+                (IsPort "" "clk" (Input) clk)
+                (let d (Var "d" 8))
+                (IsPort "" "d" (Input) d)
+                (IsPort "" "q" (Output) (Op2 (Reg 0) clk d))
+            "#,
+            )
+            .unwrap();
 
-    // STEP 2: add the `debruijnify` primitive to the egraph. This depends on
-    // the above language definitions, but it's not possible to do it in egglog,
-    // hence it's a Rust function.
-    add_debruijnify(egraph);
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let out = AnythingExtractor.extract(&serialized, &[]);
+        let verilog = to_verilog_egraph_serialize(&serialized, &out, "clk");
 
-    // STEP 3: import module enumeration rewrites. These depend on the
-    // `debruijnify` primitive.
-    egraph
-        .parse_and_run_program(r#"(include "egglog_src/module_enumeration_rewrites.egg")"#)
-        .unwrap();
-}
+        // The clock should be declared as an input port, and the register
+        // should be clocked by it.
+        assert!(verilog.contains("input [1-1:0] clk,"));
+        assert!(verilog.contains("always @(posedge clk) begin"));
 
-/// Add the `debruijnify` primitive to an [`EGraph`].
-fn add_debruijnify(egraph: &mut EGraph) {
-    struct DeBruijnify {
-        in_sort: Arc<VecSort>,
-        out_sort: Arc<VecSort>,
-        i64_sort: Arc<I64Sort>,
+        // The register should latch d, not the clock.
+        let d_wire = verilog
+            .lines()
+            .find_map(|line| line.trim().strip_suffix(" = d;"))
+            .unwrap()
+            .split(' ')
+            .last()
+            .unwrap();
+        assert!(verilog.contains(&format!("<= {};", d_wire)));
     }
 
-    impl PrimitiveLike for DeBruijnify {
-        fn name(&self) -> Symbol {
-            "debruijnify".into()
-        }
+    #[test]
+    fn compile_reg_clocked_by_clock_expression() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
 
-        fn get_type_constraints(&self) -> Box<dyn TypeConstraint> {
-            Box::new(SimpleTypeConstraint::new(
-                self.name(),
-                vec![self.in_sort.clone(), self.out_sort.clone()],
-            ))
-        }
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let sys_clk (Var "sys_clk" 1))
+                (IsPort "" "sys_clk" (Input) sys_clk)
+                (let en (Var "en" 1))
+                (IsPort "" "en" (Input) en)
+                (let d (Var "d" 8))
+                (IsPort "" "d" (Input) d)
+                (IsPort "" "q" (Output) (Op2 (Reg 0) sys_clk d))
+                (IsPort "" "gated_q" (Output) (Op2 (Reg 1) (Op2 (And) sys_clk en) d))
+            "#,
+            )
+            .unwrap();
 
-        fn apply(
-            &self,
-            values: &[crate::Value],
-            egraph: Option<&mut EGraph>,
-        ) -> Option<crate::Value> {
-            let in_vec = Vec::<Value>::load(&self.in_sort, &values[0]);
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let out = AnythingExtractor.extract(&serialized, &[]);
+        // The clock name given here isn't the name of the design's clock.
+        let verilog = to_verilog_egraph_serialize(&serialized, &out, "clk");
+
+        assert!(verilog.contains("always @(posedge sys_clk) begin"));
+        // The gated clock isn't an input, so it's referred to by its wire.
+        let gated_clk_wire = verilog
+            .lines()
+            .find(|line| line.contains('&'))
+            .and_then(|line| line.split(" = ").next())
+            .and_then(|declaration| declaration.split(' ').last())
+            .unwrap();
+        assert!(verilog.contains(&format!("always @(posedge {}) begin", gated_clk_wire)));
+        assert!(!verilog.contains("posedge clk"));
+        assert!(!verilog.contains("input clk"));
+    }
 
-            let mut seen_values: HashMap<Value, i64> = HashMap::new();
-            let mut next_id = 0;
-            let mut out = vec![];
+    #[test]
+    fn compile_multi_clk() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
 
-            let egraph = egraph.unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let d (Var "d" 8))
+                (IsPort "" "d" (Input) d)
+                (let fast_clk (Var "fast_clk" 1))
+                (IsPort "" "fast_clk" (Input) fast_clk)
+                (IsPort "" "fast_q" (Output) (Op2 (RegWithClk 0) fast_clk d))
+                (IsPort "" "slow_q" (Output) (Op1 (Reg 1) d))
+                (IsPort "" "default_q" (Output) (Op1 (Reg 2) d))
+            "#,
+            )
+            .unwrap();
 
-            for value in in_vec {
-                // Get representative value.
-                let value = egraph.find(value);
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let (_, outputs) = get_inputs_and_outputs_serialized(&serialized);
+        let output_id = |output: &str| {
+            outputs
+                .iter()
+                .find(|(name, _)| name == output)
+                .unwrap()
+                .1
+                .clone()
+        };
+        let out = AnythingExtractor.extract(&serialized, &[]);
+        // A `RegWithClk` is clocked by its clock expression, even if its
+        // eclass is assigned another clock.
+        let verilog = to_verilog_egraph_serialize_multi_clk(
+            &serialized,
+            &out,
+            &[
+                (output_id("slow_q"), "slow_clk".to_string()),
+                (output_id("fast_q"), "other_clk".to_string()),
+            ]
+            .into(),
+            "clk",
+        );
 
-                // If we haven't assinged it a number yet, give it the next one.
-                seen_values.entry(value).or_insert_with(|| {
-                    let id = next_id;
-                    next_id += 1;
-                    id
-                });
+        // Each register should be clocked by its own clock, and assigned
+        // clocks which aren't the default should be declared as inputs.
+        let lines: Vec<&str> = verilog.lines().map(str::trim).collect();
+        for (clk, init) in [("fast_clk", 0), ("slow_clk", 1), ("clk", 2)] {
+            let reg_wire = verilog
+                .lines()
+                .find_map(|line| line.trim().strip_suffix(&format!(" = {};", init)))
+                .unwrap()
+                .split(' ')
+                .last()
+                .unwrap();
+            assert!(lines.windows(2).any(|window| {
+                window[0] == format!("always @(posedge {}) begin", clk)
+                    && window[1].starts_with(&format!("{} <=", reg_wire))
+            }));
+        }
+        assert!(verilog.contains("input [1-1:0] fast_clk"));
+        assert!(verilog.contains("input slow_clk,"));
+        assert!(!verilog.contains("input clk,"));
+        assert!(!verilog.contains("other_clk"));
+    }
 
-                // Add the number to the output vector.
-                out.push(seen_values[&value].store(&self.i64_sort).unwrap());
+    #[test]
+    fn lakeroad_config_from_vars() {
+        let vars = |vars: &'static [(&'static str, &'static str)]| {
+            move |var: &str| {
+                vars.iter()
+                    .find(|(name, _)| *name == var)
+                    .map(|(_, value)| value.to_string())
             }
+        };
 
-            out.store(&self.out_sort)
-        }
+        let config = LakeroadConfig::from_vars(vars(&[
+            ("LAKEROAD_PATH", "/opt/lakeroad/bin/main.rkt"),
+            ("CHURCHROAD_ARCHITECTURE", "lattice-ecp5"),
+            ("LAKEROAD_EXTRA_ARGS", "--bitwidth 8  --pipeline-depth 0"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config,
+            LakeroadConfig {
+                binary_path: "/opt/lakeroad/bin/main.rkt".into(),
+                architecture: Architecture::LatticeEcp5,
+                timeout: std::time::Duration::from_secs(120),
+                extra_args: vec![
+                    "--bitwidth".to_string(),
+                    "8".to_string(),
+                    "--pipeline-depth".to_string(),
+                    "0".to_string(),
+                ],
+            }
+        );
+        assert_eq!(
+            config
+                .command()
+                .get_args()
+                .map(|arg| arg.to_str().unwrap())
+                .collect::<Vec<_>>(),
+            vec![
+                "--architecture",
+                "lattice-ecp5",
+                "--timeout",
+                "120",
+                "--bitwidth",
+                "8",
+                "--pipeline-depth",
+                "0"
+            ]
+        );
+
+        assert_eq!(
+            LakeroadConfig::from_vars(vars(&[("CHURCHROAD_ARCHITECTURE", "lattice-ecp5")])),
+            Err(ConfigError::Missing("LAKEROAD_PATH"))
+        );
+        assert!(matches!(
+            LakeroadConfig::from_vars(vars(&[
+                ("LAKEROAD_PATH", "lakeroad"),
+                ("CHURCHROAD_ARCHITECTURE", "lattice-ecp5"),
+                ("LAKEROAD_TIMEOUT", "soon"),
+            ])),
+            Err(ConfigError::Invalid {
+                var: "LAKEROAD_TIMEOUT",
+                ..
+            })
+        ));
+        assert!(matches!(
+            LakeroadConfig::from_vars(vars(&[
+                ("LAKEROAD_PATH", "lakeroad"),
+                ("CHURCHROAD_ARCHITECTURE", "virtex-2"),
+            ])),
+            Err(ConfigError::Invalid {
+                var: "CHURCHROAD_ARCHITECTURE",
+                ..
+            })
+        ));
     }
 
-    egraph.add_primitive(DeBruijnify {
-        i64_sort: egraph.get_sort().unwrap(),
-        in_sort: egraph
-            .get_sort_by(|s: &Arc<VecSort>| s.name() == "ExprVec".into())
-            .unwrap(),
-        out_sort: egraph
-            .get_sort_by(|s: &Arc<VecSort>| s.name() == "IVec".into())
-            .unwrap(),
-    });
-}
+    #[test]
+    fn get_ports_many_ports() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        let program = (0..150)
+            .map(|i| format!("(IsPort \"\" \"in{i}\" (Input) (Var \"in{i}\" 1))\n"))
+            .collect::<String>();
+        egraph.parse_and_run_program(&program).unwrap();
+
+        let (inputs, outputs) = get_inputs_and_outputs(&mut egraph).unwrap();
+        let names: HashSet<String> = inputs.into_iter().map(|(name, _, _)| name).collect();
+        assert_eq!(names, (0..150).map(|i| format!("in{i}")).collect());
+        assert!(outputs.is_empty());
+    }
 
-/// Generate all module enumeration rewrites used by Churchroad.
-///
-/// This function is used to generate the contents of the the
-/// `egglog_src/module_enumeration_rewrites.egg` file. A test in this file
-/// ensures that the generated file matches what this function produces.
-pub fn generate_module_enumeration_rewrites(enumeration_ruleset_name: &str) -> String {
-    format!(
-            "
-(ruleset {enumeration_ruleset_name})
-{rewrites}",
-            enumeration_ruleset_name = enumeration_ruleset_name,
-            rewrites = vec![
-                // Var
-                // Note that this puts a loop in the graph, because a Var
-                // becomes a hole applied to itself. We just need to be careful
-                // about that during extraction.
-                format!("(rewrite (Var name bw) (apply (MakeModule (Hole) (vec-of 0)) (vec-of (Var_ name bw))) :ruleset {})", enumeration_ruleset_name),
+    #[test]
+    fn get_ports_unknown_direction() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (function InOut () PortDirection)
+                (IsPort "" "a" (InOut) (Var "a" 1))
+            "#,
+            )
+            .unwrap();
 
-                // 0-ary
-                generate_module_enumeration_rewrite(&[], Some(enumeration_ruleset_name)),
-                // 1-ary
-                generate_module_enumeration_rewrite(&[true], Some(enumeration_ruleset_name)),
-                generate_module_enumeration_rewrite(&[false], Some(enumeration_ruleset_name)),
-                // 2-ary
-                generate_module_enumeration_rewrite(&[true, true], Some(enumeration_ruleset_name)),
-                generate_module_enumeration_rewrite(&[true, false], Some(enumeration_ruleset_name)),
-                generate_module_enumeration_rewrite(&[false, true], Some(enumeration_ruleset_name)),
-                generate_module_enumeration_rewrite(
-                    &[false, false],
-                    Some(enumeration_ruleset_name)
-                ),
-                // 3-ary
-                generate_module_enumeration_rewrite(
-                    &[true, true, true],
-                    Some(enumeration_ruleset_name)
-                ),
-                generate_module_enumeration_rewrite(
-                    &[true, true, false],
-                    Some(enumeration_ruleset_name)
-                ),
-                generate_module_enumeration_rewrite(
-                    &[true, false, true],
-                    Some(enumeration_ruleset_name)
-                ),
-                generate_module_enumeration_rewrite(
-                    &[true, false, false],
-                    Some(enumeration_ruleset_name)
-                ),
-                generate_module_enumeration_rewrite(
-                    &[false, true, true],
-                    Some(enumeration_ruleset_name)
-                ),
-                generate_module_enumeration_rewrite(
-                    &[false, true, false],
-                    Some(enumeration_ruleset_name)
-                ),
-                generate_module_enumeration_rewrite(
-                    &[false, false, true],
-                    Some(enumeration_ruleset_name)
-                ),
-                generate_module_enumeration_rewrite(
-                    &[false, false, false],
-                    Some(enumeration_ruleset_name)
-                ),
-                // clang-format on
-            ]
-            .join("\n"),
-        )
-}
+        assert_eq!(
+            get_ports(&mut egraph, None).unwrap_err(),
+            PortError::UnknownDirection("(InOut)".to_owned())
+        );
+    }
 
-/// Generate module enumeration rewrite.
-///
-/// - hole_indicator: a list of booleans indicating whether the Op's
-///   argument at the given index is a hole. If true, the argument will
-///   become a `(Hole)`. If not, it will expect a module application:
-///   `(apply (MakeModule graph indices) args)`.
-///
-/// ```
-/// use churchroad::generate_module_enumeration_rewrite;
-/// assert_eq!(generate_module_enumeration_rewrite(&[true, false, true], None),
-///           "(rewrite
-///   (Op3 op expr0 (apply (MakeModule graph1 _) args1) expr2)
-///   (apply (MakeModule (Op3_ op (Hole) graph1 (Hole)) (debruijnify (vec-append (vec-pop (vec-of (Var \"unused\" 0))) (vec-of expr0) args1 (vec-of expr2)))) (vec-append (vec-pop (vec-of (Var \"unused\" 0))) (vec-of expr0) args1 (vec-of expr2)))
-/// )");
-/// ```
-pub fn generate_module_enumeration_rewrite(
-    hole_indicator: &[bool],
-    ruleset: Option<&str>,
-) -> String {
-    let arity: usize = hole_indicator.len();
+    #[test]
+    fn get_ports_widths_need_typing() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 4))
+                (IsPort "" "a" (Input) a)
+                (IsPort "" "out" (Output) (Op1 (ReduceOr) a))
+            "#,
+            )
+            .unwrap();
 
-    fn make_apply_pattern(idx: usize) -> String {
-        format!("(apply (MakeModule graph{idx} _) args{idx})", idx = idx)
+        let widths = |egraph: &mut EGraph| {
+            get_ports(egraph, None)
+                .unwrap()
+                .into_iter()
+                .map(|port| (port.name, port.width))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            widths(&mut egraph),
+            vec![("a".to_owned(), Some(4)), ("out".to_owned(), None)]
+        );
+
+        egraph
+            .parse_and_run_program("(run-schedule (saturate typing))")
+            .unwrap();
+        assert_eq!(
+            widths(&mut egraph),
+            vec![("a".to_owned(), Some(4)), ("out".to_owned(), Some(1))]
+        );
     }
 
-    fn make_opaque_expr_pattern(idx: usize) -> String {
-        format!("expr{idx}", idx = idx)
+    #[test]
+    fn find_spec_for_primitive_interface_without_spec() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let dsp (PrimitiveInterfaceDSP (Var "a" 8) (Var "b" 8)))
+            "#,
+            )
+            .unwrap();
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let (interface, _) = serialized
+            .nodes
+            .iter()
+            .find(|(_, node)| node.op == "PrimitiveInterfaceDSP")
+            .unwrap();
+        assert_eq!(
+            find_spec_for_primitive_interface(&serialized, interface),
+            None
+        );
+
+        egraph
+            .parse_and_run_program(r#"(union dsp (Op2 (Mul) (Var "a" 8) (Var "b" 8)))"#)
+            .unwrap();
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let (interface, _) = serialized
+            .nodes
+            .iter()
+            .find(|(_, node)| node.op == "PrimitiveInterfaceDSP")
+            .unwrap();
+        let (choices, spec) = find_spec_for_primitive_interface(&serialized, interface).unwrap();
+        assert_eq!(serialized[&spec].op, "Op2");
+        assert_eq!(serialized[&serialized[&spec].children[0]].op, "Mul");
+        assert!(choices
+            .values()
+            .all(|node_id| !serialized[node_id].op.starts_with("PrimitiveInterface")));
     }
 
-    let arg_patterns = hole_indicator
-        .iter()
-        .enumerate()
-        .map(|(idx, is_hole)| {
-            if *is_hole {
-                make_opaque_expr_pattern(idx)
-            } else {
-                make_apply_pattern(idx)
+    #[test]
+    fn verify_lakeroad_result_mul() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let dsp (PrimitiveInterfaceDSP (Var "a" 8) (Var "b" 8)))
+                (union dsp (Op2 (Mul) (Var "a" 8) (Var "b" 8)))
+                (run-schedule (saturate typing))
+            "#,
+            )
+            .unwrap();
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let (interface, _) = serialized
+            .nodes
+            .iter()
+            .find(|(_, node)| node.op == "PrimitiveInterfaceDSP")
+            .unwrap();
+        let (choices, _) = find_spec_for_primitive_interface(&serialized, interface).unwrap();
+        let verify = |result: &str| {
+            verify_lakeroad_result(
+                &serialized,
+                &choices,
+                result,
+                std::time::Duration::from_secs(10),
+            )
+        };
+
+        assert_eq!(
+            verify(r#"(Op2 (Mul) (Var "b" 8) (Var "a" 8))"#),
+            VerificationResult::Verified
+        );
+
+        match verify(r#"(Op2 (Add) (Var "a" 8) (Var "b" 8))"#) {
+            VerificationResult::Counterexample(inputs) => {
+                let (a, b) = (inputs["a"], inputs["b"]);
+                assert_ne!(a.wrapping_mul(b) & 0xff, a.wrapping_add(b) & 0xff);
             }
-        })
-        .collect::<Vec<_>>();
+            result => panic!("expected a counterexample, got {result:?}"),
+        }
 
-    let lhs = format!(
-        "(Op{arity} op {args})",
-        arity = arity,
-        args = arg_patterns.join(" ")
-    );
+        // Registers can't be written as SMT formulas.
+        assert_eq!(
+            verify(r#"(Op1 (Reg 0) (Op2 (Mul) (Var "a" 8) (Var "b" 8)))"#),
+            VerificationResult::Unknown
+        );
+    }
 
-    let args_rhs_patterns = hole_indicator
-        .iter()
-        .enumerate()
-        .map(|(idx, is_hole)| {
-            if *is_hole {
-                "(Hole)".to_string()
-            } else {
-                format!("graph{idx}", idx = idx).to_string()
+    #[test]
+    fn verification_harness() {
+        let design = |src: &str| {
+            let mut egraph = EGraph::default();
+            import_churchroad(&mut egraph).unwrap();
+            egraph.parse_and_run_program(src).unwrap();
+            egraph
+                .parse_and_run_program("(run-schedule (saturate typing))")
+                .unwrap();
+            egraph
+        };
+        let original = r#"
+            (let a (Var "a" 4))
+            (let b (Var "b" 4))
+            (IsPort "" "sum" (Output) (Op2 (Add) a b))
+            (IsPort "" "both" (Output) (Op2 (And) a b))
+        "#;
+        let timeout = std::time::Duration::from_secs(10);
+
+        let mut harness = VerificationHarness::default();
+        harness.add_before(design(original));
+        assert!(harness.check_equivalence(timeout).is_err());
+        harness.add_after(design(original));
+        assert_eq!(
+            harness.check_equivalence(timeout),
+            Ok(EquivalenceResult::Equivalent)
+        );
+
+        harness.add_after(design(
+            r#"
+            (let a (Var "a" 4))
+            (let b (Var "b" 4))
+            (IsPort "" "sum" (Output) (Op2 (Add) b a))
+            (IsPort "" "both" (Output) (Op1 (Not) (Op2 (And) a b)))
+        "#,
+        ));
+        match harness.check_equivalence(timeout) {
+            Ok(EquivalenceResult::NotEquivalent { counterexample }) => {
+                assert_eq!(counterexample.len(), 2);
             }
-        })
-        .collect::<Vec<_>>();
+            result => panic!("expected a counterexample, got {result:?}"),
+        }
+    }
 
-    // Creates the list of arguments for the module application.
-    // the (vec-pop (vec-of ..)) thing is a hack for type inference not working
-    let args_list_expr = format!(
-        "(vec-append (vec-pop (vec-of (Var \"unused\" 0))) {args})",
-        args = hole_indicator
-            .iter()
-            .enumerate()
-            .map(|(idx, is_hole)| {
-                if *is_hole {
-                    format!("(vec-of expr{idx})", idx = idx)
-                } else {
-                    format!("args{idx}", idx = idx)
-                }
-            })
-            .collect::<Vec<_>>()
-            .join(" ")
-    );
+    #[test]
+    fn run_z3_reports_rejected_queries() {
+        let result = run_z3(
+            "(assert (= undeclared #x1))\n(check-sat)\n",
+            std::time::Duration::from_secs(10),
+        );
+        assert!(
+            matches!(&result, SatResult::Error(err) if err.contains("undeclared")),
+            "expected an error"
+        );
+    }
 
-    let rhs = format!(
-        "(apply (MakeModule (Op{arity}_ op {graphs}) (debruijnify {args})) {args})",
-        arity = arity,
-        graphs = args_rhs_patterns.join(" "),
-        args = args_list_expr,
-    );
+    #[test]
+    fn insert_lakeroad_result_unions_with_spec() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
 
-    format!(
-        "(rewrite
-  {lhs}
-  {rhs}
-{ruleset_flag})",
-        lhs = lhs,
-        rhs = rhs,
-        ruleset_flag = match ruleset {
-            Some(ruleset) => format!(":ruleset {}\n", ruleset),
-            None => "".to_string(),
-        },
-    )
-}
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 8))
+                (let b (Var "b" 8))
+                (let spec (Op2 (Mul) a b))
+                (let placeholder (Wire "placeholder" 8))
+                (let reg (Op1 (Reg 0) placeholder))
+                (union placeholder reg)
+            "#,
+            )
+            .unwrap();
+        auto_delete_wires(&mut egraph);
 
-/// List all modules present in the egraph.
-pub fn list_modules(egraph: &mut EGraph, num_variants: usize) {
-    for s in egraph
-        .parse_and_run_program(
-            format!("(query-extract :variants {num_variants} (MakeModule mod args))").as_str(),
-        )
-        .unwrap()
-    {
-        println!("{}", s);
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let class_of_op = |op: &str| {
+            let (op_id, _) = serialized.nodes.iter().find(|(_, n)| n.op == op).unwrap();
+            serialized
+                .nodes
+                .values()
+                .find(|n| n.children.first() == Some(op_id))
+                .unwrap()
+                .eclass
+                .clone()
+        };
+        let spec_class = class_of_op("Mul");
+        let reg_class = class_of_op("Reg");
+
+        let lakeroad_result = r#"
+            (GetOutput
+             (ModuleInstance "DSP48E2"
+              (StringNil) (ExprNil)
+              (StringCons "A" (StringCons "B" (StringNil)))
+              (ExprCons (Var "a" 8) (ExprCons (Var "b" 8) (ExprNil))))
+             "P")
+        "#;
+        insert_lakeroad_result(&mut egraph, &spec_class, lakeroad_result).unwrap();
+        egraph
+            .parse_and_run_program(&format!("(check (= spec {}))", lakeroad_result))
+            .unwrap();
+
+        assert_eq!(
+            insert_lakeroad_result(&mut egraph, &reg_class, lakeroad_result),
+            Err(InsertError::CyclicClass(reg_class))
+        );
+        assert!(matches!(
+            insert_lakeroad_result(&mut egraph, &spec_class, "(NotAnOp"),
+            Err(InsertError::Egglog(_))
+        ));
     }
-}
 
-/// Port name, port type, port value.
-type Ports = Vec<(String, ArcSort, Value)>;
+    #[test]
+    fn mapping_report_counts_outcomes() {
+        // Stands in for Lakeroad, deciding each candidate's fate by name.
+        let mock_synthesize = |candidate: &str| match candidate {
+            "mul8" | "mul16" => SynthesisOutcome::Synthesized,
+            "mul32" => SynthesisOutcome::Unsat,
+            "mul64" => SynthesisOutcome::Timeout,
+            _ => SynthesisOutcome::Error(format!("couldn't run Lakeroad on {}", candidate)),
+        };
 
-/// ```
-/// use churchroad::*;
-/// use egglog::{ArcSort, EGraph, Term, TermDag, Value};
-///
-/// // Get an egraph, load in a simple design.
-/// let mut egraph = EGraph::default();
-///
-/// import_churchroad(&mut egraph);
-/// egraph
-///     .parse_and_run_program(
-///         r#"
-/// ; wire declarations
-/// ; $and$<<EOF:2$1_Y
-/// (let v0 (Wire "v0" 2))
-/// ; a
-/// (let v1 (Wire "v1" 2))
-/// ; b
-/// (let v2 (Wire "v2" 1))
-/// ; o
-/// (let v3 (Wire "v3" 1))
-///
-/// ; cells
-/// ; TODO not handling signedness
-/// (let v4 (Op1 (ZeroExtend 2) v2))
-/// (union v0 (Op2 (And) v1 v4))
-/// (let v5 (Op1 (Extract 0 0) v0))
-/// (union v3 (Op1 (Extract 0 0) v5))
-///
-/// ; inputs
-/// (IsPort "" "a" (Input) (Var "a" 2))
-/// (union v1 (Var "a" 2))
-/// (IsPort "" "b" (Input) (Var "b" 1))
-/// (union v2 (Var "b" 1))
-///
-/// ; outputs
-/// (IsPort "" "o" (Output) v3)
-///
-/// ; delete wire expressions
-/// (delete (Wire "v0" 2))
-/// (delete (Wire "v1" 2))
-/// (delete (Wire "v2" 1))
-/// (delete (Wire "v3" 1))
-/// "#,
-///     )
-///     .unwrap();
-///
-/// let (inputs, outputs) = get_inputs_and_outputs(&mut egraph);
-///
-/// // We should have found two inputs, a and b.
-/// assert_eq!(inputs.len(), 2);
-///
-/// fn value_to_string(value: &Value, sort: ArcSort, egraph: &EGraph) -> String {
-///     let mut termdag = TermDag::default();
-///     let (_, term) = egraph.extract(value.clone(), &mut termdag, &sort);
-///     termdag.to_string(&term)
-/// }
-///
-/// // Get expressions for each input.
-/// let input_exprs: Vec<String> = inputs
-///     .iter()
-///     .map(|(_name, sort, value)| value_to_string(value, sort.clone(), &egraph))
-///     .collect();
-///
-/// assert_eq!(input_exprs, vec!["(Var \"a\" 2)", "(Var \"b\" 1)"]);
-///
-/// let output_expr = value_to_string(&outputs[0].2, outputs[0].1.clone(), &egraph);
-/// assert_eq!(output_expr, "(Op1 (Extract 0 0) (Op1 (Extract 0 0) (Op2 (And) (Var \"a\" 2) (Op1 (ZeroExtend 2) (Var \"b\" 1)))))");
-/// ```
-// TODO(@gussmith23): This really shouldn't require mutability.
-pub fn get_inputs_and_outputs(egraph: &mut EGraph) -> (Ports, Ports) {
-    // Get the inputs and outputs.
-    let mut inputs = vec![];
-    let mut outputs = vec![];
-    const NUM_TO_GET: usize = 100;
-    let (results, termdag) = egraph.function_to_dag("IsPort".into(), NUM_TO_GET).unwrap();
-    assert!(results.len() < NUM_TO_GET);
-    for (term, output) in &results {
-        assert!(
-            matches!(output, Term::Lit(Literal::Unit)),
-            "IsPort relation shouldn't have any outputs."
+        let mut report = MappingReport::default();
+        for candidate in ["mul8", "mul16", "mul32", "mul64", "add8"] {
+            report.record(mock_synthesize(candidate));
+        }
+
+        assert_eq!(
+            report,
+            MappingReport {
+                found: 5,
+                synthesized: 2,
+                unsat: 1,
+                timeout: 1,
+                errors: vec!["couldn't run Lakeroad on add8".to_string()],
+            }
         );
 
-        let children = match term {
-            Term::App(_, children) => children,
-            _ => panic!(),
+        let mut json = vec![];
+        report.write_json(&mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["found"], 5);
+        assert_eq!(json["synthesized"], 2);
+        assert_eq!(json["unsat"], 1);
+        assert_eq!(json["timeout"], 1);
+        assert_eq!(json["errors"][0], "couldn't run Lakeroad on add8");
+    }
+
+    #[test]
+    fn synthesis_exploration_picks_best_strategy() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 8))
+                (let b (Var "b" 8))
+                (let product (Op2 (Mul) a b))
+                (let c (Var "c" 1))
+                (let d (Var "d" 1))
+                (let conjunction (Op2 (And) c d))
+            "#,
+            )
+            .unwrap();
+
+        // The mapping rewrites need types, so the first strategy finds
+        // nothing, and the last one doesn't run at all.
+        let strategies = [
+            "(run-schedule (saturate mapping))",
+            "(run-schedule (saturate typing) (saturate mapping))",
+            "(run-schedule (saturate no-such-ruleset))",
+        ];
+        let mut result =
+            synthesis_exploration(&egraph, &strategies, &Architecture::XilinxUltrascalePlus);
+
+        assert_eq!(result.strategy, strategies[1]);
+        assert_eq!(result.report.found, 2);
+        assert!(result.report.errors.is_empty());
+        result
+            .egraph
+            .parse_and_run_program("(check (= product (PrimitiveInterfaceDSP a b)))")
+            .unwrap();
+    }
+
+    #[test]
+    fn extract_submodule_full_adder_carry() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 1))
+                (IsPort "" "a" (Input) a)
+                (let b (Var "b" 1))
+                (IsPort "" "b" (Input) b)
+                (let cin (Var "cin" 1))
+                (IsPort "" "cin" (Input) cin)
+                (let a-xor-b (Op2 (Xor) a b))
+                (let sum (Op2 (Xor) a-xor-b cin))
+                (IsPort "" "sum" (Output) sum)
+                (let carry (Op2 (Or) (Op2 (And) a b) (Op2 (And) cin a-xor-b)))
+                (IsPort "" "carry" (Output) carry)
+                (run-schedule (saturate typing))
+            "#,
+            )
+            .unwrap();
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let choices = AnythingExtractor.extract(&serialized, &[]);
+        let (_, outputs) = get_inputs_and_outputs_serialized(&serialized);
+        let output = |name: &str| {
+            outputs
+                .iter()
+                .find(|(output_name, _)| output_name == name)
+                .unwrap()
+                .1
+                .clone()
         };
 
-        let inout_term = children[2];
+        let submodule = extract_submodule(&serialized, &choices, &output("carry"));
+        let (inputs, outputs) = get_inputs_and_outputs_serialized(&submodule);
+        assert_eq!(
+            inputs
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<HashSet<_>>(),
+            HashSet::from(["a", "b", "cin"])
+        );
+        assert_eq!(outputs, vec![("carry".to_string(), output("carry"))]);
+        assert!(!submodule.classes().contains_key(&output("sum")));
 
-        enum InOut {
-            Input,
-            Output,
-        }
-        let in_or_out = match termdag.get(inout_term) {
-            Term::App(in_or_out, v) => {
-                assert_eq!(v.len(), 0);
-                if in_or_out == "Input".into() {
-                    InOut::Input
-                } else if in_or_out == "Output".into() {
-                    InOut::Output
-                } else {
-                    panic!()
-                }
-            }
-            _ => panic!(),
-        };
+        let verilog = to_verilog_egraph_serialize(&submodule, &choices, "");
+        assert!(verilog.contains("output carry"));
+        assert!(!verilog.contains(&format!("wire_{}", output("sum"))));
+    }
 
-        let churchroad_term = children[3];
+    #[test]
+    fn simplification_shrinks_adder() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
 
-        let (sort, value) = egraph
-            .eval_expr(
-                &egglog::ast::parse::ExprParser::new()
-                    .parse(&termdag.to_string(&termdag.get(churchroad_term)))
-                    .unwrap(),
+        // A full adder, with the kind of redundant width-matching the Yosys
+        // plugin emits.
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 1))
+                (let b (Var "b" 1))
+                (let cin (Var "cin" 1))
+                (let ab (Op2 (Add) (Op1 (ZeroExtend 2) a) (Op1 (ZeroExtend 2) b)))
+                (let cin-ext (Op2 (Concat) (Op0 (BV 0 1)) (Op1 (ZeroExtend 1) cin)))
+                (let sum (Op2 (Add) ab cin-ext))
+                (IsPort "" "sum" (Output) (Op1 (Extract 0 0) sum))
+                (IsPort "" "cout" (Output) (Op1 (Extract 1 1) sum))
+                (IsPort "" "a_out" (Output) (Op1 (Extract 0 0) (Op1 (ZeroExtend 2) a)))
+                (run-schedule (saturate typing))
+            "#,
             )
             .unwrap();
 
-        let port_name = children[1];
-        let port_name_str = match termdag.get(port_name) {
-            Term::Lit(Literal::String(name)) => name.to_string(),
-            _ => panic!(),
-        };
+        let num_classes =
+            |egraph: &EGraph| egraph.serialize(SerializeConfig::default()).classes().len();
+        let before = num_classes(&egraph);
+        run_simplification(&mut egraph);
+        let after = num_classes(&egraph);
+        assert!(after < before, "{} classes before, {} after", before, after);
 
-        match in_or_out {
-            InOut::Input => {
-                inputs.push((port_name_str, sort, value));
-            }
-            InOut::Output => {
-                outputs.push((port_name_str, sort, value));
-            }
-        }
+        egraph
+            .parse_and_run_program(
+                r#"
+                (check (= (Op1 (ZeroExtend 1) cin) cin))
+                (check (= cin-ext (Op1 (ZeroExtend 2) cin)))
+                (check (= (Op1 (Extract 0 0) (Op1 (ZeroExtend 2) a)) a))
+            "#,
+            )
+            .unwrap();
     }
 
-    (inputs, outputs)
-}
-
-/// Port name, port eclass.
-type PortsFromSerialized = Vec<(String, ClassId)>;
+    #[test]
+    fn fold_constants() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
 
-/// ```
-/// use churchroad::*;
-/// use egglog::{EGraph, SerializeConfig};
-///
-/// let mut egraph = EGraph::default();
-/// import_churchroad(&mut egraph);
-/// egraph
-///     .parse_and_run_program(
-///         r#"
-///     ; wire declarations
-///     ; $and$<<EOF:2$1_Y
-///     (let v0 (Wire "v0" 2))
-///     ; a
-///     (let v1 (Wire "v1" 2))
-///     ; b
-///     (let v2 (Wire "v2" 1))
-///     ; o
-///     (let v3 (Wire "v3" 1))
-///
-///     ; cells
-///     ; TODO not handling signedness
-///     (let v4 (Op1 (ZeroExtend 2) v2))
-///     (union v0 (Op2 (And) v1 v4))
-///     (let v5 (Op1 (Extract 0 0) v0))
-///     (union v3 (Op1 (Extract 0 0) v5))
-///
-///     ; inputs
-///     (IsPort "" "a" (Input) (Var "a" 2))
-///     (union v1 (Var "a" 2))
-///     (IsPort "" "b" (Input) (Var "b" 1))
-///     (union v2 (Var "b" 1))
-///
-///     ; outputs
-///     (IsPort "" "o" (Output) v3)
-///
-///     ; delete wire expressions
-///     (delete (Wire "v0" 2))
-///     (delete (Wire "v1" 2))
-///     (delete (Wire "v2" 1))
-///     (delete (Wire "v3" 1))
-///     "#,
-///     )
-///     .unwrap();
-///
-/// let serialized = egraph.serialize(SerializeConfig::default());
-/// let (inputs, outputs) = get_inputs_and_outputs_serialized(&serialized);
-///
-/// // We should have found two inputs, a and b.
-/// assert_eq!(inputs.len(), 2);
-/// assert_eq!(inputs[0].0, "a");
-/// assert_eq!(inputs[1].0, "b");
-///
-/// // We should have found one output, o.
-/// assert_eq!(outputs.len(), 1);
-/// assert_eq!(outputs[0].0, "o");
-/// ```
-pub fn get_inputs_and_outputs_serialized(
-    egraph: &egraph_serialize::EGraph,
-) -> (PortsFromSerialized, PortsFromSerialized) {
-    // Find IsPort relations.
-    #[derive(Clone)]
-    enum InputOrOutput {
-        Input(String, ClassId),
-        Output(String, ClassId),
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let wrapped-8 (Op2 (Add) (Op0 (BV 255 8)) (Op0 (BV 1 8))))
+                (let wrapped-64 (Op2 (Add) (Op0 (BV -1 64)) (Op0 (BV 1 64))))
+                (let top-bit-by-add (Op2 (Add) (Op0 (BV 9223372036854775807 64)) (Op0 (BV 1 64))))
+                (let top-bit-by-shl (Op2 (Shl) (Op0 (BV 1 64)) (Op0 (BV 63 8))))
+                (let top-bit-by-concat (Op2 (Concat) (Op0 (BV 1 1)) (Op0 (BV 0 63))))
+                (let shifted-out (Op2 (Shl) (Op0 (BV 1 64)) (Op0 (BV 64 8))))
+                (let shr (Op2 (Shr) (Op0 (BV -1 64)) (Op0 (BV 60 8))))
+                (let extract (Op1 (Extract 63 60) (Op0 (BV -1 64))))
+                (let anded (Op2 (And) (Op0 (BV 12 4)) (Op0 (BV 10 4))))
+                (let ored (Op2 (Or) (Op0 (BV 12 4)) (Op0 (BV 10 4))))
+                (let xored (Op2 (Xor) (Op0 (BV 12 4)) (Op0 (BV 10 4))))
+                (run-schedule (saturate fold-constants))
+
+                (check (= wrapped-8 (Op0 (BV 0 8))))
+                (check (= wrapped-64 (Op0 (BV 0 64))))
+                (check (= top-bit-by-add top-bit-by-shl))
+                (check (= top-bit-by-add top-bit-by-concat))
+                (check (= shifted-out (Op0 (BV 0 64))))
+                (check (= shr (Op0 (BV 15 64))))
+                (check (= extract (Op0 (BV 15 4))))
+                (check (= anded (Op0 (BV 8 4))))
+                (check (= ored (Op0 (BV 14 4))))
+                (check (= xored (Op0 (BV 6 4))))
+            "#,
+            )
+            .unwrap();
     }
 
-    fn is_port(node: &Node, egraph: &egraph_serialize::EGraph) -> Option<InputOrOutput> {
-        if node.op != "IsPort" {
-            return None;
-        }
-
-        assert_eq!(node.children.len(), 4);
-
-        let inout = &node.children[2];
+    #[test]
+    fn algebraic_rewrites_expose_mapping() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
 
-        let expr = egraph[&node.children[3]].eclass.clone();
+        // A mapping rewrite written for constant multiplicands on the left,
+        // and a design with the constant on the right.
+        egraph
+            .parse_and_run_program(
+                r#"
+                (ruleset constant-multiplier)
+                (rule
+                 ((= expr (Op2 (Mul) (Op0 (BV c bw)) x)))
+                 ((union expr (PrimitiveInterfaceDSP (Op0 (BV c bw)) x)))
+                 :ruleset constant-multiplier)
 
-        let name = egraph[&node.children[1]]
-            .op
-            .strip_prefix('\"')
-            .unwrap()
-            .strip_suffix('\"')
-            .unwrap()
-            .to_string();
+                (let a (Var "a" 8))
+                (let product (Op2 (Mul) a (Op0 (BV 3 8))))
+                (run-schedule (saturate constant-multiplier))
+                (fail (check (= product (PrimitiveInterfaceDSP (Op0 (BV 3 8)) a))))
+            "#,
+            )
+            .unwrap();
 
-        match egraph[inout].op.as_str() {
-            "Input" => Some(InputOrOutput::Input(name, expr)),
-            "Output" => Some(InputOrOutput::Output(name, expr)),
-            _ => panic!(),
-        }
+        run_algebraic_rewrites(&mut egraph, 1);
+        egraph
+            .parse_and_run_program(
+                r#"
+                (run-schedule (saturate constant-multiplier))
+                (check (= product (PrimitiveInterfaceDSP (Op0 (BV 3 8)) a)))
+            "#,
+            )
+            .unwrap();
     }
 
-    let inputs_and_outputs = egraph
-        .nodes
-        .iter()
-        .filter_map(|(_id, node)| is_port(node, egraph))
-        .collect::<Vec<_>>();
-
-    let inputs = inputs_and_outputs
-        .iter()
-        .filter_map(|io| match io {
-            InputOrOutput::Input(n, v) => Some((n.clone(), v.clone())),
-            _ => None,
-        })
-        .collect::<Vec<_>>();
-    let outputs = inputs_and_outputs
-        .iter()
-        .filter_map(|io| match io {
-            InputOrOutput::Output(n, v) => Some((n.clone(), v.clone())),
-            _ => None,
-        })
-        .collect::<Vec<_>>();
-
-    (inputs, outputs)
-}
+    #[test]
+    fn push_and_through_concat() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let a (Var "a" 2))
+                (let b (Var "b" 2))
+                (let c (Var "c" 2))
+                (let d (Var "d" 2))
+                (let anded (Op2 (And) (Op2 (Concat) a b) (Op2 (Concat) c d)))
+                (let split (Op2 (Concat) (Op2 (And) a c) (Op2 (And) b d)))
+                (let mismatched (Op2 (And) (Op2 (Concat) a b) (Op2 (Concat) (Var "e" 3) (Var "f" 1))))
+                (run-schedule (saturate typing))
+                (run-schedule push-ops-through-concat)
+                (check (= anded split))
+                (check (HasType split (Bitvector 4)))
+                (fail (check (= mismatched (Op2 (Concat) (Op2 (And) a (Var "e" 3)) (Op2 (And) b (Var "f" 1))))))
+            "#,
+            )
+            .unwrap();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        // And back again.
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+                (let split (Op2 (Concat) (Op2 (And) (Var "a" 2) (Var "c" 2)) (Op2 (And) (Var "b" 2) (Var "d" 2))))
+                (run-schedule push-ops-through-concat)
+                (check (= split (Op2 (And) (Op2 (Concat) (Var "a" 2) (Var "b" 2)) (Op2 (Concat) (Var "c" 2) (Var "d" 2)))))
+            "#,
+            )
+            .unwrap();
+    }
 
-    use std::path::Path;
+    #[test]
+    fn architecture_description() {
+        let description = Architecture::XilinxUltrascalePlus.description();
+        assert_eq!(description.name, "xilinx-ultrascale-plus");
+        assert_eq!(description.lut_inputs, Some(6));
+        assert_eq!(description.dsp_multiplier_width, Some(18));
+        assert!(description.primitive_names.contains(&"DSP48E2".to_string()));
 
-    use egglog::{EGraph, SerializeConfig};
+        assert!(
+            !Architecture::IntelCyclone10Lp
+                .description()
+                .has_distributed_ram
+        );
+    }
 
-    /// Doing some exploration of where cyclic extraction breaks in egglog with
-    /// Andrew and Vishal.
     #[test]
-    fn generate_loop() {
+    fn get_inputs_and_outputs_with_cycle() {
         let mut egraph = EGraph::default();
-        import_churchroad(&mut egraph);
+        import_churchroad(&mut egraph).unwrap();
 
         egraph
             .parse_and_run_program(
@@ -1794,330 +12000,605 @@ mod tests {
                 (let placeholder (Wire "placeholder" 8))
                 (let reg (Op1 (Reg 0) placeholder))
                 (union placeholder reg)
-                (delete (Wire "placeholder" 8))
+                (IsPort "" "out" (Output) reg)
             "#,
             )
             .unwrap();
+        auto_delete_wires(&mut egraph);
 
-        // Uncomment to write out the SVG.
-        // let serialized = egraph.serialize_for_graphviz(true);
-        // let svg_path = Path::new("tmp").with_extension("svg");
-        // serialized.to_svg_file(svg_path).unwrap();
-
-        // Extract reg from Egraph.
-        let mut _termdag = TermDag::default();
-        let (_sort, _value) = egraph
-            .eval_expr(&egglog::ast::Expr::Var((), "reg".into()))
-            .unwrap();
-        // This will panic, which is what we were trying to get to.
-        // It panics with `No cost for Value { tag: "Expr", bits: 6 }`
-        // which is basically egglog saying that it can't get a cost because
-        // of the cycle. I expected it to loop infinitely, but it's smarter than
-        // that.
-        // let (_, extracted) = egraph.extract(_value, &mut _termdag, &_sort);
+        get_inputs_and_outputs_serialized(&egraph.serialize(SerializeConfig::default()));
+    }
 
-        // Next: can we serialize the egraph? That's the first step to building
-        // a new extraction algorithm.
+    #[test]
+    fn format_churchroad_program_idempotent() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/egglog_tests");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().and_then(|e| e.to_str()) != Some("egg") {
+                continue;
+            }
+            let formatted = format_churchroad_program(&std::fs::read_to_string(&path).unwrap());
+            assert_eq!(
+                format_churchroad_program(&formatted),
+                formatted,
+                "formatting {} is not idempotent",
+                path.display()
+            );
+        }
     }
 
     #[test]
-    fn test_module_enumeration_rewrites_up_to_date() {
-        // Read in egglog_src/module_enumeration_rewrites.egg and check that it
-        // matches the output of generate_module_enumeration_rewrites.
-        let actual = std::fs::read_to_string(
-            Path::new(env!("CARGO_MANIFEST_DIR"))
-                .join("egglog_src")
-                .join("module_enumeration_rewrites.egg"),
+    fn format_churchroad_program_preserves_meaning() {
+        let program = std::fs::read_to_string(
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/egglog_tests/half_adder.egg"),
         )
         .unwrap();
-        let expected = super::generate_module_enumeration_rewrites("enumerate-modules");
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(&format_churchroad_program(&program))
+            .unwrap();
+    }
+
+    #[test]
+    fn interpret_partial_matches_interpret() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+(let placeholder (Wire "placeholder" 4))
+(let clk (Var "clk" 1))
+(let count (Op2 (Reg 0) clk (Op2 (Add) placeholder (Var "step" 4))))
+(union placeholder count)
+(IsPort "" "count" (Output) count)
+(run-schedule (saturate typing))
+"#,
+            )
+            .unwrap();
+        auto_delete_wires(&mut egraph);
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let (_, outputs) = get_inputs_and_outputs_serialized(&serialized);
+        let count = &outputs[0].1;
+
+        let env = [("clk", vec![0, 1, 0, 1, 0]), ("step", vec![3; 5])].into();
+        for time in 0..5 {
+            assert_eq!(
+                interpret_partial(&serialized, count, time, &env),
+                interpret(&serialized, count, time, &env).map(Some)
+            );
+        }
+
+        // Before the first rising edge, the register doesn't depend on `step`.
+        let env = [("clk", vec![0, 0, 1])].into();
         assert_eq!(
-            expected, actual,
-            "Copy and paste this up-to-date source into module_enumeartion_rewrites.egg:\n{}",
-            expected
+            interpret_partial(&serialized, count, 1, &env),
+            Ok(Some(InterpreterResult::Bitvector(0, 4)))
         );
+        assert_eq!(interpret_partial(&serialized, count, 2, &env), Ok(None));
+        // Nor can it be evaluated past the end of the clock's values.
+        assert_eq!(interpret_partial(&serialized, count, 3, &env), Ok(None));
     }
 
     #[test]
-    fn demo_2024_02_06() {
-        // Set the environment variable DEMO_2024_02_06_WRITE_SVGS to anything
-        // to produce SVGs.
-        fn write_svg(egraph: &EGraph, path: &str) {
-            if std::env::var("DEMO_2024_02_06_WRITE_SVGS").is_err() {
-                return;
-            }
-            let serialized = egraph.serialize_for_graphviz(true);
-            let svg_path = Path::new(path).with_extension("svg");
-            serialized.to_svg_file(svg_path).unwrap();
-        }
+    fn interpret_wide_matches_interpret() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(
+                r#"
+(let a (Var "a" 64))
+(let b (Var "b" 64))
+(let wide (Op2 (Concat) a b))
+(let doubled (Op2 (Add) wide wide))
+(IsPort "" "doubled" (Output) doubled)
+(IsPort "" "high" (Output) (Op1 (Extract 127 64) doubled))
+(IsPort "" "borrow" (Output) (Op2 (Sub) (Op1 (ZeroExtend 128) b) wide))
+(let x (Var "x" 8))
+(let y (Var "y" 8))
+(IsPort "" "narrow" (Output)
+  (Op3 (Mux) (Op2 (Eq) x y) (Op2 (Mul) x y) (Op2 (Shr) (Op2 (Sub) x y) (Op0 (BV 1 8)))))
+(run-schedule (saturate typing))
+"#,
+            )
+            .unwrap();
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let (_, outputs) = get_inputs_and_outputs_serialized(&serialized);
+        let output = |name: &str| &outputs.iter().find(|(n, _)| n == name).unwrap().1;
+
+        let env = [
+            ("a", vec![vec![0x8000_0000_0000_0001]]),
+            ("b", vec![vec![u64::MAX]]),
+        ]
+        .into();
+        assert_eq!(
+            interpret_wide(&serialized, output("doubled"), 0, &env),
+            Ok(InterpreterResultWide::Bitvector(vec![u64::MAX - 1, 3], 128))
+        );
+        assert_eq!(
+            interpret_wide(&serialized, output("high"), 0, &env),
+            Ok(InterpreterResultWide::Bitvector(vec![3], 64))
+        );
+        assert_eq!(
+            interpret_wide(&serialized, output("borrow"), 0, &env),
+            Ok(InterpreterResultWide::Bitvector(
+                vec![0, 0x7FFF_FFFF_FFFF_FFFF],
+                128
+            ))
+        );
 
-        ///////////////////////////// BEGIN DEMO ///////////////////////////////
+        for (x, y) in [(3, 5), (7, 7), (200, 13), (0, 255)] {
+            let env = [("x", vec![x]), ("y", vec![y])].into();
+            let wide_env = [("x", vec![vec![x]]), ("y", vec![vec![y]])].into();
+            let InterpreterResult::Bitvector(value, bw) =
+                interpret(&serialized, output("narrow"), 0, &env).unwrap();
+            assert_eq!(
+                interpret_wide(&serialized, output("narrow"), 0, &wide_env),
+                Ok(InterpreterResultWide::Bitvector(vec![value], bw as u32))
+            );
+        }
+    }
 
-        // We currently need to import Churchroad via Rust (rather than using an
-        // egglog `include`) because it depends on a custom primitive.
+    #[test]
+    fn interpret_wide_negative_reg_init() {
         let mut egraph = EGraph::default();
-        import_churchroad(&mut egraph);
-
-        // Churchroad programs can be very simple circuits, e.g. this one-bit and:
+        import_churchroad(&mut egraph).unwrap();
         egraph
             .parse_and_run_program(
                 r#"
-
-                (let one-bit-and (Op2 (And) (Var "a" 1) (Var "b" 1)))
-
-            "#,
+(let clk (Var "clk" 1))
+(IsPort "" "r" (Output) (Op2 (Reg -1) clk (Var "d" 128)))
+(run-schedule (saturate typing))
+"#,
             )
             .unwrap();
-        write_svg(&egraph, "1.svg");
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let (_, outputs) = get_inputs_and_outputs_serialized(&serialized);
 
-        // Clean up the last example...
-        let mut egraph = EGraph::default();
-        import_churchroad(&mut egraph);
+        let env = [("clk", vec![vec![0]]), ("d", vec![vec![0, 0]])].into();
+        assert_eq!(
+            interpret_wide(&serialized, &outputs[0].1, 0, &env),
+            Ok(InterpreterResultWide::Bitvector(
+                vec![u64::MAX, u64::MAX],
+                128
+            ))
+        );
+    }
 
-        // The first interesting feature of Churchroad is that it can represent
-        // cyclic circuits using the native features of the egraph. For example,
-        // a simple counter circuit looks like this:
-        //
-        //        ┌────┐
-        //      ┌─▼─┐ ┌┴─┐
-        //      │reg│ │+1│
-        //      └─┬─┘ └▲─┘
-        //        └────┘
-        //
-        // In Churchroad, we can capture this easily using the following
-        // commands:
+    #[test]
+    fn observe_ripple_carry_adder() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
         egraph
             .parse_and_run_program(
                 r#"
-
-                ; Instantiate a placeholder wire, which will be connected later.
-                (let placeholder (Wire "placeholder" 8))
-
-                ; Generate the +1 box, but feed it with a temporary placeholder.
-                (let plusone  (Op2 (Add) placeholder (Op0 (BV 1 8))))
-
-                ; Generate the register, whose input is the output of +1.
-                (let reg (Op1 (Reg 0) plusone))
-
-                ; Finally, connect the placeholder to the output of the register
-                ; and delete the placeholder.
-                (union placeholder reg)
-                (delete (Wire "placeholder" 8))
-
-            "#,
+(let a (Var "a" 2))
+(IsPort "" "a" (Input) a)
+(let b (Var "b" 2))
+(IsPort "" "b" (Input) b)
+(let a0 (Op1 (Extract 0 0) a))
+(let b0 (Op1 (Extract 0 0) b))
+(let a1 (Op1 (Extract 1 1) a))
+(let b1 (Op1 (Extract 1 1) b))
+(let carry (Wire "carry" 1))
+(union carry (Op2 (And) a0 b0))
+(let s1 (Op2 (Xor) (Op2 (Xor) a1 b1) carry))
+(IsPort "" "sum" (Output) (Op2 (Concat) s1 (Op2 (Xor) a0 b0)))
+(Observe "carry" carry)
+(run-schedule (saturate typing))
+"#,
             )
             .unwrap();
-        write_svg(&egraph, "2.svg");
+        auto_delete_wires(&mut egraph);
+        let serialized = egraph.serialize(SerializeConfig::default());
 
-        // Clean up the last example...
-        let mut egraph = EGraph::default();
-        import_churchroad(&mut egraph);
+        let choices = AnythingExtractor.extract(&serialized, &[]);
+        let verilog = to_verilog_egraph_serialize(&serialized, &choices, "");
+        let mut ports = verilog_port_names(&verilog);
+        ports.sort();
+        assert_eq!(ports, vec!["a", "b", "carry", "sum"]);
+        assert!(verilog.contains("output [1-1:0] carry"));
 
-        // The next interesting feature of Churchroad is that the representation
-        // and its rewrites allow it to find repeated patterns across the
-        // egraph.
-        //
-        // First, let's discuss the underlying representation that allows this.
-        // As we saw in the first example, Churchroad can represent circuits
-        // directly. However, Churchroad can also represent circuits as
-        // applications of abstract modules to concrete inputs:
+        let results =
+            interpret_design(&serialized, 0, &[("a", vec![1]), ("b", vec![3])].into()).unwrap();
+        assert_eq!(
+            results,
+            [
+                ("sum".to_string(), InterpreterResult::Bitvector(0, 2)),
+                ("carry".to_string(), InterpreterResult::Bitvector(1, 1)),
+            ]
+            .into()
+        );
+    }
+
+    #[test]
+    fn observe_only_in_its_module() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
         egraph
             .parse_and_run_program(
                 r#"
-
-                ; An abstract `and` module.
-                (let and-module (MakeModule (Op2_ (And) (Hole) (Hole)) (vec-of 0 1)))
-
-                ; We can represent a concrete `and` by applying the abstract
-                ; module to concrete inputs.
-                (let and (apply and-module (vec-of (Var "a" 1) (Var "b" 1))))
-
-            "#,
+(let a (Var "a" 1))
+(IsPort "buffer" "a" (Input) a)
+(let inner_a (Op1 (Not) a))
+(IsPort "buffer" "y" (Output) (Op1 (Not) inner_a))
+(Observe "inner_a" inner_a)
+(let b (Var "b" 1))
+(IsPort "inverter" "b" (Input) b)
+(let inner_b (Op2 (And) b b))
+(IsPort "inverter" "z" (Output) (Op1 (Not) inner_b))
+(Observe "inner_b" inner_b)
+(run-schedule (saturate typing))
+"#,
             )
             .unwrap();
-        write_svg(&egraph, "3.svg");
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let choices = AnythingExtractor.extract(&serialized, &[]);
+
+        let port_names = |verilog: String| {
+            let mut ports = verilog_port_names(&verilog)
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<_>>();
+            ports.sort();
+            ports
+        };
+        assert_eq!(
+            port_names(to_verilog_egraph_serialize_module(
+                &serialized,
+                &choices,
+                "buffer",
+                ""
+            )),
+            vec!["a", "inner_a", "y"]
+        );
+        assert_eq!(
+            port_names(to_verilog_egraph_serialize_module(
+                &serialized,
+                &choices,
+                "inverter",
+                ""
+            )),
+            vec!["b", "inner_b", "z"]
+        );
+        assert_eq!(
+            port_names(to_verilog_egraph_serialize(&serialized, &choices, "")),
+            vec!["a", "b", "inner_a", "inner_b", "y", "z"]
+        );
+    }
 
-        // Clean up the last example...
+    #[test]
+    fn write_churchroad_egg_round_trip() {
         let mut egraph = EGraph::default();
-        import_churchroad(&mut egraph);
-
-        // Translating from the first form to the second (`apply`-based) form is
-        // achieved simply with rewrites!
+        import_churchroad(&mut egraph).unwrap();
         egraph
             .parse_and_run_program(
                 r#"
-
-                ; First, "direct" form.
-                (let and (Op2 (And) (Var "a" 1) (Var "b" 1)))
-
-                ; Run module enumeration rewrites to convert to "apply" form.
-                (run-schedule (repeat 1 enumerate-modules))
-    
-            "#,
+(let a (Var "a" 4))
+(IsPort "" "a" (Input) a)
+(let b (Var "b" 4))
+(IsPort "" "b" (Input) b)
+(let clk (Var "clk" 1))
+(IsPort "" "clk" (Input) clk)
+(let count (Wire "count" 4))
+(union count (Op2 (Reg 0) clk (Op2 (Add) count a)))
+(IsPort "" "count" (Output) count)
+(let a-and-b (Op2 (And) a b))
+(union a-and-b (Op2 (And) b a))
+(IsPort "" "and" (Output) a-and-b)
+(run-schedule (saturate typing))
+"#,
             )
             .unwrap();
-        write_svg(&egraph, "4.svg");
+        auto_delete_wires(&mut egraph);
+
+        let path = std::env::temp_dir().join("write_churchroad_egg_round_trip.egg");
+        write_churchroad_egg(&egraph, &path).unwrap();
+        let mut imported = EGraph::default();
+        import_churchroad(&mut imported).unwrap();
+        imported
+            .parse_and_run_program(&std::fs::read_to_string(&path).unwrap())
+            .unwrap();
+        auto_delete_wires(&mut imported);
+        imported
+            .parse_and_run_program("(run-schedule (saturate typing))")
+            .unwrap();
 
-        // Clean up the last example...
-        let mut egraph = EGraph::default();
-        import_churchroad(&mut egraph);
+        let before = egraph.serialize(SerializeConfig::default());
+        let after = imported.serialize(SerializeConfig::default());
+        let (inputs, outputs) = get_inputs_and_outputs_serialized(&after);
+        let names = |ports: &[SerializedPort]| {
+            let mut names = ports
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        assert_eq!(names(&inputs), vec!["a", "b", "clk"]);
+        assert_eq!(names(&outputs), vec!["and", "count"]);
+        assert!(after.nodes.values().all(|node| node.op != "Wire"));
+
+        // The commuted `And` is still in the same eclass.
+        let and = &outputs.iter().find(|(name, _)| name == "and").unwrap().1;
+        assert_eq!(after.classes()[and].nodes.len(), 2);
+
+        let count = |egraph: &egraph_serialize::EGraph| {
+            let (_, outputs) = get_inputs_and_outputs_serialized(egraph);
+            outputs
+                .into_iter()
+                .find(|(name, _)| name == "count")
+                .unwrap()
+                .1
+        };
+        let env = [("clk", vec![0, 1, 0, 1, 0, 1]), ("a", vec![3; 6])].into();
+        for time in 0..6 {
+            assert_eq!(
+                interpret(&after, &count(&after), time, &env),
+                interpret(&before, &count(&before), time, &env)
+            );
+        }
+    }
 
-        // So why do this? Well the `apply`-based form allows us to find
-        // repeated patterns in the egraph. As a simple example, imagine we have
-        // a series of two `and` gates in a row. This form will allow us to
-        // discover that the two `and` gates are the same:
+    /// The names of the ports of the first module in generated Verilog, in
+    /// order.
+    fn verilog_port_names(verilog: &str) -> Vec<&str> {
+        verilog
+            .lines()
+            .skip_while(|line| !line.starts_with("module "))
+            .skip(1)
+            .take_while(|line| *line != ");")
+            .map(|line| line.trim_end_matches(',').rsplit(' ').next().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn port_index_orders_ports() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
         egraph
             .parse_and_run_program(
                 r#"
-
-                ; First, "direct" form.
-                (let and (Op2 (And) (Var "a" 1) (Op2 (And) (Var "b" 1) (Var "c" 1))))
-
-                ; Run module enumeration rewrites to convert to "apply" form.
-                (run-schedule (saturate enumerate-modules))
-    
+                (let z (Var "z" 2))
+                (IsPort "" "z" (Input) z)
+                (PortIndex "" "z" 0)
+                (let a (Var "a" 2))
+                (IsPort "" "a" (Input) a)
+                (PortIndex "" "a" 1)
+                (let m (Op2 (And) z a))
+                (IsPort "" "m" (Output) m)
+                (PortIndex "" "m" 2)
+                (let b (Var "b" 2))
+                (IsPort "" "b" (Input) b)
+                (IsPort "" "n" (Output) (Op2 (Or) m b))
+                (run-schedule (saturate typing))
             "#,
             )
             .unwrap();
-        write_svg(&egraph, "5.svg");
+
+        let ports = get_ports(&mut egraph, None).unwrap();
+        assert_eq!(
+            ports
+                .iter()
+                .map(|port| (port.name.as_str(), port.index))
+                .collect::<Vec<_>>(),
+            vec![
+                ("z", Some(0)),
+                ("a", Some(1)),
+                ("m", Some(2)),
+                ("b", None),
+                ("n", None)
+            ]
+        );
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let (inputs, _) = get_inputs_and_outputs_serialized(&serialized);
+        assert_eq!(
+            inputs
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["z", "a", "b"]
+        );
+
+        // Ports without indices come after the rest, inputs first.
+        let choices = AnythingExtractor.extract(&serialized, &[]);
+        let verilog = to_verilog_egraph_serialize(&serialized, &choices, "");
+        assert_eq!(verilog_port_names(&verilog), vec!["z", "a", "m", "b", "n"]);
     }
 
     #[test]
-    fn test_module_instance() {
-        let mut egraph = EGraph::default();
-        import_churchroad(&mut egraph);
-        egraph.parse_and_run_program(r#"
-            ; wire declarations
-            ; a
-            (let v0 (Wire "v0" 1))
-            ; b
-            (let v1 (Wire "v1" 1))
-            ; out
-            (let v2 (Wire "v2" 1))
-
-            ; cells
-            (let some_module_instance (ModuleInstance "some_module" (StringCons "p" (StringNil)) (ExprCons (Op0 (BV 4 4)) (ExprNil)) (StringCons "a" (StringCons "b" (StringNil))) (ExprCons v0 (ExprCons v1 (ExprNil)))))
-            (union (GetOutput some_module_instance "out") v2)
+    fn port_order_survives_verilog_import() {
+        let config = ImportConfig {
+            yosys_plugin: Path::new(&std::env::var("CHURCHROAD_DIR").unwrap())
+                .join("yosys-plugin")
+                .join("churchroad.so"),
+            ..Default::default()
+        };
+        let egraph = ChurchroadEGraph::from_verilog_string(
+            "module top(input [1:0] z, input [1:0] a, output [1:0] m); assign m = z & a; endmodule",
+            "top",
+            &config,
+        )
+        .unwrap()
+        .egraph;
 
-            ; inputs
-            (IsPort "" "a" (Input) (Var "a" 1))
-            (union v0 (Var "a" 1))
-            (IsPort "" "b" (Input) (Var "b" 1))
-            (union v1 (Var "b" 1))
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let choices = AnythingExtractor.extract(&serialized, &[]);
+        let verilog = to_verilog_egraph_serialize(&serialized, &choices, "");
+        assert_eq!(verilog_port_names(&verilog), vec!["z", "a", "m"]);
+    }
 
-            ; outputs
-            (IsPort "" "out" (Output) v2)
+    #[test]
+    fn detect_clocks_and_resets_from_verilog() {
+        let config = ImportConfig {
+            yosys_plugin: Path::new(&std::env::var("CHURCHROAD_DIR").unwrap())
+                .join("yosys-plugin")
+                .join("churchroad.so"),
+            ..Default::default()
+        };
+        let egraph = ChurchroadEGraph::from_verilog_string(
+            "module top(input tick, input clear, input [3:0] d, output reg [3:0] q);
+               always @(posedge tick) if (clear) q <= 0; else q <= d;
+             endmodule",
+            "top",
+            &config,
+        )
+        .unwrap()
+        .egraph;
 
-            ; delete wire expressions
-            (delete (Wire "v0" 1))
-            (delete (Wire "v1" 1))
-            (delete (Wire "v2" 1))
-            "#).unwrap();
+        let serialized = egraph.serialize(SerializeConfig::default());
+        assert_eq!(detect_clocks(&serialized), vec!["tick"]);
+        assert_eq!(detect_resets(&serialized), vec!["clear"]);
     }
 
     #[test]
-    fn extract_cycle() {
+    fn detect_clocks_by_name() {
         let mut egraph = EGraph::default();
-        import_churchroad(&mut egraph);
-
+        import_churchroad(&mut egraph).unwrap();
         egraph
             .parse_and_run_program(
                 r#"
-                (let placeholder (Wire "placeholder" 8))
-                (let reg (Op1 (Reg 0) placeholder))
-                (union placeholder reg)
-                (delete (Wire "placeholder" 8))
-                (IsPort "" "out" (Output) reg)
+                (let clk (Var "clk" 1))
+                (IsPort "" "clk" (Input) clk)
+                (let rst_n (Var "rst_n" 1))
+                (IsPort "" "rst_n" (Input) rst_n)
+                (let d (Var "d" 8))
+                (IsPort "" "d" (Input) d)
+                (IsPort "" "q" (Output) (Op1 (Reg 0) d))
             "#,
             )
             .unwrap();
 
         let serialized = egraph.serialize(SerializeConfig::default());
-        let out = AnythingExtractor.extract(&serialized, &[]);
-
-        // TODO(@gussmith23) terrible assertion, but it's a start.
-        assert_eq!(
-            "module top(
-  
-  
-  output out,
-);
-  logic out = wire_10;
-  logic wire_10 = 0;
-  
-always @(posedge clk) begin
-                            wire_10 <= wire_10;
-                        end
-
-
-endmodule",
-            to_verilog_egraph_serialize(&serialized, &out, "clk")
-        );
+        assert_eq!(detect_clocks(&serialized), vec!["clk"]);
+        assert_eq!(detect_resets(&serialized), vec!["rst_n"]);
     }
 
     #[test]
-    fn compile_module_instance() {
+    fn symbolic_interpret_registers() {
         let mut egraph = EGraph::default();
-        import_churchroad(&mut egraph);
-
+        import_churchroad(&mut egraph).unwrap();
         egraph
             .parse_and_run_program(
                 r#"
-                (let a (Var "a" 8))
-                (IsPort "" "a" (Input) a)
-                (let b (Var "b" 8))
-                (IsPort "" "b" (Input) b)
-                (IsPort "" "out" (Output) (GetOutput (ModuleInstance "some_module" (StringCons "p" (StringNil)) (ExprCons (Op0 (BV 4 4)) (ExprNil)) (StringCons "a" (StringCons "b" (StringNil))) (ExprCons a (ExprCons b (ExprNil)))) "out"))
+                (let clk (Var "clk" 1))
+                (let sel (Var "sel" 1))
+
+                ; Always holds 3, whatever sel is.
+                (let held (Wire "held" 4))
+                (union held (Op2 (Reg 3) clk (Op3 (Mux) sel held (Op0 (BV 3 4)))))
+                (IsPort "" "held" (Output) held)
+
+                ; Alternates between 1 and 2.
+                (let toggle (Wire "toggle" 4))
+                (union toggle (Op2 (Reg 1) clk (Op2 (Sub) (Op0 (BV 3 4)) toggle)))
+                (IsPort "" "toggle" (Output) toggle)
+
+                ; Counts forever.
+                (let count (Wire "count" 4))
+                (union count (Op2 (Reg 0) clk (Op2 (Add) count (Op0 (BV 1 4)))))
+                (IsPort "" "count" (Output) count)
+
+                (run-schedule (saturate typing))
             "#,
             )
             .unwrap();
-
+        auto_delete_wires(&mut egraph);
         let serialized = egraph.serialize(SerializeConfig::default());
-        let out = AnythingExtractor.extract(&serialized, &[]);
+        let (_, outputs) = get_inputs_and_outputs_serialized(&serialized);
+        let output = |name: &str| outputs.iter().find(|(n, _)| n == name).unwrap().1.clone();
 
+        let env = HashMap::default();
         assert_eq!(
-            "module top(
-  
-  input [8-1:0] a,
-  input [8-1:0] b,
-  
-  output out,
-);
-  logic out = wire_27;
-  logic wire_27;
-  logic [4-1:0] wire_19 = 4'd4;
-  logic [8-1:0] wire_13 = b;
-  logic [8-1:0] wire_10 = a;
-  
-
-  some_module #(
-    .p(wire_19)
-) module_26 (
-    .a(wire_10),
-    .b(wire_13),
-    .out(wire_27));
-endmodule",
-            to_verilog_egraph_serialize(&serialized, &out, "")
+            symbolic_interpret(&serialized, &output("held"), &env),
+            Ok(BitvectorDomain::Concrete(3))
+        );
+        assert_eq!(
+            symbolic_interpret(&serialized, &output("toggle"), &env),
+            Ok(BitvectorDomain::Range(1, 2))
+        );
+        assert_eq!(
+            symbolic_interpret(&serialized, &output("count"), &env),
+            Ok(BitvectorDomain::AnyValue)
         );
     }
 
     #[test]
-    fn get_inputs_and_outputs_with_cycle() {
+    fn diff_egraphs_module_enumeration() {
         let mut egraph = EGraph::default();
-        import_churchroad(&mut egraph);
+        import_churchroad(&mut egraph).unwrap();
+        egraph
+            .parse_and_run_program(r#"(let anded (Op2 (And) (Var "a" 1) (Var "b" 1)))"#)
+            .unwrap();
+        let before = egraph.serialize(SerializeConfig::default());
+
+        egraph
+            .parse_and_run_program("(run-schedule (repeat 1 enumerate-modules))")
+            .unwrap();
+        let after = egraph.serialize(SerializeConfig::default());
+
+        let diff = diff_egraphs(&before, &after);
+        assert!(!diff.is_empty());
+        assert!(diff
+            .new_enodes
+            .iter()
+            .any(|id| after[id].op == "MakeModule"));
+        // The design itself is unchanged.
+        assert!(diff
+            .new_enodes
+            .iter()
+            .all(|id| !matches!(after[id].op.as_str(), "Var" | "And")));
+        assert_eq!(
+            diff.unchanged_enodes + diff.new_enodes.len(),
+            after.nodes.len()
+        );
+
+        // Nothing changes when nothing runs.
+        assert!(diff_egraphs(&after, &after).is_empty());
+    }
 
+    #[test]
+    fn ports_of_multiple_modules() {
+        let mut egraph = EGraph::default();
+        import_churchroad(&mut egraph).unwrap();
         egraph
             .parse_and_run_program(
                 r#"
-                (let placeholder (Wire "placeholder" 8))
-                (let reg (Op1 (Reg 0) placeholder))
-                (union placeholder reg)
-                (delete (Wire "placeholder" 8))
-                (IsPort "" "out" (Output) reg)
+                (let a (Var "a" 4))
+                (let b (Var "b" 4))
+                (IsPort "adder" "a" (Input) a)
+                (IsPort "adder" "b" (Input) b)
+                (IsPort "adder" "sum" (Output) (Op2 (Add) a b))
+
+                (let x (Var "x" 4))
+                (IsPort "inverter" "x" (Input) x)
+                (IsPort "inverter" "y" (Output) (Op1 (Not) x))
+
+                (run-schedule (saturate typing))
             "#,
             )
             .unwrap();
 
-        get_inputs_and_outputs_serialized(&egraph.serialize(SerializeConfig::default()));
+        let mut names = |module| {
+            get_ports(&mut egraph, Some(module))
+                .unwrap()
+                .into_iter()
+                .map(|port| port.name)
+                .collect::<HashSet<_>>()
+        };
+        let adder = names("adder");
+        let inverter = names("inverter");
+        assert_eq!(adder, ["a", "b", "sum"].map(String::from).into());
+        assert_eq!(inverter, ["x", "y"].map(String::from).into());
+        assert!(adder.is_disjoint(&inverter));
+        assert_eq!(get_ports(&mut egraph, None).unwrap().len(), 5);
+
+        let serialized = egraph.serialize(SerializeConfig::default());
+        let choices = AnythingExtractor.extract(&serialized, &[]);
+        let verilog = to_verilog_egraph_serialize_module(&serialized, &choices, "adder", "");
+        assert!(verilog.contains("module adder("));
+        assert_eq!(verilog_port_names(&verilog), vec!["a", "b", "sum"]);
     }
 }
@@ -0,0 +1,285 @@
+//! Helpers for checking Churchroad designs against Verilator, for use in
+//! tests. These need `verilator` on the `PATH`, and panic when a check fails.
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    fs,
+    io::Write as IOWrite,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+use crate::{
+    get_bitwidth, get_inputs_and_outputs_serialized, to_verilog_egraph_serialize,
+    AnythingExtractor, Interpreter, InterpreterResult, VerilogTestbench,
+};
+/// Differential tester for the Verilog backend: emits `egraph` as Verilog with
+/// [`to_verilog_egraph_serialize`] and checks the emitted Verilog, simulated in
+/// Verilator, against the stateful [`Interpreter`] running on `egraph` itself.
+///
+/// Both are driven with `num_vectors` random test vectors, each a sequence of
+/// `num_cycles` cycles of input values. Vectors are run back to back, without
+/// resetting state in between. Registers are assumed to be clocked by an input
+/// named "clk", which rises halfway through each cycle; outputs are compared
+/// before it rises. Panics at the first mismatch, reporting the values of all
+/// inputs and outputs.
+pub fn differential_test(egraph: &egraph_serialize::EGraph, num_vectors: usize, num_cycles: usize) {
+    static NEXT_TEST_ID: AtomicUsize = AtomicUsize::new(0);
+    let test_output_dir = std::env::temp_dir().join(format!(
+        "churchroad_differential_test_{}_{}",
+        std::process::id(),
+        NEXT_TEST_ID.fetch_add(1, Ordering::SeqCst)
+    ));
+    fs::create_dir_all(&test_output_dir).unwrap();
+
+    let (inputs, outputs) = get_inputs_and_outputs_serialized(egraph);
+    let has_clk = inputs.iter().any(|(name, _)| name == "clk");
+    let inputs: Vec<(&str, i32)> = inputs
+        .iter()
+        .filter(|(name, _)| name != "clk")
+        .map(|(name, class_id)| {
+            let var = egraph[class_id]
+                .nodes
+                .iter()
+                .map(|node_id| &egraph[node_id])
+                .find(|node| node.op == "Var")
+                .unwrap();
+            (name.as_str(), egraph[&var.children[1]].op.parse().unwrap())
+        })
+        .collect();
+    let output_bitwidths: Vec<(&str, i32)> = outputs
+        .iter()
+        .map(|(name, class_id)| {
+            let bw = get_bitwidth(egraph, class_id).unwrap();
+            (name.as_str(), bw.try_into().unwrap())
+        })
+        .collect();
+
+    let mut rng = StdRng::seed_from_u64(0xb0bacafe);
+    let input_values: Vec<Vec<u64>> = (0..num_vectors * num_cycles)
+        .map(|_| {
+            inputs
+                .iter()
+                .map(|(_, bw)| {
+                    assert!(*bw <= 64);
+                    rng.next_u64()
+                        & ((1u64.checked_shl((*bw).try_into().unwrap()).unwrap_or(0))
+                            .wrapping_sub(1))
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut interpreter = Interpreter::new(egraph, outputs.clone()).unwrap();
+    let interpreter_results: Vec<Vec<u64>> = input_values
+        .iter()
+        .map(|values| {
+            let env: HashMap<String, u64> = inputs
+                .iter()
+                .zip(values.iter())
+                .map(|((name, _), value)| (name.to_string(), *value))
+                .collect();
+            let results = interpreter.step(&env);
+            outputs
+                .iter()
+                .map(|(name, _)| {
+                    let InterpreterResult::Bitvector(val, _) = results[name];
+                    val
+                })
+                .collect()
+        })
+        .collect();
+
+    let choices = AnythingExtractor.extract(egraph, &[]);
+    let verilog_module_path = test_output_dir.join("top.sv");
+    fs::write(
+        &verilog_module_path,
+        to_verilog_egraph_serialize(egraph, &choices, "clk"),
+    )
+    .unwrap();
+
+    // Each cycle takes two timesteps, with the clock (if any) rising between
+    // them.
+    let verilator_inputs: Vec<(&str, i32)> = has_clk
+        .then_some(("clk", 1))
+        .into_iter()
+        .chain(inputs.iter().cloned())
+        .collect();
+    let test_vectors = vec![input_values
+        .iter()
+        .flat_map(|values| {
+            [0, 1].into_iter().map(move |clk| {
+                has_clk
+                    .then_some(clk)
+                    .into_iter()
+                    .chain(values.iter().cloned())
+                    .collect::<Vec<u64>>()
+            })
+        })
+        .collect::<Vec<_>>()];
+    let verilator_output_values = run_verilator(
+        "top",
+        verilator_inputs,
+        output_bitwidths,
+        test_vectors,
+        vec![test_output_dir.clone()],
+        test_output_dir.clone(),
+        verilog_module_path.clone(),
+    );
+    assert_eq!(
+        verilator_output_values.len(),
+        2 * num_vectors * num_cycles * outputs.len()
+    );
+
+    for (cycle, (values, interpreter_values)) in input_values
+        .iter()
+        .zip(interpreter_results.iter())
+        .enumerate()
+    {
+        let verilator_values =
+            &verilator_output_values[2 * cycle * outputs.len()..(2 * cycle + 1) * outputs.len()];
+        if interpreter_values.as_slice() != verilator_values {
+            panic!(
+                "mismatch in test vector {} at clock cycle {}\ninputs: {:?}\ninterpreter outputs: {:?}\nverilator outputs: {:?}\nemitted Verilog: {}",
+                cycle / num_cycles,
+                cycle % num_cycles,
+                inputs.iter().map(|(name, _)| name).zip(values).collect::<Vec<_>>(),
+                outputs.iter().map(|(name, _)| name).zip(interpreter_values).collect::<Vec<_>>(),
+                outputs.iter().map(|(name, _)| name).zip(verilator_values).collect::<Vec<_>>(),
+                verilog_module_path.to_str().unwrap(),
+            );
+        }
+    }
+}
+
+/// Simulate the module `top_module_name` in `verilog_module_path` with
+/// Verilator, using a [`VerilogTestbench`], and return the value of each output
+/// at each step of each test case, in order.
+///
+/// inputs: list of tuples of input names and their bitwidths
+/// outputs: list of tuples of output names and their bitwidths
+/// include_dirs: list of directories to include in the Verilator compilation
+/// test_output_dir: directory to output test files to
+/// test_vectors: A vector of vectors of vectors. the ith entry in the outermost
+///   vector contains the inputs for the ith test case. The jth entry in the ith
+///   test case are the inputs at clock cycle j. The kth entry in the jth set of
+///   inputs is the value of the kth input at clock cycle j, where the inputs are
+///   ordered as they appear in the inputs vector.
+pub fn run_verilator(
+    top_module_name: &str,
+    inputs: Vec<(&str, i32)>,
+    outputs: Vec<(&str, i32)>,
+    test_vectors: Vec<Vec<Vec<u64>>>,
+    include_dirs: Vec<PathBuf>,
+    test_output_dir: PathBuf,
+    verilog_module_path: PathBuf,
+) -> Vec<u64> {
+    let testbench_path = test_output_dir.join("testbench.sv");
+
+    // just grab the filename without any leading directories
+    let filename = verilog_module_path.file_name().unwrap().to_str().unwrap();
+
+    let mut testbench = VerilogTestbench::new(top_module_name);
+    testbench.include(filename);
+    for (name, bw) in &inputs {
+        testbench.add_input(name, (*bw).try_into().unwrap());
+    }
+    for (name, bw) in &outputs {
+        testbench.add_output(name, (*bw).try_into().unwrap());
+    }
+    let testbench_prog = testbench.generate();
+
+    let executable_name = "executable";
+    let verilator_output_dir = test_output_dir.join("obj_dir");
+    let executable_path = verilator_output_dir.join(executable_name);
+
+    std::fs::write(&testbench_path, &testbench_prog).unwrap();
+
+    // TODO(@ninehusky): We can get rid of the necessity for a Makefile after this PR is merged
+    // into Verilator: https://github.com/verilator/verilator/pull/5031
+    let verilator_compile_output = std::process::Command::new("verilator")
+        .arg("-o")
+        .arg(executable_name)
+        .arg("-Wno-WIDTHTRUNC")
+        .arg("--assert")
+        .arg("--timing")
+        .arg("--binary")
+        .arg("--build")
+        .arg("--Mdir")
+        .arg(&verilator_output_dir)
+        .args(
+            include_dirs
+                .iter()
+                .map(|path| format!("-I{}", path.to_str().unwrap())),
+        )
+        .arg(testbench_path.to_str().unwrap())
+        .output()
+        .unwrap();
+
+    if !verilator_compile_output.status.success() {
+        panic!(
+            "Verilator failed, stderr: {:?}",
+            String::from_utf8(verilator_compile_output.stderr)
+        );
+    }
+
+    let mut sim_proc = std::process::Command::new(executable_path)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let num_inputs = inputs.len();
+
+    let num_test_cases = test_vectors.len();
+    let num_clock_cycles = if num_test_cases > 0 {
+        test_vectors[0].len()
+    } else {
+        0
+    };
+    assert!(test_vectors.iter().all(|v| v.len() == num_clock_cycles));
+    assert!(test_vectors
+        .iter()
+        .all(|v| v.iter().all(|inputs| inputs.len() == num_inputs)));
+
+    let mut inputs_str = String::new();
+    inputs_str
+        .write_str(&format!(
+            "{} {} {}\n",
+            num_inputs, num_test_cases, num_clock_cycles
+        ))
+        .unwrap();
+
+    for test_case in test_vectors.iter() {
+        for inputs in test_case.iter() {
+            for input in inputs.iter() {
+                inputs_str.write_str(&format!("{:X}\n", input)).unwrap();
+            }
+        }
+    }
+
+    sim_proc
+        .stdin
+        .as_mut()
+        .unwrap()
+        .write_all(inputs_str.as_bytes())
+        .unwrap();
+
+    fs::write(test_output_dir.join("inputs.txt"), inputs_str).unwrap();
+
+    let output = sim_proc.wait_with_output().unwrap();
+    let output_str = String::from_utf8(output.stdout).unwrap();
+    let verilator_output_values: Vec<u64> = output_str
+        .lines()
+        // filter all lines that don't start with "output: "
+        .filter(|line| line.starts_with("output: "))
+        .map(|line| line.trim_start_matches("output: ").parse().unwrap())
+        .collect();
+
+    fs::write(test_output_dir.join("output.txt"), output_str).unwrap();
+
+    verilator_output_values
+}
@@ -13,7 +13,7 @@ macro_rules! egglog_test {
         #[test]
         fn $name() {
             let mut egraph = egglog::EGraph::default();
-            churchroad::import_churchroad(&mut egraph);
+            churchroad::import_churchroad(&mut egraph).unwrap();
             egraph
                 .parse_and_run_program(
                     &std::fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join($path))
@@ -191,7 +191,7 @@ egglog_test!(half_adder, "tests/egglog_tests/half_adder.egg");
 #[test]
 fn antiunify() {
     let mut egraph = egglog::EGraph::default();
-    churchroad::import_churchroad(&mut egraph);
+    churchroad::import_churchroad(&mut egraph).unwrap();
 
     egraph
         .parse_and_run_program(
@@ -206,8 +206,7 @@ fn antiunify() {
 (check 
  (= 
   const
-  ; The (vec-pop (vec-of ...)) thing is a hack, should be removable in the future.
-  (apply (MakeModule (Op0_ (BV 23 8)) (vec-pop (vec-of 0))) (vec-pop (vec-of (Var "unused" 0))))))
+  (apply (MakeModule (Op0_ (BV 23 8)) (vec-empty)) (vec-empty))))
 
 
 (let not (Op1 (Not) (Var "x" 8)))
@@ -254,10 +253,87 @@ fn antiunify() {
     // serialized.to_svg_file(svg_path).unwrap();
 }
 
+#[test]
+fn debruijnify_repeated_values() {
+    let mut egraph = egglog::EGraph::default();
+    churchroad::import_churchroad(&mut egraph).unwrap();
+
+    // x appears at indices 0, 5 and 10.
+    egraph
+        .parse_and_run_program(
+            r#"
+(let out (debruijnify (vec-of (Var "x" 8) (Var "a" 8) (Var "b" 8) (Var "c" 8) (Var "d" 8) (Var "x" 8) (Var "e" 8) (Var "a" 8) (Var "f" 8) (Var "g" 8) (Var "x" 8))))
+(check (= out (vec-of 0 1 2 3 4 0 5 1 6 7 0)))
+    "#,
+        )
+        .unwrap();
+}
+
+#[test]
+fn debruijnify_numbers_by_first_occurrence() {
+    let mut egraph = egglog::EGraph::default();
+    churchroad::import_churchroad(&mut egraph).unwrap();
+
+    // The values are created in the opposite order to the one in which they
+    // appear in the vector, but numbered in the order they appear.
+    egraph
+        .parse_and_run_program(
+            r#"
+(let z (Var "z" 8))
+(let y (Var "y" 8))
+(let x (Var "x" 8))
+(let out (debruijnify (vec-of x y x z)))
+(check (= out (vec-of 0 1 0 2)))
+(let out2 (debruijnify (vec-of z x z y)))
+(check (= out2 (vec-of 0 1 0 2)))
+(union y z)
+(let out3 (debruijnify (vec-of x y x z)))
+(check (= out3 (vec-of 0 1 0 1)))
+    "#,
+        )
+        .unwrap();
+}
+
+#[test]
+fn bench_debruijnify_large_input() {
+    let mut egraph = egglog::EGraph::default();
+    churchroad::import_churchroad(&mut egraph).unwrap();
+
+    // 100 arguments drawn from 10 distinct values.
+    let args = (0..100)
+        .map(|i| format!("(Var \"v{}\" 8)", i % 10))
+        .collect::<Vec<_>>()
+        .join(" ");
+    egraph
+        .parse_and_run_program(&format!("(let args (vec-of {}))", args))
+        .unwrap();
+
+    // Debug builds are much slower, so only hold them to a loose bound.
+    let limit = if cfg!(debug_assertions) {
+        std::time::Duration::from_millis(50)
+    } else {
+        std::time::Duration::from_millis(1)
+    };
+    let start = std::time::Instant::now();
+    egraph
+        .parse_and_run_program("(let out (debruijnify args))")
+        .unwrap();
+    let elapsed = start.elapsed();
+    assert!(elapsed < limit, "debruijnify took {:?}", elapsed);
+
+    let expected = (0..100)
+        .map(|i| (i % 10).to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+    egraph
+        .parse_and_run_program(&format!("(check (= out (vec-of {})))", expected))
+        .unwrap();
+}
+
 #[test]
 fn antiunify_permuter() {
     let mut egraph = egglog::EGraph::default();
-    churchroad::import_churchroad(&mut egraph);
+    churchroad::import_churchroad(&mut egraph).unwrap();
 
     egraph
         .parse_and_run_program(
@@ -288,7 +364,7 @@ fn antiunify_permuter() {
 #[test]
 fn find_loop() {
     let mut egraph = egglog::EGraph::default();
-    churchroad::import_churchroad(&mut egraph);
+    churchroad::import_churchroad(&mut egraph).unwrap();
 
     egraph
         .parse_and_run_program(
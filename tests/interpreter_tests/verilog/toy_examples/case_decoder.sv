@@ -0,0 +1,26 @@
+// Yosys turns the case statement into a $pmux and the variable bit select
+// into a $shiftx. Neither needs pmuxtree to be imported.
+module case_decoder (
+    input logic [2:0] op,
+    input logic [7:0] a,
+    input logic [7:0] b,
+    input logic [63:0] mem,
+    input logic [5:0] idx,
+    output logic [7:0] out,
+    output logic bit_out
+);
+  always_comb begin
+    case (op)
+      3'd0: out = a + b;
+      3'd1: out = a - b;
+      3'd2: out = a & b;
+      3'd3: out = a | b;
+      3'd4: out = a ^ b;
+      3'd5: out = ~a;
+      3'd6: out = a >> b[2:0];
+      default: out = b;
+    endcase
+  end
+
+  assign bit_out = mem[idx];
+endmodule
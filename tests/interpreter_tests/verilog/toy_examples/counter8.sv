@@ -0,0 +1,14 @@
+// timescale  1 ps / 1 ps
+
+module counter8(
+    input logic clk,
+    output logic [7:0] count
+);
+    initial count = 8'd250;
+
+    always @(posedge clk) begin
+        begin
+            count <= count + 1;
+        end
+    end
+endmodule
@@ -0,0 +1,11 @@
+module pipeline(
+    input logic clk,
+    input logic [7:0] in,
+    output logic [7:0] out
+);
+    logic [7:0] stage0;
+    always @(posedge clk) begin
+        stage0 <= in;
+        out <= stage0;
+    end
+endmodule
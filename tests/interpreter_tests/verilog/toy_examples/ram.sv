@@ -0,0 +1,26 @@
+// timescale  1 ps / 1 ps
+
+// Single-port RAM with an asynchronous, read-before-write read port.
+module ram(
+    input logic clk,
+    input logic we,
+    input logic [3:0] addr,
+    input logic [7:0] din,
+    output logic [7:0] dout
+);
+    logic [7:0] mem [0:15];
+
+    initial begin
+        for (int i = 0; i < 16; i++) begin
+            mem[i] = 8'd0;
+        end
+    end
+
+    always @(posedge clk) begin
+        if (we) begin
+            mem[addr] <= din;
+        end
+    end
+
+    assign dout = mem[addr];
+endmodule
@@ -0,0 +1,9 @@
+module signed_mul (
+    input logic signed [7:0] a,
+    input logic signed [7:0] b,
+    output logic signed [7:0] out,
+    output logic lt
+);
+        assign out = a * b;
+        assign lt = a < b;
+endmodule
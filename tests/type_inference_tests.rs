@@ -9,7 +9,7 @@ macro_rules! type_inference_test {
         #[test]
         fn $test_name() {
             let mut egraph = EGraph::default();
-            import_churchroad(&mut egraph);
+            import_churchroad(&mut egraph).unwrap();
             egraph.parse_and_run_program($churchroad_src).unwrap();
             egraph
                 .parse_and_run_program("(run-schedule (saturate typing))")
@@ -177,3 +177,96 @@ type_inference_test!(
     (let v1 (Op1 (Extract 1 1) v0))
     "#
 );
+
+type_inference_test!(
+    reg_with_clk_type_test,
+    r#"
+    (let v0 (Wire "a" 8))
+    (let clk (Wire "clk" 1))
+    (let v1 (Op2 (RegWithClk 0) clk v0))
+    "#
+);
+
+type_inference_test!(
+    sign_extend_type_test,
+    r#"
+    (let v0 (Wire "a" 8))
+    (let v1 (Op1 (SignExtend 16) v0))
+    "#
+);
+
+type_inference_test!(
+    shr_narrow_shift_amount_type_test,
+    r#"
+    (let v0 (Wire "a" 8))
+    (let v1 (Wire "b" 3))
+    (let v2 (Op2 (Shr) v0 v1))
+    "#
+);
+
+type_inference_test!(
+    reg_op1_type_test,
+    r#"
+    (let v0 (Wire "a" 8))
+    (let v1 (Op1 (Reg 0) v0))
+    "#
+);
+
+type_inference_test!(
+    get_output_type_test,
+    r#"
+    (ModuleOutputBitwidth "some_module" "out" 8)
+    (let v0 (Wire "a" 8))
+    (let v1 (Op2 (And) v0 (GetOutput (ModuleInstance "some_module" (StringNil) (ExprNil) (StringCons "a" (StringNil)) (ExprCons v0 (ExprNil))) "out")))
+    "#
+);
+
+type_inference_test!(
+    mux_type_test,
+    r#"
+    (let v0 (Wire "sel" 1))
+    (let v1 (Wire "a" 8))
+    (let v2 (Wire "b" 8))
+    (let v3 (Op3 (Mux) v0 v1 v2))
+    "#
+);
+
+type_inference_test!(
+    lut_type_test,
+    r#"
+    (let v0 (Wire "a" 4))
+    (let v1 (Op1 (LUT 4 32768) v0))
+    "#
+);
+
+type_inference_test!(
+    signed_bv_type_test,
+    r#"
+    (let v0 (Op0 (SignedBV -1 8)))
+    (let v1 (Op0 (SignedBV 3 8)))
+    (let v2 (Op2 (Sub) v0 v1))
+    "#
+);
+
+type_inference_test!(
+    pmux_type_test,
+    r#"
+    (let v0 (Wire "s" 3))
+    (let v1 (Wire "a" 8))
+    (let v2 (Wire "b" 24))
+    (let v3 (Op3 (Pmux) v0 v1 v2))
+    "#
+);
+
+type_inference_test!(
+    signed_ops_type_test,
+    r#"
+    (let v0 (Wire "a" 8))
+    (let v1 (Wire "b" 8))
+    (let v2 (Wire "c" 3))
+    (let v3 (Op2 (SMul) v0 v1))
+    (let v4 (Op2 (Ashr) v3 v2))
+    (let v5 (Op2 (SLt) v4 v0))
+    (let v6 (Op1 (SignExtend 16) v4))
+    "#
+);
@@ -31,11 +31,52 @@
 USING_YOSYS_NAMESPACE
 PRIVATE_NAMESPACE_BEGIN
 
+// The name a module is written under. Modules which `hierarchy` derives for
+// particular parameter values are named like `$paramod\add2\W=s32'...`,
+// which is neither a Verilog nor an egglog identifier, so everything but
+// letters, digits and underscores becomes an underscore.
+static std::string module_id(RTLIL::IdString name)
+{
+	std::string id = RTLIL::unescape_id(name);
+	for (auto &c : id)
+		if (!isalnum((unsigned char)c) && c != '_')
+			c = '_';
+	return id;
+}
+
+// The bits of `value`, x and z bits being 0. Churchroad's constants hold an
+// i64, so `value` can be at most 64 bits wide.
+static uint64_t const_bits(const RTLIL::Const &value)
+{
+	if (GetSize(value) > 64)
+		log_error("Constant %s is wider than 64 bits.\n", log_const(value));
+	uint64_t bits = 0;
+	for (int i = 0; i < GetSize(value); i++)
+		if (value.bits[i] == State::S1)
+			bits |= uint64_t(1) << i;
+	return bits;
+}
+
+// The Churchroad constant with the bits of `value`. Constants wider than 64
+// bits are written as the concatenation of their upper bits and their low 64
+// bits.
+static std::string bv_expr(const RTLIL::Const &value)
+{
+	if (GetSize(value) > 64)
+		return stringf("(Op2 (Concat) %s %s)", bv_expr(value.extract(64, GetSize(value) - 64)).c_str(),
+			       bv_expr(value.extract(0, 64)).c_str());
+	return stringf("(Op0 (BV %lld %d))", (long long)const_bits(value), GetSize(value));
+}
+
 struct LakeroadWorker
 {
 	std::ostream &f;
 	SigMap sigmap;
 	RTLIL::Module *module;
+	// Prefixed to the names of lets and Wires, so that the programs for
+	// several modules can be run in one egraph. Empty when only the top module
+	// is written.
+	std::string prefix;
 	bool verbose;
 	bool single_bad;
 	bool cover_mode;
@@ -1263,7 +1304,7 @@ struct LakeroadWorker
 		return nid;
 	}
 
-	LakeroadWorker(std::ostream &f, RTLIL::Module *module) : f(f), sigmap(module), module(module) {}
+	LakeroadWorker(std::ostream &f, RTLIL::Module *module, std::string prefix = "") : f(f), sigmap(module), module(module), prefix(prefix) {}
 
 	void run()
 	{
@@ -1273,7 +1314,7 @@ struct LakeroadWorker
 		// IDs used to generate let expressions.
 		int id = 0;
 		auto get_new_id_str = [&]()
-		{ return stringf("v%d", id++); };
+		{ return stringf("%sv%d", prefix.c_str(), id++); };
 
 		// Get signal name.
 		auto get_signal_name = [&](const SigSpec &sig)
@@ -1353,7 +1394,7 @@ struct LakeroadWorker
 			if (sig.is_fully_const())
 			{
 				// If the signal is a constant, we can just use the constant.
-				auto const_str = stringf("(Op0 (BV %d %d))", Const(sig.as_const()).as_int(), sig.size());
+				auto const_str = bv_expr(sig.as_const());
 				auto new_id = get_new_id_str();
 				auto let_expr = let(new_id, const_str);
 				auto signal_name = get_signal_name(sig);
@@ -1443,10 +1484,87 @@ struct LakeroadWorker
 			get_expression_for_signal(sigmap(wire), -1);
 		}
 
+		// Handle memories. Their cells are skipped below.
+		f << "\n; memories\n";
+		for (auto &mem : Mem::get_all_memories(module))
+		{
+			auto mem_name = log_id(mem.memid);
+			if (mem.start_offset != 0)
+				log_error("Memory %s.%s doesn't start at address 0.\n", log_id(module), mem_name);
+			if (mem.wr_ports.size() > 1)
+				log_error("Memory %s.%s has %d write ports, but Churchroad doesn't model the priority between write ports.\n",
+									log_id(module), mem_name, GetSize(mem.wr_ports));
+
+			// Churchroad memories start with the same value in every word.
+			Const init = mem.get_init_data();
+			auto init_value = const_bits(init.extract(0, mem.width));
+			for (int i = 1; i < mem.size; i++)
+				if (const_bits(init.extract(i * mem.width, mem.width)) != init_value)
+					log_error("Memory %s.%s has different initial values in different words, which Churchroad doesn't model.\n",
+										log_id(module), mem_name);
+
+			// Clocks on the negative edge are inverted.
+			auto clock = [&](const SigSpec &clk, bool polarity)
+			{
+				auto clk_let_name = get_expression_for_signal(sigmap(clk), -1);
+				return polarity ? clk_let_name : stringf("(Op1 (Not) %s)", clk_let_name.c_str());
+			};
+
+			auto mem_let_name = get_new_id_str();
+			f << "; " << mem_name << "\n";
+			f << let(mem_let_name, stringf("(Op0 (Mem \"%s%s\" %d %d %lld))", prefix.c_str(), RTLIL::unescape_id(mem.memid).c_str(),
+																		 ceil_log2(mem.size), mem.width, (long long)init_value))
+				<< "\n";
+
+			for (auto &port : mem.wr_ports)
+			{
+				if (!port.clk_enable)
+					log_error("Memory %s.%s has an asynchronous write port.\n", log_id(module), mem_name);
+				if (port.wide_log2 > 0)
+					log_error("Memory %s.%s has a wide write port.\n", log_id(module), mem_name);
+				// Churchroad enables a write of the whole word.
+				for (auto bit : port.en)
+					if (sigmap(bit) != sigmap(port.en[0]))
+						log_error("Memory %s.%s has a write port with per-bit enables.\n", log_id(module), mem_name);
+
+				f << stringf("(MemWrite %s %s %s %s %s)\n", mem_let_name.c_str(), clock(port.clk, port.clk_polarity).c_str(),
+										 get_expression_for_signal(sigmap(port.addr), -1).c_str(), get_expression_for_signal(sigmap(port.data), -1).c_str(),
+										 get_expression_for_signal(sigmap(SigSpec(port.en[0])), -1).c_str());
+			}
+
+			for (auto &port : mem.rd_ports)
+			{
+				if (port.wide_log2 > 0)
+					log_error("Memory %s.%s has a wide read port.\n", log_id(module), mem_name);
+				bool transparent = false;
+				for (auto bit : port.transparency_mask)
+					transparent |= bit;
+
+				auto read = stringf("(Op2 (MemRead (%s)) %s %s)", transparent ? "WriteFirst" : "ReadFirst", mem_let_name.c_str(),
+														get_expression_for_signal(sigmap(port.addr), -1).c_str());
+				if (port.clk_enable)
+				{
+					// A synchronous read is an asynchronous read into a register, which
+					// sees the data being written if the port is transparent.
+					if (!port.en.is_fully_ones() || !port.arst.is_fully_zero() || !port.srst.is_fully_zero())
+						log_error("Memory %s.%s has a read port with an enable or a reset.\n", log_id(module), mem_name);
+					read = stringf("(Op2 (Reg %lld) %s %s)", (long long)const_bits(port.init_value), clock(port.clk, port.clk_polarity).c_str(),
+												 read.c_str());
+				}
+				else if (transparent)
+					log_error("Memory %s.%s has an asynchronous read port with transparency, which Churchroad doesn't model.\n",
+										log_id(module), mem_name);
+
+				f << stringf("(union %s %s)\n", get_expression_for_signal(sigmap(port.data), -1).c_str(), read.c_str());
+			}
+		}
+
 		// Handle cells
 		f << "\n; cells\n";
 		for (auto cell : module->cells())
 		{
+			if (cell->is_mem_cell())
+				continue;
 
 			if (cell->type.in(ID($logic_not), ID($not), ID($reduce_or), ID($reduce_bool), ID($reduce_and), ID($reduce_xor)))
 			{
@@ -1493,7 +1611,69 @@ struct LakeroadWorker
 
 				f << stringf("(union %s (Op1 %s %s))\n", y_let_name.c_str(), op_str.c_str(), a_let_name.c_str()).c_str();
 			}
-			else if (cell->type.in(ID($and), ID($or), ID($xor), ID($shr), ID($add), ID($shiftx), ID($mul), ID($sub)))
+			else if ((cell->type.in(ID($mul), ID($lt), ID($le), ID($gt), ID($ge)) && cell->getParam(ID::A_SIGNED).as_bool() &&
+								cell->getParam(ID::B_SIGNED).as_bool()) ||
+							 (cell->type == ID($sshr) && cell->getParam(ID::A_SIGNED).as_bool()))
+			{
+				// Signed ops. Signed operands are sign-extended to a common width,
+				// while the shift amount of $sshr is unsigned and left as it is.
+				assert(cell->connections().size() == 3);
+				auto a = sigmap(cell->getPort(ID::A));
+				auto b = sigmap(cell->getPort(ID::B));
+				auto y = sigmap(cell->getPort(ID::Y));
+				bool is_comparison = cell->type.in(ID($lt), ID($le), ID($gt), ID($ge));
+
+				int width;
+				if (is_comparison)
+					width = std::max(a.size(), b.size());
+				else if (cell->type == ID($sshr))
+					width = std::max(a.size(), y.size());
+				else
+					width = std::max(a.size(), std::max(b.size(), y.size()));
+
+				auto sign_extend = [&](const SigSpec &sig, int to_width)
+				{
+					auto expr = get_expression_for_signal(sig, -1);
+					if (to_width <= GetSize(sig))
+						return expr;
+					auto new_id = get_new_id_str();
+					f << let(new_id, stringf("(Op1 (SignExtend %d) %s)", to_width, expr.c_str())) << "\n";
+					return new_id;
+				};
+
+				auto a_let_name = sign_extend(a, width);
+				auto b_let_name = cell->type == ID($sshr) ? get_expression_for_signal(b, -1) : sign_extend(b, width);
+				auto y_let_name = get_expression_for_signal(y, -1);
+
+				std::string op_str;
+				if (cell->type == ID($mul))
+					op_str = "(SMul)";
+				else if (cell->type == ID($sshr))
+					op_str = "(Ashr)";
+				else if (cell->type == ID($lt))
+					op_str = "(SLt)";
+				else if (cell->type == ID($le))
+					op_str = "(SLe)";
+				else if (cell->type == ID($gt))
+					op_str = "(SGt)";
+				else if (cell->type == ID($ge))
+					op_str = "(SGe)";
+				else
+					log_error("This should be unreachable. You are missing an else if branch.\n");
+
+				op_str = stringf("(Op2 %s %s %s)", op_str.c_str(), a_let_name.c_str(), b_let_name.c_str());
+
+				// Comparisons give one bit, which Yosys may zero-extend. Other results
+				// may need slicing down to the output width.
+				int result_width = is_comparison ? 1 : width;
+				if (y.size() < result_width)
+					op_str = stringf("(Op1 (Extract %d %d) %s)", y.size() - 1, 0, op_str.c_str());
+				else if (y.size() > result_width)
+					op_str = stringf("(Op1 (ZeroExtend %d) %s)", y.size(), op_str.c_str());
+
+				f << stringf("(union %s %s)\n", y_let_name.c_str(), op_str.c_str()).c_str();
+			}
+			else if (cell->type.in(ID($and), ID($or), ID($xor), ID($shl), ID($shr), ID($add), ID($shiftx), ID($mul), ID($sub)))
 			{
 				// Assert that A and B are both unsigned. Note that this is a
 				// simplifying assumption. It does not have to be true, but supporting
@@ -1518,6 +1698,8 @@ struct LakeroadWorker
 					op_str = "(Or)";
 				else if (cell->type == ID($xor))
 					op_str = "(Xor)";
+				else if (cell->type == ID($shl))
+					op_str = "(Shl)";
 				// Here, $shr and $shiftx are treated the same.
 				// This is only true because we've asserted that A and B are unsigned.
 				// See #26:
@@ -1637,9 +1819,19 @@ struct LakeroadWorker
 			}
 			else if (cell->type == ID($pmux))
 			{
-				// Don't support $pmux: require them to run pmuxtree instead.
-				log_error("Unsupported cell type %s for cell %s.%s -- please run `pmuxtree` before `write_lakeroad`.\n",
-									log_id(cell->type), log_id(module), log_id(cell));
+				assert(cell->connections().size() == 4);
+				auto y = sigmap(cell->getPort(ID::Y));
+				auto s = sigmap(cell->getPort(ID::S));
+				assert(cell->getPort(ID::A).size() == y.size());
+				assert(cell->getPort(ID::B).size() == y.size() * s.size());
+				auto a_let_name = get_expression_for_signal(sigmap(cell->getPort(ID::A)), y.size());
+				auto b_let_name = get_expression_for_signal(sigmap(cell->getPort(ID::B)), -1);
+				auto s_let_name = get_expression_for_signal(s, -1);
+				auto y_let_name = get_expression_for_signal(y, -1);
+
+				f << stringf("(union %s (Op3 (Pmux) %s %s %s))\n", y_let_name.c_str(), s_let_name.c_str(), a_let_name.c_str(),
+										 b_let_name.c_str())
+								 .c_str();
 			}
 			else if (cell->has_attribute("\\src"))
 			{
@@ -1671,29 +1863,43 @@ struct LakeroadWorker
 				}
 
 				// Generate the instance.
-				// Cut the "\" off the front.
+				// Cut the "\" off the front of the instance's name.
 				// Check that it starts with "\" first, though.
-				assert(cell->type[0] == '\\');
 				assert(cell->name[0] == '\\');
-				f << stringf("(let %s (ModuleInstance \"%s\" (vec-of", cell->name.substr(1).c_str(), cell->type.substr(1).c_str()).c_str();
-				for (auto [port_name, _] : input_port_names_and_exprs)
+				auto instance_name = prefix + cell->name.substr(1);
+				f << stringf("(let %s (ModuleInstance \"%s\"", instance_name.c_str(), module_id(cell->type).c_str()).c_str();
+
+				// Parameters are constants. When `hierarchy` derives a module for
+				// the instance's parameter values, it clears them from the cell, but
+				// they're the derived module's defaults.
+				auto parameters = cell->parameters;
+				auto submodule = module->design->module(cell->type);
+				if (parameters.empty() && submodule != nullptr)
+					parameters = submodule->parameter_default_values;
+				std::string param_names = "(StringNil)";
+				std::string param_exprs = "(ExprNil)";
+				for (auto it = parameters.rbegin(); it != parameters.rend(); ++it)
 				{
-					assert(port_name[0] == '\\');
-					f << stringf(" \"%s\"", port_name.substr(1).c_str()).c_str();
+					param_names = stringf("(StringCons \"%s\" %s)", RTLIL::unescape_id(it->first).c_str(), param_names.c_str());
+					param_exprs = stringf("(ExprCons %s %s)", bv_expr(it->second).c_str(), param_exprs.c_str());
 				}
-				f << ") (vec-of";
-				for (auto [_, expr] : input_port_names_and_exprs)
+				f << " " << param_names << " " << param_exprs;
+
+				std::string input_names = "(StringNil)";
+				std::string input_exprs = "(ExprNil)";
+				for (auto it = input_port_names_and_exprs.rbegin(); it != input_port_names_and_exprs.rend(); ++it)
 				{
-					f << stringf(" %s", expr.c_str()).c_str();
+					assert(it->first[0] == '\\');
+					input_names = stringf("(StringCons \"%s\" %s)", it->first.substr(1).c_str(), input_names.c_str());
+					input_exprs = stringf("(ExprCons %s %s)", it->second.c_str(), input_exprs.c_str());
 				}
-				f << ")))\n";
+				f << " " << input_names << " " << input_exprs << "))\n";
 
 				// Hook up the outputs.
 				for (auto [port_name, expr] : output_port_names_and_exprs)
 				{
 					assert(port_name[0] == '\\');
-					assert(cell->name[0] == '\\');
-					f << stringf("(union (GetOutput %s \"%s\") %s)\n", cell->name.substr(1).c_str(), port_name.substr(1).c_str(), expr.c_str()).c_str();
+					f << stringf("(union (GetOutput %s \"%s\") %s)\n", instance_name.c_str(), port_name.substr(1).c_str(), expr.c_str()).c_str();
 				}
 			}
 			else
@@ -1702,6 +1908,10 @@ struct LakeroadWorker
 			}
 		}
 
+		// Ports are marked with the name of their module, so that multiple
+		// modules can share an egraph.
+		auto module_name = module_id(module->name);
+
 		// For each input, generate Var expression and mark it as an input port
 		// using the IsPort relation. Also, union it with the corresponding wire.
 		f << "\n; inputs\n";
@@ -1716,9 +1926,11 @@ struct LakeroadWorker
 			assert(signal_let_bound_name.count(sigspec));
 			auto let_bound_id = signal_let_bound_name.at(sigspec);
 
-			f << stringf("(let %s (Var \"%s\" %d))\n", signal_name.c_str(), signal_name.c_str(), GetSize(sigspec)).c_str();
-			f << stringf("(IsPort \"%s\" \"%s\" (Input) %s)\n", /*module name*/ "", signal_name.c_str(), signal_name.c_str()).c_str();
-			f << stringf("(union %s %s)\n", let_bound_id.c_str(), signal_name.c_str()).c_str();
+			auto let_name = prefix + signal_name;
+			f << stringf("(let %s (Var \"%s\" %d))\n", let_name.c_str(), signal_name.c_str(), GetSize(sigspec)).c_str();
+			f << stringf("(IsPort \"%s\" \"%s\" (Input) %s)\n", module_name.c_str(), signal_name.c_str(), let_name.c_str()).c_str();
+			f << stringf("(PortIndex \"%s\" \"%s\" %d)\n", module_name.c_str(), signal_name.c_str(), wire->port_id - 1).c_str();
+			f << stringf("(union %s %s)\n", let_bound_id.c_str(), let_name.c_str()).c_str();
 		}
 
 		// For each output, mark it as an output port using the IsPort relation.
@@ -1737,8 +1949,13 @@ struct LakeroadWorker
 			assert(signal_let_bound_name.count(sigspec));
 			auto let_bound_id = signal_let_bound_name.at(sigspec);
 
-			f << stringf("(let %s %s)\n", signal_name_pre_sigmap.c_str(), let_bound_id.c_str()).c_str();
-			f << stringf("(IsPort \"%s\" \"%s\" (Output) %s)\n", /*module name*/ "", signal_name_pre_sigmap.c_str(), signal_name_pre_sigmap.c_str()).c_str();
+			auto let_name = prefix + signal_name_pre_sigmap;
+			f << stringf("(let %s %s)\n", let_name.c_str(), let_bound_id.c_str()).c_str();
+			f << stringf("(IsPort \"%s\" \"%s\" (Output) %s)\n", module_name.c_str(), signal_name_pre_sigmap.c_str(), let_name.c_str()).c_str();
+			f << stringf("(PortIndex \"%s\" \"%s\" %d)\n", module_name.c_str(), signal_name_pre_sigmap.c_str(), wire->port_id - 1).c_str();
+			// Instances of this module need its output widths to be typed.
+			if (!prefix.empty())
+				f << stringf("(ModuleOutputBitwidth \"%s\" \"%s\" %d)\n", module_name.c_str(), signal_name_pre_sigmap.c_str(), GetSize(sigspec)).c_str();
 		}
 
     // Run typing rules before deleting wires -- cyclic circuits can only be typed using Wire expresions to bootstrap the types.
@@ -2012,6 +2229,16 @@ struct BtorBackend : public Backend
 	BtorBackend() : Backend("lakeroad", "write design to egglog Lakeroad IR") {}
 	void help() override
 	{
+		log("\n");
+		log("    write_lakeroad [-hierarchy] [filename]\n");
+		log("\n");
+		log("Write the top module of the current design as Churchroad.\n");
+		log("\n");
+		log("  -hierarchy\n");
+		log("    Also write the modules the top module instantiates, rather than\n");
+		log("    expecting the design to have been flattened. Instances become\n");
+		log("    GetOutputs of ModuleInstances of the written modules.\n");
+		log("\n");
 		//   |---v---|---v---|---v---|---v---|---v---|---v---|---v---|---v---|---v---|---v---|
 		// log("\n");
 		// log("    write_btor [options] [filename]\n");
@@ -2063,6 +2290,19 @@ struct BtorBackend : public Backend
 
 		RTLIL::Module *topmod = design->top_module();
 
+		// With -hierarchy, every module under the top module is written, rather
+		// than only the top module, which should then have been flattened.
+		bool keep_hierarchy = false;
+		for (size_t i = 1; i < args.size(); i++)
+		{
+			if (args[i] == "-hierarchy")
+			{
+				keep_hierarchy = true;
+				args.erase(args.begin() + i);
+				break;
+			}
+		}
+
 		size_t argidx = args.size();
 
 		if (filename == "")
@@ -2081,7 +2321,34 @@ struct BtorBackend : public Backend
 		if (topmod == nullptr)
 			log_cmd_error("No top module found.\n");
 
-		LakeroadWorker(*f, topmod).run();
+		if (!keep_hierarchy)
+		{
+			LakeroadWorker(*f, topmod).run();
+			return;
+		}
+
+		// Submodules are written before the modules which instantiate them, so
+		// that their output widths are known when the instances are typed.
+		std::vector<RTLIL::Module *> modules;
+		pool<RTLIL::Module *> visited;
+		std::function<void(RTLIL::Module *)> visit = [&](RTLIL::Module *module)
+		{
+			if (!visited.insert(module).second)
+				return;
+			for (auto cell : module->cells())
+			{
+				auto submodule = design->module(cell->type);
+				if (submodule != nullptr && !submodule->get_blackbox_attribute())
+					visit(submodule);
+			}
+			modules.push_back(module);
+		};
+		visit(topmod);
+		for (auto module : modules)
+		{
+			*f << stringf("\n; module %s\n", log_id(module));
+			LakeroadWorker(*f, module, module_id(module->name) + "-").run();
+		}
 
 		// *f << stringf("; end of yosys output\n");
 	}
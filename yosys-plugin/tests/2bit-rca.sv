@@ -58,15 +58,15 @@ endmodule
 // CHECK: (union v10 (Op2 (Xor) v7 v8))
 // CHECK: (union v4 (Op2 (Xor) v10 v11))
 // CHECK: (let i_a (Var "i_a" 2))
-// CHECK: (IsPort "" "i_a" (Input) i_a)
+// CHECK: (IsPort "top" "i_a" (Input) i_a)
 // CHECK: (union v13 i_a)
 // CHECK: (let i_b (Var "i_b" 2))
-// CHECK: (IsPort "" "i_b" (Input) i_b)
+// CHECK: (IsPort "top" "i_b" (Input) i_b)
 // CHECK: (union v14 i_b)
 // CHECK: (let o_c v12)
-// CHECK: (IsPort "" "o_c" (Output) o_c)
+// CHECK: (IsPort "top" "o_c" (Output) o_c)
 // CHECK: (let o_s v15)
-// CHECK: (IsPort "" "o_s" (Output) o_s)
+// CHECK: (IsPort "top" "o_s" (Output) o_s)
 // CHECK: (delete (Wire "v0" 1))
 // CHECK: (delete (Wire "v1" 1))
 // CHECK: (delete (Wire "v2" 1))
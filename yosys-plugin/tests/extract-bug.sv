@@ -16,9 +16,9 @@ endmodule
 // CHECK: (let v1 (Wire "v1" 1))
 // CHECK: (union v1 (Op1 (Extract 1 1) v0))
 // CHECK: (let in (Var "in" 2))
-// CHECK: (IsPort "" "in" (Input) in)
+// CHECK: (IsPort "test" "in" (Input) in)
 // CHECK: (union v0 in)
 // CHECK: (let out v1)
-// CHECK: (IsPort "" "out" (Output) out)
+// CHECK: (IsPort "test" "out" (Output) out)
 // CHECK: (delete (Wire "v0" 2))
 // CHECK: (delete (Wire "v1" 1))
@@ -0,0 +1,20 @@
+// RUN: $YOSYS -q -m $CHURCHROAD_DIR/yosys-plugin/churchroad.so \
+// RUN:   -p 'read_verilog -sv %s; prep -top test; write_lakeroad' \
+// RUN:   | FileCheck %s
+
+module test(input clk, input we, input [3:0] addr, input [7:0] din,
+            output [7:0] async_out, output reg [7:0] sync_out);
+  reg [7:0] mem [0:15];
+  initial for (integer i = 0; i < 16; i = i + 1) mem[i] = 8'd7;
+  always @(posedge clk) begin
+    if (we) mem[addr] <= din;
+    sync_out <= mem[addr];
+  end
+  assign async_out = mem[addr];
+endmodule
+
+// CHECK: ; memories
+// CHECK: (let [[MEM:v[0-9]+]] (Op0 (Mem "mem" 4 8 7)))
+// CHECK: (MemWrite [[MEM]] {{.*}})
+// CHECK: (union {{.*}}(Op2 (MemRead (ReadFirst)) [[MEM]] {{.*}})
+// CHECK: ; cells
@@ -0,0 +1,23 @@
+// RUN: $YOSYS -q -m $CHURCHROAD_DIR/yosys-plugin/churchroad.so \
+// RUN:   -p 'read_verilog -sv %s; prep -top test; write_lakeroad -hierarchy' \
+// RUN:   | FileCheck %s
+
+module some_module(input a, b, output out);
+  assign out = a & b;
+endmodule
+
+module test(input a, b, output out);
+  some_module some_module_instance(.a(a), .b(b), .out(out));
+endmodule
+
+// CHECK: ; module some_module
+// CHECK: (let some_module-a (Var "a" 1))
+// CHECK: (IsPort "some_module" "a" (Input) some_module-a)
+// CHECK: (IsPort "some_module" "out" (Output) some_module-out)
+// CHECK: (ModuleOutputBitwidth "some_module" "out" 1)
+// CHECK: ; module test
+// CHECK: (let test-some_module_instance (ModuleInstance "some_module" (StringNil) (ExprNil) (StringCons "a" (StringCons "b" (StringNil))) (ExprCons test-v0 (ExprCons test-v1 (ExprNil)))))
+// CHECK: (union (GetOutput test-some_module_instance "out") test-v2)
+// CHECK: (let test-a (Var "a" 1))
+// CHECK: (IsPort "test" "a" (Input) test-a)
+// CHECK: (IsPort "test" "out" (Output) test-out)
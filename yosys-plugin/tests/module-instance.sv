@@ -13,15 +13,16 @@ endmodule
 // CHECK: (let v0 (Wire "v0" 1))
 // CHECK: (let v1 (Wire "v1" 1))
 // CHECK: (let v2 (Wire "v2" 1))
-// CHECK: (let some_module_instance (ModuleInstance "some_module" (vec-of "a" "b") (vec-of v0 v1)))
+// CHECK: (let some_module_instance (ModuleInstance "some_module" (StringNil) (ExprNil) (StringCons "a" (StringCons "b" (StringNil))) (ExprCons v0 (ExprCons v1 (ExprNil)))))
+// CHECK: (union (GetOutput some_module_instance "out") v2)
 // CHECK: (let a (Var "a" 1))
-// CHECK: (IsPort "" "a" (Input) a)
+// CHECK: (IsPort "test" "a" (Input) a)
 // CHECK: (union v0 a)
 // CHECK: (let b (Var "b" 1))
-// CHECK: (IsPort "" "b" (Input) b)
+// CHECK: (IsPort "test" "b" (Input) b)
 // CHECK: (union v1 b)
 // CHECK: (let out v2)
-// CHECK: (IsPort "" "out" (Output) out)
+// CHECK: (IsPort "test" "out" (Output) out)
 // CHECK: (delete (Wire "v0" 1))
 // CHECK: (delete (Wire "v1" 1))
 // CHECK: (delete (Wire "v2" 1))
\ No newline at end of file
@@ -0,0 +1,18 @@
+// RUN: $YOSYS -q -m $CHURCHROAD_DIR/yosys-plugin/churchroad.so \
+// RUN:   -p 'read_verilog -sv %s; prep -top test; write_lakeroad -hierarchy' \
+// RUN:   | FileCheck %s
+
+module add_const #(parameter [7:0] K = 0) (input [7:0] x, output [7:0] y);
+  assign y = x + K;
+endmodule
+
+module test(input [7:0] a, output [7:0] b, output [7:0] c);
+  add_const #(.K(3)) u0(.x(a), .y(b));
+  add_const #(.K(5)) u1(.x(a), .y(c));
+endmodule
+
+// CHECK-DAG: (IsPort "[[U0:_paramod_add_const_K_[^"]*00000011]]" "y" (Output) [[U0]]-y)
+// CHECK-DAG: (IsPort "[[U1:_paramod_add_const_K_[^"]*00000101]]" "y" (Output) [[U1]]-y)
+// CHECK: ; module test
+// CHECK-DAG: (let test-u0 (ModuleInstance "[[U0]]" (StringCons "K" (StringNil)) (ExprCons (Op0 (BV 3 8)) (ExprNil)) (StringCons "x" (StringNil)) (ExprCons test-{{.*}} (ExprNil))))
+// CHECK-DAG: (let test-u1 (ModuleInstance "[[U1]]" (StringCons "K" (StringNil)) (ExprCons (Op0 (BV 5 8)) (ExprNil)) (StringCons "x" (StringNil)) (ExprCons test-{{.*}} (ExprNil))))
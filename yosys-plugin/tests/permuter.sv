@@ -104,16 +104,16 @@ endmodule
 // CHECK: (let v27 (Op1 (ZeroExtend 2) v26))
 // CHECK: (union v19 (Op2 (Eq) v21 v27))
 // CHECK: (let clk (Var "clk" 1))
-// CHECK: (IsPort "" "clk" (Input) clk)
+// CHECK: (IsPort "permuter_4x4_sim" "clk" (Input) clk)
 // CHECK: (union v20 clk)
 // CHECK: (let control (Var "control" 2))
-// CHECK: (IsPort "" "control" (Input) control)
+// CHECK: (IsPort "permuter_4x4_sim" "control" (Input) control)
 // CHECK: (union v21 control)
 // CHECK: (let din (Var "din" 16))
-// CHECK: (IsPort "" "din" (Input) din)
+// CHECK: (IsPort "permuter_4x4_sim" "din" (Input) din)
 // CHECK: (union v22 din)
 // CHECK: (let dout v23)
-// CHECK: (IsPort "" "dout" (Output) dout)
+// CHECK: (IsPort "permuter_4x4_sim" "dout" (Output) dout)
 // CHECK: (delete (Wire "v0" 16))
 // CHECK: (delete (Wire "v1" 1))
 // CHECK: (delete (Wire "v2" 16))
@@ -11,13 +11,13 @@ endmodule
 // CHECK: (let v2 (Wire "v2" 1))
 // CHECK: (union v2 (Op2 (And) v0 v1))
 // CHECK: (let a (Var "a" 1))
-// CHECK: (IsPort "" "a" (Input) a)
+// CHECK: (IsPort "test" "a" (Input) a)
 // CHECK: (union v0 a)
 // CHECK: (let b (Var "b" 1))
-// CHECK: (IsPort "" "b" (Input) b)
+// CHECK: (IsPort "test" "b" (Input) b)
 // CHECK: (union v1 b)
 // CHECK: (let out v2)
-// CHECK: (IsPort "" "out" (Output) out)
+// CHECK: (IsPort "test" "out" (Output) out)
 // CHECK: (delete (Wire "v0" 1))
 // CHECK: (delete (Wire "v1" 1))
 // CHECK: (delete (Wire "v2" 1))
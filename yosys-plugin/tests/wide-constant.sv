@@ -0,0 +1,9 @@
+// RUN: $YOSYS -q -m $CHURCHROAD_DIR/yosys-plugin/churchroad.so \
+// RUN:   -p 'read_verilog -sv %s; prep -top test; write_lakeroad' \
+// RUN:   | FileCheck %s
+
+module test(input [99:0] a, output [99:0] b);
+  assign b = a ^ 100'h1_0000_0000_0000_0002;
+endmodule
+
+// CHECK: (Op2 (Concat) (Op0 (BV 1 36)) (Op0 (BV 2 64)))